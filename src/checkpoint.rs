@@ -0,0 +1,117 @@
+//! Reading positions saved with `--save-offset` and picked up again with `--resume-offset`, so a
+//! later run carries on reading the same file from the row after the last one read.
+//!
+//! A checkpoint is a small CSV file holding the byte offset and line reached, along with the inode
+//! and size of the file, which tell it apart from another file put in its place since.
+
+use std::fs::{self, File, Metadata};
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const HEADER: &str = "inode,size,offset,line";
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub inode: u64,
+    /// Size of the file when the checkpoint was taken.
+    pub size: u64,
+    /// Byte offset right after the last row read.
+    pub offset: u64,
+    /// Lines before the offset, so the rows after it keep their line numbers.
+    pub line: u64,
+}
+
+impl Checkpoint {
+    pub fn new(metadata: &Metadata, offset: u64, line: u64) -> Self {
+        Checkpoint {
+            inode: metadata.ino(),
+            size: metadata.len(),
+            offset,
+            line,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let invalid = || format!("{} isn't an offset file saved by --save-offset", path.display());
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid());
+        }
+        let values = lines
+            .next()
+            .ok_or_else(invalid)?
+            .split(',')
+            .map(|value| value.trim().parse::<u64>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match values.as_slice() {
+            [inode, size, offset, line] => Ok(Checkpoint {
+                inode: *inode,
+                size: *size,
+                offset: *offset,
+                line: *line,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Writes the checkpoint through a temporary file, so a crash never leaves half of one behind.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(".tmp");
+
+        let mut file = File::create(&temporary_path)?;
+        writeln!(file, "{}", HEADER)?;
+        writeln!(file, "{},{},{},{}", self.inode, self.size, self.offset, self.line)?;
+        file.sync_all()?;
+
+        fs::rename(&temporary_path, path)
+    }
+
+    /// Checks the file about to be resumed is the one the checkpoint was taken of, and that it
+    /// still holds every byte up to the offset.
+    pub fn check(&self, metadata: &Metadata) -> Result<(), String> {
+        if metadata.ino() != self.inode {
+            return Err("the file was replaced since its offset was saved".to_string());
+        }
+        if metadata.len() < self.size {
+            return Err(format!(
+                "the file shrank from {} to {} bytes since its offset was saved",
+                self.size,
+                metadata.len()
+            ));
+        }
+        if self.offset > metadata.len() {
+            return Err(format!("the saved offset {} is beyond the end of the file", self.offset));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn saved_offsets_are_loaded_back() {
+        let file_path = env::temp_dir().join("transactioner_checkpoint.csv");
+        fs::write(&file_path, "type,client,tx,amount\ndeposit,1,1,10.0\n").unwrap();
+        let offset_path = env::temp_dir().join("transactioner_checkpoint.offset");
+
+        let saved = Checkpoint::new(&fs::metadata(&file_path).unwrap(), 22, 1);
+        saved.save(&offset_path).unwrap();
+        assert_eq!(Checkpoint::load(&offset_path), Ok(saved));
+        assert!(saved.check(&fs::metadata(&file_path).unwrap()).is_ok());
+        fs::write(&offset_path, "offset\n22\n").unwrap();
+        assert!(Checkpoint::load(&offset_path).is_err());
+
+        fs::remove_file(&offset_path).unwrap();
+        fs::remove_file(&file_path).unwrap();
+    }
+}
@@ -0,0 +1,513 @@
+//! Command line of a run, `convert` and `inspect`, parsed by clap from the options listed in `FLAGS`, along with
+//! `diff` and `generate`. clap tells the options that aren't known, along with the closest known one, prints the
+//! help and the version, and reads each value into its type, which `Options::from_matches` gets them as.
+
+use std::collections::HashSet;
+use std::iter;
+use std::time::Duration;
+
+use clap::builder::{RangedU64ValueParser, ValueParser};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+
+use crate::{diff, generate};
+use crate::{Amount, AmountFormat, ClientId, Compression, Encoding, InputFormat, Options, OutputFormat, OutputSchema};
+use crate::WorkerMessage;
+
+/// Option of a run, by its names and what it stands for.
+pub struct Flag {
+    /// Short name first, if any.
+    pub names: &'static [&'static str],
+    /// Placeholder of the value it takes, if any.
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+    /// Can be given several times, each value being kept.
+    pub repeated: bool,
+    /// Reads the value it takes, into the type `Options::from_matches` gets it as.
+    pub parser: fn() -> ValueParser,
+    /// Section of the config files the option can be set in, if any.
+    pub section: Option<Section>,
+}
+
+/// Section of a config file, see `config`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Section {
+    Input,
+    Output,
+    Limits,
+    Policies,
+}
+
+impl Section {
+    pub const ALL: [Section; 4] = [Section::Input, Section::Output, Section::Limits, Section::Policies];
+
+    /// Name of the section in the file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Input => "input",
+            Section::Output => "output",
+            Section::Limits => "limits",
+            Section::Policies => "policies",
+        }
+    }
+}
+
+impl Flag {
+    /// Long name, which the option goes by once parsed.
+    pub fn long(&self) -> &'static str {
+        self.names.iter().find(|name| name.starts_with("--")).expect("Every option has a long name")
+    }
+
+    /// Name of the option in the matches of clap.
+    pub fn id(&self) -> &'static str {
+        &self.long()[2..]
+    }
+
+    const fn repeated(self) -> Flag {
+        Flag { repeated: true, ..self }
+    }
+
+    const fn parsed(self, parser: fn() -> ValueParser) -> Flag {
+        Flag { parser, ..self }
+    }
+}
+
+const fn flag(names: &'static [&'static str], value: Option<&'static str>, help: &'static str) -> Flag {
+    Flag { names, value, help, repeated: false, parser: ValueParser::string, section: None }
+}
+
+const fn input(names: &'static [&'static str], value: Option<&'static str>, help: &'static str) -> Flag {
+    Flag { section: Some(Section::Input), ..flag(names, value, help) }
+}
+
+const fn output(names: &'static [&'static str], value: Option<&'static str>, help: &'static str) -> Flag {
+    Flag { section: Some(Section::Output), ..flag(names, value, help) }
+}
+
+const fn limit(names: &'static [&'static str], value: Option<&'static str>, help: &'static str) -> Flag {
+    Flag { section: Some(Section::Limits), ..flag(names, value, help) }
+}
+
+const fn policy(names: &'static [&'static str], value: Option<&'static str>, help: &'static str) -> Flag {
+    Flag { section: Some(Section::Policies), ..flag(names, value, help) }
+}
+
+/// Options of a run, of `convert` and of `inspect`, in the order they are printed. Those made with `input`,
+/// `output`, `limit` and `policy` can also be set in that section of a config file.
+pub const FLAGS: &[Flag] = &[
+    flag(&["--validate-only"], None, "only read the input and print what is wrong with it"),
+    flag(&["--client"], Some("<id>"), "client inspect writes the account of").parsed(client),
+    flag(&["--history"], None, "with inspect, also write each transaction of the client and its balances"),
+    limit(&["--workers"], Some("<n|auto>"), "workers the clients are spread across, 2 by default").parsed(workers),
+    limit(&["--buffer-size"], Some("<messages>"), "messages each worker can have waiting, told from the input size")
+        .parsed(buffer_size),
+    limit(&["--buffer-bytes"], Some("<bytes>"), "bytes of messages each worker can have waiting").parsed(buffer_bytes),
+    input(&["--glob"], Some("<pattern>"), "also read the files matching the pattern, can be repeated").repeated(),
+    input(&["--no-header"], None, "read CSV inputs without a header, by the position of the columns"),
+    input(&["--no-validate-header"], None, "read CSV headers without checking their columns"),
+    input(&["--delimiter"], Some("<char>"), "field delimiter of the CSV inputs, a comma by default").parsed(delimiter),
+    input(&["--fast-parse"], None, "parse plain CSV files straight from their bytes"),
+    input(&["--parse-threads"], Some("<n>"), "parse each plain CSV file in n ranges at the same time").parsed(positive),
+    input(&["--compression"], Some("<none|gzip|zstd|auto>"), "compression of the inputs, told from their start")
+        .parsed(compression),
+    input(&["--format"], Some("<auto|csv|json|tbin>"), "format of the inputs, told from their start")
+        .parsed(input_format),
+    input(&["--unique-tx-ids"], None, "drop deposits and withdrawals reusing a tx id of any client"),
+    input(&["--strict-precision"], None, "reject amounts past four decimal places instead of truncating them"),
+    input(&["--reject-scientific"], None, "reject amounts in scientific notation"),
+    input(&["--strict-types"], None, "read the aliases of the type names as unknown types"),
+    limit(&["--max-unknown-ratio"], Some("<pct>"), "fail past this percentage of rows of an unknown type")
+        .parsed(percentage),
+    input(&["--encoding"], Some("<utf8|utf8-lossy|latin1>"), "encoding of the inputs, utf8 by default")
+        .parsed(encoding),
+    limit(&["--max-field-bytes"], Some("<n>"), "bytes past which a CSV field is malformed").parsed(positive),
+    limit(&["--max-row-bytes"], Some("<n>"), "bytes past which a CSV row is malformed").parsed(positive),
+    input(&["--lenient-amounts"], None, "read amounts with whitespace and thousands separators"),
+    input(&["--decimal-comma"], None, "read amounts with a decimal comma, implies --lenient-amounts"),
+    input(&["--strict"], None, "fail on the first malformed row and on references carrying an amount"),
+    policy(&["--partial-disputes"], None, "disputes with an amount only hold that portion"),
+    policy(&["--single-dispute"], None, "a transaction can only be disputed once"),
+    policy(&["--hold-partial"], None, "disputes over more than is available hold what is left"),
+    policy(&["--two-phase-deposits"], None, "hold deposits until they are settled or voided"),
+    limit(&["--dispute-ttl"], Some("<n>"), "release disputes still open after n later transactions").parsed(count),
+    policy(&["--queue-after-lock"], None, "apply the deposits to locked accounts once they are unlocked"),
+    policy(&["--allow-overdraft"], None, "let withdrawals take the available funds below zero"),
+    policy(&["--strict-disputes"], None, "fail on resolves and chargebacks of transactions not under dispute"),
+    policy(&["--check-invariants"], None, "fail if any account ends with inconsistent balances"),
+    input(&["--check-chronology"], None, "count the rows timestamped earlier than a previous one"),
+    input(&["--check-monotonic-tx"], None, "report the clients whose tx ids aren't increasing"),
+    output(&["--extended-output"], None, "append the lifetime totals and counters to the output"),
+    output(&["--columns"], Some("<names>"), "write only the comma separated columns, in their order").parsed(columns),
+    output(&["--output-format"], Some("<csv|json|ndjson|table>"), "format of the accounts written")
+        .parsed(output_format),
+    output(&["--only-clients"], Some("<ids>"), "only write the accounts of the comma separated clients")
+        .parsed(clients),
+    output(&["--only-locked"], None, "only write the locked accounts"),
+    output(&["--unsorted"], None, "write the accounts of each worker as soon as it is done"),
+    output(&["--precision"], Some("<n>"), "decimal places of the amounts written, four by default").parsed(precision),
+    output(&["--trim-zeros"], None, "drop the trailing zeros of the amounts written"),
+    limit(&["--limit-rows"], Some("<n>"), "stop reading after n rows").parsed(count),
+    limit(&["--limit-bytes"], Some("<n>"), "stop reading after the whole lines that fit in n bytes").parsed(count),
+    input(&["--save-offset"], Some("<path>"), "save where the reading of the input stopped").parsed(path),
+    input(&["--resume-offset"], Some("<path>"), "read the input from the offset saved by --save-offset").parsed(path),
+    output(&["--output"], Some("<path>"), "write the accounts to the file instead of the standard output").parsed(path),
+    output(&["--output-shards"], Some("<dir>"), "have each worker write its accounts to the directory").parsed(path),
+    input(&["--follow"], None, "keep reading the last input as lines are appended to it"),
+    output(&["--dump-interval"], Some("<secs>"), "also write the accounts to --output every secs seconds")
+        .parsed(interval),
+    limit(&["--max-balance"], Some("<amount>"), "reject funds that would take an account over the amount")
+        .parsed(amount),
+    limit(&["--max-tx-amount"], Some("<amount>"), "reject transactions moving more than the amount").parsed(amount),
+    policy(&["--withdrawal-fee-flat"], Some("<amount>"), "charge a flat fee on top of every withdrawal").parsed(amount),
+    policy(&["--withdrawal-fee-pct"], Some("<pct>"), "charge a percentage of every withdrawal on top of it")
+        .parsed(amount),
+    output(&["--report-declined"], Some("<path>"), "write the declined withdrawals as CSV").parsed(path),
+    limit(&["--declined-cap"], Some("<n>"), "declined transactions kept per account, 1000 by default").parsed(size),
+    output(&["--report-rejected"], Some("<path>"), "write the rows that didn't make it into the accounts as CSV")
+        .parsed(path),
+    output(&["--report-locked"], Some("<path>"), "write the locked accounts and what locked them as CSV").parsed(path),
+    output(&["--report-mismatched-disputes"], Some("<path>"), "write the disputes of another client's tx as CSV")
+        .parsed(path),
+    output(&["--report-open-disputes"], Some("<path>"), "write the disputes left open as CSV").parsed(path),
+    output(&["--report-skips"], Some("<path>"), "write the skipped transactions of each client by reason as CSV")
+        .parsed(path),
+    output(&["--audit-log"], Some("<path>"), "write every change to the accounts as CSV").parsed(path),
+    output(&["--trace-client"], Some("<id>"), "write every transaction of the client, can be repeated")
+        .repeated()
+        .parsed(client),
+    output(
+        &["--trace-output"],
+        Some("<path>"),
+        "file the traced transactions are written to, needed by --trace-client",
+    )
+    .parsed(path),
+    output(&["--state-hash"], None, "print a hash of the final balances"),
+    output(&["--state-hash-file"], Some("<path>"), "also save the hash of the final balances to the file").parsed(path),
+    output(&["--summary-json"], Some("<path>"), "save the summary of the run as JSON").parsed(path),
+    output(&["--manifest"], Some("<path>"), "write how the run ended and what it wrote as JSON").parsed(path),
+    flag(&["--config"], Some("<path>"), "read options from the TOML file, $TRANSACTIONER_CONFIG without it"),
+    output(&["-q", "--quiet"], None, "only write errors on stderr"),
+    output(&["-v", "--verbose"], None, "write more on stderr, -vv for the progress of the reading"),
+    flag(&["-h", "--help"], None, "print this help"),
+    flag(&["-V", "--version"], None, "print the version"),
+];
+
+fn path() -> ValueParser {
+    ValueParser::path_buf()
+}
+
+fn client() -> ValueParser {
+    value_parser!(ClientId).into()
+}
+
+/// Comma separated clients.
+fn clients() -> ValueParser {
+    ValueParser::new(|list: &str| {
+        list.split(',').map(|client| client.trim().parse()).collect::<Result<HashSet<ClientId>, _>>()
+    })
+}
+
+/// Any number from zero.
+fn count() -> ValueParser {
+    value_parser!(u64).into()
+}
+
+fn size() -> ValueParser {
+    value_parser!(usize).into()
+}
+
+/// Any number from one.
+fn positive() -> ValueParser {
+    RangedU64ValueParser::<usize>::new().range(1..).into()
+}
+
+fn amount() -> ValueParser {
+    ValueParser::new(|value: &str| match value.parse::<Amount>() {
+        Ok(amount) if amount >= Amount::ZERO => Ok(amount),
+        _ => Err("expected an amount of zero or more"),
+    })
+}
+
+fn percentage() -> ValueParser {
+    ValueParser::new(|value: &str| match value.parse::<f64>() {
+        Ok(ratio) if (0.0..=100.0).contains(&ratio) => Ok(ratio),
+        _ => Err("expected a percentage from 0 to 100"),
+    })
+}
+
+/// Seconds, as a `Duration`.
+fn interval() -> ValueParser {
+    ValueParser::new(|value: &str| match value.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err("expected a number of seconds above zero"),
+    })
+}
+
+fn precision() -> ValueParser {
+    RangedU64ValueParser::<usize>::new().range(..=AmountFormat::MAX_PRECISION as u64).into()
+}
+
+/// A single byte, or a tab.
+fn delimiter() -> ValueParser {
+    ValueParser::new(|value: &str| match value {
+        "\\t" | "tab" => Ok(b'\t'),
+        other if other.len() == 1 && other != "\"" => Ok(other.as_bytes()[0]),
+        _ => Err("expected a single character other than a quote, or tab"),
+    })
+}
+
+/// A number of workers from one, or as many as there are threads besides the one of the reader for `auto`.
+fn workers() -> ValueParser {
+    ValueParser::new(|value: &str| match value {
+        "auto" => {
+            let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+            Ok(std::cmp::max(threads - 1, 1))
+        }
+        _ => match value.parse::<usize>() {
+            Ok(workers) if workers >= 1 => Ok(workers),
+            _ => Err("expected a number from 1 or auto"),
+        },
+    })
+}
+
+/// Messages of a channel, given in messages or in the bytes they fit in.
+fn buffer(value: &str, bytes_per_message: usize) -> Result<usize, String> {
+    let messages = value.parse::<usize>().map(|size| size / bytes_per_message).ok();
+    match messages {
+        Some(messages) if (1..=Options::MAX_BUFFER_SIZE).contains(&messages) => Ok(messages),
+        _ => Err(format!(
+            "has to hold from 1 to {} messages of {} bytes",
+            Options::MAX_BUFFER_SIZE,
+            std::mem::size_of::<WorkerMessage>()
+        )),
+    }
+}
+
+fn buffer_size() -> ValueParser {
+    ValueParser::new(|value: &str| buffer(value, 1))
+}
+
+fn buffer_bytes() -> ValueParser {
+    ValueParser::new(|value: &str| buffer(value, std::mem::size_of::<WorkerMessage>()))
+}
+
+fn compression() -> ValueParser {
+    ValueParser::new(|value: &str| Compression::parse(value).ok_or("expected none, gzip, zstd or auto"))
+}
+
+fn input_format() -> ValueParser {
+    ValueParser::new(|value: &str| InputFormat::parse(value).ok_or("expected auto, csv, json or tbin"))
+}
+
+fn encoding() -> ValueParser {
+    ValueParser::new(|value: &str| Encoding::parse(value).ok_or("expected utf8, utf8-lossy or latin1"))
+}
+
+fn columns() -> ValueParser {
+    ValueParser::new(OutputSchema::parse)
+}
+
+fn output_format() -> ValueParser {
+    ValueParser::new(|value: &str| OutputFormat::parse(value).ok_or("expected csv, json, ndjson or table"))
+}
+
+/// Subcommands taking the options of a run, `process` being the same as none.
+pub const SUBCOMMANDS: [(&str, &str); 3] = [
+    ("process", "apply the transactions of the inputs and write the accounts they leave, as without a subcommand"),
+    ("convert", "write the transactions of the input to a binary file, read faster than CSV"),
+    ("inspect", "write the account of a single client, applying only its rows"),
+];
+
+fn arg(flag: &Flag) -> Arg {
+    let mut arg = Arg::new(flag.id()).long(flag.id()).help(flag.help);
+    if let Some(short) = flag.names.iter().find(|name| !name.starts_with("--")) {
+        arg = arg.short(short.chars().nth(1).expect("Short names have a letter"));
+    }
+
+    match (flag.value, flag.long()) {
+        // None of the values can be negative, so one starting with a dash is an option given without its value
+        (Some(value), _) => arg
+            .value_name(value.trim_start_matches('<').trim_end_matches('>'))
+            .value_parser((flag.parser)())
+            .action(if flag.repeated { ArgAction::Append } else { ArgAction::Set }),
+        (None, "--help") => arg.action(ArgAction::Help),
+        (None, "--version") => arg.action(ArgAction::Version),
+        // Also as `-vv` and the like
+        (None, "--verbose") => arg.action(ArgAction::Count),
+        (None, _) => arg.action(ArgAction::SetTrue),
+    }
+}
+
+/// Options of a run along with its inputs, the same for each subcommand.
+fn run_args() -> impl Iterator<Item = Arg> {
+    let inputs = Arg::new("inputs").value_name("input").action(ArgAction::Append).help(
+        "files read one after the other into the same accounts, the standard input without any or for -",
+    );
+
+    FLAGS.iter().map(arg).chain(iter::once(inputs))
+}
+
+fn command_with(name: &'static str) -> Command {
+    Command::new(name)
+        .version(env!("CARGO_PKG_VERSION"))
+        .help_template("{name} {version}\n\n{usage-heading} {usage}\n\n{about}\n\n{all-args}")
+        .disable_help_flag(true)
+        .disable_version_flag(true)
+        .term_width(120)
+        // Options can be given again, the last value winning
+        .args_override_self(true)
+        .args(run_args())
+}
+
+/// Command line of a run, along with the `diff` and `generate` subcommands, which have options of their own.
+pub fn command() -> Command {
+    let name = env!("CARGO_PKG_NAME");
+    let mut command = command_with(name)
+        .about(
+            "Applies the transactions of the inputs, the standard input without any or for -, and writes the accounts\n\
+             they leave as CSV to the standard output.",
+        )
+        .override_usage(format!(
+            "{name} [process] [options] [<input>...]\n       \
+             {name} convert [options] <input> <output.tbin>\n       \
+             {name} inspect [options] <input> --client <id> [--history]\n       \
+             {name} diff [--epsilon <amount>] <a.csv> <b.csv>\n       \
+             {name} generate [--rows <n>] [--clients <n>] [--dispute-rate <share>]\n         \
+             [--chargeback-rate <share>] [--seed <n>] [--out <path>]",
+            name = name
+        ))
+        // An input named like a subcommand is read as one after the first input
+        .args_conflicts_with_subcommands(true)
+        .disable_help_subcommand(true);
+    for (subcommand, about) in SUBCOMMANDS {
+        let usage = match subcommand {
+            "convert" => format!("{} convert [options] <input> <output.tbin>", name),
+            "inspect" => format!("{} inspect [options] <input> --client <id> [--history]", name),
+            _ => format!("{} process [options] [<input>...]", name),
+        };
+        command = command.subcommand(command_with(subcommand).about(about).override_usage(usage));
+    }
+
+    // Their help flag is disabled along with the one of a run, which is listed in `FLAGS`
+    let help = Arg::new("help").short('h').long("help").action(ArgAction::Help).help("print this help");
+    for subcommand in [diff::command(), generate::command()] {
+        command = command.subcommand(subcommand.term_width(120).arg(help.clone()));
+    }
+
+    command
+}
+
+/// Matches of the arguments following the name of the binary.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<ArgMatches, clap::Error> {
+    command().try_get_matches_from(iter::once(env!("CARGO_PKG_NAME").to_string()).chain(args))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use clap::error::ErrorKind;
+
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn error(list: &[&str]) -> clap::Error {
+        parse(args(list)).expect_err("Parsing unreadable arguments")
+    }
+
+    fn inputs(matches: &ArgMatches) -> Vec<&str> {
+        matches.get_many::<String>("inputs").into_iter().flatten().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn every_option_of_the_help_is_known() {
+        for flag in FLAGS {
+            for name in flag.names {
+                let list = match flag.value {
+                    Some(_) => args(&["input.csv", name, "1"]),
+                    None => args(&["input.csv", name]),
+                };
+                // Not every option takes a 1
+                if let Err(e) = parse(list) {
+                    assert_ne!(e.kind(), ErrorKind::UnknownArgument, "{}: {}", name, e);
+                }
+            }
+        }
+
+        let help = command().render_help().to_string();
+        assert!(help.contains("  --trace-client <id>  "), "{}", help);
+        assert!(help.lines().all(|line| line.len() <= 120), "{}", help);
+    }
+
+    #[test]
+    fn process_is_the_same_as_no_subcommand() {
+        let matches = parse(args(&["input.csv", "--strict"])).unwrap();
+        assert!(matches.subcommand().is_none());
+        assert_eq!(inputs(&matches), ["input.csv"]);
+        assert!(matches.get_flag("strict"));
+
+        let matches = parse(args(&["process", "input.csv", "--strict"])).unwrap();
+        let (name, process) = matches.subcommand().unwrap();
+        assert_eq!((name, inputs(process), process.get_flag("strict")), ("process", vec!["input.csv"], true));
+        let matches = parse(args(&["inspect", "input.csv", "--client", "42"])).unwrap();
+        let (name, inspect) = matches.subcommand().unwrap();
+        assert_eq!((name, inspect.get_one::<ClientId>("client")), ("inspect", Some(&42)));
+
+        // Only as the first argument
+        assert_eq!(inputs(&parse(args(&["input.csv", "process"])).unwrap()), ["input.csv", "process"]);
+        assert_eq!(inputs(&parse(args(&["input.csv", "diff"])).unwrap()), ["input.csv", "diff"]);
+
+        // diff and generate have options of their own
+        let matches = parse(args(&["diff", "a.csv", "b.csv", "--epsilon", "0.01"])).unwrap();
+        assert_eq!(matches.subcommand_name(), Some("diff"));
+        assert_eq!(error(&["generate", "--strict"]).kind(), ErrorKind::UnknownArgument);
+        assert_eq!(error(&["diff", "--help"]).kind(), ErrorKind::DisplayHelp);
+    }
+
+    #[test]
+    fn values_are_taken_as_they_are() {
+        // But for those looking like an option, which leave the option before them without a value
+        for list in [&["input.csv", "--output", "--help"][..], &["--output"]] {
+            assert_eq!(error(list).kind(), ErrorKind::InvalidValue, "{:?}", list);
+        }
+        assert_eq!(error(&["--client", "-1"]).kind(), ErrorKind::UnknownArgument);
+        let matches = parse(args(&["--output", "-", "--delimiter", ";"])).unwrap();
+        assert_eq!(matches.get_one::<PathBuf>("output"), Some(&PathBuf::from("-")));
+        assert_eq!(matches.get_one::<u8>("delimiter"), Some(&b';'));
+
+        // The last one wins, but for the options that can be repeated
+        let matches = parse(args(&["--precision", "1", "--precision", "2", "--fast-parse", "--fast-parse"])).unwrap();
+        assert_eq!(matches.get_one::<usize>("precision"), Some(&2));
+        let matches = parse(args(&["--trace-client", "1", "--trace-client", "2"])).unwrap();
+        let clients: Vec<&ClientId> = matches.get_many("trace-client").unwrap().collect();
+        assert_eq!(clients, [&1, &2]);
+
+        for list in [&["-vv"][..], &["-v", "--verbose"]] {
+            assert_eq!(parse(args(list)).unwrap().get_count("verbose"), 2, "{:?}", list);
+        }
+    }
+
+    #[test]
+    fn help_and_version_win_over_the_options_but_unknown_ones() {
+        assert_eq!(error(&["--dump-interval", "5", "--help"]).kind(), ErrorKind::DisplayHelp);
+        assert_eq!(error(&["process", "-h"]).kind(), ErrorKind::DisplayHelp);
+        assert_eq!(error(&["-V"]).kind(), ErrorKind::DisplayVersion);
+        assert_eq!(error(&["--bogus", "--help"]).kind(), ErrorKind::UnknownArgument);
+    }
+
+    #[test]
+    fn unknown_options_name_the_closest_known_one() {
+        for (unknown, known) in [("--outptu", Some("--output")), ("--strict-dispute", Some("--strict-disputes"))] {
+            let e = error(&["input.csv", unknown]);
+            assert_eq!(e.kind(), ErrorKind::UnknownArgument);
+            assert!(e.to_string().contains(&format!("similar argument exists: '{}'", known.unwrap())), "{}", e);
+        }
+        for unknown in ["--bogus", "--vv"] {
+            let e = error(&["input.csv", unknown]);
+            assert_eq!(e.kind(), ErrorKind::UnknownArgument);
+            assert!(!e.to_string().contains("similar argument"), "{}", e);
+        }
+    }
+}
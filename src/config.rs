@@ -0,0 +1,331 @@
+//! Options of a run read from a TOML file, given by `--config` or the `TRANSACTIONER_CONFIG` environment variable,
+//! so the ones a recurring job passes each time are kept in one place.
+//!
+//! Each key is the name of an option of the command line without its dashes, in the section of the options it
+//! belongs to, `[input]`, `[output]`, `[limits]` or `[policies]` as told by `cli::FLAGS`, `files` in `[input]`
+//! standing for the inputs. The keys are turned into the arguments they stand
+//! for, which go before the ones of the command line, and those also given on the command line are left out, so the
+//! command line always wins over the file, which wins over the defaults. Only the part of TOML options need is read:
+//! sections, and keys holding a string, a number, a boolean or an array of those on a single line.
+
+use std::collections::HashSet;
+use std::fs;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use clap::parser::ValueSource;
+
+use crate::cli::{self, Section};
+
+/// Value of a key, numbers being kept as they are written.
+#[derive(Debug, PartialEq)]
+enum Value {
+    Text(String),
+    Number(String),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+/// Arguments of the command line, those of the config file given by `--config`, or else by `env`, going first.
+/// Subcommands stay first. Arguments clap can't read are left as they are, for it to tell what is wrong with them.
+pub fn resolve(args: Vec<String>, env: Option<String>) -> Result<Vec<String>, String> {
+    let matches = match cli::parse(args.clone()) {
+        Ok(matches) => matches,
+        Err(_) => return Ok(args),
+    };
+    let subcommand = matches.subcommand();
+    let matches = subcommand.map_or(&matches, |(_, matches)| matches);
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    let flagged = matches.get_one::<String>("config");
+    let path = match flagged {
+        Some(path) => path.clone(),
+        None => match env {
+            Some(path) if !path.is_empty() => path,
+            _ => return Ok(args),
+        },
+    };
+
+    let text = fs::read_to_string(&path).map_err(|e| format!("Could not read the config file {}: {}", path, e))?;
+    let mut config = arguments(&text).map_err(|e| format!("{} in the config file {}", e, path))?;
+    config.retain(|(flag, _)| !given(if flag == "files" { "inputs" } else { flag }));
+
+    let split = subcommand.is_some() as usize;
+    let mut resolved = args[..split].to_vec();
+    for (flag, values) in config {
+        for value in values {
+            if flag != "files" {
+                resolved.push(format!("--{}", flag));
+            }
+            resolved.extend(value);
+        }
+    }
+    resolved.extend_from_slice(&args[split..]);
+    // Told to the options when it came from the environment, like the ones from the command line
+    if flagged.is_none() {
+        resolved.extend(["--config".to_string(), path]);
+    }
+
+    Ok(resolved)
+}
+
+/// Option of the file, by the name of its flag, along with the arguments of each time it's given, none for the
+/// flags without a value.
+type Setting = (String, Vec<Vec<String>>);
+
+fn arguments(text: &str) -> Result<Vec<Setting>, String> {
+    let mut section: Option<Section> = None;
+    let mut seen = HashSet::new();
+    let mut arguments = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let invalid = |e: String| format!("Invalid line {}: {}", index + 1, e);
+        let mut chars = line.chars().peekable();
+        skip_blank(&mut chars);
+        match chars.peek() {
+            None | Some('#') => continue,
+            Some('[') => {
+                chars.next();
+                let name: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                let name = name.trim();
+                expect_end(&mut chars).map_err(invalid)?;
+                section = Section::ALL.iter().copied().find(|known| known.name() == name);
+                if section.is_none() {
+                    return Err(format!("Unknown section [{}]", name));
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(invalid(format!("expected a key, found {:?}", line.trim())));
+        }
+        let section = section.ok_or_else(|| format!("Key {} has to be in a section", key))?;
+        let flag = key.replace('_', "-");
+        let known = match flag.as_str() {
+            "files" => section == Section::Input,
+            _ => cli::FLAGS.iter().any(|known| known.section == Some(section) && known.id() == flag),
+        };
+        let section_name = section.name();
+        if !known {
+            return Err(format!("Unknown key {}.{}", section_name, key));
+        }
+        if !seen.insert(flag.clone()) {
+            return Err(format!("Key {}.{} is given twice", section_name, key));
+        }
+
+        let value = parse_value(&mut chars).map_err(invalid)?;
+        expect_end(&mut chars).map_err(invalid)?;
+        arguments.push((flag, values(value).map_err(|e| format!("Invalid value of {}.{}: {}", section_name, key, e))?));
+    }
+
+    Ok(arguments)
+}
+
+/// Arguments of each time the flag of a value is given, a flag of its own being given for each item of an array.
+fn values(value: Value) -> Result<Vec<Vec<String>>, String> {
+    let single = |value: Value| match value {
+        Value::Text(text) | Value::Number(text) => Ok(vec![text]),
+        Value::Boolean(_) | Value::Array(_) => Err("expected a string or a number".to_string()),
+    };
+
+    match value {
+        Value::Boolean(true) => Ok(vec![Vec::new()]),
+        // The same as leaving the key out, there's no way to turn an option off
+        Value::Boolean(false) => Ok(Vec::new()),
+        Value::Array(items) => items.into_iter().map(single).collect(),
+        value => Ok(vec![single(value)?]),
+    }
+}
+
+fn skip_blank(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+}
+
+/// Checks nothing but a comment is left on the line.
+fn expect_end(chars: &mut Peekable<Chars>) -> Result<(), String> {
+    skip_blank(chars);
+    match chars.next() {
+        None | Some('#') => Ok(()),
+        Some(c) => Err(format!("unexpected {:?}", c)),
+    }
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<Value, String> {
+    skip_blank(chars);
+    match chars.peek().copied() {
+        Some(quote @ ('"' | '\'')) => {
+            chars.next();
+            parse_string(chars, quote).map(Value::Text)
+        }
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            loop {
+                skip_blank(chars);
+                if chars.next_if_eq(&']').is_some() {
+                    break;
+                }
+                match parse_value(chars)? {
+                    Value::Array(_) => return Err("arrays can't hold arrays".to_string()),
+                    item => items.push(item),
+                }
+                skip_blank(chars);
+                match chars.next() {
+                    Some(',') => {}
+                    Some(']') => break,
+                    _ => return Err("unterminated array, which has to be on a single line".to_string()),
+                }
+            }
+            Ok(Value::Array(items))
+        }
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(c) = chars.next_if(|c| !matches!(c, ' ' | '\t' | ',' | ']' | '#')) {
+                word.push(c);
+            }
+            match word.as_str() {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ if is_number(&word) => Ok(Value::Number(word.replace('_', ""))),
+                _ => Err(format!("expected a value, found {:?}", word)),
+            }
+        }
+        None => Err("missing value".to_string()),
+    }
+}
+
+fn is_number(word: &str) -> bool {
+    let digits = word.strip_prefix(['+', '-']).unwrap_or(word);
+    digits.starts_with(|c: char| c.is_ascii_digit())
+        && digits.chars().all(|c| c.is_ascii_digit() || matches!(c, '_' | '.' | 'e' | 'E' | '+' | '-'))
+}
+
+/// Reads a string up to its closing `quote`, basic strings being unescaped and literal ones kept as they are.
+fn parse_string(chars: &mut Peekable<Chars>, quote: char) -> Result<String, String> {
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            Some(c) if c == quote => return Ok(text),
+            Some('\\') if quote == '"' => match chars.next() {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some('"') => text.push('"'),
+                Some('\\') => text.push('\\'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32);
+                    text.push(c.ok_or_else(|| format!("invalid escape \\u{}", code))?);
+                }
+                Some(other) => return Err(format!("invalid escape \\{}", other)),
+                None => return Err("unterminated string".to_string()),
+            },
+            Some(c) => text.push(c),
+            None => return Err("unterminated string".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::env;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::Options;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn options(list: &[&str], env: Option<&str>) -> Result<Options, String> {
+        let args = resolve(args(list), env.map(String::from))?;
+        cli::parse(args).map_err(|e| e.to_string()).and_then(|matches| Options::from_matches(&matches))
+    }
+
+    #[test]
+    fn the_command_line_wins_over_the_file() {
+        let config_path = env::temp_dir().join("transactioner_config.toml");
+        let config = config_path.to_string_lossy().to_string();
+        fs::write(
+            &config_path,
+            "# Nightly job\n[input]\nfiles = [\"a.csv\", 'b.csv']\ndelimiter = \";\"\nfast_parse = true\n\n\
+             [output]\nprecision = 2 # two decimals\ntrace-client = [1, 2]\ntrace-output = \"t.csv\"\n\n\
+             [limits]\nworkers = 4\n\n[policies]\nsingle_dispute = false\n",
+        )
+        .unwrap();
+
+        let defaults = options(&["input.csv"], None).unwrap();
+        assert_eq!(defaults.output.amounts.precision, 4);
+        assert_eq!((defaults.workers, defaults.reader.delimiter, defaults.reader.fast_parse), (None, None, false));
+
+        let configured = options(&["--config", &config], None).unwrap();
+        assert_eq!(configured.inputs, [PathBuf::from("a.csv"), PathBuf::from("b.csv")]);
+        assert_eq!(configured.output.amounts.precision, 2);
+        let reader = configured.reader;
+        assert_eq!((configured.workers, reader.delimiter, reader.fast_parse), (Some(4), Some(b';'), true));
+        assert_eq!(configured.trace_clients, HashSet::from([1, 2]));
+        assert!(!configured.policy.single_dispute);
+        assert_eq!(configured.config, Some(config_path.clone()));
+
+        // The options of the file go first, but for those given on the command line, and the subcommand stays first
+        let list = ["inspect", "c.csv", "--client", "1", "--precision", "0", "--workers", "1", "--delimiter", "|"];
+        let resolved = resolve(args(&list), Some(config.clone())).unwrap();
+        let mut expected = args(&["inspect", "--fast-parse", "--trace-client", "1", "--trace-client", "2"]);
+        expected.extend(args(&["--trace-output", "t.csv"]));
+        expected.extend(args(&list[1..]));
+        expected.extend(args(&["--config", &config]));
+        assert_eq!(resolved, expected);
+        let flagged = options(&list, Some(&config)).unwrap();
+        assert_eq!(flagged.inputs, [PathBuf::from("c.csv")]);
+        assert_eq!(flagged.output.amounts.precision, 0);
+        assert_eq!((flagged.workers, flagged.reader.delimiter, flagged.reader.fast_parse), (Some(1), Some(b'|'), true));
+        assert_eq!(flagged.inspect_client, Some(1));
+        assert_eq!(flagged.config, Some(config_path.clone()));
+
+        // The command line names the file over the environment
+        assert_eq!(options(&["--config", &config], Some("missing.toml")).unwrap().config, Some(config_path.clone()));
+        assert!(options(&[], Some("missing.toml")).is_err());
+        // Arguments clap can't read are left to it
+        assert_eq!(resolve(args(&["--bogus"]), Some(config.clone())).unwrap(), args(&["--bogus"]));
+        fs::remove_file(&config_path).unwrap();
+    }
+
+    #[test]
+    fn unknown_keys_and_invalid_lines_are_errors() {
+        let config_path = env::temp_dir().join("transactioner_invalid_config.toml");
+        let config = config_path.to_string_lossy().to_string();
+        for (text, error) in [
+            ("[output]\nprecisoin = 2\n", "Unknown key output.precisoin"),
+            ("[limits]\nprecision = 2\n", "Unknown key limits.precision"),
+            ("[output]\nfiles = [\"a.csv\"]\n", "Unknown key output.files"),
+            // Options of a run that can't be set in a file
+            ("[input]\nconfig = \"other.toml\"\n", "Unknown key input.config"),
+            ("[output]\nhelp = true\n", "Unknown key output.help"),
+            ("[outputs]\nprecision = 2\n", "Unknown section [outputs]"),
+            ("precision = 2\n", "Key precision has to be in a section"),
+            ("[output]\nprecision = 2\nprecision = 3\n", "Key output.precision is given twice"),
+            ("[output]\nprecision = \"2\n", "Invalid line 2: unterminated string"),
+            ("[output]\nprecision = 2 2\n", "Invalid line 2: unexpected '2'"),
+            ("[limits]\nworkers = [[1]]\n", "Invalid line 2: arrays can't hold arrays"),
+            ("[limits]\nworkers = [true]\n", "Invalid value of limits.workers: expected a string or a number"),
+        ] {
+            fs::write(&config_path, text).unwrap();
+            let e = resolve(args(&["--config", &config]), None).unwrap_err();
+            assert_eq!(e, format!("{} in the config file {}", error, config));
+        }
+        fs::remove_file(&config_path).unwrap();
+
+        // Every option listed in a section is a key of it
+        for flag in cli::FLAGS {
+            if let Some(section) = flag.section {
+                let text = format!("[{}]\n{} = true\n", section.name(), flag.id().replace('-', "_"));
+                assert!(arguments(&text).is_ok(), "{}", flag.long());
+            }
+        }
+    }
+}
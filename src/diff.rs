@@ -0,0 +1,266 @@
+//! `transactioner diff <a.csv> <b.csv>`, comparing the accounts of two CSV outputs, to tell which balances a change
+//! of the engine moved when an archive is run again.
+//!
+//! Accounts are matched by client, and currency for outputs with the column, whatever the order of their rows.
+//! Amounts only differ past the `--epsilon` given, the rest of the fields have to be equal.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use clap::{value_parser, Arg, ArgMatches, Command};
+
+use crate::{log, timestamp, Amount, ClientId, ClientState, Currency};
+
+/// Column of the output compared, by its name and what an account holds in it.
+type Field<T> = (&'static str, fn(&ClientState) -> T);
+
+/// Files compared by `diff`, and how far apart their amounts can be.
+#[derive(Debug, PartialEq)]
+pub struct DiffOptions {
+    pub before: PathBuf,
+    pub after: PathBuf,
+    pub epsilon: Amount,
+}
+
+impl DiffOptions {
+    /// Reads the options from what clap matched, see `command`.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let path = |id: &str| matches.get_one::<PathBuf>(id).expect("Both files are required").clone();
+
+        DiffOptions {
+            before: path("before"),
+            after: path("after"),
+            epsilon: matches.get_one::<Amount>("epsilon").copied().unwrap_or(Amount::ZERO),
+        }
+    }
+}
+
+/// Arguments of `diff`, a subcommand of `cli::command`.
+pub fn command() -> Command {
+    let epsilon = |value: &str| match value.parse::<Amount>() {
+        Ok(amount) if amount >= Amount::ZERO => Ok(amount),
+        _ => Err("expected an amount of zero or more".to_string()),
+    };
+
+    Command::new("diff")
+        .about("compare the accounts of two CSV outputs, exiting with 1 if they differ and 2 if they can't be read")
+        .arg(Arg::new("epsilon").long("epsilon").value_name("amount").value_parser(epsilon).help(
+            "how much the amounts can differ by, zero by default",
+        ))
+        .arg(Arg::new("before").value_name("a.csv").required(true).value_parser(value_parser!(PathBuf)).help(
+            "output the accounts are compared to",
+        ))
+        .arg(Arg::new("after").value_name("b.csv").required(true).value_parser(value_parser!(PathBuf)).help(
+            "output the differences are told of, like the one of a run after a change",
+        ))
+}
+
+/// How an account of the second file differs from the one of the first.
+#[derive(Debug, PartialEq)]
+pub enum Difference {
+    /// Account only found in the second file.
+    Added(ClientId, Currency),
+    /// Account only found in the first file.
+    Removed(ClientId, Currency),
+    /// Field with another value in each file, along with how much it went up for the numbers.
+    Changed {
+        client: ClientId,
+        currency: Currency,
+        field: &'static str,
+        before: String,
+        after: String,
+        delta: Option<String>,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let account = |f: &mut fmt::Formatter<'_>, client: &ClientId, currency: &Currency| {
+            write!(f, "client {}", client)?;
+            if *currency != Currency::NONE {
+                write!(f, " in {}", currency)?;
+            }
+
+            Ok(())
+        };
+
+        match self {
+            Difference::Added(client, currency) => {
+                write!(f, "added ")?;
+                account(f, client, currency)
+            }
+            Difference::Removed(client, currency) => {
+                write!(f, "removed ")?;
+                account(f, client, currency)
+            }
+            Difference::Changed { client, currency, field, before, after, delta } => {
+                account(f, client, currency)?;
+                write!(f, ": {} {} -> {}", field, before, after)?;
+                match delta {
+                    Some(delta) => write!(f, " ({})", delta),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// Reads the accounts of an output file, each of which it may only hold once.
+pub fn read_accounts(path: &Path) -> Result<HashMap<(ClientId, Currency), ClientState>, String> {
+    let invalid = |e: csv::Error| format!("Could not read the accounts of {}: {}", path.display(), e);
+
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_path(path).map_err(invalid)?;
+    let mut accounts = HashMap::new();
+    for state in reader.deserialize::<ClientState>() {
+        let state = state.map_err(invalid)?;
+        if accounts.insert((state.client, state.currency), state).is_some() {
+            return Err(format!("Client {} is found more than once in {}", state.client, path.display()));
+        }
+    }
+
+    Ok(accounts)
+}
+
+/// Differences between the accounts of two files, sorted by client and currency, and by column for each of them.
+pub fn compare(
+    before: &HashMap<(ClientId, Currency), ClientState>,
+    after: &HashMap<(ClientId, Currency), ClientState>,
+    epsilon: Amount,
+) -> Vec<Difference> {
+    let mut keys: Vec<&(ClientId, Currency)> = before.keys().chain(after.keys()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut differences = Vec::new();
+    for key in keys {
+        let (client, currency) = *key;
+        let (before, after) = match (before.get(key), after.get(key)) {
+            (Some(before), Some(after)) => (before, after),
+            (Some(_), None) => {
+                differences.push(Difference::Removed(client, currency));
+                continue;
+            }
+            _ => {
+                differences.push(Difference::Added(client, currency));
+                continue;
+            }
+        };
+
+        let mut changed = |field: &'static str, before: String, after: String, delta: Option<String>| {
+            if before != after {
+                differences.push(Difference::Changed { client, currency, field, before, after, delta });
+            }
+        };
+
+        let amounts: [Field<Amount>; 9] = [
+            ("available", |state| state.available),
+            ("held", |state| state.held),
+            ("total", |state| state.total),
+            ("total_deposited", |state| state.total_deposited),
+            ("total_withdrawn", |state| state.total_withdrawn),
+            ("chargeback_shortfall", |state| state.chargeback_shortfall),
+            ("pending", |state| state.pending),
+            ("fees", |state| state.total_fees),
+            ("net_adjustment", |state| state.net_adjustment),
+        ];
+        for (field, amount) in amounts {
+            let (before, after) = (amount(before), amount(after));
+            // Told apart without the saturation of `Amount`, so amounts at either end of the range still differ
+            if (after.0 as i128 - before.0 as i128).abs() > epsilon.0 as i128 {
+                let delta = after - before;
+                let sign = if delta > Amount::ZERO { "+" } else { "" };
+                changed(field, before.to_string(), after.to_string(), Some(format!("{}{}", sign, delta)));
+            }
+        }
+
+        let counts: [Field<u32>; 2] = [
+            ("dispute_count", |state| state.dispute_count),
+            ("chargeback_count", |state| state.chargeback_count),
+        ];
+        for (field, count) in counts {
+            let delta = count(after) as i64 - count(before) as i64;
+            let delta = format!("{}{}", if delta > 0 { "+" } else { "" }, delta);
+            changed(field, count(before).to_string(), count(after).to_string(), Some(delta));
+        }
+
+        changed("locked", before.locked.to_string(), after.locked.to_string(), None);
+        changed("closed", before.closed.to_string(), after.closed.to_string(), None);
+
+        let timestamp = |millis: Option<i64>| millis.map(timestamp::format).unwrap_or_default();
+        changed("first_timestamp", timestamp(before.first_timestamp), timestamp(after.first_timestamp), None);
+        changed("last_timestamp", timestamp(before.last_timestamp), timestamp(after.last_timestamp), None);
+    }
+
+    differences
+}
+
+/// Prints the differences between the two files, returning whether there are any.
+pub fn run(options: &DiffOptions) -> Result<bool, String> {
+    let before = read_accounts(&options.before)?;
+    let after = read_accounts(&options.after)?;
+
+    let differences = compare(&before, &after, options.epsilon);
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    log::info!(
+        "Found {} difference/s between the {} account/s of {} and the {} of {}",
+        differences.len(),
+        before.len(),
+        options.before.display(),
+        after.len(),
+        options.after.display()
+    );
+
+    Ok(!differences.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use std::iter;
+
+    use super::*;
+
+    #[test]
+    fn diffs_match_the_accounts_by_client() {
+        let read = |name: &str| read_accounts(&Path::new("test_data").join(name)).unwrap();
+        let before = read("diff_before.csv");
+
+        assert!(compare(&before, &before, Amount::ZERO).is_empty());
+        assert!(compare(&before, &read("diff_reordered.csv"), Amount::ZERO).is_empty());
+
+        // The cent of client 1 is within the epsilon
+        let differences: Vec<String> =
+            compare(&before, &read("diff_after.csv"), Amount(1)).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            differences,
+            [
+                "client 2: available 135.0000 -> 133.5000 (-1.5000)",
+                "client 2: held 0.0000 -> 1.5000 (+1.5000)",
+                "client 2: dispute_count 0 -> 1 (+1)",
+                "client 2: locked false -> true",
+                "removed client 3",
+                "added client 4",
+            ]
+        );
+        assert_eq!(compare(&before, &read("diff_after.csv"), Amount::ZERO).len(), 8);
+
+        let error = read_accounts(Path::new("test_data/diff_duplicated.csv")).unwrap_err();
+        assert_eq!(error, "Client 1 is found more than once in test_data/diff_duplicated.csv");
+
+        let options = |list: &[&str]| {
+            let matches = command().try_get_matches_from(iter::once("diff").chain(list.iter().copied()));
+            matches.map(|matches| DiffOptions::from_matches(&matches))
+        };
+        assert_eq!(
+            options(&["a.csv", "--epsilon", "0.01", "b.csv"]).unwrap(),
+            DiffOptions { before: PathBuf::from("a.csv"), after: PathBuf::from("b.csv"), epsilon: Amount(100) }
+        );
+        assert_eq!(options(&["a.csv", "b.csv"]).unwrap().epsilon, Amount::ZERO);
+        assert!(options(&["a.csv"]).is_err());
+        assert!(options(&["a.csv", "b.csv", "c.csv"]).is_err());
+        assert!(options(&["a.csv", "b.csv", "--epsilon", "-1"]).is_err());
+        assert!(options(&["a.csv", "b.csv", "--epsilon", "x"]).is_err());
+    }
+}
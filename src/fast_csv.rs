@@ -0,0 +1,325 @@
+//! Fast path for plain CSV files held in memory, whose rows are split and parsed straight from
+//! the bytes of the file into `Transaction` values, without going through serde or allocating
+//! per record.
+//!
+//! Only files without quotes are taken, quoting is left to the `csv` crate along with the rest of
+//! its rules. For those files the rows, line numbers and errors match what the `csv` reader gives.
+
+use std::sync::Arc;
+
+use crate::{
+    field_count_error, idempotency_key_hash, is_skipped_line, oversized_row_error, repeats_header, timestamp, Amount,
+    ClientId, Currency, ReadOutcome, RowLimits, Transaction, TransactionType,
+};
+
+/// Columns of the header the transactions are read from.
+#[derive(Debug, Default)]
+struct Columns {
+    r#type: usize,
+    client: usize,
+    tx: usize,
+    amount: usize,
+    counterparty: Option<usize>,
+    idempotency_key: Option<usize>,
+    currency: Option<usize>,
+    timestamp: Option<usize>,
+}
+
+/// Rows of a plain CSV file held in memory, or of a range of its lines.
+pub struct FastRecords {
+    data: Arc<Vec<u8>>,
+    delimiter: u8,
+    limits: RowLimits,
+    headers: csv::StringRecord,
+    columns: Arc<Columns>,
+    /// Start of the next line.
+    position: usize,
+    /// End of the range being read.
+    end: usize,
+    /// Lines read so far, the latest being the one of the current row.
+    line: u64,
+    /// Byte ranges of the fields of the current row.
+    fields: Vec<(usize, usize)>,
+}
+
+impl FastRecords {
+    /// Whether the file can go through the fast path.
+    pub fn supports(data: &[u8]) -> bool {
+        !data.contains(&b'"') && std::str::from_utf8(data).is_ok()
+    }
+
+    /// Reads the header of the file, which `supports` has to accept. Files without one are read
+    /// with the `headers` given instead.
+    pub fn new(data: Vec<u8>, delimiter: u8, limits: RowLimits, headers: Option<csv::StringRecord>) -> Self {
+        let end = data.len();
+        let mut records = FastRecords {
+            data: Arc::new(data),
+            delimiter,
+            limits,
+            headers: csv::StringRecord::new(),
+            columns: Arc::default(),
+            position: 0,
+            end,
+            line: 0,
+            fields: Vec::new(),
+        };
+
+        // Like the csv crate, a byte order mark isn't part of the first column name
+        if records.data.starts_with(b"\xEF\xBB\xBF") {
+            records.position = 3;
+        }
+
+        if let Some(headers) = headers {
+            records.headers = headers;
+        } else if records.next_header_line() {
+            for index in 0..records.fields.len() {
+                let name = records.field(index).to_lowercase();
+                records.headers.push_field(&name);
+            }
+        }
+
+        let column = |name: &str| records.headers.iter().position(|header| header == name);
+        records.columns = Arc::new(Columns {
+            r#type: column("type").unwrap_or_default(),
+            client: column("client").unwrap_or_default(),
+            tx: column("tx").unwrap_or_default(),
+            amount: column("amount").unwrap_or_default(),
+            counterparty: column("counterparty"),
+            idempotency_key: column("idempotency_key"),
+            currency: column("currency"),
+            timestamp: column("timestamp"),
+        });
+
+        records
+    }
+
+    /// Splits the rows left into up to `count` ranges of about the same size, each starting at the
+    /// beginning of a line and counting lines from where it starts in the file.
+    pub fn split(&self, count: usize) -> Vec<FastRecords> {
+        let mut ranges = Vec::with_capacity(count);
+        let mut start = self.position;
+        let mut line = self.line;
+        let size = (self.end - start) / count.max(1) + 1;
+
+        while start < self.end {
+            let cut = std::cmp::min(start + size, self.end);
+            let end = self.data[cut..self.end]
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .map_or(self.end, |offset| cut + offset + 1);
+
+            ranges.push(FastRecords {
+                data: Arc::clone(&self.data),
+                delimiter: self.delimiter,
+                limits: self.limits,
+                headers: self.headers.clone(),
+                columns: Arc::clone(&self.columns),
+                position: start,
+                end,
+                line,
+                fields: Vec::new(),
+            });
+            line += self.data[start..end].iter().filter(|byte| **byte == b'\n').count() as u64;
+            start = end;
+        }
+
+        ranges
+    }
+
+    pub fn headers(&self) -> &csv::StringRecord {
+        &self.headers
+    }
+
+    /// Offset of the end of the current row.
+    pub fn offset(&self) -> usize {
+        std::cmp::min(self.position, self.end)
+    }
+
+    /// Line the current row was read from.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// Splits the next line into its fields, unless it is blank or a comment.
+    fn next_line(&mut self) -> ReadOutcome {
+        if self.position >= self.end {
+            return ReadOutcome::End;
+        }
+
+        let start = self.position;
+        let end = self.data[start..self.end]
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map_or(self.end, |offset| start + offset);
+        self.position = end + 1;
+        self.line += 1;
+
+        // Blank lines are skipped even if they hold whitespace, like the \r of CRLF files
+        if is_skipped_line(&self.data[start..end], self.delimiter) {
+            return ReadOutcome::Skipped;
+        }
+        let end = if self.data[end - 1] == b'\r' { end - 1 } else { end };
+
+        self.fields.clear();
+        let mut field_start = start;
+        for index in start..=end {
+            if index == end || self.data[index] == self.delimiter {
+                self.fields.push((field_start, index));
+                field_start = index + 1;
+            }
+        }
+
+        ReadOutcome::Row
+    }
+
+    /// Moves to the first line that isn't blank or a comment, returning whether there was one.
+    fn next_header_line(&mut self) -> bool {
+        loop {
+            match self.next_line() {
+                ReadOutcome::Row => return true,
+                ReadOutcome::Skipped | ReadOutcome::Header => continue,
+                ReadOutcome::End => return false,
+            }
+        }
+    }
+
+    /// Moves to the next line. A row that doesn't have as many fields as the header, or goes over the
+    /// limits, is an error, the rows after it can still be read.
+    pub fn read(&mut self) -> Result<ReadOutcome, String> {
+        let outcome = self.next_line();
+
+        let bounds = (self.fields.first(), self.fields.last());
+        if let (ReadOutcome::Row, (Some((start, _)), Some((_, end)))) = (outcome, bounds) {
+            // Like the csv reader, the limits are those of the line as it is, before trimming
+            let limit = if self.fields.iter().any(|(start, end)| end - start > self.limits.field_bytes) {
+                Some("--max-field-bytes")
+            } else if end - start > self.limits.row_bytes {
+                Some("--max-row-bytes")
+            } else {
+                None
+            };
+            if let Some(limit) = limit {
+                return Err(oversized_row_error(self.line, *start as u64, limit));
+            }
+        }
+
+        if outcome == ReadOutcome::Row && self.fields.len() != self.headers.len() {
+            return Err(field_count_error(self.line, self.fields.len(), self.headers.len()));
+        }
+        let fields = (0..self.fields.len()).map(|index| self.field(index));
+        if outcome == ReadOutcome::Row && repeats_header(fields, &self.headers) {
+            return Ok(ReadOutcome::Header);
+        }
+
+        Ok(outcome)
+    }
+
+    fn field(&self, index: usize) -> &str {
+        let (start, end) = self.fields[index];
+        // Checked to be UTF-8 as a whole by `supports`, and fields are split on ASCII bytes
+        std::str::from_utf8(&self.data[start..end]).unwrap_or_default().trim()
+    }
+
+    /// Raw client of the current row.
+    pub fn client(&self) -> &str {
+        self.field(self.columns.client)
+    }
+
+    /// Raw type of the current row.
+    pub fn type_name(&self) -> &str {
+        self.field(self.columns.r#type)
+    }
+
+    /// Raw amount of the current row.
+    pub fn amount(&self) -> &str {
+        self.field(self.columns.amount)
+    }
+
+    /// Parses the current row, the way the serde deserializers of `Transaction` do, with `amount` in
+    /// place of the raw amount, as the lenient parsing may have rewritten it.
+    pub fn transaction(&self, amount: &str) -> Result<Transaction, String> {
+        let invalid = |name: &str, value: &str, e: &dyn std::fmt::Display| {
+            format!("CSV deserialize error: line: {}: invalid {} {:?}: {}", self.line, name, value, e)
+        };
+        let client_id = |name: &str, value: &str| value.parse::<ClientId>().map_err(|e| invalid(name, value, &e));
+
+        let client = client_id("client", self.field(self.columns.client))?;
+        let tx = self.field(self.columns.tx);
+        let tx = tx.parse().map_err(|e| invalid("tx", tx, &e))?;
+
+        let amount = if amount.is_empty() {
+            Amount::ZERO
+        } else {
+            Amount::parse(amount, true).map_err(|e| invalid("amount", amount, &e))?
+        };
+
+        let counterparty = match self.columns.counterparty.map(|column| self.field(column)) {
+            Some(value) if !value.is_empty() => Some(client_id("counterparty", value)?),
+            _ => None,
+        };
+        let currency = match self.columns.currency.map(|column| self.field(column)) {
+            Some(code) if !code.is_empty() => Currency::parse(code).map_err(|e| invalid("currency", code, &e))?,
+            _ => Currency::NONE,
+        };
+        let timestamp = match self.columns.timestamp.map(|column| self.field(column)) {
+            Some(text) if !text.is_empty() => Some(timestamp::parse(text).map_err(|e| invalid("timestamp", text, &e))?),
+            _ => None,
+        };
+
+        Ok(Transaction {
+            r#type: TransactionType::from_name(self.field(self.columns.r#type)),
+            client,
+            tx,
+            amount,
+            counterparty,
+            idempotency_key: self.columns.idempotency_key.and_then(|column| idempotency_key_hash(self.field(column))),
+            currency,
+            timestamp,
+            row: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn records(data: &str) -> FastRecords {
+        FastRecords::new(data.as_bytes().to_vec(), b',', RowLimits::default(), None)
+    }
+
+    #[test]
+    fn only_unquoted_utf8_files_are_supported() {
+        assert!(FastRecords::supports(b"type,client,tx,amount\ndeposit,1,1,1.0\n"));
+        assert!(!FastRecords::supports(b"type,client,tx,amount\n\"deposit\",1,1,1.0\n"));
+        assert!(!FastRecords::supports(b"type,client,tx,amount\ndeposit,1,1,\xff\n"));
+    }
+
+    #[test]
+    fn ranges_start_at_a_line_and_count_lines_from_there() {
+        let rows: String = (1..=10).map(|tx| format!("deposit,1,{},1.0\n", tx)).collect();
+        let whole = records(&format!("type,client,tx,amount\n{}", rows));
+
+        for count in [1, 3, 16] {
+            let mut read = Vec::new();
+            for mut range in whole.split(count) {
+                while range.read().unwrap() != ReadOutcome::End {
+                    read.push((range.line(), range.transaction(range.amount()).unwrap().tx));
+                }
+            }
+            assert_eq!(read, (1..=10).map(|tx| (tx as u64 + 1, tx)).collect::<Vec<_>>(), "{} ranges", count);
+        }
+    }
+
+    #[test]
+    fn malformed_rows_leave_the_next_ones_readable() {
+        let mut records = records("type,client,tx,amount\ndeposit,1,1\n\nwithdrawal,1,2,0.5\ntype,client,tx,amount\n");
+
+        assert!(records.read().unwrap_err().contains("line: 2"));
+        assert_eq!(records.read(), Ok(ReadOutcome::Skipped));
+        assert_eq!(records.read(), Ok(ReadOutcome::Row));
+        assert_eq!((records.type_name(), records.client(), records.amount()), ("withdrawal", "1", "0.5"));
+        assert_eq!(records.read(), Ok(ReadOutcome::Header));
+        assert_eq!(records.read(), Ok(ReadOutcome::End));
+    }
+}
@@ -0,0 +1,162 @@
+//! Tells the format of an input from its first bytes, for `--format auto`, once any compression is
+//! out of the way.
+//!
+//! Binary files start with their magic, JSON Lines with an object, and the delimiter of a CSV input
+//! is the one found as many times on every line, the header first. Rows with decimal commas or
+//! braces in a field are told apart from the header that way.
+
+use std::fmt;
+
+use crate::{is_skipped_line, InputFormat};
+
+/// Bytes of an input looked at.
+pub const SAMPLE_SIZE: usize = 8 * 1024;
+/// Delimiters told apart, the first one winning a tie.
+const DELIMITERS: [u8; 4] = [b',', b'\t', b';', b'|'];
+/// Lines of the sample the delimiter is checked on.
+const MAX_LINES: usize = 20;
+
+/// What the start of an input looked like.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sniffed {
+    pub format: InputFormat,
+    /// Delimiter of a CSV input, `None` if its lines have none of them.
+    pub delimiter: Option<u8>,
+}
+
+impl fmt::Display for Sniffed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.format, self.delimiter) {
+            (InputFormat::Json, _) => write!(f, "JSON Lines"),
+            (InputFormat::Binary, _) => write!(f, "binary records"),
+            (InputFormat::Csv, Some(b'\t')) => write!(f, "CSV separated by tabs"),
+            (InputFormat::Csv, Some(delimiter)) => write!(f, "CSV separated by '{}'", delimiter as char),
+            (InputFormat::Csv, None) => write!(f, "CSV of a single column"),
+        }
+    }
+}
+
+/// Tells the format of an input from its first bytes, `None` if it has nothing but blank and
+/// comment lines to tell it by.
+pub fn sniff(start: &[u8]) -> Option<Sniffed> {
+    if start.starts_with(b"TBIN") {
+        return Some(Sniffed { format: InputFormat::Binary, delimiter: None });
+    }
+
+    let start = start.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(start);
+    let mut lines: Vec<&[u8]> = start.split(|byte| *byte == b'\n').collect();
+    // The last line is cut short unless the sample holds the whole input
+    let cut = if start.len() < SAMPLE_SIZE || start.ends_with(b"\n") { None } else { lines.pop() };
+    let mut lines: Vec<&[u8]> = lines.into_iter().filter(|line| !is_blank(line)).take(MAX_LINES).collect();
+
+    // A first line longer than the sample is all there is to go by
+    let complete = !lines.is_empty();
+    if !complete {
+        lines.extend(cut.filter(|cut| !is_blank(cut)));
+    }
+    if is_object(lines.first()?, complete) {
+        return Some(Sniffed { format: InputFormat::Json, delimiter: None });
+    }
+
+    let counts = |delimiter: u8| -> Vec<usize> { lines.iter().map(|line| count_unquoted(line, delimiter)).collect() };
+    let mut best: Option<(bool, usize, u8)> = None;
+    for delimiter in DELIMITERS {
+        let counts = counts(delimiter);
+        if counts[0] == 0 {
+            continue;
+        }
+        let consistent = counts.iter().all(|count| *count == counts[0]);
+        if best.is_none_or(|(best_consistent, best_count, _)| (consistent, counts[0]) > (best_consistent, best_count)) {
+            best = Some((consistent, counts[0], delimiter));
+        }
+    }
+
+    Some(Sniffed { format: InputFormat::Csv, delimiter: best.map(|(_, _, delimiter)| delimiter) })
+}
+
+/// Whether a line is blank or a comment, whatever the delimiter.
+fn is_blank(line: &[u8]) -> bool {
+    DELIMITERS.iter().all(|delimiter| is_skipped_line(line, *delimiter))
+}
+
+/// Whether a line holds a JSON object, rather than a CSV row whose first field starts with a brace.
+/// The end of the object is only looked for on a `complete` line.
+fn is_object(line: &[u8], complete: bool) -> bool {
+    let line = line.trim_ascii();
+    let inside = match line.strip_prefix(b"{") {
+        Some(inside) => inside.trim_ascii_start(),
+        None => return false,
+    };
+
+    (inside.starts_with(b"\"") || inside.starts_with(b"}")) && (line.ends_with(b"}") || !complete)
+}
+
+/// Delimiters of a line outside of its quoted fields.
+fn count_unquoted(line: &[u8], delimiter: u8) -> usize {
+    let mut quoted = false;
+    let mut count = 0;
+    for byte in line {
+        match *byte {
+            b'"' => quoted = !quoted,
+            byte if byte == delimiter && !quoted => count += 1,
+            _ => {}
+        }
+    }
+
+    count
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+    use std::io::{BufReader, Read};
+
+    use super::*;
+    use crate::{gzip, zstd, Compression};
+
+    #[test]
+    fn formats_are_sniffed_from_the_start_of_the_input() {
+        let sniffed = |start: &[u8]| sniff(start).map(|x| (x.format, x.delimiter));
+        let csv = |delimiter: u8| Some((InputFormat::Csv, Some(delimiter)));
+        let fixture = |path: &str| {
+            let mut data = Vec::new();
+            let file = BufReader::new(File::open(path).unwrap());
+            match Compression::detect(&fs::read(path).unwrap()) {
+                Compression::Gzip => gzip::GzDecoder::new(file).read_to_end(&mut data),
+                Compression::Zstd => zstd::ZstdDecoder::new(file).read_to_end(&mut data),
+                Compression::None => BufReader::new(file).read_to_end(&mut data),
+            }
+            .unwrap();
+            data.truncate(SAMPLE_SIZE);
+
+            sniffed(&data)
+        };
+
+        let comma_separated =
+            ["test_data/15.csv", "test_data/15.csv.gz", "test_data/5_000.csv.zst", "test_data/20_commented.csv"];
+        for path in comma_separated {
+            assert_eq!(fixture(path), csv(b','), "Sniffing {}", path);
+        }
+        assert_eq!(fixture("test_data/excel_export.csv"), csv(b','));
+        assert_eq!(fixture("test_data/15.tsv"), csv(b'\t'));
+        assert_eq!(fixture("test_data/15_semicolon.csv"), csv(b';'));
+        assert_eq!(fixture("test_data/15.ndjson"), Some((InputFormat::Json, None)));
+        assert_eq!(fixture("test_data/15.jsonl"), Some((InputFormat::Json, None)));
+        assert_eq!(sniffed(b"TBIN\x01\x00\x2c\x00"), Some((InputFormat::Binary, None)));
+
+        // Braces, decimal commas and quoted delimiters don't fool it
+        assert_eq!(sniffed(b"{type},client,tx,amount\n{x},1,1,1.0\n"), csv(b','));
+        assert_eq!(sniffed(b"{\"type\",client,tx,amount\n"), csv(b','));
+        assert_eq!(sniffed(b"type;client;tx;amount\ndeposit;1;1;1,5\ndeposit;1;2;2,5\n"), csv(b';'));
+        assert_eq!(sniffed(b"type,client,tx,amount,note\ndeposit,1,1,1.0,\"a;b;c;d\"\n"), csv(b','));
+        assert_eq!(sniffed(b"type\tclient\ttx\tamount\ndeposit\t1\t1\t1,000.5\n"), csv(b'\t'));
+        assert_eq!(sniffed(b"type\n"), Some((InputFormat::Csv, None)));
+        assert_eq!(sniffed(b"\n# nothing yet\n"), None);
+        assert_eq!(sniffed(b""), None);
+
+        // A line longer than the sample is only seen in part
+        let mut long_object = b"{\"type\":\"deposit\",\"note\":\"".to_vec();
+        long_object.resize(SAMPLE_SIZE, b'x');
+        assert_eq!(sniffed(&long_object), Some((InputFormat::Json, None)));
+    }
+}
@@ -0,0 +1,289 @@
+//! `transactioner generate`, writing a CSV input of synthetic transactions to benchmark and fuzz the engine with
+//! inputs of any size, the same seed always giving the same file.
+//!
+//! Rows are written as they are drawn, keeping only the available funds of each client and the disputes to come: a
+//! deposit picked to be disputed is some rows later, and the dispute is resolved or charged back some rows after that.
+//! Every row applies to the accounts with the default options: withdrawals never take more than is available,
+//! disputes only hold deposits the funds are still there for, and a client charged back gets no more rows.
+
+use std::cmp::Reverse;
+use std::collections::binary_heap::{BinaryHeap, PeekMut};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{value_parser, Arg, ArgMatches, Command};
+
+use crate::{log, ClientId};
+
+/// Most rows between a deposit and its dispute, and between a dispute and its settlement.
+const MAX_DELAY: u64 = 10_000;
+
+/// Largest amount of a deposit, in cents.
+const MAX_DEPOSIT: u64 = 500_000;
+
+/// What `generate` writes, and where.
+#[derive(Debug, PartialEq)]
+pub struct GenerateOptions {
+    pub rows: u64,
+    pub clients: ClientId,
+    /// Share of the deposits disputed later on.
+    pub dispute_rate: f64,
+    /// Share of the disputes charged back, the others being resolved.
+    pub chargeback_rate: f64,
+    pub seed: u64,
+    /// File written, the standard output without any.
+    pub out: Option<PathBuf>,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions {
+            rows: 1_000_000,
+            clients: 1_000,
+            dispute_rate: 0.02,
+            chargeback_rate: 0.3,
+            seed: 0,
+            out: None,
+        }
+    }
+}
+
+impl GenerateOptions {
+    /// Reads the options from what clap matched, see `command`, the defaults standing for those not given.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let defaults = GenerateOptions::default();
+
+        GenerateOptions {
+            rows: matches.get_one::<u32>("rows").map_or(defaults.rows, |rows| *rows as u64),
+            clients: matches.get_one::<ClientId>("clients").copied().unwrap_or(defaults.clients),
+            dispute_rate: matches.get_one::<f64>("dispute-rate").copied().unwrap_or(defaults.dispute_rate),
+            chargeback_rate: matches.get_one::<f64>("chargeback-rate").copied().unwrap_or(defaults.chargeback_rate),
+            seed: matches.get_one::<u64>("seed").copied().unwrap_or(defaults.seed),
+            out: matches.get_one::<PathBuf>("out").cloned(),
+        }
+    }
+}
+
+/// Arguments of `generate`, a subcommand of `cli::command`.
+pub fn command() -> Command {
+    let rate = |value: &str| match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err("expected a share from 0 to 1".to_string()),
+    };
+    let option = |id: &'static str, value: &'static str, help: &'static str| {
+        Arg::new(id).long(id).value_name(value).help(help)
+    };
+
+    Command::new("generate")
+        .about("write a CSV input of synthetic transactions, the same seed always giving the same file")
+        // Tx ids are given to the rows in order, so there can't be more rows than ids
+        .arg(option("rows", "n", "rows written, 1000000 by default").value_parser(value_parser!(u32)))
+        .arg(
+            option("clients", "n", "clients the rows are spread across, 1000 by default")
+                .value_parser(value_parser!(ClientId).range(1..)),
+        )
+        .arg(
+            option("dispute-rate", "share", "share of the deposits disputed later on, 0.02 by default")
+                .value_parser(rate),
+        )
+        .arg(
+            option("chargeback-rate", "share", "share of the disputes charged back, 0.3 by default")
+                .value_parser(rate),
+        )
+        .arg(option("seed", "n", "seed of the rows drawn, 0 by default").value_parser(value_parser!(u64)))
+        .arg(option("out", "path", "file written, the standard output without it").value_parser(value_parser!(PathBuf)))
+}
+
+/// SplitMix64, small and fast, which is all the data needs as long as a seed always gives the same numbers.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Number below `n`, with a bias too small to matter for any `n` used here.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// Number between 0 and 1.
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.unit() < probability
+    }
+}
+
+/// What a client holds so far, in cents.
+#[derive(Default)]
+struct Client {
+    available: u64,
+    /// Charged back, so it gets no more rows.
+    locked: bool,
+    /// Where the client is in the list of the unlocked ones.
+    slot: usize,
+}
+
+/// Row written once the rows before it are, for a deposit picked to be disputed.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Event {
+    Dispute,
+    Settle,
+}
+
+/// Dispute or settlement to come, by the row it is due at, then client, tx id and amount, the earliest first.
+type Scheduled = Reverse<(u64, Event, ClientId, u32, u64)>;
+
+fn cents(amount: u64) -> String {
+    format!("{}.{:02}", amount / 100, amount % 100)
+}
+
+/// Writes the rows to `out`, returning how many were written.
+pub fn write<W: Write>(options: &GenerateOptions, out: W) -> io::Result<u64> {
+    let mut out = BufWriter::new(out);
+    let mut random = Random(options.seed);
+
+    let mut clients: Vec<Client> =
+        (0..options.clients as usize).map(|slot| Client { slot, ..Default::default() }).collect();
+    // Clients that weren't charged back yet, the only ones getting rows
+    let mut unlocked: Vec<ClientId> = (1..=options.clients).collect();
+    let mut events: BinaryHeap<Scheduled> = BinaryHeap::new();
+
+    writeln!(out, "type,client,tx,amount")?;
+    let mut tx = 0u32;
+    'rows: for row in 0..options.rows {
+        loop {
+            let (event, client, disputed, amount) = match events.peek_mut() {
+                Some(next) if (next.0).0 <= row => {
+                    let Reverse((_, event, client, disputed, amount)) = PeekMut::pop(next);
+                    (event, client, disputed, amount)
+                }
+                _ => break,
+            };
+            let account = &mut clients[client as usize - 1];
+            // Nothing is written for a client charged back meanwhile, nor to dispute funds withdrawn since
+            if account.locked {
+                continue;
+            }
+            match event {
+                Event::Dispute if account.available < amount => continue,
+                Event::Dispute => {
+                    account.available -= amount;
+                    events.push(Reverse((row + 1 + random.below(MAX_DELAY), Event::Settle, client, disputed, amount)));
+                    writeln!(out, "dispute,{},{},", client, disputed)?;
+                }
+                Event::Settle if random.chance(options.chargeback_rate) => {
+                    account.locked = true;
+                    let slot = account.slot;
+                    unlocked.swap_remove(slot);
+                    if let Some(moved) = unlocked.get(slot) {
+                        clients[*moved as usize - 1].slot = slot;
+                    }
+                    writeln!(out, "chargeback,{},{},", client, disputed)?;
+                }
+                Event::Settle => {
+                    account.available += amount;
+                    writeln!(out, "resolve,{},{},", client, disputed)?;
+                }
+            }
+            continue 'rows;
+        }
+
+        tx += 1;
+        // With every client charged back the rest of the rows are deposits the accounts won't take
+        if unlocked.is_empty() {
+            let client = random.below(options.clients as u64) + 1;
+            writeln!(out, "deposit,{},{},{}", client, tx, cents(100 + random.below(MAX_DEPOSIT)))?;
+            continue;
+        }
+        let client = unlocked[random.below(unlocked.len() as u64) as usize];
+        let account = &mut clients[client as usize - 1];
+
+        if account.available > 0 && random.chance(0.4) {
+            let amount = 1 + random.below(account.available.min(MAX_DEPOSIT));
+            account.available -= amount;
+            writeln!(out, "withdrawal,{},{},{}", client, tx, cents(amount))?;
+        } else {
+            // Mostly small amounts, a few up to the largest
+            let amount = 100 + (random.unit().powi(2) * (MAX_DEPOSIT - 100) as f64) as u64;
+            account.available += amount;
+            if random.chance(options.dispute_rate) {
+                events.push(Reverse((row + 1 + random.below(MAX_DELAY), Event::Dispute, client, tx, amount)));
+            }
+            writeln!(out, "deposit,{},{},{}", client, tx, cents(amount))?;
+        }
+    }
+
+    out.flush()?;
+    Ok(options.rows)
+}
+
+/// Writes the rows to `--out`, or the standard output.
+pub fn run(options: &GenerateOptions) -> Result<(), String> {
+    let written = match &options.out {
+        Some(path) => File::create(path)
+            .and_then(|file| write(options, file))
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))?,
+        None => write(options, io::stdout().lock()).map_err(|e| format!("Could not write the rows: {}", e))?,
+    };
+    log::info!(
+        "Generated {} row/s for {} client/s{}",
+        written,
+        options.clients,
+        options.out.as_ref().map(|path| format!(" to {}", path.display())).unwrap_or_default()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::iter;
+
+    use super::*;
+
+    #[test]
+    fn the_same_seed_gives_the_same_rows() {
+        let options = GenerateOptions {
+            rows: 20_000,
+            clients: 1_000,
+            dispute_rate: 0.05,
+            seed: 42,
+            ..Default::default()
+        };
+        let generated = |options: &GenerateOptions| {
+            let mut bytes = Vec::new();
+            assert_eq!(write(options, &mut bytes).unwrap(), options.rows);
+            bytes
+        };
+        let bytes = generated(&options);
+        assert_eq!(generated(&options), bytes);
+        assert_ne!(generated(&GenerateOptions { seed: 43, ..options }), bytes);
+        assert!(bytes.starts_with(b"type,client,tx,amount\n"));
+    }
+
+    #[test]
+    fn arguments() {
+        let options = |list: &[&str]| {
+            let matches = command().try_get_matches_from(iter::once("generate").chain(list.iter().copied()));
+            matches.map(|matches| GenerateOptions::from_matches(&matches)).map_err(|e| e.kind())
+        };
+        assert_eq!(
+            options(&["--rows", "10", "--seed", "7", "--out", "a.csv"]),
+            Ok(GenerateOptions { rows: 10, seed: 7, out: Some(PathBuf::from("a.csv")), ..Default::default() })
+        );
+        assert_eq!(options(&[]), Ok(GenerateOptions::default()));
+        assert!(options(&["--clients", "0"]).is_err());
+        assert!(options(&["--dispute-rate", "1.5"]).is_err());
+        assert!(options(&["--rows", "5000000000"]).is_err());
+        assert!(options(&["input.csv"]).is_err());
+    }
+}
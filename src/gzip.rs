@@ -0,0 +1,511 @@
+//! Gzip decoding for compressed inputs, covering what reading a `.csv.gz` file takes: one or
+//! more concatenated members made of stored, fixed and dynamic Huffman blocks.
+//!
+//! Huffman codes are decoded a bit at a time, which is slower than a table based decoder
+//! but still well ahead of what the CSV parsing downstream can take.
+
+use std::io::{self, BufRead, Read};
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Furthest back a match can reference.
+const WINDOW: usize = 32 * 1024;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// Order in which the code lengths of the code length alphabet are sent.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const FLAG_HEADER_CRC: u8 = 2;
+const FLAG_EXTRA: u8 = 4;
+const FLAG_NAME: u8 = 8;
+const FLAG_COMMENT: u8 = 16;
+
+/// Whether the start of an input is the start of a gzip stream.
+pub fn is_gzip(start: &[u8]) -> bool {
+    start.starts_with(&MAGIC)
+}
+
+fn corrupt(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt gzip input, {}", reason))
+}
+
+struct BitReader<R> {
+    inner: R,
+    bits: u64,
+    count: u32,
+}
+
+impl<R: BufRead> BitReader<R> {
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.count < count {
+            let byte = match self.inner.fill_buf()?.first() {
+                Some(byte) => *byte,
+                None => return Err(corrupt("the input ends abruptly")),
+            };
+            self.inner.consume(1);
+            self.bits |= (byte as u64) << self.count;
+            self.count += 8;
+        }
+
+        let value = (self.bits & ((1 << count) - 1)) as u32;
+        self.bits >>= count;
+        self.count -= count;
+
+        Ok(value)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        self.bits(8).map(|byte| byte as u8)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        self.bits(16).map(|value| value as u16)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(self.u16()? as u32 | (self.u16()? as u32) << 16)
+    }
+
+    /// Drops the bits left of the current byte, stored blocks and trailers start on a byte boundary.
+    fn align(&mut self) {
+        let partial = self.count % 8;
+        self.bits >>= partial;
+        self.count -= partial;
+    }
+
+    fn at_end(&mut self) -> io::Result<bool> {
+        Ok(self.count == 0 && self.inner.fill_buf()?.is_empty())
+    }
+}
+
+/// Canonical Huffman code, the symbols are sorted by code so that decoding only needs
+/// how many codes there are of each length.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let mut counts = [0_u16; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+
+        // Incomplete codes are fine as long as the missing codes never show up
+        let mut left: i32 = 1;
+        for count in &counts[1..] {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(corrupt("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0_u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate().filter(|(_, length)| **length != 0) {
+            symbols[offsets[*length as usize] as usize] = symbol as u16;
+            offsets[*length as usize] += 1;
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn fixed() -> (Huffman, Huffman) {
+        let mut lengths = [8_u8; 288];
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+
+        // Built from constant lengths, which are always a valid code
+        (Huffman::new(&lengths).unwrap(), Huffman::new(&[5; 30]).unwrap())
+    }
+
+    fn decode<R: BufRead>(&self, bits: &mut BitReader<R>) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0_i32, 0_i32, 0_i32);
+
+        for count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = *count as i32;
+
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(corrupt("invalid Huffman code"))
+    }
+}
+
+enum State {
+    /// Expecting the header of a member, members can be concatenated one after another.
+    Member,
+    /// Expecting the header of a block, or the trailer after the last block of the member.
+    Block,
+    Stored(usize),
+    Codes(Box<(Huffman, Huffman)>),
+    Done,
+}
+
+/// Decompresses the gzip stream of the inner reader.
+pub struct GzDecoder<R> {
+    bits: BitReader<R>,
+    state: State,
+    last_block: bool,
+    /// Decompressed data, the part before `position` is kept as the window matches copy from.
+    output: Vec<u8>,
+    position: usize,
+    crc_table: [u32; 256],
+    crc: u32,
+    size: u32,
+}
+
+impl<R: BufRead> GzDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        let mut crc_table = [0_u32; 256];
+        for (byte, entry) in crc_table.iter_mut().enumerate() {
+            *entry = (0..8).fold(byte as u32, |crc, _| if crc & 1 == 1 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 });
+        }
+
+        GzDecoder {
+            bits: BitReader {
+                inner,
+                bits: 0,
+                count: 0,
+            },
+            state: State::Member,
+            last_block: false,
+            output: Vec::with_capacity(4 * WINDOW),
+            position: 0,
+            crc_table,
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let bits = &mut self.bits;
+
+        if bits.u16()? != u16::from_le_bytes(MAGIC) {
+            return Err(corrupt("not a gzip member"));
+        }
+        if bits.byte()? != 8 {
+            return Err(corrupt("unknown compression method"));
+        }
+
+        let flags = bits.byte()?;
+        // Modification time, extra flags and operating system
+        for _ in 0..6 {
+            bits.byte()?;
+        }
+        if flags & FLAG_EXTRA != 0 {
+            for _ in 0..bits.u16()? {
+                bits.byte()?;
+            }
+        }
+        for flag in [FLAG_NAME, FLAG_COMMENT] {
+            if flags & flag != 0 {
+                while bits.byte()? != 0 {}
+            }
+        }
+        if flags & FLAG_HEADER_CRC != 0 {
+            bits.u16()?;
+        }
+
+        self.crc = 0;
+        self.size = 0;
+        self.last_block = false;
+
+        Ok(())
+    }
+
+    fn read_trailer(&mut self) -> io::Result<()> {
+        self.bits.align();
+
+        if self.bits.u32()? != self.crc {
+            return Err(corrupt("checksum mismatch"));
+        }
+        if self.bits.u32()? != self.size {
+            return Err(corrupt("size mismatch"));
+        }
+
+        Ok(())
+    }
+
+    fn read_block_header(&mut self) -> io::Result<State> {
+        self.last_block = self.bits.bits(1)? == 1;
+
+        match self.bits.bits(2)? {
+            0 => {
+                self.bits.align();
+                let length = self.bits.u16()?;
+
+                if self.bits.u16()? != !length {
+                    return Err(corrupt("stored block length mismatch"));
+                }
+
+                Ok(State::Stored(length as usize))
+            }
+            1 => Ok(State::Codes(Box::new(Huffman::fixed()))),
+            2 => self.read_dynamic_codes().map(|codes| State::Codes(Box::new(codes))),
+            _ => Err(corrupt("invalid block type")),
+        }
+    }
+
+    fn read_dynamic_codes(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let bits = &mut self.bits;
+        let literals = bits.bits(5)? as usize + 257;
+        let distances = bits.bits(5)? as usize + 1;
+        let code_lengths = bits.bits(4)? as usize + 4;
+
+        let mut lengths = [0_u8; 19];
+        for index in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[*index] = bits.bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&lengths)?;
+
+        let mut lengths = vec![0_u8; literals + distances];
+        let mut index = 0;
+        while index < lengths.len() {
+            let (length, repeat) = match code_length_code.decode(bits)? {
+                length @ 0..=15 => (length as u8, 1),
+                16 if index > 0 => (lengths[index - 1], 3 + bits.bits(2)? as usize),
+                17 => (0, 3 + bits.bits(3)? as usize),
+                18 => (0, 11 + bits.bits(7)? as usize),
+                _ => return Err(corrupt("invalid code lengths")),
+            };
+
+            if index + repeat > lengths.len() {
+                return Err(corrupt("too many code lengths"));
+            }
+            lengths[index..index + repeat].fill(length);
+            index += repeat;
+        }
+
+        if lengths[256] == 0 {
+            return Err(corrupt("missing end of block code"));
+        }
+
+        Ok((Huffman::new(&lengths[..literals])?, Huffman::new(&lengths[literals..])?))
+    }
+
+    /// Decodes up to a window worth of symbols, returning whether the block ended.
+    fn decode_symbols(&mut self, literals: &Huffman, distances: &Huffman) -> io::Result<bool> {
+        let target = self.output.len() + WINDOW;
+
+        while self.output.len() < target {
+            let symbol = literals.decode(&mut self.bits)? as usize;
+
+            if symbol < 256 {
+                self.output.push(symbol as u8);
+                continue;
+            }
+            if symbol == 256 {
+                return Ok(true);
+            }
+
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(corrupt("invalid length code"));
+            }
+            let length = LENGTH_BASE[symbol] as usize + self.bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+            let symbol = distances.decode(&mut self.bits)? as usize;
+            if symbol >= DISTANCE_BASE.len() {
+                return Err(corrupt("invalid distance code"));
+            }
+            let distance = DISTANCE_BASE[symbol] as usize + self.bits.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+
+            if distance > self.output.len() {
+                return Err(corrupt("distance too far back"));
+            }
+            // Matches can overlap the bytes they produce, so they are copied one byte at a time
+            let start = self.output.len() - distance;
+            for offset in 0..length {
+                let byte = self.output[start + offset];
+                self.output.push(byte);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Moves the decoding forward, returning `false` once the whole input has been decoded.
+    fn step(&mut self) -> io::Result<bool> {
+        // Everything has been read by now, only the window has to stay around
+        if self.output.len() >= 3 * WINDOW {
+            let consumed = self.output.len() - WINDOW;
+            self.output.drain(..consumed);
+            self.position -= consumed;
+        }
+
+        let start = self.output.len();
+        self.state = match std::mem::replace(&mut self.state, State::Done) {
+            State::Member => {
+                self.read_header()?;
+                State::Block
+            }
+            State::Block if self.last_block => {
+                self.read_trailer()?;
+
+                if self.bits.at_end()? {
+                    State::Done
+                } else {
+                    State::Member
+                }
+            }
+            State::Block => self.read_block_header()?,
+            State::Stored(remaining) => {
+                let length = remaining.min(WINDOW);
+                for _ in 0..length {
+                    let byte = self.bits.byte()?;
+                    self.output.push(byte);
+                }
+
+                if remaining > length {
+                    State::Stored(remaining - length)
+                } else {
+                    State::Block
+                }
+            }
+            State::Codes(codes) => {
+                if self.decode_symbols(&codes.0, &codes.1)? {
+                    State::Block
+                } else {
+                    State::Codes(codes)
+                }
+            }
+            State::Done => return Ok(false),
+        };
+
+        let crc_table = &self.crc_table;
+        let new_output = &self.output[start..];
+        self.crc = !new_output
+            .iter()
+            .fold(!self.crc, |crc, byte| crc_table[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8));
+        self.size = self.size.wrapping_add(new_output.len() as u32);
+
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.output.len() {
+            if !self.step()? {
+                return Ok(0);
+            }
+        }
+
+        let length = buf.len().min(self.output.len() - self.position);
+        buf[..length].copy_from_slice(&self.output[self.position..self.position + length]);
+        self.position += length;
+
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+    use std::io::BufReader;
+
+    use super::*;
+
+    /// Member of a single stored block holding `STORED_DATA`.
+    const STORED: &str = "1f8b0800000000000003012600d9ff747970652c636c69656e742c74782c616d6f756e740a\
+                          6465706f7369742c312c312c312e300ab1c3c1d326000000";
+    const STORED_DATA: &[u8] = b"type,client,tx,amount\ndeposit,1,1,1.0\n";
+    /// Member of a single fixed Huffman block holding `FIXED_DATA`, with a file name in its header.
+    const FIXED: &str = "1f8b0808000000000003662e637376002bcf2cc948294a2c4fccd131d431d231d033e50200e9f48b5813000000";
+    const FIXED_DATA: &[u8] = b"withdrawal,1,2,0.5\n";
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap()).collect()
+    }
+
+    fn decode(input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        GzDecoder::new(input).read_to_end(&mut decoded)?;
+
+        Ok(decoded)
+    }
+
+    fn error(input: &[u8]) -> String {
+        decode(input).expect_err("Decoding a corrupt member").to_string()
+    }
+
+    #[test]
+    fn every_kind_of_block_is_decoded() {
+        assert!(is_gzip(&bytes(STORED)));
+        assert_eq!(decode(&bytes(STORED)).unwrap(), STORED_DATA);
+        assert_eq!(decode(&bytes(FIXED)).unwrap(), FIXED_DATA);
+
+        // Written by gzip with dynamic Huffman blocks
+        let dynamic = fs::read("test_data/15.csv.gz").unwrap();
+        assert_eq!(decode(&dynamic).unwrap(), fs::read("test_data/15.csv").unwrap());
+    }
+
+    #[test]
+    fn concatenated_members_are_decoded_one_after_another() {
+        let members = [bytes(STORED), bytes(FIXED), bytes(STORED)].concat();
+        assert_eq!(decode(&members).unwrap(), [STORED_DATA, FIXED_DATA, STORED_DATA].concat());
+    }
+
+    #[test]
+    fn corrupt_members_fail_the_decoding() {
+        let stored = bytes(STORED);
+        let trailer = stored.len() - 8;
+
+        let mut checksum = stored.clone();
+        checksum[trailer] ^= 1;
+        assert!(error(&checksum).contains("checksum mismatch"));
+        let mut size = stored.clone();
+        size[trailer + 4] ^= 1;
+        assert!(error(&size).contains("size mismatch"));
+
+        let mut length = stored.clone();
+        length[13] ^= 1;
+        assert!(error(&length).contains("stored block length mismatch"));
+        // The block type bits set to 3, which no block has
+        let mut block_type = stored.clone();
+        block_type[10] |= 6;
+        assert!(error(&block_type).contains("invalid block type"));
+        assert!(error(b"PK\x03\x04 not gzip").contains("not a gzip member"));
+
+        // A member cut anywhere, a second member included, but between the members
+        for input in [stored.clone(), bytes(FIXED), [bytes(STORED), bytes(FIXED)].concat()] {
+            for end in (1..input.len()).filter(|end| *end != stored.len()) {
+                assert!(decode(&input[..end]).is_err(), "Cut at {} of {}", end, input.len());
+            }
+        }
+    }
+
+    #[test]
+    fn gzip_decoding_keeps_every_byte() {
+        let mut decoded = Vec::new();
+        let file = BufReader::new(File::open("test_data/5_000.csv.gz").unwrap());
+        GzDecoder::new(file).read_to_end(&mut decoded).unwrap();
+
+        // The first 5000 transactions of the performance file
+        let plain = fs::read("test_data/perf/100_000.csv").unwrap();
+        let end = plain.iter().enumerate().filter(|(_, byte)| **byte == b'\n').nth(5_000).unwrap().0;
+
+        assert_eq!(decoded.len(), end + 1);
+        assert!(decoded == plain[..=end]);
+    }
+}
@@ -0,0 +1,252 @@
+//! Inputs streamed from `http://` URLs, through a small HTTP/1.1 client built on the standard
+//! library like the decoders, so no HTTP crate is needed.
+//!
+//! Bodies sent with a `Content-Length`, chunked or up to the end of the connection are read, and
+//! redirects to other `http://` URLs are followed.
+//! Compressed bodies need nothing special, they are told apart by their first bytes downstream.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// Redirects followed before giving up on a URL.
+const MAX_REDIRECTS: usize = 5;
+/// How long a stalled connection is waited on before it fails the reading.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Whether an input path is a URL rather than a file.
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("http://"))
+}
+
+/// Path of a URL without its query, which tells the format of the input like a file name does.
+pub fn without_query(path: &Path) -> &Path {
+    match path.to_str() {
+        Some(url) if is_url(path) => Path::new(url.split('?').next().unwrap_or(url)),
+        _ => path,
+    }
+}
+
+fn invalid(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// Host, port and target of an `http://` URL.
+fn split_url(url: &str) -> io::Result<(String, u16, String)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| invalid(format!("{} isn't an http:// URL", url)))?;
+
+    let (authority, target) = match rest.find(['/', '?']) {
+        Some(index) if rest[index..].starts_with('?') => (&rest[..index], format!("/{}", &rest[index..])),
+        Some(index) => (&rest[..index], rest[index..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| invalid(format!("invalid port in {}", url)))?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(invalid(format!("no host in {}", url)));
+    }
+
+    Ok((host.to_string(), port, target))
+}
+
+/// How the end of a body is told.
+#[derive(Debug)]
+enum Framing {
+    Length(u64),
+    /// Bytes left in the current chunk, and whether the last chunk was read.
+    Chunked { left: u64, done: bool },
+    UntilClose,
+}
+
+/// Body of a response, read as it arrives.
+pub struct Body {
+    input: BufReader<TcpStream>,
+    framing: Framing,
+}
+
+/// Response whose body is left to read, header names being lowercase.
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    input: BufReader<TcpStream>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.as_str())
+    }
+}
+
+fn request(url: &str, method: &str) -> io::Result<Response> {
+    let (host, port, target) = split_url(url)?;
+    let stream = TcpStream::connect((host.as_str(), port))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let host_header = if port == 80 { host } else { format!("{}:{}", host, port) };
+    write!(
+        &stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: transactioner\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        method, target, host_header
+    )?;
+
+    let mut input = BufReader::new(stream);
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .filter(|_| line.starts_with("HTTP/1."))
+        .ok_or_else(|| invalid(format!("not an HTTP response: {:?}", line.trim_end())))?;
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Err(invalid("the connection closed within the headers".to_string()));
+        }
+        match line.trim_end().split_once(':') {
+            Some((name, value)) => headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string())),
+            None => break,
+        }
+    }
+
+    Ok(Response { status, headers, input })
+}
+
+/// Where a redirect leads, relative locations being resolved against the URL redirected from.
+fn redirect_target(url: &str, location: &str) -> String {
+    if location.contains("://") {
+        return location.to_string();
+    }
+
+    let origin_end = url.find("://").map_or(0, |index| index + 3);
+    let origin = match url[origin_end..].find('/') {
+        Some(index) => &url[..origin_end + index],
+        None => url,
+    };
+    format!("{}{}", origin, location)
+}
+
+/// Sends a GET request for the URL, following redirects.
+pub fn get(url: &str) -> io::Result<Body> {
+    let mut url = url.to_string();
+
+    for _ in 0..=MAX_REDIRECTS {
+        let response = request(&url, "GET")?;
+
+        match response.status {
+            200 => {
+                let length = response.header("content-length").and_then(|length| length.parse().ok());
+                let chunked = response.header("transfer-encoding").is_some_and(|value| value.contains("chunked"));
+                let framing = match length {
+                    _ if chunked => Framing::Chunked { left: 0, done: false },
+                    Some(length) => Framing::Length(length),
+                    None => Framing::UntilClose,
+                };
+
+                return Ok(Body { input: response.input, framing });
+            }
+            status @ (301 | 302 | 303 | 307 | 308) => {
+                let location = response.header("location");
+                let location = location.ok_or_else(|| invalid(format!("redirect {} without a location", status)))?;
+                url = redirect_target(&url, location);
+            }
+            status => return Err(io::Error::other(format!("the server answered with status {}", status))),
+        }
+    }
+
+    Err(invalid(format!("more than {} redirects", MAX_REDIRECTS)))
+}
+
+/// Length of the body at a URL, as told by a HEAD request. Servers that don't answer those, like
+/// those of presigned URLs only valid for GET requests, give `None`.
+pub fn content_length(url: &str) -> Option<u64> {
+    let mut url = url.to_string();
+
+    for _ in 0..=MAX_REDIRECTS {
+        let response = request(&url, "HEAD").ok()?;
+
+        match response.status {
+            200 => return response.header("content-length")?.parse().ok(),
+            301 | 302 | 303 | 307 | 308 => url = redirect_target(&url, response.header("location")?),
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+fn cut_short() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "the connection closed before the end of the body")
+}
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        match &mut self.framing {
+            Framing::Length(0) => Ok(0),
+            Framing::Length(left) => {
+                let wanted = std::cmp::min(buf.len() as u64, *left) as usize;
+                let read = self.input.read(&mut buf[..wanted])?;
+                if read == 0 {
+                    return Err(cut_short());
+                }
+                *left -= read as u64;
+
+                Ok(read)
+            }
+            Framing::Chunked { done: true, .. } => Ok(0),
+            Framing::Chunked { left, done } => {
+                if *left == 0 {
+                    let mut line = String::new();
+                    // Every chunk but the first follows the CRLF ending the previous one
+                    while line.trim().is_empty() {
+                        line.clear();
+                        if self.input.read_line(&mut line)? == 0 {
+                            return Err(cut_short());
+                        }
+                    }
+                    let size = line.trim().split(';').next().unwrap_or_default();
+                    let size = u64::from_str_radix(size, 16);
+                    *left = size.map_err(|_| invalid(format!("invalid chunk size {:?}", line.trim())))?;
+
+                    // The trailers after the last chunk are of no use here
+                    if *left == 0 {
+                        *done = true;
+                        return Ok(0);
+                    }
+                }
+
+                let wanted = std::cmp::min(buf.len() as u64, *left) as usize;
+                let read = self.input.read(&mut buf[..wanted])?;
+                if read == 0 {
+                    return Err(cut_short());
+                }
+                *left -= read as u64;
+
+                Ok(read)
+            }
+            Framing::UntilClose => self.input.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_http_urls_are_inputs() {
+        assert!(is_url(Path::new("http://example.com/input.csv")));
+        assert!(!is_url(Path::new("https://example.com/input.csv")));
+        assert!(!is_url(Path::new("input.csv")));
+    }
+}
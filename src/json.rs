@@ -0,0 +1,244 @@
+//! Reading of JSON Lines inputs, where every line holds one flat JSON object with the same
+//! fields as a CSV row, and writing of the accounts as such objects.
+//!
+//! Each object is turned into a pair of CSV records, its keys and its values, so the rows go
+//! through the same deserialization as those of a CSV file.
+
+use std::fmt::Write;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Splits the flat JSON object in `line` into its `keys` and their `values`. Strings are
+/// unescaped, numbers and booleans are kept as they are written and `null` is left empty,
+/// like a missing CSV field.
+pub fn parse_object(line: &str, keys: &mut csv::StringRecord, values: &mut csv::StringRecord) -> Result<(), String> {
+    keys.clear();
+    values.clear();
+
+    let mut chars = line.chars().peekable();
+    let mut value = String::new();
+
+    expect(&mut chars, '{')?;
+    skip_whitespace(&mut chars);
+
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            expect(&mut chars, '"')?;
+            parse_string(&mut chars, &mut value)?;
+            keys.push_field(&value);
+
+            expect(&mut chars, ':')?;
+            parse_value(&mut chars, &mut value)?;
+            values.push_field(value.trim());
+
+            match next_token(&mut chars) {
+                Some(',') => continue,
+                Some('}') => break,
+                Some(other) => return Err(format!("expected ',' or '}}', found '{}'", other)),
+                None => return Err("unexpected end of line".to_string()),
+            }
+        }
+    }
+
+    match next_token(&mut chars) {
+        None => Ok(()),
+        Some(other) => Err(format!("unexpected '{}' after the object", other)),
+    }
+}
+
+/// Value of a field written by `write_object`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Value<'a> {
+    /// Numbers and booleans, written as they are.
+    Raw(&'a str),
+    String(&'a str),
+    Null,
+}
+
+/// Writes a flat JSON object holding `fields` in their order to `out`, on a single line.
+pub fn write_object<'a>(out: &mut String, fields: impl IntoIterator<Item = (&'a str, Value<'a>)>) {
+    out.push('{');
+    for (index, (key, value)) in fields.into_iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        write_string(out, key);
+        out.push(':');
+        match value {
+            Value::Raw(text) => out.push_str(text),
+            Value::String(text) => write_string(out, text),
+            Value::Null => out.push_str("null"),
+        }
+    }
+    out.push('}');
+}
+
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn next_token(chars: &mut Peekable<Chars>) -> Option<char> {
+    skip_whitespace(chars);
+    chars.next()
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), String> {
+    match next_token(chars) {
+        Some(c) if c == expected => Ok(()),
+        Some(other) => Err(format!("expected '{}', found '{}'", expected, other)),
+        None => Err("unexpected end of line".to_string()),
+    }
+}
+
+/// Reads a scalar value into `value`. Objects and arrays have no CSV counterpart, so they are rejected.
+fn parse_value(chars: &mut Peekable<Chars>, value: &mut String) -> Result<(), String> {
+    value.clear();
+    skip_whitespace(chars);
+
+    match chars.peek() {
+        Some('"') => {
+            chars.next();
+            parse_string(chars, value)
+        }
+        Some('{') | Some('[') => Err("nested objects and arrays aren't supported".to_string()),
+        Some(_) => {
+            while let Some(&c) = chars.peek() {
+                if c == ',' || c == '}' || c.is_whitespace() {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+
+            let is_number = value.starts_with(|c: char| c == '-' || c.is_ascii_digit())
+                && value.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+
+            match value.as_str() {
+                "null" => {
+                    value.clear();
+                    Ok(())
+                }
+                "true" | "false" => Ok(()),
+                _ if is_number => Ok(()),
+                _ => Err(format!("invalid value {}", value)),
+            }
+        }
+        None => Err("unexpected end of line".to_string()),
+    }
+}
+
+/// Reads the rest of a string whose opening quote has already been consumed.
+fn parse_string(chars: &mut Peekable<Chars>, value: &mut String) -> Result<(), String> {
+    value.clear();
+
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(()),
+            Some('\\') => {
+                let escaped = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let high = parse_hex(chars)?;
+                        let code = if (0xD800..0xDC00).contains(&high) {
+                            // Characters outside the basic plane are written as a surrogate pair
+                            if chars.next() != Some('\\') || chars.next() != Some('u') {
+                                return Err("unpaired surrogate in string".to_string());
+                            }
+                            let low = parse_hex(chars)?;
+                            if !(0xDC00..0xE000).contains(&low) {
+                                return Err("unpaired surrogate in string".to_string());
+                            }
+                            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                        } else {
+                            high
+                        };
+                        char::from_u32(code).ok_or_else(|| "unpaired surrogate in string".to_string())?
+                    }
+                    Some(other) => return Err(format!("invalid escape '\\{}' in string", other)),
+                    None => return Err("unterminated string".to_string()),
+                };
+                value.push(escaped);
+            }
+            Some(c) => value.push(c),
+            None => return Err("unterminated string".to_string()),
+        }
+    }
+}
+
+fn parse_hex(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+    let mut code = 0;
+    for _ in 0..4 {
+        let digit = chars
+            .next()
+            .and_then(|c| c.to_digit(16))
+            .ok_or_else(|| "invalid unicode escape in string".to_string())?;
+        code = code * 16 + digit;
+    }
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_objects() {
+        let mut keys = csv::StringRecord::new();
+        let mut values = csv::StringRecord::new();
+
+        parse_object(
+            r#"{"type": "deposit", "client": 1, "amount": -1.5e-2, "key": "a\"b\u00e9\ud83d\ude00", "currency": null, "x": true}"#,
+            &mut keys,
+            &mut values,
+        )
+        .unwrap();
+        assert_eq!(keys, vec!["type", "client", "amount", "key", "currency", "x"]);
+        assert_eq!(values, vec!["deposit", "1", "-1.5e-2", "a\"b\u{e9}\u{1f600}", "", "true"]);
+
+        parse_object("  { }  ", &mut keys, &mut values).unwrap();
+        assert!(keys.is_empty() && values.is_empty());
+
+        for line in [
+            r#"{"type": "deposit""#,
+            r#"{"type": "deposit",}"#,
+            r#"{"type": deposit}"#,
+            r#"{"amount": [1]}"#,
+            r#"{"amount": 1} 2"#,
+            r#"{"key": "\ud83d"}"#,
+            r#"["deposit"]"#,
+        ] {
+            assert!(parse_object(line, &mut keys, &mut values).is_err(), "Parsing {}", line);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Diagnostics written to the standard error, at the level `-q/--quiet` and `-v/--verbose` choose for the run.
+//! Errors are always written, `--quiet` leaves out everything else, each `-v` lets in more detail.
+//!
+//! The level is kept in a global, set once the options are read, since diagnostics come from the reader, the workers
+//! and the writers alike.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How much is written to the standard error, each level including the ones before it.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// What made the run fail, the only thing `--quiet` writes.
+    Error,
+    /// The settings of the run and what it found along the way, written by default.
+    #[default]
+    Info,
+    /// Each input and worker as it's done with, with `-v`.
+    Verbose,
+    /// The rows read so far, once a second, with `-vv`.
+    Progress,
+}
+
+impl Level {
+    const ALL: [Level; 4] = [Level::Error, Level::Info, Level::Verbose, Level::Progress];
+
+    /// The level of `-v` given `count` times.
+    pub fn verbose(count: usize) -> Self {
+        Self::ALL[std::cmp::min(Level::Info as usize + count, Self::ALL.len() - 1)]
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether diagnostics of `level` are written.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Lets through a message of each key, like the index of a worker, at most once per interval.
+pub struct Throttle {
+    interval: Duration,
+    last: Mutex<Vec<Option<Instant>>>,
+}
+
+impl Throttle {
+    pub const fn new(interval: Duration) -> Self {
+        Throttle { interval, last: Mutex::new(Vec::new()) }
+    }
+
+    /// Whether a message of `key` is due now.
+    pub fn ready(&self, key: usize) -> bool {
+        self.ready_at(key, Instant::now())
+    }
+
+    pub fn ready_at(&self, key: usize, now: Instant) -> bool {
+        let mut last = match self.last.lock() {
+            Ok(last) => last,
+            Err(_) => return false,
+        };
+        if last.len() <= key {
+            last.resize(key + 1, None);
+        }
+
+        match last[key] {
+            Some(at) if now.saturating_duration_since(at) < self.interval => false,
+            _ => {
+                last[key] = Some(now);
+                true
+            }
+        }
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Error) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Info) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Verbose) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Progress) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use {error, info, progress, verbose};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn throttled_messages_are_let_through_once_per_interval() {
+        let throttle = Throttle::new(Duration::from_secs(1));
+        let start = Instant::now();
+
+        assert!(throttle.ready_at(1, start));
+        assert!(!throttle.ready_at(1, start + Duration::from_millis(999)));
+        // Every key is throttled apart
+        assert!(throttle.ready_at(0, start + Duration::from_millis(999)));
+        assert!(throttle.ready_at(1, start + Duration::from_secs(1)));
+        assert!(!throttle.ready_at(1, start + Duration::from_millis(1500)));
+        assert!(!throttle.ready_at(0, start + Duration::from_millis(1500)));
+        assert!(throttle.ready_at(0, start + Duration::from_secs(2)));
+    }
+}
@@ -1,15 +1,40 @@
-use std::collections::{hash_map::Entry::Occupied, hash_map::Entry::Vacant, HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{hash_map::Entry::Occupied, hash_map::Entry::Vacant, HashMap, HashSet, VecDeque};
 use std::{env, fs};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::error::Error;
 use std::fmt;
+use std::hash::Hasher;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::path::Path;
+use std::sync::mpsc::{RecvError, TryRecvError};
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
 
+use clap::ArgMatches;
 use serde::{Deserialize, Deserializer};
 use tokio::runtime::Builder;
-use twox_hash::RandomXxHashBuilder64;
-use tokio::sync::mpsc::Sender;
+use twox_hash::{RandomXxHashBuilder64, XxHash64};
+use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{oneshot, watch};
+
+mod checkpoint;
+mod cli;
+mod config;
+mod diff;
+mod fast_csv;
+mod format_sniffer;
+mod generate;
+mod gzip;
+mod http;
+mod json;
+mod log;
+mod signal;
+mod tbin;
+mod timestamp;
+mod zstd;
 
 type ClientId = u16;
 type ClientAccounts = HashMap<ClientId, ClientAccount, RandomXxHashBuilder64>;
@@ -19,14 +44,82 @@ where
     D: Deserializer<'de>,
 {
     let buf = String::deserialize(deserializer)?;
-    match buf.as_str() {
-        "deposit" => Ok(TransactionType::Deposit),
-        "withdrawal" => Ok(TransactionType::Withdrawal),
-        "dispute" => Ok(TransactionType::Dispute),
-        "resolve" => Ok(TransactionType::Resolve),
-        "chargeback" => Ok(TransactionType::Chargeback),
-        _ => Ok(TransactionType::Unknown),
+    Ok(TransactionType::from_name(&buf))
+}
+
+/// Truncates the amounts with more decimal places than an `Amount` keeps, the reader tells those
+/// apart from the raw field since a deserializer has no way to report it. Reference rows often leave
+/// the amount empty, it reads as zero which the types moving funds don't accept.
+fn amount_deserializer<'de, D>(deserializer: D) -> Result<Amount, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct AmountVisitor;
+
+    impl serde::de::Visitor<'_> for AmountVisitor {
+        type Value = Amount;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "a decimal amount")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Amount, E> {
+            if value.is_empty() {
+                return Ok(Amount::ZERO);
+            }
+
+            Amount::parse(value, true).map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_str(AmountVisitor)
+}
+
+/// Rows leaving the currency empty are in the implicit currency, just like those of files without the column.
+fn currency_deserializer<'de, D>(deserializer: D) -> Result<Currency, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let code = String::deserialize(deserializer)?;
+
+    if code.is_empty() {
+        return Ok(Currency::NONE);
+    }
+
+    Currency::parse(&code).map_err(serde::de::Error::custom)
+}
+
+/// Rows leaving the timestamp empty have none, like those of files without the column.
+fn timestamp_deserializer<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(text) if !text.is_empty() => timestamp::parse(&text).map(Some).map_err(serde::de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+/// Idempotency keys are only ever compared, so they are kept as a hash to keep `Transaction` small and `Copy`.
+fn idempotency_key_deserializer<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let key = Option::<String>::deserialize(deserializer)?;
+
+    Ok(key.and_then(|key| idempotency_key_hash(&key)))
+}
+
+/// Hash an idempotency key is kept as, an empty key stands for no key at all.
+fn idempotency_key_hash(key: &str) -> Option<u64> {
+    if key.is_empty() {
+        return None;
     }
+
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(key.as_bytes());
+
+    Some(hasher.finish())
 }
 
 #[derive(Debug, Deserialize, Copy, Clone)]
@@ -35,426 +128,11377 @@ struct Transaction {
     r#type: TransactionType,
     client: ClientId,
     tx: u32,
-    amount: f32,
+    #[serde(deserialize_with = "amount_deserializer")]
+    amount: Amount,
+    /// Receiving client of a transfer or merged client of a merge, the column is optional
+    /// since no other type uses it.
+    #[serde(default)]
+    counterparty: Option<ClientId>,
+    /// Upstream key shared by the retries of a same deposit or withdrawal, if any.
+    #[serde(default, deserialize_with = "idempotency_key_deserializer")]
+    idempotency_key: Option<u64>,
+    /// Currency of the amount, the column is optional and files without it are in a single implicit currency.
+    #[serde(default, deserialize_with = "currency_deserializer")]
+    currency: Currency,
+    /// Milliseconds since the epoch of the optional `timestamp` column, only ever reported, the
+    /// order transactions apply in is the one of the input.
+    #[serde(default, deserialize_with = "timestamp_deserializer")]
+    timestamp: Option<i64>,
+    /// Line of the input file the transaction was read from, it only goes up through the
+    /// file so it also gives the order in which the transactions of a client have to apply.
+    #[serde(skip)]
+    row: u64,
+}
+
+impl Transaction {
+    /// Columns every input needs, the rest are optional and any unknown one is ignored.
+    const REQUIRED_FIELDS: [&'static str; 4] = ["type", "client", "tx", "amount"];
+
+    /// Header of the files without one, whose columns are the required ones in this order.
+    fn positional_headers() -> csv::StringRecord {
+        csv::StringRecord::from(Self::REQUIRED_FIELDS.to_vec())
+    }
+
+    /// First required column missing from `headers`, fields are matched by name so their order doesn't matter.
+    fn missing_field(headers: &csv::StringRecord) -> Option<&'static str> {
+        Self::REQUIRED_FIELDS
+            .iter()
+            .copied()
+            .find(|field| !headers.iter().any(|header| header == *field))
+    }
+
+    /// Checks the amount can be safely applied, the types moving funds need a strictly positive one.
+    fn validate(&self) -> bool {
+        match self.r#type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer => {
+                self.amount.is_positive()
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether the transaction only references a previous one by its tx id and so doesn't need an amount.
+    fn is_reference(&self, partial_disputes: bool) -> bool {
+        match self.r#type {
+            TransactionType::Dispute => !partial_disputes,
+            TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Settle
+            | TransactionType::Void => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Eq, PartialEq, Copy, Clone)]
-#[repr(u8)]
+#[repr(u16)]
 enum TransactionType {
     Deposit = 0,
     Withdrawal = 1,
     Dispute = 2,
     Resolve = 4,
     Chargeback = 8,
+    Unlock = 32,
+    Transfer = 64,
+    Close = 128,
+    Merge = 256,
+    Settle = 512,
+    Void = 1024,
+    Adjustment = 2048,
     Unknown = 16,
 }
 
-#[derive(Debug, Default)]
-struct ClientAccount {
-    client: ClientId,
-    available: f32,
-    held: f32,
-    locked: bool,
-    transactions: HashMap<u32, f32, RandomXxHashBuilder64>,
-    disputed_transactions: HashSet<u32, RandomXxHashBuilder64>,
-}
+impl TransactionType {
+    /// Every type, in the order the summary counts them.
+    const ALL: [TransactionType; 13] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Unlock,
+        TransactionType::Transfer,
+        TransactionType::Close,
+        TransactionType::Merge,
+        TransactionType::Settle,
+        TransactionType::Void,
+        TransactionType::Adjustment,
+        TransactionType::Unknown,
+    ];
 
-impl ClientAccount {
-    pub fn new(client: ClientId) -> Self {
-        ClientAccount {
-            client,
-            ..Default::default()
+    /// Name of the type in the input, the one `from_name` reads.
+    fn name(self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Close => "close",
+            TransactionType::Merge => "merge",
+            TransactionType::Settle => "settle",
+            TransactionType::Void => "void",
+            TransactionType::Adjustment => "adjustment",
+            TransactionType::Unknown => "unknown",
         }
     }
 
-    pub fn apply_transaction(&mut self, transaction: Transaction) {
-        // If the transaction doesn't belong to this account
-        // or the account is locked, we skip it.
-        if transaction.client != self.client || self.locked {
-            return;
+    /// Type by its name in the input, whatever its case, the names not known are read as `Unknown`.
+    fn from_name(name: &str) -> Self {
+        // Most inputs are in lowercase, the rest are only lowercased when they have to be
+        if name.bytes().any(|byte| byte.is_ascii_uppercase()) {
+            return Self::from_name(&name.to_ascii_lowercase());
         }
 
-        match transaction.r#type {
-            TransactionType::Deposit => {
-                if let Vacant(entry) = self.transactions.entry(transaction.tx) {
-                    self.available += transaction.amount;
-                    entry.insert(transaction.amount);
-                }
-            }
-            TransactionType::Withdrawal if self.available >= transaction.amount => {
-                if let Vacant(entry) = self.transactions.entry(transaction.tx) {
-                    self.available -= transaction.amount;
-                    entry.insert(-transaction.amount);
-                }
-            }
-            TransactionType::Dispute => {
-                if let Occupied(entry) = self.transactions.entry(transaction.tx) {
-                    let held_amount = entry.get();
-                    // If there are not enough funds to hold
-                    // we consider the dispute erroneous
-                    // because the disputed funds have already
-                    // been withdrawn by a previous transaction
-                    if self.available >= *held_amount {
-                        self.available -= held_amount;
-                        self.held += held_amount;
-                        self.disputed_transactions.insert(transaction.tx);
-                    }
-                }
-            }
-            TransactionType::Resolve => {
-                if let Occupied(entry) = self.transactions.entry(transaction.tx) {
-                    if self.disputed_transactions.contains(entry.key()) {
-                        let held_amount = entry.get();
-                        self.available += held_amount;
-                        self.held -= held_amount;
-                        self.disputed_transactions.remove(entry.key());
-                    }
-                }
-            }
-            TransactionType::Chargeback => {
-                if let Occupied(entry) = self.transactions.entry(transaction.tx) {
-                    if self.disputed_transactions.contains(entry.key()) {
-                        let held_amount = entry.get();
-                        self.held -= held_amount;
-                        self.disputed_transactions.remove(entry.key());
-                        self.locked = true;
-                    }
-                }
-            }
-            _ => {
-                // If a transaction record was malformed, we ignore it.
-            }
+        match name {
+            "deposit" => TransactionType::Deposit,
+            "withdrawal" => TransactionType::Withdrawal,
+            "dispute" => TransactionType::Dispute,
+            "resolve" => TransactionType::Resolve,
+            "chargeback" => TransactionType::Chargeback,
+            "unlock" => TransactionType::Unlock,
+            "transfer" => TransactionType::Transfer,
+            "close" => TransactionType::Close,
+            "merge" => TransactionType::Merge,
+            "settle" => TransactionType::Settle,
+            "void" => TransactionType::Void,
+            "adjustment" => TransactionType::Adjustment,
+            _ => TransactionType::Unknown,
         }
     }
+
+    /// Type by the other names some feeds use for it, read unless `--strict-types` is given.
+    fn from_alias(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "withdraw" => Some(TransactionType::Withdrawal),
+            "charge_back" | "charge-back" => Some(TransactionType::Chargeback),
+            _ => None,
+        }
+    }
+}
+
+/// Where a recorded transaction stands after the settlement and disputes it went through, whether it is
+/// currently under dispute is tracked separately in `ClientAccount::disputes.open`.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum TransactionState {
+    /// A two-phase deposit waiting for its settle or void, its funds are held meanwhile.
+    Pending,
+    Applied,
+    Resolved,
+    ChargedBack,
+    Voided,
 }
 
+/// What an account remembers about an applied deposit or withdrawal, the amount is always
+/// stored as a positive value and the type tells disputes which way the funds moved.
 #[derive(Debug, Copy, Clone)]
-struct ClientState {
-    client: ClientId,
-    available: f32,
-    held: f32,
-    locked: bool,
+struct TransactionRecord {
+    r#type: TransactionType,
+    amount: Amount,
+    state: TransactionState,
+    currency: Currency,
 }
 
-impl From<ClientAccount> for ClientState {
-    fn from(ca: ClientAccount) -> Self {
-        ClientState {
-            client: ca.client,
-            available: ca.available,
-            held: ca.held,
-            locked: ca.locked,
-        }
-    }
+/// What happened to a transaction once it reached its account.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum ApplyOutcome {
+    Applied,
+    InsufficientFunds,
+    DuplicateTx,
+    AccountLocked,
+    AccountClosed,
+    LimitExceeded,
+    Queued,
+    UnknownTxReference,
+    BalanceOverflow,
+    Ignored,
 }
 
-impl fmt::Display for ClientState {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{},{:.4},{:.4},{:.4},{}",
-            self.client,
-            self.available,
-            self.held,
-            self.available + self.held,
-            self.locked
-        )
+impl ApplyOutcome {
+    /// Why the transaction wasn't applied, named like the skip counters, `None` if it was or is queued to be.
+    fn rejection(self) -> Option<&'static str> {
+        match self {
+            ApplyOutcome::Applied | ApplyOutcome::Queued => None,
+            ApplyOutcome::InsufficientFunds => Some("insufficient_funds"),
+            ApplyOutcome::DuplicateTx => Some("duplicate_tx"),
+            ApplyOutcome::AccountLocked => Some("account_locked"),
+            ApplyOutcome::AccountClosed => Some("account_closed"),
+            ApplyOutcome::LimitExceeded => Some("limit_exceeded"),
+            ApplyOutcome::UnknownTxReference => Some("unknown_tx_reference"),
+            ApplyOutcome::BalanceOverflow => Some("balance_overflow"),
+            ApplyOutcome::Ignored => Some("ignored"),
+        }
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = env::args().collect();
+/// Caps on balances and amounts, transactions going over any of them are rejected.
+#[derive(Debug, Default, Copy, Clone)]
+struct Limits {
+    /// Most funds an account can hold, counting both available and held ones.
+    max_balance: Option<Amount>,
+    /// Biggest amount a single deposit, withdrawal or transfer can move.
+    max_tx_amount: Option<Amount>,
+}
 
-    if args.len() != 2 {
-        eprintln!("Missing input file, exiting...")
-    } else {
-        let file_path = Path::new(&args[1]).to_owned();
-        let metadata = fs::metadata(&file_path)?;
+/// Fees charged on top of the withdrawn amount, a withdrawal needs enough funds to cover both.
+#[derive(Debug, Default, Copy, Clone)]
+struct Fees {
+    withdrawal_flat: Amount,
+    /// Percentage of the withdrawn amount, rounded to four decimal places like the amounts.
+    withdrawal_pct: Amount,
+}
 
-        if !file_path.exists() {
-            eprintln!("File path is invalid, exiting...");
+impl AccountPolicy {
+    /// Default amount of idempotency keys remembered per account, retries are expected
+    /// to come shortly after the original.
+    const DEFAULT_IDEMPOTENCY_WINDOW: usize = 10_000;
+}
 
-            return Ok(())
+impl Default for AccountPolicy {
+    fn default() -> Self {
+        AccountPolicy {
+            declined_cap: 0,
+            partial_disputes: false,
+            allow_overdraft: false,
+            audit_log: false,
+            strict_disputes: false,
+            hold_partial: false,
+            queue_after_lock: false,
+            single_dispute: false,
+            idempotency_window: Self::DEFAULT_IDEMPOTENCY_WINDOW,
+            check_monotonic_tx: false,
+            two_phase_deposits: false,
+            dispute_ttl: None,
+            check_invariants: false,
+            limits: Limits::default(),
+            fees: Fees::default(),
         }
+    }
+}
 
-        // After some profiling, it seems that the general best amount of worker is only 2, the limiting factor in the
-        // code seems to be the speed at which you can read the CSV file, so more threads aren't worth it unless
-        // significant increases in read performance are achieved.
-        let num_workers = 2;
-        // Here we try to estimate the best buffer size taking into account the amount of work each worker is going to process
-        // the more work each worker has assigned the higher the chance a small buffer may be filled before being processed
-        let work_per_worker = ((metadata.len() as usize / num_workers) / 25_000_000) + 1;
-        // Min buffer size is 120KB max size is 60MB
-        let buffer_size = std::cmp::min(10_000 * work_per_worker, 5_000_000);
+impl Fees {
+    fn withdrawal_fee(&self, amount: Amount) -> Amount {
+        self.withdrawal_flat + amount.percentage(self.withdrawal_pct)
+    }
+}
 
-        eprintln!("Using {} worker thread/s to process {:?} using a channel buffer size of {} Bytes", num_workers, &file_path, buffer_size * std::mem::size_of::<Transaction>());
+impl Limits {
+    fn exceeds_tx_amount(&self, amount: Amount) -> bool {
+        self.max_tx_amount.is_some_and(|max| amount > max)
+    }
 
-        let rt = Builder::new_multi_thread()
-            .worker_threads(num_workers + 1)
-            .build()?;
+    fn exceeds_balance(&self, available: Amount, held: Amount, amount: Amount) -> bool {
+        self.max_balance.is_some_and(|max| {
+            available.0 as i128 + held.0 as i128 + amount.0 as i128 > max.0 as i128
+        })
+    }
+}
 
-        rt.block_on(async {
-            let mut handle_set = Vec::with_capacity(num_workers);
-            let mut sender_set = Vec::with_capacity(num_workers);
-            let results_vec = Arc::new(Mutex::new(Vec::with_capacity(num_workers)));
+/// Knobs that change how accounts react to transactions, shared by every account of a run.
+#[derive(Debug, Copy, Clone)]
+struct AccountPolicy {
+    /// How many declined deposits and withdrawals each account keeps around for reporting.
+    declined_cap: usize,
+    /// Whether a dispute row with an amount disputes only that part of the transaction.
+    partial_disputes: bool,
+    /// Whether withdrawals can take the available funds below zero.
+    allow_overdraft: bool,
+    /// Whether accounts keep the events of an audit log.
+    audit_log: bool,
+    /// Whether accounts keep the resolves and chargebacks that had no dispute to settle.
+    strict_disputes: bool,
+    /// Whether a dispute over more than the available funds holds what is left instead of being dropped.
+    hold_partial: bool,
+    /// Whether deposits to a locked account wait for it to be unlocked instead of being rejected.
+    queue_after_lock: bool,
+    /// Whether a transaction can only be disputed once, even if that dispute was resolved.
+    single_dispute: bool,
+    /// How many of the latest idempotency keys each account remembers.
+    idempotency_window: usize,
+    /// Whether accounts check the tx ids of their deposits and withdrawals are strictly increasing.
+    check_monotonic_tx: bool,
+    /// Whether deposits are held until a settle makes them available or a void takes them back.
+    two_phase_deposits: bool,
+    /// Amount of later transactions of the same client after which an open dispute is released.
+    dispute_ttl: Option<u64>,
+    /// Whether the balances of every account are checked against what flowed through it at the end.
+    check_invariants: bool,
+    limits: Limits,
+    fees: Fees,
+}
 
-            for _ in 0..num_workers {
-                let (tx, mut rx) = tokio::sync::mpsc::channel(buffer_size);
-                sender_set.push(tx);
-                let worker_results_vec = results_vec.clone();
-                handle_set.push(rt.spawn(async move {
-                    let mut account_map = ClientAccounts::default();
-                    while let Some(transaction) = rx.recv().await {
-                        process_transaction(transaction, &mut account_map)
-                    }
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum DeclineReason {
+    InsufficientFunds,
+    AccountLocked,
+    AccountClosed,
+    LimitExceeded,
+}
 
-                    if let Ok(mut data) = worker_results_vec.lock() {
-                        data.push(account_map.into_values().map(ClientState::from).collect());
-                    }
-                }));
-            }
+impl fmt::Display for DeclineReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeclineReason::InsufficientFunds => write!(f, "insufficient_funds"),
+            DeclineReason::AccountLocked => write!(f, "account_locked"),
+            DeclineReason::AccountClosed => write!(f, "account_closed"),
+            DeclineReason::LimitExceeded => write!(f, "limit_exceeded"),
+        }
+    }
+}
 
-            handle_set.push(rt.spawn(async move {
-                let _ = extract_records(file_path, num_workers, sender_set).await;
-            }));
+#[derive(Debug, Copy, Clone)]
+struct DeclinedTransaction {
+    client: ClientId,
+    tx: u32,
+    row: u64,
+    amount: Amount,
+    reason: DeclineReason,
+}
 
-            futures::future::join_all(handle_set).await;
+/// Row that didn't make it into the accounts, for `--report-rejected`. Rows that couldn't be parsed only have
+/// their row number.
+#[derive(Debug, Copy, Clone)]
+struct RejectedTransaction {
+    row: u64,
+    client: Option<ClientId>,
+    tx: Option<u32>,
+    r#type: Option<TransactionType>,
+    amount: Option<Amount>,
+    reason: &'static str,
+}
 
-            if let Ok(data) = results_vec.lock() {
-                print_client_accounts_state(data.as_ref());
-            };
-        });
+impl RejectedTransaction {
+    fn new(transaction: &Transaction, reason: &'static str) -> Self {
+        RejectedTransaction {
+            row: transaction.row,
+            client: Some(transaction.client),
+            tx: Some(transaction.tx),
+            r#type: Some(transaction.r#type),
+            // Only the disputes and settlements can go without, and the rows carrying one had it dropped
+            amount: Some(transaction.amount).filter(|amount| *amount != Amount::ZERO),
+            reason,
+        }
     }
 
-    Ok(())
+    fn unparsed(row: u64, reason: &'static str) -> Self {
+        RejectedTransaction { row, client: None, tx: None, r#type: None, amount: None, reason }
+    }
 }
 
-async fn extract_records<P: AsRef<Path>>(
-    file_path: P,
-    num_workers: usize,
-    sender_vec: Vec<Sender<Transaction>>
-) -> Result<(), Box<dyn Error>> {
-    let mut reader = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(file_path)?;
+/// Transaction of a client traced with `--trace-client`, along with the balances it left the account with.
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct TraceEntry {
+    client: ClientId,
+    tx: u32,
+    row: u64,
+    r#type: TransactionType,
+    amount: Amount,
+    available: Amount,
+    held: Amount,
+    outcome: ApplyOutcome,
+}
 
-    for entry in reader.deserialize() {
-        let transaction: Transaction = entry?;
+/// Clients whose transactions are traced by the worker owning each of them, and where it records them.
+#[derive(Debug, Clone)]
+struct Tracer {
+    clients: Arc<HashSet<ClientId>>,
+    entries: Arc<Mutex<Vec<TraceEntry>>>,
+}
 
-        let worker_index = transaction.client as usize % num_workers;
+impl Tracer {
+    /// Records `transaction` if `client` is traced, with the balances of its currency the account was left with.
+    fn record(&self, accounts: &ClientAccounts, client: ClientId, transaction: &Transaction, outcome: ApplyOutcome) {
+        if !self.clients.contains(&client) {
+            return;
+        }
 
-        if let Err(e) = sender_vec[worker_index].try_send(transaction) {
-            if let TrySendError::Full(_) = e {
-                eprintln!("Buffer full for worker {}, waiting...", worker_index);
-                sender_vec[worker_index].send(transaction).await?;
-            } else {
-                return Err(Box::new(e));
-            }
+        let balance = accounts.get(&client).map(|account| account.balance(transaction.currency));
+        let (available, held) = balance.unwrap_or((Amount::ZERO, Amount::ZERO));
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.push(TraceEntry {
+                client,
+                tx: transaction.tx,
+                row: transaction.row,
+                r#type: transaction.r#type,
+                amount: transaction.amount,
+                available,
+                held,
+                outcome,
+            });
         }
     }
+}
 
-    Ok(())
+/// Resolve or chargeback referencing a transaction that wasn't under dispute.
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct UndisputedSettlement {
+    client: ClientId,
+    tx: u32,
+    row: u64,
+    r#type: TransactionType,
 }
 
-fn process_transaction(tx: Transaction, accounts: &mut ClientAccounts) {
-    match accounts.entry(tx.client) {
-        Occupied(mut account) => account.get_mut().apply_transaction(tx),
-        Vacant(entry) => {
-            let mut account = ClientAccount::new(tx.client);
-            account.apply_transaction(tx);
-            entry.insert(account);
-        }
-    }
+/// A change in the state of an account, as written to the audit log.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum AccountEvent {
+    Deposited,
+    Withdrew,
+    WithdrawalDeclined,
+    Held,
+    Released,
+    ChargedBack,
+    Locked,
+    Unlocked,
+    Closed,
+    DepositDeclined,
+    TransferredOut,
+    TransferredIn,
+    MergedOut,
+    MergedIn,
+    Settled,
+    Voided,
+    DisputeExpired,
+    Adjusted,
 }
 
-fn print_client_accounts_state(accounts: &[Vec<ClientState>]) {
-    println!("client,available,held,total,locked");
-    for account_group in accounts {
-        for account in account_group {
-            println!("{}", account);
+impl fmt::Display for AccountEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountEvent::Deposited => write!(f, "deposited"),
+            AccountEvent::Withdrew => write!(f, "withdrew"),
+            AccountEvent::WithdrawalDeclined => write!(f, "withdrawal_declined"),
+            AccountEvent::Held => write!(f, "held"),
+            AccountEvent::Released => write!(f, "released"),
+            AccountEvent::ChargedBack => write!(f, "charged_back"),
+            AccountEvent::Locked => write!(f, "locked"),
+            AccountEvent::Unlocked => write!(f, "unlocked"),
+            AccountEvent::Closed => write!(f, "closed"),
+            AccountEvent::DepositDeclined => write!(f, "deposit_declined"),
+            AccountEvent::TransferredOut => write!(f, "transferred_out"),
+            AccountEvent::TransferredIn => write!(f, "transferred_in"),
+            AccountEvent::MergedOut => write!(f, "merged_out"),
+            AccountEvent::MergedIn => write!(f, "merged_in"),
+            AccountEvent::Settled => write!(f, "settled"),
+            AccountEvent::Voided => write!(f, "voided"),
+            AccountEvent::DisputeExpired => write!(f, "dispute_expired"),
+            AccountEvent::Adjusted => write!(f, "adjusted"),
         }
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use float_cmp::approx_eq;
+/// An audit log line, the balances are the ones the account was left with after the event.
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct AuditEntry {
+    client: ClientId,
+    tx: u32,
+    /// Row of the input the event came from.
+    row: u64,
+    event: AccountEvent,
+    amount: Amount,
+    available: Amount,
+    held: Amount,
+}
+
+/// The chargeback that left an account locked.
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct LockCause {
+    tx: u32,
+    row: u64,
+    amount: Amount,
+}
 
-    const EPSILON: f32 = 0.00001;
+/// A dispute still open once the input is over, its funds being held.
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct OpenDispute {
+    client: ClientId,
+    tx: u32,
+    held: Amount,
+    currency: Currency,
+}
 
-    impl PartialEq for ClientState {
-        fn eq(&self, other: &Self) -> bool {
-            self.client == other.client
-                && approx_eq!(f32, self.available, other.available, epsilon = EPSILON)
-                && approx_eq!(f32, self.held, other.held, epsilon = EPSILON)
-                && self.locked == other.locked
+/// Three letter code of the currency a transaction is in, kept as its upper case ASCII bytes
+/// so `Transaction` stays small and `Copy`. `NONE` stands for the implicit currency.
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
+struct Currency([u8; 3]);
+
+impl Currency {
+    const NONE: Currency = Currency([0; 3]);
+
+    fn parse(code: &str) -> Result<Currency, String> {
+        match code.as_bytes() {
+            [a, b, c] if code.bytes().all(|byte| byte.is_ascii_alphabetic()) => {
+                Ok(Currency([a.to_ascii_uppercase(), b.to_ascii_uppercase(), c.to_ascii_uppercase()]))
+            }
+            _ => Err(format!("Invalid currency {}", code)),
         }
     }
+}
 
-    impl Eq for ClientState {}
-
-    impl PartialEq for Transaction {
-        fn eq(&self, other: &Self) -> bool {
-            self.r#type == other.r#type
-                && self.client == other.client
-                && self.tx == other.tx
-                && approx_eq!(f32, self.amount, other.amount, epsilon = EPSILON)
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Currency::NONE {
+            return Ok(());
         }
+
+        // Only ever built from ASCII letters
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
     }
+}
 
-    impl Eq for Transaction {}
+/// A monetary amount in minor units of 1/10000, the most decimal places the input amounts have,
+/// so amounts and balances add up exactly. Arithmetic on balances is checked.
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
+struct Amount(i64);
 
-    #[tokio::test]
-    async fn happy_path() {
-        let file_path = "test_data/20.csv";
+impl Amount {
+    const DECIMALS: usize = 4;
+    const SCALE: i64 = 10_000;
+    const ZERO: Amount = Amount(0);
 
-        let expected_result = ClientState {
-            client: 1,
-            available: 55.0,
-            held: 0.0,
-            locked: false,
-        };
+    fn checked_add(self, amount: Amount) -> Option<Amount> {
+        self.0.checked_add(amount.0).map(Amount)
+    }
 
-        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+    fn checked_sub(self, amount: Amount) -> Option<Amount> {
+        self.0.checked_sub(amount.0).map(Amount)
+    }
 
-        tokio::spawn(async move {
-            extract_records(file_path, 1, vec![tx]).await.expect("Should finish correctly");
-        });
+    fn is_positive(self) -> bool {
+        self.0 > 0
+    }
 
-        let mut transaction_vec = Vec::with_capacity(20);
+    /// `pct` percent of the amount, rounded half away from zero to the nearest minor unit.
+    fn percentage(self, pct: Amount) -> Amount {
+        let divisor = 100 * Self::SCALE as i128;
+        let product = self.0 as i128 * pct.0 as i128;
+        let rounded = (product + product.signum() * divisor / 2) / divisor;
 
-        while let Some(data) = rx.recv().await {
-            transaction_vec.push(data);
-        }
+        Amount(rounded as i64)
+    }
+}
 
-        assert_eq!(transaction_vec.len(), 20);
+/// The pieces of a decimal number as written, in scientific notation or not.
+struct DecimalParts<'a> {
+    negative: bool,
+    whole: &'a str,
+    fraction: &'a str,
+    exponent: i64,
+}
 
-        let mut accounts = ClientAccounts::default();
+impl<'a> DecimalParts<'a> {
+    fn split(value: &'a str) -> Option<Self> {
+        let (negative, digits) = match value.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, value.strip_prefix('+').unwrap_or(value)),
+        };
+        let (mantissa, exponent) = match digits.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, exponent.parse().ok()?),
+            None => (digits, 0),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
 
-        for transactions in transaction_vec {
-            process_transaction(transactions, &mut accounts)
+        if (whole.is_empty() && fraction.is_empty())
+            || !whole.bytes().chain(fraction.bytes()).all(|byte| byte.is_ascii_digit())
+        {
+            return None;
         }
 
-        let account_states: Vec<ClientState> = accounts.into_values().map(ClientState::from).collect();
-
-        assert_eq!(account_states.len(), 1);
-        assert_eq!(account_states[0], expected_result);
+        Some(DecimalParts {
+            negative,
+            whole,
+            fraction,
+            exponent,
+        })
     }
 
-    #[tokio::test]
-    async fn happy_path_with_all_types() {
-        let file_path = "test_data/15.csv";
+    /// Decimal places of the number once the exponent is applied.
+    fn places(&self) -> i64 {
+        (self.fraction.len() as i64).saturating_sub(self.exponent)
+    }
+}
 
-        let expected_results = vec![
-            ClientState {
-                client: 1,
-                available: 100.0,
-                held: 0.0,
-                locked: true,
-            },
-            ClientState {
-                client: 2,
-                available: 135.0,
-                held: 0.0,
-                locked: false,
-            },
-            ClientState {
-                client: 3,
-                available: 100.0,
-                held: 0.0,
-                locked: false,
-            },
-        ];
+impl Amount {
+    /// Whether a decimal number has more decimal places than an `Amount` can keep.
+    fn exceeds_precision(value: &str) -> bool {
+        DecimalParts::split(value).is_some_and(|parts| parts.places() > Self::DECIMALS as i64)
+    }
 
-        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+    fn is_scientific(value: &str) -> bool {
+        value.contains(['e', 'E'])
+    }
 
-        tokio::spawn(async move {
-            extract_records(file_path, 1, vec![tx]).await.expect("Should finish correctly");
-        });
+    /// Whether the value is one of the spellings of NaN and infinity floating point parsers accept.
+    fn is_non_finite(value: &str) -> bool {
+        let value = value.strip_prefix(['-', '+']).unwrap_or(value);
 
-        let mut transaction_vec = Vec::with_capacity(20);
+        ["nan", "inf", "infinity"].iter().any(|name| value.eq_ignore_ascii_case(name))
+    }
 
-        while let Some(data) = rx.recv().await {
-            transaction_vec.push(data);
+    /// Rewrites an amount written for people as a plain decimal number. Surrounding whitespace is
+    /// dropped, and so are the thousands separators, commas or spaces splitting the whole part in
+    /// groups of three digits, or points and spaces with a decimal comma. A separator anywhere else
+    /// gives `None`, so a decimal comma is never taken for one.
+    fn normalize(value: &str, decimal_comma: bool) -> Option<Cow<'_, str>> {
+        let value = value.trim();
+        let (separator, point) = if decimal_comma { ('.', ',') } else { (',', '.') };
+        let separated = value.contains([',', ' ']) || decimal_comma && value.contains('.');
+        if !separated {
+            return Some(Cow::Borrowed(value));
         }
 
-        assert_eq!(transaction_vec.len(), 15);
+        let unsigned = value.strip_prefix(['-', '+']).unwrap_or(value);
+        let sign = &value[..value.len() - unsigned.len()];
+        let (whole, fraction) = match unsigned.split_once(point) {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (unsigned, None),
+        };
 
+        let groups: Vec<&str> = whole.split([separator, ' ']).collect();
+        let grouped = groups.iter().enumerate().all(|(index, group)| {
+            let size = if index == 0 { 1..=3 } else { 3..=3 };
+            size.contains(&group.len()) && group.bytes().all(|byte| byte.is_ascii_digit())
+        });
+        if groups.len() > 1 && !grouped {
+            return None;
+        }
+
+        let mut plain = format!("{}{}", sign, groups.concat());
+        if let Some(fraction) = fraction {
+            plain.push('.');
+            plain.push_str(fraction);
+        }
+
+        Some(Cow::Owned(plain))
+    }
+
+    /// Parses a decimal number straight into minor units, the decimal places past the fourth
+    /// are dropped when truncating and make the number invalid otherwise.
+    fn parse(value: &str, truncate: bool) -> Result<Amount, String> {
+        let invalid = || format!("Invalid amount {}", value);
+
+        let parts = DecimalParts::split(value).ok_or_else(invalid)?;
+        let excess = parts.places() - Self::DECIMALS as i64;
+        if excess > 0 && !truncate {
+            return Err(invalid());
+        }
+
+        let digits = parts.whole.len() + parts.fraction.len();
+        let kept = digits.saturating_sub(excess.max(0) as usize);
+
+        // Built with its sign from the start, so the most negative amount can be read too
+        let sign = if parts.negative { -1 } else { 1 };
+        let mut units: i64 = 0;
+        for digit in parts.whole.bytes().chain(parts.fraction.bytes()).take(kept) {
+            units = units
+                .checked_mul(10)
+                .and_then(|units| units.checked_add(sign * (digit - b'0') as i64))
+                .ok_or_else(invalid)?;
+        }
+        if excess < 0 && units != 0 {
+            let shift = 10_i64.checked_pow((-excess).min(u32::MAX as i64) as u32);
+            units = shift.and_then(|shift| units.checked_mul(shift)).ok_or_else(invalid)?;
+        }
+
+        Ok(Amount(units))
+    }
+}
+
+impl std::str::FromStr for Amount {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Amount::parse(value, false)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format(AmountFormat::default()))
+    }
+}
+
+/// How amounts are written to the output.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+struct AmountFormat {
+    /// Decimal places written, below the four an `Amount` keeps the amount is rounded half away from zero.
+    precision: usize,
+    /// Drop the trailing zeros of the decimal part, along with the point if nothing is left of it.
+    trim_zeros: bool,
+}
+
+impl AmountFormat {
+    const MAX_PRECISION: usize = 8;
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        AmountFormat {
+            precision: Amount::DECIMALS,
+            trim_zeros: false,
+        }
+    }
+}
+
+impl Amount {
+    fn format(self, format: AmountFormat) -> FormattedAmount {
+        FormattedAmount(self, format)
+    }
+}
+
+struct FormattedAmount(Amount, AmountFormat);
+
+impl fmt::Display for FormattedAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let FormattedAmount(Amount(units), format) = *self;
+
+        // The whole units are kept apart from the decimals, so both fit in 64 bits at the maximum precision
+        let magnitude = units.unsigned_abs();
+        let scale = 10_u64.pow(format.precision as u32);
+        let (mut whole, mut fraction) = (magnitude / Amount::SCALE as u64, magnitude % Amount::SCALE as u64);
+        if format.precision < Amount::DECIMALS {
+            let divisor = 10_u64.pow((Amount::DECIMALS - format.precision) as u32);
+            fraction = (fraction + divisor / 2) / divisor;
+            if fraction == scale {
+                whole += 1;
+                fraction = 0;
+            }
+        } else {
+            fraction *= 10_u64.pow((format.precision - Amount::DECIMALS) as u32);
+        }
+
+        // Amounts rounded to zero are written without a sign
+        let sign = if units < 0 && (whole > 0 || fraction > 0) { "-" } else { "" };
+        // The trailing zeros are trimmed off the digits, so nothing is allocated along the way
+        let mut width = format.precision;
+        while format.trim_zeros && width > 0 && fraction % 10 == 0 {
+            fraction /= 10;
+            width -= 1;
+        }
+
+        if width == 0 {
+            write!(f, "{}{}", sign, whole)
+        } else {
+            write!(f, "{}{}.{:0width$}", sign, whole, fraction, width = width)
+        }
+    }
+}
+
+/// Plain arithmetic saturates, it is only used for totals and the amounts being moved,
+/// balances go through the checked methods so an overflow can be turned down.
+impl std::ops::Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0))
+    }
+}
+
+impl std::ops::Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_sub(other.0))
+    }
+}
+
+impl std::ops::Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        Amount(self.0.saturating_neg())
+    }
+}
+
+impl std::ops::AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        *self = *self + other;
+    }
+}
+
+impl std::ops::SubAssign for Amount {
+    fn sub_assign(&mut self, other: Amount) {
+        *self = *self - other;
+    }
+}
+
+impl std::iter::Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, |total, amount| total + amount)
+    }
+}
+
+/// A balance that doesn't add up, found when checking the invariants of an account.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum InvariantViolation {
+    NegativeAvailable(Amount),
+    NegativeHeld(Amount),
+    /// The total doesn't match the deposits, withdrawals, chargebacks and the like the account went through.
+    TotalMismatch { total: Amount, expected: Amount },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::NegativeAvailable(available) => write!(f, "available funds are negative: {}", available),
+            InvariantViolation::NegativeHeld(held) => write!(f, "held funds are negative: {}", held),
+            InvariantViolation::TotalMismatch { total, expected } => {
+                write!(f, "total of {} doesn't match the {} that flowed through the account", total, expected)
+            }
+        }
+    }
+}
+
+/// Why the transactions of an account that didn't apply were skipped.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+struct SkipCounters {
+    wrong_client: u32,
+    account_locked: u32,
+    account_closed: u32,
+    duplicate_tx: u32,
+    insufficient_funds: u32,
+    unknown_tx_reference: u32,
+    limit_exceeded: u32,
+    balance_overflow: u32,
+    ignored: u32,
+}
+
+impl SkipCounters {
+    const HEADER: &'static str = "wrong_client,account_locked,account_closed,duplicate_tx,insufficient_funds,unknown_tx_reference,limit_exceeded,balance_overflow,ignored";
+
+    fn record(&mut self, outcome: ApplyOutcome) {
+        match outcome {
+            ApplyOutcome::AccountLocked => self.account_locked += 1,
+            ApplyOutcome::AccountClosed => self.account_closed += 1,
+            ApplyOutcome::DuplicateTx => self.duplicate_tx += 1,
+            ApplyOutcome::InsufficientFunds => self.insufficient_funds += 1,
+            ApplyOutcome::UnknownTxReference => self.unknown_tx_reference += 1,
+            ApplyOutcome::LimitExceeded => self.limit_exceeded += 1,
+            ApplyOutcome::BalanceOverflow => self.balance_overflow += 1,
+            ApplyOutcome::Ignored => self.ignored += 1,
+            ApplyOutcome::Applied | ApplyOutcome::Queued => {}
+        }
+    }
+
+    fn merge(&mut self, other: &SkipCounters) {
+        self.wrong_client += other.wrong_client;
+        self.account_locked += other.account_locked;
+        self.account_closed += other.account_closed;
+        self.duplicate_tx += other.duplicate_tx;
+        self.insufficient_funds += other.insufficient_funds;
+        self.unknown_tx_reference += other.unknown_tx_reference;
+        self.limit_exceeded += other.limit_exceeded;
+        self.balance_overflow += other.balance_overflow;
+        self.ignored += other.ignored;
+    }
+}
+
+impl fmt::Display for SkipCounters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{},{},{},{}",
+            self.wrong_client,
+            self.account_locked,
+            self.account_closed,
+            self.duplicate_tx,
+            self.insufficient_funds,
+            self.unknown_tx_reference,
+            self.limit_exceeded,
+            self.balance_overflow,
+            self.ignored
+        )
+    }
+}
+
+/// Deposits and withdrawals whose tx id wasn't greater than the previous one of the same client.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+struct TxOrderAnomalies {
+    out_of_order: u32,
+    repeated: u32,
+}
+
+impl TxOrderAnomalies {
+    fn is_empty(&self) -> bool {
+        self.out_of_order == 0 && self.repeated == 0
+    }
+}
+
+/// Everything a merged account hands over to the account it is merged into.
+#[derive(Debug, Default)]
+struct MergedAccount {
+    /// Available and held funds of each currency.
+    balances: Vec<(Currency, Amount, Amount)>,
+    transactions: HashMap<u32, TransactionRecord, RandomXxHashBuilder64>,
+    disputed_transactions: HashMap<u32, Amount, RandomXxHashBuilder64>,
+    dispute_shortfalls: HashMap<u32, Amount, RandomXxHashBuilder64>,
+    /// How many transactions of the merged account came after each of its open disputes.
+    dispute_ages: HashMap<u32, u64, RandomXxHashBuilder64>,
+}
+
+/// Balances and lifetime counters an account keeps apart for each currency it holds.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+struct CurrencyBalances {
+    available: Amount,
+    held: Amount,
+    total_deposited: Amount,
+    total_withdrawn: Amount,
+    /// Fees charged on withdrawals, not part of `total_withdrawn`.
+    total_fees: Amount,
+    /// Sum of the signed amounts of every adjustment applied.
+    net_adjustment: Amount,
+    /// Funds taken by chargebacks, less the ones given back by reversed withdrawals.
+    charged_back: Amount,
+    /// Funds received through transfers and merges, less the ones sent through them.
+    net_transferred: Amount,
+    /// Shortfalls of the disputes that ended in a chargeback.
+    chargeback_shortfall: Amount,
+}
+
+/// Disputes of an account, those open and how the others went.
+#[derive(Debug, Default)]
+struct Disputes {
+    /// Transactions currently under dispute along with the amount each dispute holds.
+    open: HashMap<u32, Amount, RandomXxHashBuilder64>,
+    /// Part of a disputed amount that couldn't be held because it was already spent.
+    shortfalls: HashMap<u32, Amount, RandomXxHashBuilder64>,
+    /// Value of `transaction_count` when each open dispute was raised, only kept when disputes expire.
+    opened_at: HashMap<u32, u64, RandomXxHashBuilder64>,
+    count: u32,
+    chargebacks: u32,
+    duplicates: u32,
+    /// Disputes rejected because their transaction had already been disputed and resolved.
+    repeated: u32,
+    expired: u32,
+    undisputed_resolves: u32,
+    undisputed_chargebacks: u32,
+}
+
+/// Transactions of an account only kept for the reports asking for them.
+#[derive(Debug, Default)]
+struct AccountRecords {
+    declined: Vec<DeclinedTransaction>,
+    /// Audit log of the account, only filled in when the policy asks for it.
+    events: Vec<AuditEntry>,
+    /// Resolves and chargebacks without a dispute, only kept when the policy asks for it.
+    undisputed_settlements: Vec<UndisputedSettlement>,
+    /// Disputes of a tx id the account never saw, told apart from those of the tx of another client once every
+    /// worker is done.
+    unknown_disputes: Vec<Transaction>,
+}
+
+/// Idempotency keys of the latest deposits and withdrawals of an account, oldest first in `order`.
+#[derive(Debug, Default)]
+struct Idempotency {
+    keys: HashSet<u64, RandomXxHashBuilder64>,
+    order: VecDeque<u64>,
+    /// Deposits and withdrawals rejected as retries of one already seen.
+    retried: u32,
+}
+
+/// Order of the tx ids of an account, only tracked when checking they are monotonic.
+#[derive(Debug, Default)]
+struct TxOrder {
+    /// Latest tx id of a deposit or withdrawal.
+    last: Option<u32>,
+    anomalies: TxOrderAnomalies,
+}
+
+#[derive(Debug, Default)]
+struct ClientAccount {
+    client: ClientId,
+    /// Balances and counters of `currency`, those of the other currencies are kept in `other_balances`.
+    balances: CurrencyBalances,
+    currency: Currency,
+    other_balances: HashMap<Currency, CurrencyBalances, RandomXxHashBuilder64>,
+    locked: bool,
+    closed: bool,
+    locked_by: Option<LockCause>,
+    /// Close transactions ignored because the account was locked at the time.
+    locked_close_attempts: u32,
+    unlocks: u32,
+    /// Deposits received while locked, kept as they were read to be applied in order once the account is unlocked.
+    pending: Vec<Transaction>,
+    /// Deposits released by the last unlock, left for the worker to apply after it.
+    released: Vec<Transaction>,
+    transactions: HashMap<u32, TransactionRecord, RandomXxHashBuilder64>,
+    disputes: Disputes,
+    records: AccountRecords,
+    adjustments: u32,
+    skips: SkipCounters,
+    idempotency: Idempotency,
+    tx_order: TxOrder,
+    /// Transactions of this client applied so far, the clock disputes expire by.
+    transaction_count: u64,
+    /// Disputes and the like dropped because they weren't in the currency of the transaction they reference.
+    currency_mismatches: u32,
+    /// Earliest and latest timestamps among the rows of the client that carry one.
+    first_timestamp: Option<i64>,
+    last_timestamp: Option<i64>,
+}
+
+impl ClientAccount {
+    pub fn new(client: ClientId) -> Self {
+        ClientAccount {
+            client,
+            ..Default::default()
+        }
+    }
+
+    /// Makes the balances and counters of the account those of the given currency, the ones
+    /// of the previous currency are put aside until it is selected again.
+    fn select_currency(&mut self, currency: Currency) {
+        if currency == self.currency {
+            return;
+        }
+
+        let selected = self.other_balances.remove(&currency).unwrap_or_default();
+        let previous = std::mem::replace(&mut self.balances, selected);
+        // A currency nothing ever moved in is dropped, so it doesn't show up in the output
+        if previous != CurrencyBalances::default() {
+            self.other_balances.insert(self.currency, previous);
+        }
+        self.currency = currency;
+    }
+
+    /// Balances of every currency the account holds, sorted by currency.
+    fn currency_balances(&self) -> Vec<(Currency, CurrencyBalances)> {
+        let mut balances: Vec<(Currency, CurrencyBalances)> =
+            self.other_balances.iter().map(|(currency, balances)| (*currency, *balances)).collect();
+
+        let current = self.balances;
+        if current != CurrencyBalances::default() || balances.is_empty() {
+            balances.push((self.currency, current));
+        }
+        balances.sort_by_key(|(currency, _)| *currency);
+
+        balances
+    }
+
+    /// Available and held funds of the given currency.
+    fn balance(&self, currency: Currency) -> (Amount, Amount) {
+        if currency == self.currency {
+            return (self.balances.available, self.balances.held);
+        }
+
+        self.other_balances
+            .get(&currency)
+            .map_or((Amount::ZERO, Amount::ZERO), |balances| (balances.available, balances.held))
+    }
+
+    /// One state for each currency the account holds, sorted by currency.
+    fn into_states(mut self) -> Vec<ClientState> {
+        self.states()
+    }
+
+    /// Same as `into_states`, leaving the account as it was.
+    fn states(&mut self) -> Vec<ClientState> {
+        let selected = self.currency;
+        let states = self
+            .currency_balances()
+            .into_iter()
+            .map(|(currency, _)| {
+                self.select_currency(currency);
+
+                ClientState::from(&*self)
+            })
+            .collect();
+        self.select_currency(selected);
+
+        states
+    }
+
+    /// Record of the transaction referenced by a dispute, resolve, chargeback, settle or void,
+    /// which has to be in the currency of the transaction.
+    fn referenced_record(&mut self, transaction: &Transaction) -> Option<TransactionRecord> {
+        let record = *self.transactions.get(&transaction.tx)?;
+
+        if record.currency != transaction.currency {
+            self.currency_mismatches += 1;
+
+            return None;
+        }
+
+        Some(record)
+    }
+
+    /// Changes both balances at once, if either of them can't take the change
+    /// neither is modified and `false` is returned.
+    fn move_funds(&mut self, available_change: Amount, held_change: Amount) -> bool {
+        match (
+            self.balances.available.checked_add(available_change),
+            self.balances.held.checked_add(held_change),
+        ) {
+            (Some(available), Some(held)) => {
+                self.balances.available = available;
+                self.balances.held = held;
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn set_state(&mut self, tx: u32, state: TransactionState) {
+        if let Some(record) = self.transactions.get_mut(&tx) {
+            record.state = state;
+        }
+    }
+
+    fn record_event(&mut self, event: AccountEvent, tx: u32, row: u64, amount: Amount, policy: &AccountPolicy) {
+        if policy.audit_log {
+            self.records.events.push(AuditEntry {
+                client: self.client,
+                tx,
+                row,
+                event,
+                amount,
+                available: self.balances.available,
+                held: self.balances.held,
+            });
+        }
+    }
+
+    /// Releases the disputes that went through the whole `dispute_ttl` worth of later
+    /// transactions without being resolved or charged back, as if they had been resolved.
+    /// `row` is the one of the transaction they expire at.
+    /// Takes the deposits queued while locked that the last unlock released, to be applied in order, each with its
+    /// own row and timestamp.
+    fn take_released(&mut self) -> Vec<Transaction> {
+        std::mem::take(&mut self.released)
+    }
+
+    fn expire_disputes(&mut self, row: u64, policy: &AccountPolicy) {
+        let ttl = match policy.dispute_ttl {
+            Some(ttl) if !self.disputes.opened_at.is_empty() => ttl,
+            _ => return,
+        };
+
+        let transaction_count = self.transaction_count;
+        let expired: Vec<u32> = self
+            .disputes
+            .opened_at
+            .iter()
+            .filter(|(_, opened_at)| transaction_count - **opened_at >= ttl)
+            .map(|(tx, _)| *tx)
+            .collect();
+
+        for tx in expired {
+            let (record, disputed_amount) = match (self.transactions.get(&tx), self.disputes.open.get(&tx)) {
+                (Some(record), Some(amount)) => (*record, *amount),
+                _ => {
+                    self.disputes.opened_at.remove(&tx);
+                    continue;
+                }
+            };
+
+            self.select_currency(record.currency);
+            let released = match record.r#type {
+                TransactionType::Deposit => self.move_funds(disputed_amount, -disputed_amount),
+                TransactionType::Withdrawal => self.move_funds(Amount::ZERO, -disputed_amount),
+                _ => true,
+            };
+
+            // Left open if releasing it would overflow, it is tried again with the next transaction
+            if released {
+                self.disputes.open.remove(&tx);
+                self.disputes.shortfalls.remove(&tx);
+                self.disputes.opened_at.remove(&tx);
+                self.set_state(tx, TransactionState::Resolved);
+                self.disputes.expired += 1;
+                self.record_event(AccountEvent::DisputeExpired, tx, row, disputed_amount, policy);
+            }
+        }
+    }
+
+    /// Disputes and the like reference older tx ids on purpose, so only the types introducing new ones are checked.
+    fn check_tx_order(&mut self, transaction: &Transaction) {
+        if !matches!(transaction.r#type, TransactionType::Deposit | TransactionType::Withdrawal) {
+            return;
+        }
+
+        match self.tx_order.last {
+            Some(last) if transaction.tx == last => self.tx_order.anomalies.repeated += 1,
+            Some(last) if transaction.tx < last => self.tx_order.anomalies.out_of_order += 1,
+            _ => self.tx_order.last = Some(transaction.tx),
+        }
+    }
+
+    /// Whether the transaction is a retry of one already applied.
+    fn is_retry(&mut self, transaction: &Transaction) -> bool {
+        match transaction.idempotency_key {
+            Some(key) if self.idempotency.keys.contains(&key) => {
+                self.idempotency.retried += 1;
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remembers the key of an applied transaction, forgetting the oldest one once the window is full.
+    fn remember_key(&mut self, transaction: &Transaction, policy: &AccountPolicy) {
+        if let (Some(key), true) = (transaction.idempotency_key, policy.idempotency_window > 0) {
+            if self.idempotency.order.len() == policy.idempotency_window {
+                if let Some(oldest) = self.idempotency.order.pop_front() {
+                    self.idempotency.keys.remove(&oldest);
+                }
+            }
+
+            self.idempotency.keys.insert(key);
+            self.idempotency.order.push_back(key);
+        }
+    }
+
+    fn settle_undisputed(&mut self, transaction: Transaction, policy: &AccountPolicy) {
+        if transaction.r#type == TransactionType::Resolve {
+            self.disputes.undisputed_resolves += 1;
+        } else {
+            self.disputes.undisputed_chargebacks += 1;
+        }
+
+        if policy.strict_disputes {
+            self.records.undisputed_settlements.push(UndisputedSettlement {
+                client: self.client,
+                tx: transaction.tx,
+                row: transaction.row,
+                r#type: transaction.r#type,
+            });
+        }
+    }
+
+    fn decline(&mut self, transaction: Transaction, reason: DeclineReason, policy: &AccountPolicy) {
+        let event = if transaction.r#type == TransactionType::Deposit {
+            AccountEvent::DepositDeclined
+        } else {
+            AccountEvent::WithdrawalDeclined
+        };
+        self.record_event(event, transaction.tx, transaction.row, transaction.amount, policy);
+
+        if self.records.declined.len() < policy.declined_cap {
+            self.records.declined.push(DeclinedTransaction {
+                client: self.client,
+                tx: transaction.tx,
+                row: transaction.row,
+                amount: transaction.amount,
+                reason,
+            });
+        }
+    }
+
+    /// Whether this account can send the given transfer, only the currency of the transfer is selected
+    /// so the receiving side can be checked before committing to either of them.
+    fn check_transfer_out(&mut self, transaction: &Transaction, policy: &AccountPolicy) -> ApplyOutcome {
+        self.select_currency(transaction.currency);
+
+        if self.locked {
+            ApplyOutcome::AccountLocked
+        } else if self.closed {
+            ApplyOutcome::AccountClosed
+        } else if self.transactions.contains_key(&transaction.tx) {
+            ApplyOutcome::DuplicateTx
+        } else if policy.limits.exceeds_tx_amount(transaction.amount) {
+            ApplyOutcome::LimitExceeded
+        } else if self.balances.available < transaction.amount {
+            ApplyOutcome::InsufficientFunds
+        } else if self.balances.available.checked_sub(transaction.amount).is_none() {
+            ApplyOutcome::BalanceOverflow
+        } else {
+            ApplyOutcome::Applied
+        }
+    }
+
+    /// Whether this account can receive the given transfer.
+    fn check_transfer_in(&mut self, transaction: &Transaction, policy: &AccountPolicy) -> ApplyOutcome {
+        self.select_currency(transaction.currency);
+
+        if self.locked {
+            ApplyOutcome::AccountLocked
+        } else if self.closed {
+            ApplyOutcome::AccountClosed
+        } else if self.transactions.contains_key(&transaction.tx) {
+            ApplyOutcome::DuplicateTx
+        } else if policy.limits.exceeds_balance(self.balances.available, self.balances.held, transaction.amount) {
+            ApplyOutcome::LimitExceeded
+        } else if self.balances.available.checked_add(transaction.amount).is_none() {
+            ApplyOutcome::BalanceOverflow
+        } else {
+            ApplyOutcome::Applied
+        }
+    }
+
+    fn transfer_out(&mut self, transaction: Transaction, policy: &AccountPolicy) {
+        // Already checked by `check_transfer_out`
+        self.move_funds(-transaction.amount, Amount::ZERO);
+        self.balances.net_transferred -= transaction.amount;
+        self.record_event(AccountEvent::TransferredOut, transaction.tx, transaction.row, transaction.amount, policy);
+        // Recorded so the tx id can't be reused, disputes over a transfer
+        // are raised by the receiving side.
+        self.transactions.insert(
+            transaction.tx,
+            TransactionRecord {
+                r#type: TransactionType::Transfer,
+                amount: transaction.amount,
+                state: TransactionState::Applied,
+                currency: transaction.currency,
+            },
+        );
+    }
+
+    fn transfer_in(&mut self, transaction: Transaction, policy: &AccountPolicy) {
+        // Already checked by `check_transfer_in`
+        self.move_funds(transaction.amount, Amount::ZERO);
+        self.balances.net_transferred += transaction.amount;
+        self.record_event(AccountEvent::TransferredIn, transaction.tx, transaction.row, transaction.amount, policy);
+        // For the receiving side a transfer behaves exactly like a deposit,
+        // so disputing it holds the transferred funds here.
+        self.transactions.insert(
+            transaction.tx,
+            TransactionRecord {
+                r#type: TransactionType::Deposit,
+                amount: transaction.amount,
+                state: TransactionState::Applied,
+                currency: transaction.currency,
+            },
+        );
+    }
+
+    /// Whether this account can be merged into another one.
+    fn check_merge_out(&self) -> ApplyOutcome {
+        if self.locked {
+            ApplyOutcome::AccountLocked
+        } else if self.closed {
+            ApplyOutcome::AccountClosed
+        } else {
+            ApplyOutcome::Applied
+        }
+    }
+
+    /// Whether the given merged account can be merged into this one, the tx ids of
+    /// both histories need to be distinct for later disputes to find the right one.
+    fn check_merge_in(&self, merged: &MergedAccount, policy: &AccountPolicy) -> ApplyOutcome {
+        if self.locked {
+            ApplyOutcome::AccountLocked
+        } else if self.closed {
+            ApplyOutcome::AccountClosed
+        } else if merged.transactions.keys().any(|tx| self.transactions.contains_key(tx)) {
+            ApplyOutcome::DuplicateTx
+        } else {
+            // Each currency is merged into the balances of the same currency
+            for (currency, merged_available, merged_held) in &merged.balances {
+                let (available, held) = self.balance(*currency);
+
+                if policy.limits.exceeds_balance(available, held, *merged_available + *merged_held) {
+                    return ApplyOutcome::LimitExceeded;
+                }
+                if available.checked_add(*merged_available).is_none() || held.checked_add(*merged_held).is_none() {
+                    return ApplyOutcome::BalanceOverflow;
+                }
+            }
+
+            ApplyOutcome::Applied
+        }
+    }
+
+    /// Takes out the funds and history of this account so they can be checked by the
+    /// account it is merged into, `restore_merged` gives them back if the merge is refused.
+    fn take_merged(&mut self) -> MergedAccount {
+        let transaction_count = self.transaction_count;
+        let mut balances = Vec::new();
+
+        for (currency, _) in self.currency_balances() {
+            self.select_currency(currency);
+            self.balances.net_transferred -= self.balances.available + self.balances.held;
+            let available = std::mem::take(&mut self.balances.available);
+            balances.push((currency, available, std::mem::take(&mut self.balances.held)));
+        }
+
+        MergedAccount {
+            balances,
+            transactions: std::mem::take(&mut self.transactions),
+            disputed_transactions: std::mem::take(&mut self.disputes.open),
+            dispute_shortfalls: std::mem::take(&mut self.disputes.shortfalls),
+            dispute_ages: self
+                .disputes
+                .opened_at
+                .drain()
+                .map(|(tx, opened_at)| (tx, transaction_count - opened_at))
+                .collect(),
+        }
+    }
+
+    fn restore_merged(&mut self, merged: MergedAccount) {
+        for (currency, available, held) in merged.balances {
+            self.select_currency(currency);
+            self.balances.net_transferred += available + held;
+            self.balances.available = available;
+            self.balances.held = held;
+        }
+        self.transactions = merged.transactions;
+        self.disputes.open = merged.disputed_transactions;
+        self.disputes.shortfalls = merged.dispute_shortfalls;
+        self.adopt_dispute_ages(merged.dispute_ages);
+    }
+
+    fn adopt_dispute_ages(&mut self, dispute_ages: HashMap<u32, u64, RandomXxHashBuilder64>) {
+        for (tx, age) in dispute_ages {
+            self.disputes.opened_at.insert(tx, self.transaction_count.saturating_sub(age));
+        }
+    }
+
+    fn merge_out(&mut self, transaction: Transaction, policy: &AccountPolicy) {
+        self.closed = true;
+        self.record_event(AccountEvent::MergedOut, transaction.tx, transaction.row, Amount::ZERO, policy);
+    }
+
+    fn merge_in(&mut self, merged: MergedAccount, transaction: Transaction, policy: &AccountPolicy) {
+        for (currency, available, held) in merged.balances {
+            self.select_currency(currency);
+            // Already checked by `check_merge_in`
+            self.move_funds(available, held);
+            self.balances.net_transferred += available + held;
+            self.record_event(AccountEvent::MergedIn, transaction.tx, transaction.row, available + held, policy);
+        }
+
+        self.transactions.extend(merged.transactions);
+        self.disputes.open.extend(merged.disputed_transactions);
+        self.disputes.shortfalls.extend(merged.dispute_shortfalls);
+        self.adopt_dispute_ages(merged.dispute_ages);
+    }
+
+    /// Disputes of the account that were neither resolved nor charged back, by tx id.
+    fn open_disputes(&self) -> Vec<OpenDispute> {
+        let mut disputes: Vec<OpenDispute> = self
+            .disputes
+            .open
+            .iter()
+            .map(|(tx, held)| OpenDispute {
+                client: self.client,
+                tx: *tx,
+                held: *held,
+                currency: self.transactions.get(tx).map_or(Currency::NONE, |record| record.currency),
+            })
+            .collect();
+        disputes.sort_by_key(|dispute| dispute.tx);
+
+        disputes
+    }
+
+    /// Checks the balances of each currency hold up against its lifetime counters.
+    fn check_invariants(&self, policy: &AccountPolicy) -> Vec<(Currency, InvariantViolation)> {
+        let mut violations = Vec::new();
+
+        for (currency, balances) in self.currency_balances() {
+            // Negative adjustments are allowed to overdraw the account whatever the policy says
+            if balances.available < Amount::ZERO && !policy.allow_overdraft && balances.net_adjustment >= Amount::ZERO {
+                violations.push((currency, InvariantViolation::NegativeAvailable(balances.available)));
+            }
+            if balances.held < Amount::ZERO {
+                violations.push((currency, InvariantViolation::NegativeHeld(balances.held)));
+            }
+
+            // Pending two-phase deposits are held without being counted as deposited yet
+            let pending: Amount = self
+                .transactions
+                .values()
+                .filter(|record| record.state == TransactionState::Pending && record.currency == currency)
+                .map(|record| record.amount)
+                .sum();
+            // A disputed withdrawal holds funds that already left until it is resolved or charged back
+            let withdrawals_held: Amount = self
+                .disputes
+                .open
+                .iter()
+                .filter(|(tx, _)| {
+                    self.transactions.get(tx).is_some_and(|record| {
+                        record.r#type == TransactionType::Withdrawal && record.currency == currency
+                    })
+                })
+                .map(|(_, held)| *held)
+                .sum();
+            let expected = balances.total_deposited - balances.total_withdrawn - balances.total_fees
+                + balances.net_adjustment
+                + balances.net_transferred
+                - balances.charged_back
+                + pending
+                + withdrawals_held;
+            let total = balances.available + balances.held;
+            if total != expected {
+                violations.push((currency, InvariantViolation::TotalMismatch { total, expected }));
+            }
+        }
+
+        violations
+    }
+
+    pub fn apply_transaction(&mut self, transaction: Transaction, policy: &AccountPolicy) -> ApplyOutcome {
+        // If the transaction doesn't belong to this account we skip it.
+        if transaction.client != self.client {
+            self.skips.wrong_client += 1;
+
+            return ApplyOutcome::Ignored;
+        }
+
+        if policy.check_monotonic_tx {
+            self.check_tx_order(&transaction);
+        }
+
+        if let Some(timestamp) = transaction.timestamp {
+            self.first_timestamp = Some(self.first_timestamp.map_or(timestamp, |first| first.min(timestamp)));
+            self.last_timestamp = Some(self.last_timestamp.map_or(timestamp, |last| last.max(timestamp)));
+        }
+
+        self.expire_disputes(transaction.row, policy);
+        self.transaction_count += 1;
+        self.select_currency(transaction.currency);
+
+        let outcome = self.apply(transaction, policy);
+        self.skips.record(outcome);
+
+        outcome
+    }
+
+    fn apply(&mut self, transaction: Transaction, policy: &AccountPolicy) -> ApplyOutcome {
+        // A locked account doesn't accept new funds movements, but disputes
+        // over its previous transactions still have to be settled.
+        if self.locked
+            && matches!(transaction.r#type, TransactionType::Deposit | TransactionType::Withdrawal)
+        {
+            if transaction.r#type == TransactionType::Withdrawal {
+                self.decline(transaction, DeclineReason::AccountLocked, policy);
+            } else if policy.queue_after_lock {
+                self.pending.push(transaction);
+
+                return ApplyOutcome::Queued;
+            }
+
+            return ApplyOutcome::AccountLocked;
+        }
+
+        // Same goes for closed accounts
+        if self.closed
+            && matches!(transaction.r#type, TransactionType::Deposit | TransactionType::Withdrawal)
+        {
+            if transaction.r#type == TransactionType::Withdrawal {
+                self.decline(transaction, DeclineReason::AccountClosed, policy);
+            }
+
+            return ApplyOutcome::AccountClosed;
+        }
+
+        match transaction.r#type {
+            TransactionType::Deposit => {
+                if self.transactions.contains_key(&transaction.tx) || self.is_retry(&transaction) {
+                    return ApplyOutcome::DuplicateTx;
+                }
+
+                if policy.limits.exceeds_tx_amount(transaction.amount)
+                    || policy.limits.exceeds_balance(self.balances.available, self.balances.held, transaction.amount)
+                {
+                    self.decline(transaction, DeclineReason::LimitExceeded, policy);
+
+                    return ApplyOutcome::LimitExceeded;
+                }
+
+                // Until settled a two-phase deposit only adds to the held funds
+                let (applied, state) = if policy.two_phase_deposits {
+                    (self.move_funds(Amount::ZERO, transaction.amount), TransactionState::Pending)
+                } else {
+                    (self.move_funds(transaction.amount, Amount::ZERO), TransactionState::Applied)
+                };
+
+                if !applied {
+                    return ApplyOutcome::BalanceOverflow;
+                }
+
+                if state == TransactionState::Applied {
+                    self.balances.total_deposited += transaction.amount;
+                }
+                self.record_event(AccountEvent::Deposited, transaction.tx, transaction.row, transaction.amount, policy);
+                self.remember_key(&transaction, policy);
+                self.transactions.insert(
+                    transaction.tx,
+                    TransactionRecord {
+                        r#type: TransactionType::Deposit,
+                        amount: transaction.amount,
+                        state,
+                        currency: transaction.currency,
+                    },
+                );
+
+                ApplyOutcome::Applied
+            }
+            TransactionType::Withdrawal => {
+                if self.transactions.contains_key(&transaction.tx) || self.is_retry(&transaction) {
+                    return ApplyOutcome::DuplicateTx;
+                }
+
+                if policy.limits.exceeds_tx_amount(transaction.amount) {
+                    self.decline(transaction, DeclineReason::LimitExceeded, policy);
+
+                    return ApplyOutcome::LimitExceeded;
+                }
+
+                // The fee is only charged, the stored amount and with it what a dispute holds is the withdrawn one
+                let fee = policy.fees.withdrawal_fee(transaction.amount);
+
+                if !policy.allow_overdraft && self.balances.available < transaction.amount + fee {
+                    self.decline(transaction, DeclineReason::InsufficientFunds, policy);
+
+                    return ApplyOutcome::InsufficientFunds;
+                }
+
+                if !self.move_funds(-(transaction.amount + fee), Amount::ZERO) {
+                    return ApplyOutcome::BalanceOverflow;
+                }
+
+                self.balances.total_withdrawn += transaction.amount;
+                self.balances.total_fees += fee;
+                self.record_event(AccountEvent::Withdrew, transaction.tx, transaction.row, transaction.amount, policy);
+                self.remember_key(&transaction, policy);
+                self.transactions.insert(
+                    transaction.tx,
+                    TransactionRecord {
+                        r#type: TransactionType::Withdrawal,
+                        amount: transaction.amount,
+                        state: TransactionState::Applied,
+                        currency: transaction.currency,
+                    },
+                );
+
+                ApplyOutcome::Applied
+            }
+            TransactionType::Dispute => {
+                // A transaction can only be under one dispute at a time,
+                // repeated dispute rows would hold the same funds twice
+                if self.disputes.open.contains_key(&transaction.tx) {
+                    self.disputes.duplicates += 1;
+                    return ApplyOutcome::Ignored;
+                }
+
+                let record = match self.referenced_record(&transaction) {
+                    Some(record) => record,
+                    None => return ApplyOutcome::UnknownTxReference,
+                };
+
+                // The funds of a charged back or voided transaction are already gone,
+                // and those of a pending deposit aren't available yet
+                if matches!(
+                    record.state,
+                    TransactionState::ChargedBack | TransactionState::Voided | TransactionState::Pending
+                ) {
+                    return ApplyOutcome::Ignored;
+                }
+
+                if policy.single_dispute && record.state == TransactionState::Resolved {
+                    self.disputes.repeated += 1;
+                    return ApplyOutcome::Ignored;
+                }
+
+                // Unless the row disputes only part of the transaction the whole amount is disputed
+                let disputed_amount = if policy.partial_disputes
+                    && transaction.amount.is_positive()
+                    && transaction.amount <= record.amount
+                {
+                    transaction.amount
+                } else {
+                    record.amount
+                };
+
+                // Part of the disputed funds may have already been withdrawn, in that case
+                // only what's left is held and the rest is remembered as a shortfall
+                let shortfall = match record.r#type {
+                    TransactionType::Deposit if policy.hold_partial && self.balances.available < disputed_amount => {
+                        disputed_amount - self.balances.available.max(Amount::ZERO)
+                    }
+                    _ => Amount::ZERO,
+                };
+                let disputed_amount = disputed_amount - shortfall;
+
+                let applied = match record.r#type {
+                    // If there are not enough funds to hold
+                    // we consider the dispute erroneous
+                    // because the disputed funds have already
+                    // been withdrawn by a previous transaction
+                    TransactionType::Deposit if self.balances.available < disputed_amount => {
+                        return ApplyOutcome::InsufficientFunds
+                    }
+                    TransactionType::Deposit => self.move_funds(-disputed_amount, disputed_amount),
+                    // A disputed withdrawal puts the withdrawn funds on hold
+                    // until the dispute is settled, available is left untouched
+                    TransactionType::Withdrawal => self.move_funds(Amount::ZERO, disputed_amount),
+                    _ => return ApplyOutcome::Ignored,
+                };
+
+                if !applied {
+                    return ApplyOutcome::BalanceOverflow;
+                }
+
+                self.disputes.open.insert(transaction.tx, disputed_amount);
+                if policy.dispute_ttl.is_some() {
+                    self.disputes.opened_at.insert(transaction.tx, self.transaction_count);
+                }
+                if shortfall.is_positive() {
+                    self.disputes.shortfalls.insert(transaction.tx, shortfall);
+                }
+                self.disputes.count += 1;
+                self.record_event(AccountEvent::Held, transaction.tx, transaction.row, disputed_amount, policy);
+
+                ApplyOutcome::Applied
+            }
+            TransactionType::Resolve => {
+                let record = match self.referenced_record(&transaction) {
+                    Some(record) => record,
+                    None => return ApplyOutcome::UnknownTxReference,
+                };
+
+                let disputed_amount = match self.disputes.open.get(&transaction.tx) {
+                    Some(amount) => *amount,
+                    None => {
+                        self.settle_undisputed(transaction, policy);
+
+                        return ApplyOutcome::Ignored;
+                    }
+                };
+
+                let applied = match record.r#type {
+                    TransactionType::Deposit => self.move_funds(disputed_amount, -disputed_amount),
+                    // The withdrawal stands, so the held funds are released
+                    TransactionType::Withdrawal => self.move_funds(Amount::ZERO, -disputed_amount),
+                    _ => true,
+                };
+
+                if !applied {
+                    return ApplyOutcome::BalanceOverflow;
+                }
+
+                self.disputes.open.remove(&transaction.tx);
+                self.disputes.shortfalls.remove(&transaction.tx);
+                self.disputes.opened_at.remove(&transaction.tx);
+                self.set_state(transaction.tx, TransactionState::Resolved);
+                self.record_event(AccountEvent::Released, transaction.tx, transaction.row, disputed_amount, policy);
+
+                ApplyOutcome::Applied
+            }
+            TransactionType::Chargeback => {
+                let record = match self.referenced_record(&transaction) {
+                    Some(record) => record,
+                    None => return ApplyOutcome::UnknownTxReference,
+                };
+
+                let disputed_amount = match self.disputes.open.get(&transaction.tx) {
+                    Some(amount) => *amount,
+                    None => {
+                        self.settle_undisputed(transaction, policy);
+
+                        return ApplyOutcome::Ignored;
+                    }
+                };
+
+                let (applied, charged_back) = match record.r#type {
+                    TransactionType::Deposit => (self.move_funds(Amount::ZERO, -disputed_amount), disputed_amount),
+                    // The withdrawal is reversed, so the held funds are given back
+                    TransactionType::Withdrawal => {
+                        (self.move_funds(disputed_amount, -disputed_amount), -disputed_amount)
+                    }
+                    _ => (true, Amount::ZERO),
+                };
+
+                if !applied {
+                    return ApplyOutcome::BalanceOverflow;
+                }
+
+                self.balances.charged_back += charged_back;
+                self.disputes.open.remove(&transaction.tx);
+                self.disputes.opened_at.remove(&transaction.tx);
+                if let Some(shortfall) = self.disputes.shortfalls.remove(&transaction.tx) {
+                    self.balances.chargeback_shortfall += shortfall;
+                }
+                // Even if only part of it was disputed the transaction can't be disputed again
+                self.set_state(transaction.tx, TransactionState::ChargedBack);
+                self.record_event(AccountEvent::ChargedBack, transaction.tx, transaction.row, disputed_amount, policy);
+
+                if !self.locked {
+                    self.locked = true;
+                    self.locked_by = Some(LockCause {
+                        tx: transaction.tx,
+                        row: transaction.row,
+                        amount: disputed_amount,
+                    });
+                    self.record_event(AccountEvent::Locked, transaction.tx, transaction.row, disputed_amount, policy);
+                }
+                self.disputes.chargebacks += 1;
+
+                ApplyOutcome::Applied
+            }
+            // Settle and void finish a pending two-phase deposit
+            TransactionType::Settle | TransactionType::Void => {
+                let record = match self.referenced_record(&transaction) {
+                    Some(record) => record,
+                    None => return ApplyOutcome::UnknownTxReference,
+                };
+
+                if record.state != TransactionState::Pending {
+                    return ApplyOutcome::Ignored;
+                }
+
+                let (applied, state, event) = if transaction.r#type == TransactionType::Settle {
+                    (
+                        self.move_funds(record.amount, -record.amount),
+                        TransactionState::Applied,
+                        AccountEvent::Settled,
+                    )
+                } else {
+                    (self.move_funds(Amount::ZERO, -record.amount), TransactionState::Voided, AccountEvent::Voided)
+                };
+
+                if !applied {
+                    return ApplyOutcome::BalanceOverflow;
+                }
+
+                if state == TransactionState::Applied {
+                    self.balances.total_deposited += record.amount;
+                }
+                self.set_state(transaction.tx, state);
+                self.record_event(event, transaction.tx, transaction.row, record.amount, policy);
+
+                ApplyOutcome::Applied
+            }
+            // Adjustments are operator corrections, they go through whatever the state of the account
+            // and its balance and aren't stored, so there's nothing to dispute later on
+            TransactionType::Adjustment => {
+                if !self.move_funds(transaction.amount, Amount::ZERO) {
+                    return ApplyOutcome::BalanceOverflow;
+                }
+
+                self.adjustments += 1;
+                self.balances.net_adjustment += transaction.amount;
+                self.record_event(AccountEvent::Adjusted, transaction.tx, transaction.row, transaction.amount, policy);
+
+                ApplyOutcome::Applied
+            }
+            // Closing doesn't wipe the account, its funds stay so disputes can still be settled
+            TransactionType::Close => {
+                if self.locked {
+                    self.locked_close_attempts += 1;
+
+                    ApplyOutcome::Ignored
+                } else if self.closed {
+                    ApplyOutcome::Ignored
+                } else {
+                    self.closed = true;
+                    self.record_event(AccountEvent::Closed, transaction.tx, transaction.row, Amount::ZERO, policy);
+
+                    ApplyOutcome::Applied
+                }
+            }
+            // Administrative transaction lifting the lock left by a chargeback
+            TransactionType::Unlock if self.locked => {
+                self.locked = false;
+                self.locked_by = None;
+                self.unlocks += 1;
+                self.record_event(AccountEvent::Unlocked, transaction.tx, transaction.row, Amount::ZERO, policy);
+                self.released.append(&mut self.pending);
+
+                ApplyOutcome::Applied
+            }
+            _ => {
+                // If a transaction record was malformed, we ignore it.
+                ApplyOutcome::Ignored
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct ClientState {
+    client: ClientId,
+    currency: Currency,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    total_deposited: Amount,
+    total_withdrawn: Amount,
+    total_fees: Amount,
+    net_adjustment: Amount,
+    dispute_count: u32,
+    chargeback_count: u32,
+    locked_by: Option<LockCause>,
+    closed: bool,
+    chargeback_shortfall: Amount,
+    pending: Amount,
+    skips: SkipCounters,
+    tx_order: TxOrderAnomalies,
+    first_timestamp: Option<i64>,
+    last_timestamp: Option<i64>,
+}
+
+/// Row of an account as the CSV output writes it, for `diff` to read it back. The columns of the extended output
+/// and the currency may be missing, like in the default output.
+#[derive(Debug, Deserialize)]
+struct ClientStateRow {
+    client: ClientId,
+    #[serde(deserialize_with = "amount_deserializer")]
+    available: Amount,
+    #[serde(deserialize_with = "amount_deserializer")]
+    held: Amount,
+    #[serde(deserialize_with = "amount_deserializer")]
+    total: Amount,
+    locked: bool,
+    #[serde(default, deserialize_with = "amount_deserializer")]
+    total_deposited: Amount,
+    #[serde(default, deserialize_with = "amount_deserializer")]
+    total_withdrawn: Amount,
+    #[serde(default)]
+    dispute_count: u32,
+    #[serde(default)]
+    chargeback_count: u32,
+    #[serde(default)]
+    closed: bool,
+    #[serde(default, deserialize_with = "amount_deserializer")]
+    chargeback_shortfall: Amount,
+    #[serde(default, deserialize_with = "amount_deserializer")]
+    pending: Amount,
+    #[serde(default, deserialize_with = "amount_deserializer")]
+    fees: Amount,
+    #[serde(default, deserialize_with = "amount_deserializer")]
+    net_adjustment: Amount,
+    #[serde(default, deserialize_with = "timestamp_deserializer")]
+    first_timestamp: Option<i64>,
+    #[serde(default, deserialize_with = "timestamp_deserializer")]
+    last_timestamp: Option<i64>,
+    #[serde(default, deserialize_with = "currency_deserializer")]
+    currency: Currency,
+}
+
+impl<'de> Deserialize<'de> for ClientState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let row = ClientStateRow::deserialize(deserializer)?;
+
+        Ok(ClientState {
+            client: row.client,
+            currency: row.currency,
+            available: row.available,
+            held: row.held,
+            total: row.total,
+            locked: row.locked,
+            total_deposited: row.total_deposited,
+            total_withdrawn: row.total_withdrawn,
+            total_fees: row.fees,
+            net_adjustment: row.net_adjustment,
+            dispute_count: row.dispute_count,
+            chargeback_count: row.chargeback_count,
+            closed: row.closed,
+            chargeback_shortfall: row.chargeback_shortfall,
+            pending: row.pending,
+            first_timestamp: row.first_timestamp,
+            last_timestamp: row.last_timestamp,
+            ..ClientState::default()
+        })
+    }
+}
+
+impl ClientState {
+    fn header(options: OutputOptions) -> String {
+        let mut names: Vec<&str> = options.schema.columns().iter().map(|column| column.name()).collect();
+        if options.with_currency {
+            names.push("currency");
+        }
+
+        names.join(",")
+    }
+
+    fn formatted(&self, options: OutputOptions) -> FormattedClientState<'_> {
+        FormattedClientState(self, options)
+    }
+}
+
+impl From<ClientAccount> for ClientState {
+    fn from(ca: ClientAccount) -> Self {
+        ClientState::from(&ca)
+    }
+}
+
+/// State of the account in its currently selected currency.
+impl From<&ClientAccount> for ClientState {
+    fn from(ca: &ClientAccount) -> Self {
+        ClientState {
+            client: ca.client,
+            currency: ca.currency,
+            available: ca.balances.available,
+            held: ca.balances.held,
+            total: ca.balances.available + ca.balances.held,
+            locked: ca.locked,
+            total_deposited: ca.balances.total_deposited,
+            total_withdrawn: ca.balances.total_withdrawn,
+            total_fees: ca.balances.total_fees,
+            net_adjustment: ca.balances.net_adjustment,
+            dispute_count: ca.disputes.count,
+            chargeback_count: ca.disputes.chargebacks,
+            locked_by: ca.locked_by,
+            closed: ca.closed,
+            chargeback_shortfall: ca.balances.chargeback_shortfall,
+            pending: ca
+                .pending
+                .iter()
+                .filter(|deposit| deposit.currency == ca.currency)
+                .map(|deposit| deposit.amount)
+                .sum(),
+            skips: ca.skips,
+            tx_order: ca.tx_order.anomalies,
+            first_timestamp: ca.first_timestamp,
+            last_timestamp: ca.last_timestamp,
+        }
+    }
+}
+
+impl fmt::Display for ClientState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.formatted(OutputOptions::default()))
+    }
+}
+
+/// Column of the account states an output can hold, but for the currency, which is appended to the rows of the
+/// outputs with some.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Column {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    TotalDeposited,
+    TotalWithdrawn,
+    DisputeCount,
+    ChargebackCount,
+    Closed,
+    ChargebackShortfall,
+    Pending,
+    Fees,
+    NetAdjustment,
+    FirstTimestamp,
+    LastTimestamp,
+    /// Transaction of the chargeback that locked the account last, if any.
+    LockedByTx,
+}
+
+impl Column {
+    const ALL: [Column; 17] = [
+        Column::Client,
+        Column::Available,
+        Column::Held,
+        Column::Total,
+        Column::Locked,
+        Column::TotalDeposited,
+        Column::TotalWithdrawn,
+        Column::DisputeCount,
+        Column::ChargebackCount,
+        Column::Closed,
+        Column::ChargebackShortfall,
+        Column::Pending,
+        Column::Fees,
+        Column::NetAdjustment,
+        Column::FirstTimestamp,
+        Column::LastTimestamp,
+        Column::LockedByTx,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Column::Client => "client",
+            Column::Available => "available",
+            Column::Held => "held",
+            Column::Total => "total",
+            Column::Locked => "locked",
+            Column::TotalDeposited => "total_deposited",
+            Column::TotalWithdrawn => "total_withdrawn",
+            Column::DisputeCount => "dispute_count",
+            Column::ChargebackCount => "chargeback_count",
+            Column::Closed => "closed",
+            Column::ChargebackShortfall => "chargeback_shortfall",
+            Column::Pending => "pending",
+            Column::Fees => "fees",
+            Column::NetAdjustment => "net_adjustment",
+            Column::FirstTimestamp => "first_timestamp",
+            Column::LastTimestamp => "last_timestamp",
+            Column::LockedByTx => "locked_by_tx",
+        }
+    }
+
+    /// Whether the JSON outputs write the column as a string rather than a number or boolean.
+    fn is_text(self) -> bool {
+        matches!(self, Column::FirstTimestamp | Column::LastTimestamp)
+    }
+}
+
+/// Columns written for each account and their order, without any column repeated. Kept in place rather than in a
+/// `Vec`, so the options holding the schema stay `Copy`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct OutputSchema {
+    columns: [Column; Column::ALL.len()],
+    len: usize,
+}
+
+impl OutputSchema {
+    /// The columns existing consumers of the output parse.
+    const DEFAULT: OutputSchema = OutputSchema::of(&[
+        Column::Client,
+        Column::Available,
+        Column::Held,
+        Column::Total,
+        Column::Locked,
+    ]);
+    /// The default columns followed by the lifetime counters of `--extended-output`.
+    const EXTENDED: OutputSchema = OutputSchema::of(&[
+        Column::Client,
+        Column::Available,
+        Column::Held,
+        Column::Total,
+        Column::Locked,
+        Column::TotalDeposited,
+        Column::TotalWithdrawn,
+        Column::DisputeCount,
+        Column::ChargebackCount,
+        Column::Closed,
+        Column::ChargebackShortfall,
+        Column::Pending,
+        Column::Fees,
+        Column::NetAdjustment,
+        Column::FirstTimestamp,
+        Column::LastTimestamp,
+    ]);
+
+    const fn of(columns: &[Column]) -> Self {
+        let mut schema = OutputSchema { columns: [Column::Client; Column::ALL.len()], len: columns.len() };
+        let mut index = 0;
+        while index < columns.len() {
+            schema.columns[index] = columns[index];
+            index += 1;
+        }
+
+        schema
+    }
+
+    /// Reads the comma separated column names of `--columns`.
+    fn parse(list: &str) -> Result<Self, String> {
+        let mut columns = Vec::new();
+        for name in list.split(',').map(str::trim) {
+            let column = Column::ALL.iter().find(|column| column.name() == name).ok_or_else(|| {
+                let names: Vec<&str> = Column::ALL.iter().map(|column| column.name()).collect();
+                format!("unknown column {:?}, the columns are {}", name, names.join(","))
+            })?;
+            if columns.contains(column) {
+                return Err(format!("column {} is selected more than once", name));
+            }
+            columns.push(*column);
+        }
+
+        Ok(OutputSchema::of(&columns))
+    }
+
+    fn columns(&self) -> &[Column] {
+        &self.columns[..self.len]
+    }
+}
+
+impl Default for OutputSchema {
+    fn default() -> Self {
+        OutputSchema::DEFAULT
+    }
+}
+
+/// Which columns of the account states are written and how their amounts look.
+#[derive(Debug, Default, Copy, Clone)]
+struct OutputOptions {
+    /// Columns written for each account, but for the currency.
+    schema: OutputSchema,
+    /// Append the currency of each row after every other column, so the rest keep their position.
+    with_currency: bool,
+    amounts: AmountFormat,
+    format: OutputFormat,
+}
+
+/// How the rows of the account states are written.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+enum OutputFormat {
+    #[default]
+    Csv,
+    /// A single JSON array holding an object per row.
+    Json,
+    /// An object per line, like the JSON Lines inputs.
+    Ndjson,
+    /// Columns aligned for a terminal, the numbers with thousands separators, only meant to be read by people.
+    Table,
+}
+
+impl OutputFormat {
+    /// Formats by the name given to `--output-format`.
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(OutputFormat::Csv),
+            "json" => Some(OutputFormat::Json),
+            "ndjson" => Some(OutputFormat::Ndjson),
+            "table" => Some(OutputFormat::Table),
+            _ => None,
+        }
+    }
+
+    /// Extension of the files written in the format.
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Table => "txt",
+        }
+    }
+}
+
+/// Accounts kept in the output, every account being processed all the same since any row may reference them.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct OutputFilter {
+    /// Only the accounts of these clients.
+    clients: Option<HashSet<ClientId>>,
+    /// Only the locked accounts, on top of the clients if both are given.
+    only_locked: bool,
+}
+
+impl OutputFilter {
+    fn keeps(&self, state: &ClientState) -> bool {
+        let kept_client = self.clients.as_ref().is_none_or(|clients| clients.contains(&state.client));
+
+        kept_client && (state.locked || !self.only_locked)
+    }
+
+    /// The accounts the filter keeps, borrowed as they are if it keeps them all.
+    fn apply<'a>(&self, accounts: &'a [Vec<ClientState>]) -> Cow<'a, [Vec<ClientState>]> {
+        if *self == OutputFilter::default() {
+            return Cow::Borrowed(accounts);
+        }
+
+        let kept = accounts.iter().map(|group| group.iter().filter(|state| self.keeps(state)).cloned().collect());
+        Cow::Owned(kept.collect())
+    }
+}
+
+struct FormattedClientState<'a>(&'a ClientState, OutputOptions);
+
+impl FormattedClientState<'_> {
+    /// Hands each column of the row to `field`, in the order of `ClientState::header`, without allocating but for
+    /// the timestamps.
+    fn for_each_field(&self, mut field: impl FnMut(&dyn fmt::Display)) {
+        let FormattedClientState(state, options) = *self;
+        let amount = |amount: Amount| amount.format(options.amounts);
+        let timestamp = |timestamp: Option<i64>| timestamp.map(timestamp::format).unwrap_or_default();
+
+        for column in options.schema.columns() {
+            match column {
+                Column::Client => field(&state.client),
+                Column::Available => field(&amount(state.available)),
+                Column::Held => field(&amount(state.held)),
+                Column::Total => field(&amount(state.total)),
+                Column::Locked => field(&state.locked),
+                Column::TotalDeposited => field(&amount(state.total_deposited)),
+                Column::TotalWithdrawn => field(&amount(state.total_withdrawn)),
+                Column::DisputeCount => field(&state.dispute_count),
+                Column::ChargebackCount => field(&state.chargeback_count),
+                Column::Closed => field(&state.closed),
+                Column::ChargebackShortfall => field(&amount(state.chargeback_shortfall)),
+                Column::Pending => field(&amount(state.pending)),
+                Column::Fees => field(&amount(state.total_fees)),
+                Column::NetAdjustment => field(&amount(state.net_adjustment)),
+                Column::FirstTimestamp => field(&timestamp(state.first_timestamp)),
+                Column::LastTimestamp => field(&timestamp(state.last_timestamp)),
+                Column::LockedByTx => match state.locked_by {
+                    Some(cause) => field(&cause.tx),
+                    None => field(&""),
+                },
+            }
+        }
+
+        if options.with_currency {
+            field(&state.currency);
+        }
+    }
+
+    /// Columns of the row, in the order of `ClientState::header`.
+    fn fields(&self) -> Vec<String> {
+        let mut fields = Vec::new();
+        self.for_each_field(|field| fields.push(field.to_string()));
+
+        fields
+    }
+}
+
+impl FormattedClientState<'_> {
+    /// The row as a JSON object keyed by the columns of the header, the amounts and counts as numbers and the
+    /// empty timestamps as `null`.
+    fn json(&self) -> String {
+        let header = ClientState::header(self.1);
+        let fields = self.fields();
+        // The currency, when written, is the one column past those of the schema
+        let mut columns = self.1.schema.columns().iter().map(|column| Some(*column)).chain([None]);
+        let values = header.split(',').zip(&fields).map(|(name, field)| {
+            let value = match columns.next().flatten() {
+                _ if field.is_empty() => json::Value::Null,
+                Some(column) if !column.is_text() => json::Value::Raw(field),
+                _ => json::Value::String(field),
+            };
+            (name, value)
+        });
+
+        let mut object = String::new();
+        json::write_object(&mut object, values);
+
+        object
+    }
+}
+
+impl fmt::Display for FormattedClientState<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.fields().join(","))
+    }
+}
+
+/// Both halves of a transfer between clients owned by different workers, the receiving half
+/// reports whether it can take the funds and the sending half decides the final outcome.
+#[derive(Debug)]
+struct TransferOut {
+    transaction: Transaction,
+    incoming_check: oneshot::Receiver<ApplyOutcome>,
+    outcome: oneshot::Sender<ApplyOutcome>,
+}
+
+#[derive(Debug)]
+struct TransferIn {
+    transaction: Transaction,
+    incoming_check: oneshot::Sender<ApplyOutcome>,
+    outcome: oneshot::Receiver<ApplyOutcome>,
+}
+
+/// What the receiving half of a merge answers, a refused merge hands the merged account back.
+type MergeReply = Result<(), (ApplyOutcome, MergedAccount)>;
+
+/// Both halves of a merge between clients owned by different workers, the merged half
+/// hands its account over and the receiving half decides whether it takes it.
+#[derive(Debug)]
+struct MergeOut {
+    transaction: Transaction,
+    merged: oneshot::Sender<Result<MergedAccount, ApplyOutcome>>,
+    reply: oneshot::Receiver<MergeReply>,
+}
+
+#[derive(Debug)]
+struct MergeIn {
+    transaction: Transaction,
+    merged: oneshot::Receiver<Result<MergedAccount, ApplyOutcome>>,
+    reply: oneshot::Sender<MergeReply>,
+}
+
+/// Items sent from the reader to the workers, transfer and merge halves are boxed since
+/// they are rare and would otherwise make every message in the channel buffers bigger.
+#[derive(Debug)]
+enum WorkerMessage {
+    Transaction(Transaction),
+    TransferOut(Box<TransferOut>),
+    TransferIn(Box<TransferIn>),
+    MergeOut(Box<MergeOut>),
+    MergeIn(Box<MergeIn>),
+    /// Asks for the state of every account of the worker, after the rows sent before.
+    Snapshot(oneshot::Sender<Vec<ClientState>>),
+}
+
+impl WorkerMessage {
+    fn transaction(&self) -> Option<&Transaction> {
+        match self {
+            WorkerMessage::Transaction(transaction) => Some(transaction),
+            WorkerMessage::TransferOut(transfer) => Some(&transfer.transaction),
+            WorkerMessage::TransferIn(transfer) => Some(&transfer.transaction),
+            WorkerMessage::MergeOut(merge) => Some(&merge.transaction),
+            WorkerMessage::MergeIn(merge) => Some(&merge.transaction),
+            WorkerMessage::Snapshot(_) => None,
+        }
+    }
+}
+
+/// Amount of transactions that ended up with each `ApplyOutcome`.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+struct OutcomeCounters {
+    applied: u64,
+    insufficient_funds: u64,
+    duplicate_tx: u64,
+    account_locked: u64,
+    account_closed: u64,
+    limit_exceeded: u64,
+    queued: u64,
+    unknown_tx_reference: u64,
+    balance_overflow: u64,
+    ignored: u64,
+}
+
+impl OutcomeCounters {
+    fn record(&mut self, outcome: ApplyOutcome) {
+        match outcome {
+            ApplyOutcome::Applied => self.applied += 1,
+            ApplyOutcome::InsufficientFunds => self.insufficient_funds += 1,
+            ApplyOutcome::DuplicateTx => self.duplicate_tx += 1,
+            ApplyOutcome::AccountLocked => self.account_locked += 1,
+            ApplyOutcome::AccountClosed => self.account_closed += 1,
+            ApplyOutcome::LimitExceeded => self.limit_exceeded += 1,
+            ApplyOutcome::Queued => self.queued += 1,
+            ApplyOutcome::UnknownTxReference => self.unknown_tx_reference += 1,
+            ApplyOutcome::BalanceOverflow => self.balance_overflow += 1,
+            ApplyOutcome::Ignored => self.ignored += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &OutcomeCounters) {
+        self.applied += other.applied;
+        self.insufficient_funds += other.insufficient_funds;
+        self.duplicate_tx += other.duplicate_tx;
+        self.account_locked += other.account_locked;
+        self.account_closed += other.account_closed;
+        self.limit_exceeded += other.limit_exceeded;
+        self.queued += other.queued;
+        self.unknown_tx_reference += other.unknown_tx_reference;
+        self.balance_overflow += other.balance_overflow;
+        self.ignored += other.ignored;
+    }
+}
+
+/// Rows read of each `TransactionType`, in the order of `TransactionType::ALL`.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+struct TypeCounters([u64; TransactionType::ALL.len()]);
+
+impl TypeCounters {
+    fn record(&mut self, r#type: TransactionType) {
+        if let Some(index) = TransactionType::ALL.iter().position(|other| *other == r#type) {
+            self.0[index] += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &TypeCounters) {
+        for (count, other) in self.0.iter_mut().zip(other.0) {
+            *count += other;
+        }
+    }
+
+    fn count(&self, r#type: TransactionType) -> u64 {
+        self.iter().find(|(other, _)| *other == r#type).map_or(0, |(_, count)| count)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (TransactionType, u64)> + '_ {
+        TransactionType::ALL.iter().copied().zip(self.0.iter().copied())
+    }
+}
+
+/// Counters about the processed transactions that don't make it into the output
+/// but are worth reporting once the run finishes.
+#[derive(Debug, Default, Copy, Clone)]
+struct Diagnostics {
+    outcomes: OutcomeCounters,
+    duplicate_disputes: u64,
+    repeated_disputes: u64,
+    expired_disputes: u64,
+    retried_transactions: u64,
+    locked_close_attempts: u64,
+    adjustments: u64,
+    reused_tx_ids: u64,
+    invalid_amounts: u64,
+    truncated_amounts: u64,
+    imprecise_amounts: u64,
+    non_finite_amounts: u64,
+    scientific_amounts: u64,
+    mismatched_disputes: u64,
+    currency_mismatches: u64,
+    reference_amounts: u64,
+    undisputed_resolves: u64,
+    undisputed_chargebacks: u64,
+    pending_deposits: u64,
+    skips: SkipCounters,
+    rows: u64,
+    /// Rows of each type, as parsed by the reader.
+    types: TypeCounters,
+    /// Rows in a named currency, which give the streamed output its currency column.
+    currency_rows: u64,
+    malformed_rows: u64,
+    /// Rows whose type was only read through an alias of its name.
+    aliased_types: u64,
+    /// Rows invalid UTF-8 was replaced in, with `--encoding utf8-lossy`.
+    lossy_rows: u64,
+    /// Rows timestamped earlier than a row read before them, only counted when checking the chronology.
+    out_of_order_timestamps: u64,
+    /// Blank and comment lines of the inputs.
+    skipped_lines: u64,
+    /// Rows repeating the header of their input, not counted as rows.
+    repeated_headers: u64,
+    /// Rows of other clients than the one inspected, which are counted as rows but not parsed.
+    other_client_rows: u64,
+    /// Reading stopped at the row or byte limit before the end of the input.
+    truncated: bool,
+}
+
+impl Diagnostics {
+    fn record_account(&mut self, account: &ClientAccount) {
+        self.duplicate_disputes += account.disputes.duplicates as u64;
+        self.repeated_disputes += account.disputes.repeated as u64;
+        self.expired_disputes += account.disputes.expired as u64;
+        self.retried_transactions += account.idempotency.retried as u64;
+        self.locked_close_attempts += account.locked_close_attempts as u64;
+        self.adjustments += account.adjustments as u64;
+        self.currency_mismatches += account.currency_mismatches as u64;
+        self.undisputed_resolves += account.disputes.undisputed_resolves as u64;
+        self.undisputed_chargebacks += account.disputes.undisputed_chargebacks as u64;
+        self.pending_deposits += account.pending.len() as u64;
+        self.skips.merge(&account.skips);
+    }
+
+    fn merge(&mut self, other: &Diagnostics) {
+        self.outcomes.merge(&other.outcomes);
+        self.duplicate_disputes += other.duplicate_disputes;
+        self.repeated_disputes += other.repeated_disputes;
+        self.expired_disputes += other.expired_disputes;
+        self.retried_transactions += other.retried_transactions;
+        self.locked_close_attempts += other.locked_close_attempts;
+        self.adjustments += other.adjustments;
+        self.reused_tx_ids += other.reused_tx_ids;
+        self.invalid_amounts += other.invalid_amounts;
+        self.truncated_amounts += other.truncated_amounts;
+        self.imprecise_amounts += other.imprecise_amounts;
+        self.non_finite_amounts += other.non_finite_amounts;
+        self.scientific_amounts += other.scientific_amounts;
+        self.mismatched_disputes += other.mismatched_disputes;
+        self.currency_mismatches += other.currency_mismatches;
+        self.reference_amounts += other.reference_amounts;
+        self.undisputed_resolves += other.undisputed_resolves;
+        self.undisputed_chargebacks += other.undisputed_chargebacks;
+        self.pending_deposits += other.pending_deposits;
+        self.skips.merge(&other.skips);
+        self.rows += other.rows;
+        self.types.merge(&other.types);
+        self.currency_rows += other.currency_rows;
+        self.malformed_rows += other.malformed_rows;
+        self.aliased_types += other.aliased_types;
+        self.lossy_rows += other.lossy_rows;
+        self.out_of_order_timestamps += other.out_of_order_timestamps;
+        self.skipped_lines += other.skipped_lines;
+        self.repeated_headers += other.repeated_headers;
+        self.other_client_rows += other.other_client_rows;
+        self.truncated |= other.truncated;
+    }
+
+    /// Writes what was counted, counters of a feature left off by the options only being written with `-v`
+    /// when they are zero, like those that are zero by default.
+    fn report(&self, reader: &ReaderOptions, policy: &AccountPolicy) {
+        let outcomes = &self.outcomes;
+        let counter = |count: u64, enabled: bool, message: String| {
+            if count > 0 || enabled {
+                log::info!("{}", message);
+            } else {
+                log::verbose!("{}", message);
+            }
+        };
+
+        if self.truncated {
+            log::info!("Output truncated, reading stopped at the --limit-rows or --limit-bytes limit");
+        }
+        log::info!("Processed {} row/s", self.rows);
+        counter(self.malformed_rows, false, format!("{} row/s skipped due to parse errors", self.malformed_rows));
+        counter(self.skipped_lines, false, format!("{} blank or comment line/s skipped", self.skipped_lines));
+        counter(self.repeated_headers, false, format!("{} repeated header row/s skipped", self.repeated_headers));
+        counter(
+            outcomes.queued + self.pending_deposits,
+            policy.queue_after_lock,
+            format!("Queued {} deposit/s on locked accounts, {} still pending", outcomes.queued, self.pending_deposits),
+        );
+        counter(self.duplicate_disputes, false, format!("Ignored {} duplicate dispute/s", self.duplicate_disputes));
+        counter(
+            self.repeated_disputes,
+            policy.single_dispute,
+            format!("Rejected {} dispute/s over an already resolved tx", self.repeated_disputes),
+        );
+        counter(
+            self.expired_disputes,
+            policy.dispute_ttl.is_some(),
+            format!("Released {} expired dispute/s", self.expired_disputes),
+        );
+        counter(
+            self.retried_transactions,
+            false,
+            format!("Rejected {} retried transaction/s with a known idempotency key", self.retried_transactions),
+        );
+        counter(
+            self.locked_close_attempts,
+            false,
+            format!("Ignored {} attempt/s to close a locked account", self.locked_close_attempts),
+        );
+        counter(self.adjustments, false, format!("Applied {} balance adjustment/s", self.adjustments));
+        counter(
+            self.reused_tx_ids,
+            reader.unique_tx_ids,
+            format!("Dropped {} transaction/s for tx id reuse", self.reused_tx_ids),
+        );
+        counter(
+            self.invalid_amounts,
+            false,
+            format!("Rejected {} transaction/s with an invalid amount", self.invalid_amounts),
+        );
+        counter(
+            self.truncated_amounts,
+            reader.lenient_amounts,
+            format!("Truncated {} amount/s to four decimal places", self.truncated_amounts),
+        );
+        counter(
+            self.imprecise_amounts,
+            reader.strict_precision,
+            format!("Rejected {} transaction/s with more than four decimal places", self.imprecise_amounts),
+        );
+        counter(
+            self.non_finite_amounts,
+            false,
+            format!("Rejected {} transaction/s with a NaN or infinite amount", self.non_finite_amounts),
+        );
+        counter(
+            self.scientific_amounts,
+            reader.reject_scientific,
+            format!("Rejected {} transaction/s with an amount in scientific notation", self.scientific_amounts),
+        );
+        counter(
+            self.mismatched_disputes,
+            false,
+            format!("Dropped {} dispute/s referencing a tx of another client", self.mismatched_disputes),
+        );
+        counter(
+            self.currency_mismatches,
+            false,
+            format!("Dropped {} dispute/s and the like in another currency than their tx", self.currency_mismatches),
+        );
+        counter(
+            self.reference_amounts,
+            reader.strict,
+            format!("Found {} dispute/s and the like carrying an amount", self.reference_amounts),
+        );
+        counter(
+            self.out_of_order_timestamps,
+            reader.check_chronology,
+            format!("Found {} row/s timestamped earlier than a previous row", self.out_of_order_timestamps),
+        );
+        counter(
+            self.aliased_types,
+            false,
+            format!("Read {} row/s whose type is an alias, like withdraw for withdrawal", self.aliased_types),
+        );
+        counter(
+            self.lossy_rows,
+            reader.encoding == Encoding::Utf8Lossy,
+            format!("Replaced invalid UTF-8 in {} row/s", self.lossy_rows),
+        );
+        counter(
+            self.undisputed_resolves + self.undisputed_chargebacks,
+            false,
+            format!(
+                "Ignored {} resolve/s and {} chargeback/s without a prior dispute",
+                self.undisputed_resolves, self.undisputed_chargebacks
+            ),
+        );
+    }
+}
+
+/// Hash of the final balances, the same for the same accounts whatever the amount of workers, the order they are
+/// done in or the machine. Accounts go in sorted by client and currency, each as its client, currency, the minor
+/// units of its available, held and total funds in little endian and its locked flag, all of a fixed width.
+fn state_hash<'a>(states: impl Iterator<Item = &'a ClientState>) -> u64 {
+    let mut states: Vec<&ClientState> = states.collect();
+    states.sort_unstable_by_key(|state| (state.client, state.currency));
+
+    let mut hasher = XxHash64::with_seed(0);
+    // Tells the encoding apart from any later one
+    hasher.write(b"transactioner state v1");
+    for state in states {
+        hasher.write(&state.client.to_le_bytes());
+        hasher.write(&state.currency.0);
+        for amount in [state.available, state.held, state.total] {
+            hasher.write(&amount.0.to_le_bytes());
+        }
+        hasher.write_u8(state.locked as u8);
+    }
+
+    hasher.finish()
+}
+
+/// Accounts written at the end of a run, added up for its summary.
+#[derive(Debug, Default)]
+struct AccountTotals {
+    accounts: u64,
+    locked_accounts: u64,
+    /// Available and held funds of every account added up by currency.
+    balances: Vec<(Currency, Amount, Amount)>,
+}
+
+impl AccountTotals {
+    fn new(accounts: &[Vec<ClientState>]) -> Self {
+        let mut totals = AccountTotals {
+            accounts: client_states(accounts).count() as u64,
+            locked_accounts: client_states(accounts).filter(|state| state.locked).count() as u64,
+            balances: Vec::new(),
+        };
+        for state in accounts.iter().flatten() {
+            totals.add_balances(state.currency, state.available, state.held);
+        }
+
+        totals
+    }
+
+    /// Adds the states of the currencies of a single client.
+    fn add_account(&mut self, states: &[ClientState]) {
+        self.accounts += 1;
+        self.locked_accounts += states.first().is_some_and(|state| state.locked) as u64;
+        for state in states {
+            self.add_balances(state.currency, state.available, state.held);
+        }
+    }
+
+    /// Adds up the totals of another part of the accounts, like a shard written by a worker.
+    fn merge(&mut self, other: &AccountTotals) {
+        self.accounts += other.accounts;
+        self.locked_accounts += other.locked_accounts;
+        for (currency, available, held) in &other.balances {
+            self.add_balances(*currency, *available, *held);
+        }
+    }
+
+    fn add_balances(&mut self, currency: Currency, available: Amount, held: Amount) {
+        match self.balances.iter_mut().find(|(total_currency, _, _)| *total_currency == currency) {
+            Some((_, total_available, total_held)) => {
+                *total_available += available;
+                *total_held += held;
+            }
+            None => {
+                self.balances.push((currency, available, held));
+                self.balances.sort_by_key(|(currency, _, _)| *currency);
+            }
+        }
+    }
+}
+
+/// Figures of a whole run, reported on stderr as a table once the accounts are written, and saved as a JSON object
+/// by `--summary-json`.
+#[derive(Debug)]
+struct Summary {
+    diagnostics: Diagnostics,
+    totals: AccountTotals,
+    duration: Duration,
+}
+
+impl Summary {
+    fn new(diagnostics: Diagnostics, mut totals: AccountTotals, duration: Duration) -> Self {
+        if totals.balances.is_empty() {
+            totals.balances.push((Currency::NONE, Amount::ZERO, Amount::ZERO));
+        }
+
+        Summary { diagnostics, totals, duration }
+    }
+
+    /// Names and values of the figures, the balances of a currency being suffixed by its code.
+    fn fields(&self) -> Vec<(String, String)> {
+        let diagnostics = &self.diagnostics;
+        let mut fields = vec![
+            ("rows".to_string(), diagnostics.rows.to_string()),
+            ("malformed_rows".to_string(), diagnostics.malformed_rows.to_string()),
+            ("skipped_lines".to_string(), diagnostics.skipped_lines.to_string()),
+            ("repeated_headers".to_string(), diagnostics.repeated_headers.to_string()),
+        ];
+        for (r#type, count) in diagnostics.types.iter() {
+            fields.push((format!("{}_rows", r#type.name()), count.to_string()));
+        }
+
+        fields.push(("accounts".to_string(), self.totals.accounts.to_string()));
+        fields.push(("locked_accounts".to_string(), self.totals.locked_accounts.to_string()));
+        for (currency, available, held) in &self.totals.balances {
+            let suffix = if *currency == Currency::NONE { String::new() } else { format!("_{}", currency) };
+            fields.push((format!("available{}", suffix), available.to_string()));
+            fields.push((format!("held{}", suffix), held.to_string()));
+        }
+
+        let skips = diagnostics.skips.to_string();
+        for (reason, count) in SkipCounters::HEADER.split(',').zip(skips.split(',')) {
+            fields.push((format!("skipped_{}", reason), count.to_string()));
+        }
+        fields.push(("duration_seconds".to_string(), format!("{:.3}", self.duration.as_secs_f64())));
+
+        fields
+    }
+
+    /// Writes the figures as a table, the counters of rows and skips that are zero only with `-v`.
+    fn report(&self) {
+        let fields = self.fields();
+        let width = fields.iter().map(|(name, _)| name.len()).max().unwrap_or_default();
+        let counter =
+            |name: &str| name.ends_with("_rows") || name.starts_with("skipped_") || name == "repeated_headers";
+
+        log::info!("Summary:");
+        for (name, value) in fields {
+            if counter(&name) && value == "0" {
+                log::verbose!("  {:width$}  {:>12}", name, value, width = width);
+            } else {
+                log::info!("  {:width$}  {:>12}", name, value, width = width);
+            }
+        }
+    }
+
+    /// Writes the figures to `path` as a single JSON object, every value being a number.
+    fn write_json(&self, path: &Path) -> io::Result<()> {
+        let fields = self.fields();
+        let mut object = String::new();
+        json::write_object(&mut object, fields.iter().map(|(name, value)| (name.as_str(), json::Value::Raw(value))));
+        object.push('\n');
+
+        fs::write(path, object)
+    }
+}
+
+/// What `--manifest` tells of a run, for whoever scheduled it to know how it ended and what it wrote without
+/// reading its stderr. It's written however the run ends, from the inputs and outputs given by the options, the
+/// outputs being those found at their path once the run is over.
+struct Manifest {
+    path: Option<PathBuf>,
+    inputs: Vec<PathBuf>,
+    /// Files written by the options, by the name of their flag, a directory standing for the files in it.
+    outputs: Vec<(&'static str, PathBuf)>,
+    workers: Option<usize>,
+    /// Figures of the run, only known once it went through the whole input.
+    summary: Option<Summary>,
+    started: Instant,
+}
+
+impl Manifest {
+    fn new(options: &Options, started: Instant) -> Self {
+        let mut outputs = Vec::new();
+        let flags = [
+            ("output", &options.output_path),
+            ("output-shards", &options.output_shards),
+            ("convert", &options.convert_output),
+            ("report-declined", &options.report_declined),
+            ("report-rejected", &options.report_rejected),
+            ("report-locked", &options.report_locked),
+            ("report-mismatched-disputes", &options.report_mismatched_disputes),
+            ("report-open-disputes", &options.report_open_disputes),
+            ("report-skips", &options.report_skips),
+            ("audit-log", &options.audit_log),
+            ("trace-output", &options.trace_output),
+            ("summary-json", &options.summary_json),
+            ("state-hash-file", &options.state_hash_file),
+            ("save-offset", &options.save_offset),
+        ];
+        for (flag, path) in flags {
+            if let Some(path) = path {
+                outputs.push((flag, path.clone()));
+            }
+        }
+
+        Manifest {
+            path: options.manifest.clone(),
+            inputs: options.inputs.clone(),
+            outputs,
+            workers: None,
+            summary: None,
+            started,
+        }
+    }
+
+    /// Writes the manifest if one was asked for, telling the error the run ended with if any. The run is
+    /// `partial` when it went through but skipped malformed rows or stopped at a limit.
+    fn finish(&self, result: Result<(), String>) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        let status = match (&result, &self.summary) {
+            (Err(_), _) => "failed",
+            (Ok(()), Some(summary)) if summary.diagnostics.malformed_rows > 0 || summary.diagnostics.truncated => {
+                "partial"
+            }
+            (Ok(()), _) => "succeeded",
+        };
+        let error = result.err();
+        let workers = self.workers.map(|workers| workers.to_string());
+        let duration = format!("{:.3}", self.started.elapsed().as_secs_f64());
+
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let path = input.to_string_lossy();
+                // The standard input, pipes and URLs tell no size
+                let metadata = fs::metadata(input).ok().filter(|metadata| metadata.is_file());
+                let bytes = metadata.map(|metadata| metadata.len().to_string());
+                let mut object = String::new();
+                json::write_object(&mut object, [("path", json::Value::String(&path)), ("bytes", raw_or_null(&bytes))]);
+                object
+            })
+            .collect();
+
+        let object = |fields: Vec<(String, String)>| {
+            let mut object = String::new();
+            let values = fields.iter().map(|(name, value)| (name.as_str(), json::Value::Raw(value)));
+            json::write_object(&mut object, values);
+            object
+        };
+        let summary = self.summary.as_ref().map(|summary| object(summary.fields()));
+        // Rows turned down before reaching an account, by the reasons of `--report-rejected`
+        let rejected = self.summary.as_ref().map(|summary| {
+            let diagnostics = &summary.diagnostics;
+            let counts = [
+                ("malformed_row", diagnostics.malformed_rows),
+                ("invalid_amount", diagnostics.invalid_amounts),
+                ("non_finite_amount", diagnostics.non_finite_amounts),
+                ("scientific_amount", diagnostics.scientific_amounts),
+                ("imprecise_amount", diagnostics.imprecise_amounts),
+                ("reused_tx_id", diagnostics.reused_tx_ids),
+                ("wrong_client", diagnostics.mismatched_disputes),
+                ("currency_mismatch", diagnostics.currency_mismatches),
+                ("retried_transaction", diagnostics.retried_transactions),
+            ];
+            object(counts.iter().map(|(name, count)| (name.to_string(), count.to_string())).collect())
+        });
+
+        let mut outputs = Vec::new();
+        for (flag, path) in &self.outputs {
+            let mut files = Vec::new();
+            match fs::read_dir(path) {
+                Ok(entries) => files.extend(entries.flatten().map(|entry| entry.path())),
+                Err(_) => files.push(path.clone()),
+            }
+            files.sort();
+
+            for file in files {
+                // Outputs the run never got to write are left out
+                let (bytes, hash) = match file_hash(&file) {
+                    Ok(found) => found,
+                    Err(_) => continue,
+                };
+                let (path, bytes, hash) = (file.to_string_lossy(), bytes.to_string(), format!("{:016x}", hash));
+                let mut object = String::new();
+                json::write_object(
+                    &mut object,
+                    [
+                        ("flag", json::Value::String(flag)),
+                        ("path", json::Value::String(&path)),
+                        ("bytes", json::Value::Raw(&bytes)),
+                        ("xxh64", json::Value::String(&hash)),
+                    ],
+                );
+                outputs.push(object);
+            }
+        }
+
+        let (inputs, outputs) = (format!("[{}]", inputs.join(",")), format!("[{}]", outputs.join(",")));
+        let mut manifest = String::new();
+        json::write_object(
+            &mut manifest,
+            [
+                ("status", json::Value::String(status)),
+                ("error", error.as_deref().map_or(json::Value::Null, json::Value::String)),
+                ("workers", raw_or_null(&workers)),
+                ("duration_seconds", json::Value::Raw(&duration)),
+                ("inputs", json::Value::Raw(&inputs)),
+                ("summary", raw_or_null(&summary)),
+                ("rejected", raw_or_null(&rejected)),
+                ("outputs", json::Value::Raw(&outputs)),
+            ],
+        );
+        manifest.push('\n');
+
+        if let Err(e) = fs::write(path, manifest) {
+            log::error!("Could not write the manifest to {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn raw_or_null(value: &Option<String>) -> json::Value<'_> {
+    value.as_deref().map_or(json::Value::Null, json::Value::Raw)
+}
+
+/// Size of a file and the XxHash64 of its bytes, with a seed of 0.
+fn file_hash(path: &Path) -> io::Result<(u64, u64)> {
+    let mut file = File::open(path)?;
+    let mut hasher = XxHash64::with_seed(0);
+    let mut buffer = vec![0; 64 * 1024];
+    let mut bytes = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok((bytes, hasher.finish()));
+        }
+        hasher.write(&buffer[..read]);
+        bytes += read as u64;
+    }
+}
+
+/// Dispute referencing a tx id that only appeared for a different client.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct MismatchedDispute {
+    client: ClientId,
+    tx: u32,
+    row: u64,
+    owner: ClientId,
+}
+
+/// Why the reader turned down the amount of a row before deserializing it.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum AmountRejection {
+    NonFinite,
+    Scientific,
+    ExcessPrecision,
+}
+
+impl AmountRejection {
+    /// Name of the rejection in the report of `--report-rejected`.
+    fn name(self) -> &'static str {
+        match self {
+            AmountRejection::NonFinite => "non_finite_amount",
+            AmountRejection::Scientific => "scientific_amount",
+            AmountRejection::ExcessPrecision => "imprecise_amount",
+        }
+    }
+}
+
+impl fmt::Display for AmountRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmountRejection::NonFinite => write!(f, "non finite amount"),
+            AmountRejection::Scientific => write!(f, "amount in scientific notation"),
+            AmountRejection::ExcessPrecision => write!(f, "amount with more than four decimal places"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct RejectedAmount {
+    row: u64,
+    reason: AmountRejection,
+}
+
+/// What the reader found out about the input on its own, before any transaction is applied.
+#[derive(Debug, Default)]
+struct ReaderOutput {
+    diagnostics: Diagnostics,
+    /// Rows rejected because of their amount, only kept with strict precision.
+    rejected_amounts: Vec<RejectedAmount>,
+    /// Rows referencing a previous transaction that carry an amount anyway, only kept in strict mode.
+    reference_amount_rows: Vec<u64>,
+    /// Rows dropped before reaching the workers, only kept for `--report-rejected`.
+    rejected_rows: Vec<RejectedTransaction>,
+    /// Where the reading of the input stopped, only kept when saving offsets.
+    checkpoint: Option<checkpoint::Checkpoint>,
+    unknown_types: UnknownTypes,
+    /// Errors of the first malformed rows, and rows of the first tx ids reused across clients, only kept when
+    /// validating the input.
+    malformed_errors: Vec<String>,
+    reused_tx_id_rows: Vec<u64>,
+}
+
+impl ReaderOutput {
+    /// Malformed rows and reused tx ids listed when validating the input.
+    const MAX_LISTED: usize = 100;
+}
+
+/// Names of the types read as `Unknown`, lowercased, with the rows of each of them. Only the first
+/// `MAX_NAMES` names are kept, so an input of nothing but typos doesn't take up memory for each of its rows.
+#[derive(Debug, Default, PartialEq)]
+struct UnknownTypes {
+    names: HashMap<String, u64>,
+    /// Rows of the names past the first `MAX_NAMES`.
+    others: u64,
+}
+
+impl UnknownTypes {
+    const MAX_NAMES: usize = 100;
+
+    fn record(&mut self, name: &str) {
+        if let Some(count) = self.names.get_mut(name) {
+            *count += 1;
+        } else if self.names.len() < Self::MAX_NAMES {
+            self.names.insert(name.to_string(), 1);
+        } else {
+            self.others += 1;
+        }
+    }
+
+    /// Names with their rows, the most frequent first.
+    fn sorted(&self) -> Vec<(&str, u64)> {
+        let mut names: Vec<(&str, u64)> = self.names.iter().map(|(name, count)| (name.as_str(), *count)).collect();
+        names.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        names
+    }
+
+    fn report(&self) {
+        if self.names.is_empty() {
+            return;
+        }
+
+        let rows = self.names.values().sum::<u64>() + self.others;
+        let names: Vec<String> = self.sorted().iter().map(|(name, count)| format!("{:?} {}", name, count)).collect();
+        log::info!("Read {} row/s of an unknown type: {}", rows, names.join(", "));
+        if self.others > 0 {
+            log::info!("Only the first {} types are listed, leaving out {} row/s", Self::MAX_NAMES, self.others);
+        }
+    }
+}
+
+/// Settings that affect how the input file is read before transactions reach the workers.
+#[derive(Debug, Default, Copy, Clone)]
+struct ReaderOptions {
+    /// Treat tx ids as unique across all clients instead of only within each client.
+    unique_tx_ids: bool,
+    /// Keep the rows dropped by the reader, the malformed ones included.
+    keep_rejected: bool,
+    /// Reject the rows with amounts of more than four decimal places instead of truncating them,
+    /// and keep the rows of every rejected amount.
+    strict_precision: bool,
+    /// Reject the rows with amounts in scientific notation instead of reading them.
+    reject_scientific: bool,
+    /// Read amounts with thousands separators, see `Amount::normalize`.
+    lenient_amounts: bool,
+    /// Lenient amounts have a decimal comma, and points as thousands separators.
+    decimal_comma: bool,
+    /// Read the aliases of the type names as unknown types, see `TransactionType::from_alias`.
+    strict_types: bool,
+    /// Only read the input to tell what is wrong with it, without sending anything to the workers.
+    validate_only: bool,
+    /// Only parse the rows of this client, the others being told apart by their raw client field.
+    only_client: Option<ClientId>,
+    /// Percentage of the rows read past which rows of an unknown type fail the run.
+    max_unknown_ratio: Option<f64>,
+    /// Encoding of the text of every input.
+    encoding: Encoding,
+    /// Sizes past which CSV rows are malformed.
+    row_limits: RowLimits,
+    /// Keep the rows of the reference transactions carrying an amount, so the run can fail over them.
+    strict: bool,
+    /// Disputes carry the amount they hold, so they aren't checked for one.
+    partial_disputes: bool,
+    /// Format of every input file, told from the start of each file when missing, see `format_sniffer`.
+    format: Option<InputFormat>,
+    /// Field delimiter of every CSV input, a tab for `.tsv` files and a comma for the rest when missing.
+    delimiter: Option<u8>,
+    /// Parse the plain CSV files straight from their bytes, see `fast_csv`.
+    fast_parse: bool,
+    /// Threads parsing the ranges of each plain CSV file at the same time with the fast parser,
+    /// one or none parse the file as a whole.
+    parse_threads: usize,
+    /// Count the rows timestamped earlier than a previous one, across every input.
+    check_chronology: bool,
+    /// The CSV inputs have no header, their columns are the required ones by position.
+    no_header: bool,
+    /// Read the CSV headers as they are, without checking their columns first.
+    no_validate_header: bool,
+    /// Compression of every input, told from the first bytes of each input when missing.
+    compression: Option<Compression>,
+    /// Keep reading the last input once its end is reached, as more lines are appended to it.
+    follow: bool,
+    /// Rows read across every input before the reading stops.
+    limit_rows: Option<u64>,
+    /// Bytes read across every input before the reading stops, after decompression and in whole lines.
+    limit_bytes: Option<u64>,
+    /// Keep where the reading of the input stopped, so a later run can resume from there.
+    save_offset: bool,
+    /// Where the reading of the input resumes from, after its header.
+    resume_from: Option<checkpoint::Checkpoint>,
+}
+
+/// Extension telling what a file holds, the one before a `.gz` or `.zst` one for compressed files.
+fn content_extension(path: &Path) -> Option<&str> {
+    let path = http::without_query(path);
+    let path = match path.file_stem() {
+        Some(stem) if path.extension().is_some_and(|extension| extension == "gz" || extension == "zst") => {
+            Path::new(stem)
+        }
+        _ => path,
+    };
+
+    path.extension().and_then(|extension| extension.to_str())
+}
+
+/// Field delimiter of a CSV file by its extension.
+fn default_delimiter(path: &Path) -> u8 {
+    match content_extension(path) {
+        Some("tsv") => b'\t',
+        _ => b',',
+    }
+}
+
+/// Format of an input file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum InputFormat {
+    Csv,
+    /// JSON Lines, one object per line with the same fields as the CSV columns.
+    Json,
+    /// Records written by `transactioner convert`.
+    Binary,
+}
+
+impl InputFormat {
+    /// Formats by the name given to `--format`, `auto` being none in particular.
+    fn parse(name: &str) -> Option<Option<Self>> {
+        match name {
+            "csv" => Some(Some(InputFormat::Csv)),
+            "json" => Some(Some(InputFormat::Json)),
+            "tbin" => Some(Some(InputFormat::Binary)),
+            "auto" => Some(None),
+            _ => None,
+        }
+    }
+
+    /// Tells the format from the extension of the file, looking past a `.gz` or `.zst` one.
+    fn from_path(path: &Path) -> Self {
+        match content_extension(path) {
+            Some("ndjson") | Some("jsonl") => InputFormat::Json,
+            Some("tbin") => InputFormat::Binary,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+/// Compression of an input file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Compressions by the name given to `--compression`, `auto` being none in particular.
+    fn parse(name: &str) -> Option<Option<Self>> {
+        match name {
+            "none" => Some(Some(Compression::None)),
+            "gzip" => Some(Some(Compression::Gzip)),
+            "zstd" => Some(Some(Compression::Zstd)),
+            "auto" => Some(None),
+            _ => None,
+        }
+    }
+
+    /// Tells the compression from the first bytes of the input, whatever the name of the file.
+    fn detect(start: &[u8]) -> Self {
+        if gzip::is_gzip(start) {
+            Compression::Gzip
+        } else if zstd::is_zstd(start) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Encoding of the text of an input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+enum Encoding {
+    /// Rows with invalid UTF-8 are malformed.
+    #[default]
+    Utf8,
+    /// Every byte is a character, see `Latin1Input`.
+    Latin1,
+    /// Invalid UTF-8 is replaced with U+FFFD and the rows it was found in counted.
+    Utf8Lossy,
+}
+
+impl Encoding {
+    /// Encodings by the name given to `--encoding`.
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "utf8" => Some(Encoding::Utf8),
+            "latin1" => Some(Encoding::Latin1),
+            "utf8-lossy" => Some(Encoding::Utf8Lossy),
+            _ => None,
+        }
+    }
+}
+
+/// Reads its input on a blocking thread of its own, a few chunks ahead of what has been consumed,
+/// so reading and decompressing the file overlap with parsing it and sending the transactions.
+struct ReadAhead {
+    chunks: std::sync::mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+    /// Whether the input can keep it waiting for long, like a followed file.
+    waits: bool,
+}
+
+impl ReadAhead {
+    const CHUNK_SIZE: usize = 64 * 1024;
+    /// Chunks read before the thread waits for them to be consumed.
+    const CHUNKS_AHEAD: usize = 16;
+
+    fn new(mut input: Box<dyn Read + Send>, waits: bool) -> Self {
+        let (sender, chunks) = std::sync::mpsc::sync_channel(Self::CHUNKS_AHEAD);
+
+        tokio::task::spawn_blocking(move || loop {
+            let mut chunk = vec![0; Self::CHUNK_SIZE];
+            let result = match input.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => {
+                    chunk.truncate(read);
+                    Ok(chunk)
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = result.is_err();
+
+            // Nobody is reading anymore once the receiving side is gone
+            if sender.send(result).is_err() || failed {
+                break;
+            }
+        });
+
+        ReadAhead {
+            chunks,
+            chunk: Vec::new(),
+            position: 0,
+            waits,
+        }
+    }
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() {
+            let chunks = &self.chunks;
+            // The runtime thread is handed over while waiting, the tasks queued on it would wait as well otherwise
+            let chunk = match chunks.try_recv() {
+                Err(TryRecvError::Empty) if self.waits => tokio::task::block_in_place(|| chunks.recv()),
+                Err(TryRecvError::Empty) => chunks.recv(),
+                chunk => chunk.map_err(|_| RecvError),
+            };
+            match chunk {
+                Ok(chunk) => self.chunk = chunk?,
+                // The thread is done with the input
+                Err(_) => return Ok(0),
+            }
+            self.position = 0;
+        }
+
+        let read = std::cmp::min(buf.len(), self.chunk.len() - self.position);
+        buf[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+
+        Ok(read)
+    }
+}
+
+/// Whether a line of an input is only there for people, being blank or a `#` comment.
+fn is_skipped_line(line: &[u8], delimiter: u8) -> bool {
+    match line.iter().find(|byte| !byte.is_ascii_whitespace() || **byte == delimiter) {
+        None => true,
+        Some(byte) => *byte == b'#',
+    }
+}
+
+/// Whether a row repeats the header, as inputs concatenated along with their headers have. The
+/// first fields differ for every other row, so those are only gone through up to there.
+fn repeats_header<'a>(mut fields: impl Iterator<Item = &'a str>, headers: &csv::StringRecord) -> bool {
+    headers.iter().all(|header| fields.next().is_some_and(|field| field.eq_ignore_ascii_case(header)))
+}
+
+/// Sizes past which a CSV row is cut short, so a broken quote can't have the rest of the input read
+/// as a single field.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct RowLimits {
+    field_bytes: usize,
+    row_bytes: usize,
+}
+
+impl Default for RowLimits {
+    fn default() -> Self {
+        RowLimits {
+            field_bytes: 4 * 1024,
+            row_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// Row `CommentLines` cut short at one of the `RowLimits`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct CutRow {
+    /// Offset of the start of the row in what is handed out.
+    offset: u64,
+    /// Bytes of the input left out after the limit, up to the end of the line.
+    dropped: u64,
+    /// Option of the limit the row went over.
+    limit: &'static str,
+}
+
+/// Error of a row that goes over one of the `RowLimits`.
+fn oversized_row_error(line: u64, offset: u64, limit: &str) -> String {
+    format!("CSV error: line: {}: the row at byte {} of the input goes over {}", line, offset, limit)
+}
+
+/// Blanks out the comment and blank lines of a CSV input with spaces, which the reader then skips
+/// and counts. The csv crate would drop empty lines on its own without counting them, so they get
+/// a space added. The lines of quoted fields are left as they are.
+///
+/// Rows going over the `limits` are cut short there, dropping the rest of the line and closing their
+/// quoted field if they were in one, so the rows after them are read as usual.
+struct CommentLines<R> {
+    input: BufReader<R>,
+    delimiter: u8,
+    limits: RowLimits,
+    line: Vec<u8>,
+    position: usize,
+    first_line: bool,
+    /// Whether the lines so far left a quoted field open.
+    in_quotes: bool,
+    /// Offset of the current line in what has been handed out.
+    line_offset: u64,
+    /// Offset of the start of the current row, and its bytes in the lines before the current one.
+    row_offset: u64,
+    row_bytes: usize,
+    /// Bytes of the current field so far.
+    field_bytes: usize,
+    /// Offsets of the spaces added to empty lines and of the quotes closing cut rows, the only bytes
+    /// that aren't in the input.
+    padding: Arc<Mutex<Vec<u64>>>,
+    cuts: Arc<Mutex<Vec<CutRow>>>,
+}
+
+impl<R: Read> CommentLines<R> {
+    fn new(input: R, delimiter: u8, limits: RowLimits) -> Self {
+        CommentLines {
+            input: BufReader::with_capacity(ReadAhead::CHUNK_SIZE, input),
+            delimiter,
+            limits,
+            line: Vec::new(),
+            position: 0,
+            first_line: true,
+            in_quotes: false,
+            line_offset: 0,
+            row_offset: 0,
+            row_bytes: 0,
+            field_bytes: 0,
+            padding: Arc::default(),
+            cuts: Arc::default(),
+        }
+    }
+
+    /// Offsets of the spaces added so far, see `InputRecords::file_offset`.
+    fn padding(&self) -> Arc<Mutex<Vec<u64>>> {
+        Arc::clone(&self.padding)
+    }
+
+    /// Rows cut short so far, in the order of the input.
+    fn cuts(&self) -> Arc<Mutex<Vec<CutRow>>> {
+        Arc::clone(&self.cuts)
+    }
+}
+
+impl<R: Read> CommentLines<R> {
+    /// Reads the next line, returning whether there was one.
+    fn next_line(&mut self) -> io::Result<bool> {
+        let first = std::mem::replace(&mut self.first_line, false);
+        self.line_offset += self.line.len() as u64;
+        self.line.clear();
+        self.position = 0;
+
+        // Never read past the limit, a line can be as long as the input
+        let left = self.limits.row_bytes.saturating_sub(self.row_bytes) + 1;
+        if (&mut self.input).take(left as u64).read_until(b'\n', &mut self.line)? == 0 {
+            return Ok(false);
+        }
+        let capped = self.line.len() == left && !self.line.ends_with(b"\n");
+
+        // The csv crate drops the byte order mark itself
+        let content = match self.line.strip_prefix(b"\xEF\xBB\xBF") {
+            Some(content) if first => content,
+            _ => &self.line[..],
+        };
+
+        if self.in_quotes || capped || !is_skipped_line(content, self.delimiter) {
+            if !self.in_quotes {
+                self.row_offset = self.line_offset;
+            }
+            let mut cut = None;
+            for index in self.line.len() - content.len()..self.line.len() {
+                match self.line[index] {
+                    // Escaped quotes come in pairs, so they leave the field quoted
+                    b'"' => self.in_quotes = !self.in_quotes,
+                    b'\n' if !self.in_quotes => continue,
+                    byte if byte == self.delimiter && !self.in_quotes => {
+                        self.field_bytes = 0;
+                        continue;
+                    }
+                    _ => {}
+                }
+
+                self.field_bytes += 1;
+                if self.field_bytes > self.limits.field_bytes {
+                    cut = Some((index, "--max-field-bytes"));
+                    break;
+                }
+                if self.row_bytes + index >= self.limits.row_bytes {
+                    cut = Some((index, "--max-row-bytes"));
+                    break;
+                }
+            }
+
+            match cut {
+                Some((index, limit)) => self.cut_row(index, limit)?,
+                None if self.in_quotes => self.row_bytes += self.line.len(),
+                None => {
+                    self.row_bytes = 0;
+                    self.field_bytes = 0;
+                }
+            }
+        } else if self.line == b"\n" {
+            self.line.insert(0, b' ');
+            if let Ok(mut padding) = self.padding.lock() {
+                padding.push(self.line_offset);
+            }
+        } else {
+            // Same length as the line, so offsets in the output still match those of the input
+            for byte in self.line.iter_mut().filter(|byte| **byte != b'\n') {
+                *byte = b' ';
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> CommentLines<R> {
+    /// Cuts the current row short before `index` of its line, dropping the rest of the line.
+    fn cut_row(&mut self, index: usize, limit: &'static str) -> io::Result<()> {
+        let mut dropped = (self.line.len() - index) as u64;
+        let mut newline = self.line.ends_with(b"\n");
+        self.line.truncate(index);
+
+        if newline {
+            dropped -= 1;
+        } else {
+            loop {
+                let buffer = self.input.fill_buf()?;
+                if buffer.is_empty() {
+                    break;
+                }
+                let (count, found) = match buffer.iter().position(|byte| *byte == b'\n') {
+                    Some(position) => (position + 1, true),
+                    None => (buffer.len(), false),
+                };
+                self.input.consume(count);
+                dropped += count as u64 - found as u64;
+
+                if found {
+                    newline = true;
+                    break;
+                }
+            }
+        }
+
+        if self.in_quotes {
+            if let Ok(mut padding) = self.padding.lock() {
+                padding.push(self.line_offset + self.line.len() as u64);
+            }
+            self.line.push(b'"');
+        }
+        if newline {
+            self.line.push(b'\n');
+        }
+        if let Ok(mut cuts) = self.cuts.lock() {
+            cuts.push(CutRow { offset: self.row_offset, dropped, limit });
+        }
+
+        self.in_quotes = false;
+        self.row_bytes = 0;
+        self.field_bytes = 0;
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for CommentLines<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+
+        while read < buf.len() {
+            // What is buffered is handed out before reading again, which could wait on a followed file
+            if self.position == self.line.len() && (read > 0 && self.input.buffer().is_empty() || !self.next_line()?) {
+                break;
+            }
+
+            let count = std::cmp::min(buf.len() - read, self.line.len() - self.position);
+            buf[read..read + count].copy_from_slice(&self.line[self.position..self.position + count]);
+            self.position += count;
+            read += count;
+        }
+
+        Ok(read)
+    }
+}
+
+/// Appends a chunk of `input` to `buffer`, returning how much was read.
+fn read_more(input: &mut impl Read, buffer: &mut Vec<u8>) -> io::Result<usize> {
+    let start = buffer.len();
+    buffer.resize(start + ReadAhead::CHUNK_SIZE, 0);
+    let read = input.read(&mut buffer[start..]);
+    buffer.truncate(start + *read.as_ref().unwrap_or(&0));
+
+    read
+}
+
+/// Reads a file that is still being written, waiting for more once its end is reached until `stop`
+/// is set. Only whole lines are handed out, one being appended is held back until its newline arrives.
+struct FollowFile<R> {
+    input: R,
+    stop: &'static AtomicBool,
+    buffer: Vec<u8>,
+    position: usize,
+    /// End of the last whole line of the buffer.
+    complete: usize,
+}
+
+impl<R: Read> FollowFile<R> {
+    /// How long to wait before looking for appended lines again.
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    fn new(input: R, stop: &'static AtomicBool) -> Self {
+        FollowFile {
+            input,
+            stop,
+            buffer: Vec::new(),
+            position: 0,
+            complete: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for FollowFile<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.complete {
+            // A line left half written when stopping is never read
+            if self.stop.load(Ordering::SeqCst) {
+                return Ok(0);
+            }
+
+            self.buffer.drain(..self.complete);
+            self.position = 0;
+
+            let read = read_more(&mut self.input, &mut self.buffer)?;
+            if read == 0 {
+                std::thread::sleep(Self::POLL_INTERVAL);
+            }
+            self.complete = self.buffer.iter().rposition(|byte| *byte == b'\n').map_or(0, |index| index + 1);
+        }
+
+        let length = buf.len().min(self.complete - self.position);
+        buf[..length].copy_from_slice(&self.buffer[self.position..self.position + length]);
+        self.position += length;
+
+        Ok(length)
+    }
+}
+
+/// Bytes left to read across the inputs under `--limit-bytes`.
+#[derive(Debug)]
+struct InputLimit {
+    bytes_left: AtomicU64,
+    reached: AtomicBool,
+}
+
+impl InputLimit {
+    fn new(bytes: u64) -> Self {
+        InputLimit {
+            bytes_left: AtomicU64::new(bytes),
+            reached: AtomicBool::new(false),
+        }
+    }
+
+    /// Takes what can be read of the start of `data` from the bytes left: the whole lines that fit, or all of it at
+    /// the end of the input. Nothing is left to read once a line doesn't fit.
+    fn take(&self, data: &[u8], at_end: bool) -> usize {
+        let left = self.bytes_left.load(Ordering::SeqCst);
+        let whole_lines = |data: &[u8]| data.iter().rposition(|byte| *byte == b'\n').map_or(0, |index| index + 1);
+
+        if data.len() as u64 > left {
+            self.reached.store(true, Ordering::SeqCst);
+            self.bytes_left.store(0, Ordering::SeqCst);
+
+            return whole_lines(&data[..left as usize]);
+        }
+
+        let taken = if at_end { data.len() } else { whole_lines(data) };
+        self.bytes_left.store(left - taken as u64, Ordering::SeqCst);
+
+        taken
+    }
+
+    fn reached(&self) -> bool {
+        self.reached.load(Ordering::SeqCst)
+    }
+}
+
+/// Decodes a Latin-1 input into UTF-8, every byte being the code point of its character. The bytes
+/// past ASCII take two bytes once decoded, so the offsets of what is parsed aren't those of the input.
+struct Latin1Input<R> {
+    input: R,
+    decoded: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> Latin1Input<R> {
+    fn new(input: R) -> Self {
+        Latin1Input { input, decoded: Vec::new(), position: 0 }
+    }
+}
+
+impl<R: Read> Read for Latin1Input<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.decoded.len() {
+            let mut raw = [0; 8192];
+            let read = self.input.read(&mut raw)?;
+
+            self.decoded.clear();
+            self.position = 0;
+            for byte in &raw[..read] {
+                match byte {
+                    0..=0x7F => self.decoded.push(*byte),
+                    _ => self.decoded.extend_from_slice(&[0xC0 | byte >> 6, 0x80 | (byte & 0x3F)]),
+                }
+            }
+        }
+
+        let length = buf.len().min(self.decoded.len() - self.position);
+        buf[..length].copy_from_slice(&self.decoded[self.position..self.position + length]);
+        self.position += length;
+
+        Ok(length)
+    }
+}
+
+/// Hands out the start of an input read ahead of the rest, then the rest. An input that failed within
+/// its start fails there, once the rows before are read, like a connection dropped early on.
+struct ReadStart<R> {
+    start: io::Cursor<Vec<u8>>,
+    error: Option<io::Error>,
+    input: R,
+}
+
+impl<R: Read> Read for ReadStart<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.start.read(buf)? {
+            0 if !buf.is_empty() => match self.error.take() {
+                Some(error) => Err(error),
+                None => self.input.read(buf),
+            },
+            read => Ok(read),
+        }
+    }
+}
+
+/// Reads its input up to the bytes left of `limit`, ending at the last line that fits.
+struct LimitedInput<R> {
+    input: R,
+    limit: Arc<InputLimit>,
+    buffer: Vec<u8>,
+    position: usize,
+    /// End of what can be handed out of the buffer.
+    end: usize,
+    done: bool,
+}
+
+impl<R: Read> LimitedInput<R> {
+    fn new(input: R, limit: Arc<InputLimit>) -> Self {
+        LimitedInput {
+            input,
+            limit,
+            buffer: Vec::new(),
+            position: 0,
+            end: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitedInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.end {
+            if self.done {
+                return Ok(0);
+            }
+
+            self.buffer.drain(..self.end);
+            self.position = 0;
+
+            let read = read_more(&mut self.input, &mut self.buffer)?;
+            self.end = self.limit.take(&self.buffer, read == 0);
+            self.done = read == 0 || self.limit.reached();
+        }
+
+        let length = buf.len().min(self.end - self.position);
+        buf[..length].copy_from_slice(&self.buffer[self.position..self.position + length]);
+        self.position += length;
+
+        Ok(length)
+    }
+}
+
+/// Rows of an input file, read as CSV records whatever the format of the file.
+enum InputRecords {
+    Csv {
+        reader: csv::Reader<Box<dyn Read + Send>>,
+        /// See `CommentLines::padding`.
+        padding: Arc<Mutex<Vec<u64>>>,
+        /// See `CommentLines::cuts`.
+        cuts: Arc<Mutex<Vec<CutRow>>>,
+        /// Replace invalid UTF-8 instead of failing the row, see `Encoding::Utf8Lossy`.
+        lossy: bool,
+        /// Rows invalid UTF-8 was replaced in.
+        replaced_rows: u64,
+    },
+    Json {
+        input: BufReader<Box<dyn Read + Send>>,
+        text: String,
+        line: u64,
+        lossy: bool,
+        replaced_rows: u64,
+    },
+    Fast(fast_csv::FastRecords),
+    /// Records written by `transactioner convert`, see `tbin`.
+    Binary {
+        input: BufReader<Box<dyn Read + Send>>,
+        record: [u8; tbin::RECORD_SIZE],
+    },
+    /// Ranges of a plain CSV file parsed in parallel, read back in the order of the file.
+    Chunks {
+        chunks: VecDeque<std::sync::mpsc::Receiver<ParsedChunk>>,
+        rows: std::vec::IntoIter<(u64, ParsedRow)>,
+    },
+}
+
+/// A row as the reader makes of it before checking it against the rest of the input.
+#[derive(Debug)]
+enum ParsedRow {
+    /// A blank or comment line.
+    Skipped,
+    /// A row repeating the header.
+    Header,
+    /// A row of another client than `ReaderOptions::only_client`, left unparsed.
+    OtherClient,
+    Malformed(String),
+    Rejected(AmountRejection),
+    /// A transaction, whose amount may have been truncated and type read through an alias. The type of the rows
+    /// read as `Unknown` is kept as it was in the input, lowercased.
+    Transaction { transaction: Transaction, truncated: bool, aliased: bool, unknown_type: Option<String> },
+}
+
+/// Fields of a row `parse_row` looks at before parsing it, as they are in the input.
+#[derive(Copy, Clone)]
+struct RawFields<'a> {
+    client: &'a str,
+    type_name: &'a str,
+    amount: &'a str,
+}
+
+/// Rows parsed by a chunk task along with their lines, an empty batch ends the chunk.
+type ParsedChunk = Vec<(u64, ParsedRow)>;
+
+/// Checks the raw amount of a row and parses its transaction. The amount is checked on the raw
+/// field, NaN and infinity would otherwise stop the parsing, and aliases are told from the raw type.
+fn parse_row(
+    line: u64,
+    fields: RawFields<'_>,
+    options: &ReaderOptions,
+    transaction: impl FnOnce(&str) -> Result<Transaction, String>,
+) -> ParsedRow {
+    let RawFields { client, type_name, amount } = fields;
+    // A client field that can't be read isn't the one asked for either
+    if options.only_client.is_some_and(|only| client.parse() != Ok(only)) {
+        return ParsedRow::OtherClient;
+    }
+
+    // Lenient amounts are rewritten as plain decimal numbers, which is what the rest goes by
+    let amount = match options.lenient_amounts {
+        true => match Amount::normalize(amount, options.decimal_comma) {
+            Some(amount) => amount,
+            None => {
+                let error = format!("invalid amount {:?}: misplaced thousands separator", amount);
+                return ParsedRow::Malformed(format!("CSV deserialize error: line: {}: {}", line, error));
+            }
+        },
+        false => Cow::Borrowed(amount),
+    };
+    let amount = amount.as_ref();
+
+    if Amount::is_non_finite(amount) {
+        return ParsedRow::Rejected(AmountRejection::NonFinite);
+    } else if options.reject_scientific && Amount::is_scientific(amount) {
+        return ParsedRow::Rejected(AmountRejection::Scientific);
+    }
+
+    let truncated = Amount::exceeds_precision(amount);
+    if truncated && options.strict_precision {
+        return ParsedRow::Rejected(AmountRejection::ExcessPrecision);
+    }
+
+    match transaction(amount) {
+        Ok(mut transaction) => {
+            let alias = match transaction.r#type {
+                TransactionType::Unknown if !options.strict_types => TransactionType::from_alias(type_name),
+                _ => None,
+            };
+            if let Some(r#type) = alias {
+                transaction.r#type = r#type;
+            }
+            let unknown_type = match transaction.r#type {
+                // Lowercased like the names that are known
+                TransactionType::Unknown => Some(type_name.trim().to_ascii_lowercase()),
+                _ => None,
+            };
+
+            ParsedRow::Transaction { transaction, truncated, aliased: alias.is_some(), unknown_type }
+        }
+        Err(error) => ParsedRow::Malformed(error),
+    }
+}
+
+/// Parses the file in `threads` ranges at the same time, each on a blocking thread of its own.
+fn parse_chunks(records: fast_csv::FastRecords, threads: usize, options: ReaderOptions) -> InputRecords {
+    /// Rows sent at once, so the channel isn't touched for every row.
+    const BATCH_SIZE: usize = 4_096;
+
+    let mut chunks = VecDeque::with_capacity(threads);
+
+    for mut chunk in records.split(threads) {
+        // Not bounded, the chunks after the one being read have to go on parsing meanwhile
+        let (sender, receiver) = std::sync::mpsc::channel();
+        chunks.push_back(receiver);
+
+        tokio::task::spawn_blocking(move || {
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+            loop {
+                let parsed = match chunk.read() {
+                    Ok(ReadOutcome::Row) => {
+                        let transaction = |amount: &str| chunk.transaction(amount);
+                        let (client, type_name, amount) = (chunk.client(), chunk.type_name(), chunk.amount());
+                        parse_row(chunk.line(), RawFields { client, type_name, amount }, &options, transaction)
+                    }
+                    Ok(ReadOutcome::Skipped) => ParsedRow::Skipped,
+                    Ok(ReadOutcome::Header) => ParsedRow::Header,
+                    Ok(ReadOutcome::End) => break,
+                    Err(error) => ParsedRow::Malformed(error),
+                };
+                batch.push((chunk.line(), parsed));
+
+                // Nobody is reading anymore once the receiving side is gone
+                if batch.len() == BATCH_SIZE && sender.send(std::mem::take(&mut batch)).is_err() {
+                    return;
+                }
+            }
+
+            if !batch.is_empty() {
+                let _ = sender.send(batch);
+            }
+            let _ = sender.send(Vec::new());
+        });
+    }
+
+    InputRecords::Chunks {
+        chunks,
+        rows: Vec::new().into_iter(),
+    }
+}
+
+/// What reading the next line of an input gave.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ReadOutcome {
+    Row,
+    /// A blank or comment line, which is skipped.
+    Skipped,
+    /// A row repeating the header, see `repeats_header`.
+    Header,
+    End,
+}
+
+/// Failure to read the next row of an input.
+#[derive(Debug)]
+enum ReadError {
+    /// The row is malformed, the rows after it can still be read.
+    Row(String),
+    /// The input can't be read any further.
+    Input(Box<dyn Error>),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Input(e.into())
+    }
+}
+
+impl InputRecords {
+    /// Reads a CSV input through `CommentLines`, a `ReadAhead` thread and the `csv` crate.
+    fn csv(input: Box<dyn Read + Send>, delimiter: u8, follow: bool, options: &ReaderOptions) -> Self {
+        let lines = CommentLines::new(input, delimiter, options.row_limits);
+        let (padding, cuts) = (lines.padding(), lines.cuts());
+        let lossy = options.encoding == Encoding::Utf8Lossy;
+        let input: Box<dyn Read + Send> = Box::new(ReadAhead::new(Box::new(lines), follow));
+
+        // The header is read as a record, so the lines before it can be skipped
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .trim(csv::Trim::All)
+            .delimiter(delimiter)
+            // The default terminator puts the rows of CRLF files a line behind,
+            // the \r left at the end of the last field is trimmed instead
+            .terminator(csv::Terminator::Any(b'\n'))
+            .flexible(true)
+            .from_reader(input);
+
+        InputRecords::Csv { reader, padding, cuts, lossy, replaced_rows: 0 }
+    }
+
+    /// Reads the next row into `record`, with `headers` naming its fields. Returns whether there was one.
+    fn read(
+        &mut self,
+        headers: &mut csv::StringRecord,
+        record: &mut csv::StringRecord,
+    ) -> Result<ReadOutcome, ReadError> {
+        match self {
+            InputRecords::Fast(records) => records.read().map_err(ReadError::Row),
+            // Read through `next_row`
+            InputRecords::Chunks { .. } => Ok(ReadOutcome::End),
+            InputRecords::Binary { input, record } => match tbin::read_record(input, record)? {
+                true => Ok(ReadOutcome::Row),
+                false => Ok(ReadOutcome::End),
+            },
+            InputRecords::Csv { reader, padding, cuts, lossy, replaced_rows } => {
+                // Read as bytes, so invalid UTF-8 can be told where it is or replaced
+                let mut bytes = std::mem::take(record).into_byte_record();
+                let read = reader.read_byte_record(&mut bytes).map_err(|e| ReadError::Input(e.into()))?;
+
+                if let Some(position) = bytes.position().filter(|_| read) {
+                    let (start, end) = (position.byte(), reader.position().byte());
+                    let cut = cuts.lock().ok().and_then(|cuts| {
+                        let index = cuts.partition_point(|cut| cut.offset < start);
+                        cuts.get(index).copied().filter(|cut| cut.offset < end)
+                    });
+                    if let Some(cut) = cut {
+                        let offset = Self::unpadded(padding, cuts, start);
+                        return Err(ReadError::Row(oversized_row_error(position.line(), offset, cut.limit)));
+                    }
+                }
+                *record = match csv::StringRecord::from_byte_record(bytes) {
+                    Ok(text) => text,
+                    Err(e) if *lossy => {
+                        *replaced_rows += 1;
+                        let bytes = e.into_byte_record();
+                        let mut text: csv::StringRecord = bytes.iter().map(String::from_utf8_lossy).collect();
+                        text.set_position(bytes.position().cloned());
+                        text
+                    }
+                    Err(e) => {
+                        let (field, valid_up_to) = (e.utf8_error().field(), e.utf8_error().valid_up_to());
+                        let bytes = e.into_byte_record();
+                        let position = bytes.position().cloned().unwrap_or_else(csv::Position::new);
+                        // Quotes aside, the fields before it and their delimiters lead to the byte
+                        let start: usize = bytes.iter().take(field).map(|field| field.len() + 1).sum();
+                        let offset = Self::unpadded(padding, cuts, position.byte()) + (start + valid_up_to) as u64;
+
+                        return Err(ReadError::Row(format!(
+                            "CSV parse error: line: {}: invalid UTF-8 in the {} column near byte {} of the input, \
+                             see --encoding",
+                            position.line(),
+                            headers.get(field).unwrap_or("?"),
+                            offset
+                        )));
+                    }
+                };
+                // Like `read_record` does, the first record read as bytes is left untrimmed
+                record.trim();
+
+                if !read {
+                    return Ok(ReadOutcome::End);
+                }
+
+                // Blank and comment lines are left with nothing once trimmed, see `CommentLines`
+                if record.len() == 1 && record[0].is_empty() {
+                    return Ok(ReadOutcome::Skipped);
+                }
+
+                if record.len() != headers.len() {
+                    let line = record.position().map_or(0, |position| position.line());
+                    return Err(ReadError::Row(field_count_error(line, record.len(), headers.len())));
+                }
+                if repeats_header(record.iter(), headers) {
+                    return Ok(ReadOutcome::Header);
+                }
+
+                Ok(ReadOutcome::Row)
+            }
+            InputRecords::Json { input, text, line, lossy, replaced_rows } => {
+                let mut bytes = std::mem::take(text).into_bytes();
+                bytes.clear();
+                if input.read_until(b'\n', &mut bytes)? == 0 {
+                    return Ok(ReadOutcome::End);
+                }
+                *line += 1;
+
+                *text = match String::from_utf8(bytes) {
+                    Ok(object) => object,
+                    Err(e) if *lossy => {
+                        *replaced_rows += 1;
+                        String::from_utf8_lossy(e.as_bytes()).into_owned()
+                    }
+                    Err(e) => {
+                        return Err(ReadError::Row(format!(
+                            "JSON parse error (line: {}): invalid UTF-8 at byte {} of the line, see --encoding",
+                            line,
+                            e.utf8_error().valid_up_to()
+                        )));
+                    }
+                };
+
+                // Files saved by Windows editors can start with a byte order mark
+                let object = if *line == 1 { text.trim_start_matches('\u{feff}') } else { text.as_str() };
+                if is_skipped_line(object.as_bytes(), b',') {
+                    return Ok(ReadOutcome::Skipped);
+                }
+
+                json::parse_object(object, headers, record)
+                    .map_err(|e| ReadError::Row(format!("JSON parse error (line: {}): {}", line, e)))?;
+
+                if let Some(field) = Transaction::missing_field(headers) {
+                    return Err(ReadError::Row(format!(
+                        "JSON parse error (line: {}): missing the required {} field",
+                        line, field
+                    )));
+                }
+
+                let mut position = csv::Position::new();
+                position.set_line(*line);
+                record.set_position(Some(position));
+
+                Ok(ReadOutcome::Row)
+            }
+        }
+    }
+
+    /// Reads the header of a CSV input, its first line that isn't blank or a comment, returning
+    /// the line it was found at or zero for an empty input.
+    fn read_header(&mut self, headers: &mut csv::StringRecord) -> Result<u64, Box<dyn Error>> {
+        if let InputRecords::Csv { reader, .. } = self {
+            while reader.read_record(headers)? {
+                if headers.len() != 1 || !headers[0].is_empty() {
+                    // Column names are matched case-insensitively
+                    let line = headers.position().map_or(0, |position| position.line());
+                    *headers = headers.iter().map(str::to_lowercase).collect();
+
+                    return Ok(line);
+                }
+            }
+        }
+
+        headers.clear();
+        Ok(0)
+    }
+
+    /// Bytes and lines read up to the end of the latest row, the bytes being those of what is parsed.
+    /// Neither is kept for JSON inputs and files parsed in ranges.
+    fn position(&self) -> (u64, u64) {
+        match self {
+            InputRecords::Fast(records) => (records.offset() as u64, records.line()),
+            InputRecords::Csv { reader, .. } => (reader.position().byte(), reader.position().line().saturating_sub(1)),
+            InputRecords::Json { .. } | InputRecords::Binary { .. } | InputRecords::Chunks { .. } => (0, 0),
+        }
+    }
+
+    /// Offset in the input of an offset in what is parsed, leaving out the spaces `CommentLines` added.
+    fn file_offset(&self, offset: u64) -> u64 {
+        match self {
+            InputRecords::Csv { padding, cuts, .. } => Self::unpadded(padding, cuts, offset),
+            _ => offset,
+        }
+    }
+
+    /// See `file_offset`, the bytes dropped from cut rows being put back.
+    fn unpadded(padding: &Mutex<Vec<u64>>, cuts: &Mutex<Vec<CutRow>>, offset: u64) -> u64 {
+        let dropped = |cuts: &Vec<CutRow>| cuts.iter().take_while(|cut| cut.offset < offset).map(|cut| cut.dropped).sum();
+        let dropped: u64 = cuts.lock().map_or(0, |cuts| dropped(&cuts));
+        offset - padding.lock().map_or(0, |padding| padding.partition_point(|at| *at < offset) as u64) + dropped
+    }
+
+    /// Rows invalid UTF-8 was replaced in so far.
+    fn replaced_rows(&self) -> u64 {
+        match self {
+            InputRecords::Csv { replaced_rows, .. } | InputRecords::Json { replaced_rows, .. } => *replaced_rows,
+            _ => 0,
+        }
+    }
+
+    /// Reads and parses the next row, returning it along with the line it was read from, which is
+    /// zero if the row was too malformed to tell, or `None` at the end of the input.
+    fn next_row(
+        &mut self,
+        headers: &mut csv::StringRecord,
+        record: &mut csv::StringRecord,
+        options: &ReaderOptions,
+    ) -> Result<Option<(u64, ParsedRow)>, Box<dyn Error>> {
+        if let InputRecords::Chunks { chunks, rows } = self {
+            loop {
+                if let Some(row) = rows.next() {
+                    return Ok(Some(row));
+                }
+
+                match chunks.front().map(|chunk| chunk.recv()) {
+                    Some(Ok(batch)) if batch.is_empty() => {
+                        chunks.pop_front();
+                    }
+                    Some(Ok(batch)) => *rows = batch.into_iter(),
+                    Some(Err(_)) => return Err("A parsing thread stopped before the end of its chunk".into()),
+                    None => return Ok(None),
+                }
+            }
+        }
+
+        match self.read(headers, record) {
+            Ok(ReadOutcome::Row) => {}
+            // No line needed, skipped lines don't show up anywhere
+            Ok(ReadOutcome::Skipped) => return Ok(Some((0, ParsedRow::Skipped))),
+            Ok(ReadOutcome::Header) => return Ok(Some((0, ParsedRow::Header))),
+            Ok(ReadOutcome::End) => return Ok(None),
+            Err(ReadError::Row(error)) => return Ok(Some((0, ParsedRow::Malformed(error)))),
+            Err(ReadError::Input(e)) => return Err(e),
+        }
+
+        match self {
+            InputRecords::Fast(records) => {
+                let line = records.line();
+                let transaction = |amount: &str| records.transaction(amount);
+                let (client, type_name, amount) = (records.client(), records.type_name(), records.amount());
+                Ok(Some((line, parse_row(line, RawFields { client, type_name, amount }, options, transaction))))
+            }
+            // Converted from rows that were already checked
+            InputRecords::Binary { record, .. } => match tbin::decode(record) {
+                Ok((_, transaction)) if options.only_client.is_some_and(|client| client != transaction.client) => {
+                    Ok(Some((0, ParsedRow::OtherClient)))
+                }
+                Ok((line, transaction)) => {
+                    let (truncated, aliased, unknown_type) = (false, false, None);
+                    Ok(Some((line, ParsedRow::Transaction { transaction, truncated, aliased, unknown_type })))
+                }
+                Err(e) => Ok(Some((0, ParsedRow::Malformed(format!("Binary record error: {}", e))))),
+            },
+            _ => {
+                let field = |name: &str| {
+                    let column = headers.iter().position(|header| header == name);
+                    (column, column.and_then(|column| record.get(column)).unwrap_or_default())
+                };
+                let (amount_column, amount) = field("amount");
+                let (_, type_name) = field("type");
+                let (_, client) = field("client");
+                let line = record.position().map_or(0, |position| position.line());
+                let transaction = |amount: &str| {
+                    let deserialized = match amount_column {
+                        // The amount was rewritten by the lenient parsing
+                        Some(column) if record.get(column) != Some(amount) => {
+                            let mut rewritten: csv::StringRecord = record
+                                .iter()
+                                .enumerate()
+                                .map(|(index, field)| if index == column { amount } else { field })
+                                .collect();
+                            rewritten.set_position(record.position().cloned());
+                            rewritten.deserialize(Some(headers))
+                        }
+                        _ => record.deserialize(Some(headers)),
+                    };
+                    deserialized.map_err(|e| e.to_string())
+                };
+
+                Ok(Some((line, parse_row(line, RawFields { client, type_name, amount }, options, transaction))))
+            }
+        }
+    }
+}
+
+/// Error of a row that doesn't have as many fields as the header.
+fn field_count_error(line: u64, fields: usize, header_fields: usize) -> String {
+    format!(
+        "CSV error: line: {}: found record with {} fields, but the header has {} fields",
+        line, fields, header_fields
+    )
+}
+
+/// Checks the header names the columns a transaction needs, each of them once. A header left in one
+/// piece most likely uses another delimiter, which is suggested.
+fn check_headers(headers: &csv::StringRecord) -> Result<(), String> {
+    if headers.len() == 1 {
+        let suggestion = [(";", ";"), ("\t", "\\t"), ("|", "|"), (",", ",")]
+            .iter()
+            .find(|(other, _)| headers[0].contains(other));
+
+        if let Some((_, name)) = suggestion {
+            return Err(format!("The header has a single column, did you mean --delimiter '{}'?", name));
+        }
+    }
+
+    match Transaction::missing_field(headers) {
+        // An empty input has no header at all, and nothing to read either
+        Some(_) if headers.is_empty() => Ok(()),
+        // A transaction where the header should be is most likely the first row of a file without one
+        Some(_) if TransactionType::from_name(&headers[0]) != TransactionType::Unknown => Err(format!(
+            "The header reads like a {} transaction, did you mean --no-header?",
+            &headers[0]
+        )),
+        _ => {
+            let missing: Vec<&str> = Transaction::REQUIRED_FIELDS
+                .iter()
+                .copied()
+                .filter(|field| !headers.iter().any(|header| header == *field))
+                .collect();
+            let mut duplicated = Vec::new();
+            for (index, header) in headers.iter().enumerate() {
+                if headers.iter().take(index).any(|x| x == header) && !duplicated.contains(&header) {
+                    duplicated.push(header);
+                }
+            }
+
+            let columns = |names: &[&str]| {
+                format!("{} column{}", names.join(", "), if names.len() > 1 { "s" } else { "" })
+            };
+            let mut problems = Vec::new();
+            if !missing.is_empty() {
+                problems.push(format!("is missing the required {}", columns(&missing)));
+            }
+            if !duplicated.is_empty() {
+                problems.push(format!("has the {} more than once", columns(&duplicated)));
+            }
+
+            match problems.is_empty() {
+                true => Ok(()),
+                false => Err(format!("The header {}, see --no-validate-header", problems.join(" and "))),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    /// Files read one after the other into the same accounts, in sorted order.
+    inputs: Vec<PathBuf>,
+    reader: ReaderOptions,
+    policy: AccountPolicy,
+    report_declined: Option<PathBuf>,
+    report_rejected: Option<PathBuf>,
+    report_locked: Option<PathBuf>,
+    report_mismatched_disputes: Option<PathBuf>,
+    report_open_disputes: Option<PathBuf>,
+    audit_log: Option<PathBuf>,
+    /// Clients whose every transaction is written to `trace_output`.
+    trace_clients: HashSet<ClientId>,
+    trace_output: Option<PathBuf>,
+    report_skips: Option<PathBuf>,
+    /// File the summary of the run is saved to as JSON.
+    summary_json: Option<PathBuf>,
+    /// Report the hash of the final balances, see `state_hash`.
+    state_hash: bool,
+    /// File the hash of the final balances is saved to.
+    state_hash_file: Option<PathBuf>,
+    output: OutputOptions,
+    output_filter: OutputFilter,
+    /// Write the accounts of each worker as soon as it is done, rather than all of them sorted by client.
+    unsorted: bool,
+    /// File the accounts are written to instead of the standard output.
+    output_path: Option<PathBuf>,
+    /// Directory each worker writes its own accounts to, instead of merging them into a single output.
+    output_shards: Option<PathBuf>,
+    /// How often the accounts so far are written to `output_path` while the input is read.
+    dump_interval: Option<Duration>,
+    /// File the offset reached in the input is saved to.
+    save_offset: Option<PathBuf>,
+    /// File holding the offset the input is resumed from.
+    resume_offset: Option<PathBuf>,
+    /// File the transactions of the input are written to by `convert`, instead of being applied.
+    convert_output: Option<PathBuf>,
+    /// File some of the options were read from, see `config`.
+    config: Option<PathBuf>,
+    /// Client whose account `inspect` writes, only its rows being applied.
+    inspect_client: Option<ClientId>,
+    /// Also write each transaction of the inspected client, along with the balances it left.
+    history: bool,
+    /// Diagnostics written to the standard error.
+    log_level: log::Level,
+    /// File describing how the run ended and what it wrote, as JSON.
+    manifest: Option<PathBuf>,
+    /// Workers the clients are spread across, `DEFAULT_WORKERS` when missing.
+    workers: Option<usize>,
+    /// Messages the channel of each worker holds, estimated from the size of the inputs when missing.
+    buffer_size: Option<usize>,
+}
+
+impl Options {
+    /// Amount of declined transactions kept per account when `--report-declined` is used
+    const DEFAULT_DECLINED_CAP: usize = 1_000;
+    /// After some profiling, it seems that the general best amount of worker is only 2, the limiting factor in the
+    /// code seems to be the speed at which you can read the CSV file, so more threads aren't worth it unless
+    /// significant increases in read performance are achieved.
+    const DEFAULT_WORKERS: usize = 2;
+    /// Messages the channel of a worker can hold at most with `--buffer-size` or `--buffer-bytes`, gigabytes of them.
+    const MAX_BUFFER_SIZE: usize = 100_000_000;
+    /// Input path standing for the standard input, which is also read when no path is given.
+    const STDIN: &'static str = "-";
+
+    /// Reads the options of a run from what clap matched, see `cli::command`, which reads each value into its type.
+    fn from_matches(matches: &ArgMatches) -> Result<Self, String> {
+        // `process` is the same as no subcommand
+        let (subcommand, matches) = matches.subcommand().unwrap_or(("process", matches));
+        let converts = subcommand == "convert";
+        let inspects = subcommand == "inspect";
+        let flag = |id: &str| matches.get_flag(id);
+        let path = |id: &str| matches.get_one::<PathBuf>(id).cloned();
+        let amount = |id: &str| matches.get_one::<Amount>(id).copied();
+        let count = |id: &str| matches.get_one::<u64>(id).copied();
+        let size = |id: &str| matches.get_one::<usize>(id).copied();
+        let quiet = flag("quiet");
+        let verbose = matches.get_count("verbose");
+
+        let inputs = matches.get_many::<String>("inputs").into_iter().flatten();
+        let mut inputs: Vec<PathBuf> = inputs.map(PathBuf::from).collect();
+        let mut options = Options::default();
+        // The output of `convert` comes last, before the inputs of `--glob`
+        if converts {
+            match inputs.len() {
+                2 => options.convert_output = inputs.pop(),
+                _ => return Err("convert needs an input and an output file".to_string()),
+            }
+        }
+        for pattern in matches.get_many::<String>("glob").into_iter().flatten() {
+            inputs.extend(expand_glob(pattern)?);
+        }
+
+        let defaults = ReaderOptions::default();
+        options.reader = ReaderOptions {
+            unique_tx_ids: flag("unique-tx-ids"),
+            strict_precision: flag("strict-precision"),
+            reject_scientific: flag("reject-scientific"),
+            lenient_amounts: flag("lenient-amounts") || flag("decimal-comma"),
+            decimal_comma: flag("decimal-comma"),
+            strict_types: flag("strict-types"),
+            validate_only: flag("validate-only"),
+            max_unknown_ratio: matches.get_one::<f64>("max-unknown-ratio").copied(),
+            encoding: matches.get_one::<Encoding>("encoding").copied().unwrap_or(defaults.encoding),
+            row_limits: RowLimits {
+                field_bytes: size("max-field-bytes").unwrap_or(defaults.row_limits.field_bytes),
+                row_bytes: size("max-row-bytes").unwrap_or(defaults.row_limits.row_bytes),
+            },
+            strict: flag("strict"),
+            format: matches.get_one::<Option<InputFormat>>("format").copied().unwrap_or(defaults.format),
+            delimiter: matches.get_one::<u8>("delimiter").copied(),
+            fast_parse: flag("fast-parse") || matches.contains_id("parse-threads"),
+            parse_threads: size("parse-threads").unwrap_or(defaults.parse_threads),
+            check_chronology: flag("check-chronology"),
+            no_header: flag("no-header"),
+            no_validate_header: flag("no-validate-header"),
+            compression: matches.get_one::<Option<Compression>>("compression").copied().unwrap_or(defaults.compression),
+            follow: flag("follow"),
+            limit_rows: count("limit-rows"),
+            limit_bytes: count("limit-bytes"),
+            ..defaults
+        };
+
+        let policy = &mut options.policy;
+        policy.partial_disputes = flag("partial-disputes");
+        policy.allow_overdraft = flag("allow-overdraft");
+        policy.strict_disputes = flag("strict-disputes");
+        policy.hold_partial = flag("hold-partial");
+        policy.queue_after_lock = flag("queue-after-lock");
+        policy.single_dispute = flag("single-dispute");
+        policy.check_monotonic_tx = flag("check-monotonic-tx");
+        policy.two_phase_deposits = flag("two-phase-deposits");
+        policy.check_invariants = flag("check-invariants");
+        policy.dispute_ttl = count("dispute-ttl");
+        policy.limits.max_balance = amount("max-balance");
+        policy.limits.max_tx_amount = amount("max-tx-amount");
+        policy.fees.withdrawal_flat = amount("withdrawal-fee-flat").unwrap_or(Amount::ZERO);
+        policy.fees.withdrawal_pct = amount("withdrawal-fee-pct").unwrap_or(Amount::ZERO);
+
+        let output = &mut options.output;
+        if flag("extended-output") {
+            output.schema = OutputSchema::EXTENDED;
+        }
+        if let Some(schema) = matches.get_one::<OutputSchema>("columns") {
+            output.schema = *schema;
+        }
+        if let Some(format) = matches.get_one::<OutputFormat>("output-format") {
+            output.format = *format;
+        }
+        output.amounts.precision = size("precision").unwrap_or(output.amounts.precision);
+        output.amounts.trim_zeros = flag("trim-zeros");
+        options.output_filter.only_locked = flag("only-locked");
+        options.output_filter.clients = matches.get_one::<HashSet<ClientId>>("only-clients").cloned();
+        options.unsorted = flag("unsorted");
+
+        options.report_declined = path("report-declined");
+        options.report_rejected = path("report-rejected");
+        options.report_locked = path("report-locked");
+        options.report_mismatched_disputes = path("report-mismatched-disputes");
+        options.report_open_disputes = path("report-open-disputes");
+        options.report_skips = path("report-skips");
+        options.audit_log = path("audit-log");
+        options.trace_clients = matches.get_many::<ClientId>("trace-client").into_iter().flatten().copied().collect();
+        options.trace_output = path("trace-output");
+        options.state_hash_file = path("state-hash-file");
+        options.state_hash = flag("state-hash") || options.state_hash_file.is_some();
+        options.summary_json = path("summary-json");
+        options.manifest = path("manifest");
+        options.output_path = path("output");
+        options.output_shards = path("output-shards");
+        options.save_offset = path("save-offset");
+        options.resume_offset = path("resume-offset");
+        options.dump_interval = matches.get_one::<Duration>("dump-interval").copied();
+        options.workers = size("workers");
+        options.buffer_size = size("buffer-size").or_else(|| size("buffer-bytes"));
+        options.inspect_client = matches.get_one::<ClientId>("client").copied();
+        options.history = flag("history");
+        options.config = matches.get_one::<String>("config").map(PathBuf::from);
+
+        if quiet && verbose > 0 {
+            return Err("--quiet and --verbose can't be used together".to_string());
+        }
+        options.log_level = if quiet { log::Level::Error } else { log::Level::verbose(verbose as usize) };
+
+        options.reader.keep_rejected = options.report_rejected.is_some();
+        options.reader.partial_disputes = options.policy.partial_disputes;
+        options.policy.audit_log = options.audit_log.is_some();
+        options.reader.save_offset = options.save_offset.is_some();
+
+        match (options.trace_clients.is_empty(), &options.trace_output) {
+            (false, None) => return Err("--trace-client needs a --trace-output".to_string()),
+            (true, Some(_)) => return Err("--trace-output needs a --trace-client".to_string()),
+            _ => {}
+        }
+
+        if options.report_declined.is_some() {
+            options.policy.declined_cap = size("declined-cap").unwrap_or(Self::DEFAULT_DECLINED_CAP);
+        }
+
+        if converts && (options.reader.follow || options.dump_interval.is_some()) {
+            return Err("convert reads the input once, without --follow or --dump-interval".to_string());
+        }
+        if inspects {
+            match options.inspect_client {
+                Some(client) => options.reader.only_client = Some(client),
+                None => return Err("inspect needs the --client to write the account of".to_string()),
+            }
+            if options.reader.follow || options.dump_interval.is_some() || options.reader.validate_only {
+                return Err("inspect reads the input once, without --follow, --dump-interval or --validate-only".into());
+            }
+        } else if options.inspect_client.is_some() || options.history {
+            return Err("--client and --history can only be used with inspect".to_string());
+        }
+        if options.reader.validate_only {
+            if converts {
+                return Err("--validate-only can't be used with convert".to_string());
+            }
+            if options.reader.follow || options.dump_interval.is_some() {
+                return Err("--validate-only reads the input once, without --follow or --dump-interval".to_string());
+            }
+        }
+
+        if inputs.is_empty() {
+            inputs.push(PathBuf::from(Self::STDIN));
+        }
+        inputs.sort();
+        inputs.dedup();
+
+        if options.dump_interval.is_some() && options.output_path.is_none() {
+            return Err("--dump-interval needs an --output file".to_string());
+        }
+        if flag("extended-output") && matches.contains_id("columns") {
+            return Err("--extended-output and --columns can't be used together".to_string());
+        }
+        if matches.contains_id("buffer-size") && matches.contains_id("buffer-bytes") {
+            return Err("--buffer-size and --buffer-bytes can't be used together".to_string());
+        }
+        // Shards are written by the workers as they are done, before any check could fail the run
+        if options.output_shards.is_some() {
+            if options.output_path.is_some() {
+                return Err("--output-shards and --output can't be used together".to_string());
+            }
+            if options.output.format == OutputFormat::Table {
+                return Err("--output-shards can't be written as a table".to_string());
+            }
+            if options.policy.check_invariants || options.policy.strict_disputes {
+                return Err("--output-shards can't be used with --check-invariants or --strict-disputes".to_string());
+            }
+        }
+        // The standard input already waits for more until it is closed
+        let is_file = |input: &PathBuf| input != Path::new(Self::STDIN) && !http::is_url(input);
+        if options.reader.follow && !inputs.last().is_some_and(is_file) {
+            return Err("--follow needs a file to follow".to_string());
+        }
+        // Offsets are those of a single file, which the standard input and URLs can't be sought in
+        let keeps_offset = options.save_offset.is_some() || options.resume_offset.is_some();
+        if keeps_offset && (inputs.len() != 1 || !is_file(&inputs[0])) {
+            return Err("--save-offset and --resume-offset need a single input file".to_string());
+        }
+        options.inputs = inputs;
+
+        Ok(options)
+    }
+}
+
+/// Lists the files matching `pattern`, where `*` and `?` in its file name stand for any run of characters
+/// and any single character.
+fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match (pattern.first(), name.first()) {
+            (None, None) => true,
+            (Some('*'), _) => matches(&pattern[1..], name) || (!name.is_empty() && matches(pattern, &name[1..])),
+            (Some('?'), Some(_)) => matches(&pattern[1..], &name[1..]),
+            (Some(p), Some(n)) if p == n => matches(&pattern[1..], &name[1..]),
+            _ => false,
+        }
+    }
+
+    let path = Path::new(pattern);
+    let file_pattern: Vec<char> = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("Invalid pattern {} for --glob", pattern))?
+        .chars()
+        .collect();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let entries = fs::read_dir(dir).map_err(|e| format!("Can't list {} for --glob: {}", dir.display(), e))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Can't list {} for --glob: {}", dir.display(), e))?;
+        let name: Vec<char> = entry.file_name().to_string_lossy().chars().collect();
+        if matches(&file_pattern, &name) && entry.path().is_file() {
+            files.push(path.with_file_name(entry.file_name()));
+        }
+    }
+
+    if files.is_empty() {
+        return Err(format!("No files match {} for --glob", pattern));
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Runs `diff`, exiting like diff(1) with 1 for differences and 2 for trouble.
+fn run_diff(matches: &ArgMatches) -> ! {
+    let result = diff::run(&diff::DiffOptions::from_matches(matches));
+    std::process::exit(match result {
+        Ok(differs) => differs as i32,
+        Err(e) => {
+            log::error!("{}, exiting...", e);
+            2
+        }
+    });
+}
+
+/// Runs `generate`, exiting with 1 if the rows can't be written.
+fn run_generate(matches: &ArgMatches) -> ! {
+    if let Err(e) = generate::run(&generate::GenerateOptions::from_matches(matches)) {
+        log::error!("{}, exiting...", e);
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+/// Reads the options of a run from the config file and the command line. Like clap, which prints the help and the
+/// version, the arguments that can't be read exit with 2.
+fn read_options() -> Options {
+    let args = config::resolve(env::args().skip(1).collect(), env::var("TRANSACTIONER_CONFIG").ok());
+    let options = args.and_then(|args| Options::from_matches(&cli::parse(args).unwrap_or_else(|e| e.exit())));
+    match options {
+        Ok(options) => options,
+        Err(e) => {
+            log::error!("{}, exiting...", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Ends a run that failed before reading anything with 1, telling the manifest why.
+fn exit_failed(manifest: &Manifest, e: String) -> ! {
+    log::error!("{}, exiting...", e);
+    manifest.finish(Err(e));
+    std::process::exit(1);
+}
+
+/// Bytes of rows the inputs hold as far as can be told, before reading them.
+fn input_len(file_paths: &[PathBuf]) -> io::Result<u64> {
+    let mut input_len = 0;
+    for file_path in file_paths {
+        // There's no telling how much the standard input holds, so it gets the smallest buffer
+        if file_path == Path::new(Options::STDIN) {
+            continue;
+        }
+
+        // The body length is only known if the server tells it
+        if http::is_url(file_path) {
+            input_len += http::content_length(&file_path.to_string_lossy()).unwrap_or_default();
+            continue;
+        }
+
+        // Pipes and devices can only be read once and tell no size, so they get the smallest buffer too
+        let metadata = fs::metadata(file_path)?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let file_len = metadata.len();
+        // Transaction files compress to around a fifth of their size, zstd frames may tell theirs
+        input_len += match file_path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => file_len * 5,
+            Some("zst") => {
+                let mut start = [0; 18];
+                let read = File::open(file_path)?.read(&mut start)?;
+                zstd::content_size(&start[..read]).unwrap_or(file_len * 5)
+            }
+            _ => file_len,
+        };
+    }
+
+    Ok(input_len)
+}
+
+/// Here we try to estimate the best buffer size taking into account the amount of work each worker is going to process
+/// the more work each worker has assigned the higher the chance a small buffer may be filled before being processed.
+/// The size of compressed and streamed inputs says little about their rows, which is what `--buffer-size` is for.
+fn estimated_buffer_size(input_len: u64, num_workers: usize) -> usize {
+    let work_per_worker = ((input_len as usize / num_workers) / 25_000_000) + 1;
+    // Min buffer size is 10K messages max size is 5M messages
+    std::cmp::min(10_000 * work_per_worker, 5_000_000)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    // Like those of a run, the arguments of diff and generate that can't be read exit with 2
+    let matches = cli::parse(env::args().skip(1)).unwrap_or_else(|e| e.exit());
+    match matches.subcommand() {
+        Some(("diff", matches)) => run_diff(matches),
+        Some(("generate", matches)) => run_generate(matches),
+        _ => {}
+    }
+    let mut options = read_options();
+    log::set_level(options.log_level);
+    if let Some(config) = &options.config {
+        log::verbose!("Read options from {}, those of the command line taking precedence", config.display());
+    }
+    let mut manifest = Manifest::new(&options, started);
+
+    if let Some(offset_path) = &options.resume_offset {
+        match checkpoint::Checkpoint::load(offset_path) {
+            Ok(checkpoint) => options.reader.resume_from = Some(checkpoint),
+            Err(e) => exit_failed(&manifest, e.to_string()),
+        }
+    }
+
+    let missing =
+        |path: &&PathBuf| path.as_path() != Path::new(Options::STDIN) && !http::is_url(path) && !path.exists();
+    if let Some(file_path) = options.inputs.iter().find(missing) {
+        exit_failed(&manifest, format!("File path {:?} is invalid", file_path));
+    }
+
+    if options.reader.validate_only {
+        let result = validate(&options.inputs, options.reader);
+        manifest.finish(result.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+
+        // Like a run failing, an input that isn't clean exits with 1
+        return match result {
+            Ok(true) => Ok(()),
+            Ok(false) => std::process::exit(1),
+            Err(e) => Err(e),
+        };
+    }
+
+    if let Some(client) = options.inspect_client {
+        let result = inspect(&options.inputs, options.reader, options.policy, options.output, client, options.history);
+        manifest.finish(result.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+
+        return result;
+    }
+
+    if let Some(convert_output) = &options.convert_output {
+        let result = convert(&options.inputs, options.reader, convert_output);
+        manifest.finish(result.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+
+        return result;
+    }
+
+    if let Some(dir) = &options.output_shards {
+        if let Err(e) = fs::create_dir_all(dir) {
+            exit_failed(&manifest, format!("Could not create the shards directory {}: {}", dir.display(), e));
+        }
+    }
+
+    let num_workers = options.workers.unwrap_or(Options::DEFAULT_WORKERS);
+    manifest.workers = Some(num_workers);
+    let buffer_size = match options.buffer_size {
+        Some(buffer_size) => buffer_size,
+        None => estimated_buffer_size(input_len(&options.inputs)?, num_workers),
+    };
+
+    let inputs = match options.inputs.as_slice() {
+        [file_path] => format!("{:?}", file_path),
+        file_paths => format!("{} files {:?}", file_paths.len(), file_paths),
+    };
+    log::info!(
+        "Using {} worker thread/s to process {} using a channel buffer size of {} Bytes, {} message/s{}",
+        num_workers,
+        inputs,
+        buffer_size * std::mem::size_of::<WorkerMessage>(),
+        buffer_size,
+        if options.buffer_size.is_some() { "" } else { " estimated from the size of the input" }
+    );
+
+    // Reading stops on Ctrl-C, the accounts read so far are written as usual
+    if options.reader.follow {
+        signal::handle_interrupts();
+    }
+
+    let rt = Builder::new_multi_thread()
+        .worker_threads(num_workers + 1)
+        .enable_time()
+        .build()?;
+
+    let result = rt.block_on(process(Arc::new(options), num_workers, buffer_size, &mut manifest));
+    manifest.finish(result.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+
+    result
+}
+
+/// What the workers and the reader of a run leave behind, gathered once they are all done.
+#[derive(Default)]
+struct Collected {
+    /// Accounts of each worker, once it is done.
+    states: Mutex<Vec<Vec<ClientState>>>,
+    diagnostics: Mutex<Diagnostics>,
+    declined: Mutex<Vec<DeclinedTransaction>>,
+    mismatched_disputes: Mutex<Vec<MismatchedDispute>>,
+    rejected_amounts: Mutex<Vec<RejectedAmount>>,
+    reference_amount_rows: Mutex<Vec<u64>>,
+    unknown_types: Mutex<UnknownTypes>,
+    events: Mutex<Vec<AuditEntry>>,
+    open_disputes: Mutex<Vec<OpenDispute>>,
+    undisputed_settlements: Mutex<Vec<UndisputedSettlement>>,
+    violations: Mutex<Vec<(ClientId, Currency, InvariantViolation)>>,
+    shards: Mutex<Vec<Result<Shard, String>>>,
+    /// What the writer of the streamed accounts wrote, if they are streamed.
+    streamed_totals: Mutex<Option<Result<AccountTotals, String>>>,
+    /// How the writer of `--report-rejected` did, if given.
+    rejected_report: Mutex<Option<Result<(), String>>>,
+    /// Only the message of the error the reading stopped with is kept.
+    reader_error: Mutex<Option<String>>,
+    checkpoint: Mutex<Option<checkpoint::Checkpoint>>,
+}
+
+impl Collected {
+    /// Takes what the reports need out of the accounts of a worker.
+    fn add_worker(&self, accounts: &mut ClientAccounts, diagnostics: &Diagnostics, options: &Options) {
+        let policy = &options.policy;
+        if let Ok(mut data) = self.diagnostics.lock() {
+            data.merge(diagnostics);
+        }
+
+        if policy.declined_cap > 0 {
+            if let Ok(mut data) = self.declined.lock() {
+                for account in accounts.values_mut() {
+                    data.append(&mut account.records.declined);
+                }
+            }
+        }
+
+        if policy.strict_disputes {
+            if let Ok(mut data) = self.undisputed_settlements.lock() {
+                for account in accounts.values_mut() {
+                    data.append(&mut account.records.undisputed_settlements);
+                }
+            }
+        }
+
+        if policy.check_invariants {
+            if let Ok(mut data) = self.violations.lock() {
+                for account in accounts.values() {
+                    for (currency, violation) in account.check_invariants(policy) {
+                        data.push((account.client, currency, violation));
+                    }
+                }
+            }
+        }
+
+        if policy.audit_log {
+            if let Ok(mut data) = self.events.lock() {
+                for account in accounts.values_mut() {
+                    data.append(&mut account.records.events);
+                }
+            }
+        }
+
+        if options.report_open_disputes.is_some() {
+            if let Ok(mut data) = self.open_disputes.lock() {
+                for account in accounts.values() {
+                    data.extend(account.open_disputes());
+                }
+            }
+        }
+    }
+
+    /// Takes what the reader found out, but for the rejected rows, which go to their own writer.
+    fn add_reader(&self, result: &mut ReaderOutput) {
+        if let Ok(mut data) = self.diagnostics.lock() {
+            data.merge(&result.diagnostics);
+        }
+
+        if let Ok(mut data) = self.rejected_amounts.lock() {
+            data.append(&mut result.rejected_amounts);
+        }
+
+        if let Ok(mut data) = self.reference_amount_rows.lock() {
+            data.append(&mut result.reference_amount_rows);
+        }
+
+        if let Ok(mut data) = self.unknown_types.lock() {
+            *data = std::mem::take(&mut result.unknown_types);
+        }
+
+        if let Ok(mut data) = self.checkpoint.lock() {
+            *data = result.checkpoint.take();
+        }
+    }
+
+    /// Fails the run if the reading did, or if any check asked for found something wrong with the accounts.
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        // The accounts only went through part of the file, so they aren't worth printing
+        if let Ok(data) = self.reader_error.lock() {
+            if let Some(e) = data.as_ref() {
+                return Err(format!("Stopped reading the input: {}", e).into());
+            }
+        }
+
+        if let Ok(data) = self.rejected_amounts.lock() {
+            for rejected in data.iter() {
+                log::info!("Row {}: {}", rejected.row, rejected.reason);
+            }
+        }
+
+        if let Ok(data) = self.reference_amount_rows.lock() {
+            if !data.is_empty() {
+                for row in data.iter() {
+                    log::error!("Row {}: amount on a transaction referencing a previous one", row);
+                }
+
+                return Err(format!("Found {} reference transaction/s carrying an amount", data.len()).into());
+            }
+        }
+
+        if let Ok(mut data) = self.violations.lock() {
+            if !data.is_empty() {
+                data.sort_by_key(|(client, currency, _)| (*client, *currency));
+
+                for (client, currency, violation) in data.iter() {
+                    if *currency == Currency::NONE {
+                        log::error!("Client {}: {}", client, violation);
+                    } else {
+                        log::error!("Client {} in {}: {}", client, currency, violation);
+                    }
+                }
+
+                return Err(format!("Found {} invariant violation/s", data.len()).into());
+            }
+        }
+
+        if let Ok(mut data) = self.undisputed_settlements.lock() {
+            if !data.is_empty() {
+                data.sort_by_key(|settlement| settlement.row);
+
+                for settlement in data.iter() {
+                    log::error!(
+                        "Row {}: {:?} of tx {} for client {} without a prior dispute",
+                        settlement.row, settlement.r#type, settlement.tx, settlement.client
+                    );
+                }
+
+                return Err(format!("Found {} resolve/s or chargeback/s without a prior dispute", data.len()).into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Options {
+    /// Whether the accounts are written as each worker is done. The table needs every account to size its columns,
+    /// and the checks failing the run after the workers are done need them before any is written, so those are
+    /// never streamed.
+    fn streams(&self) -> bool {
+        self.unsorted
+            && self.output_shards.is_none()
+            && self.output.format != OutputFormat::Table
+            && !self.policy.check_invariants
+            && !self.policy.strict_disputes
+    }
+
+    /// Whether the accounts are kept once written. Streaming and sharding only spare the memory of the accounts if
+    /// no report needs them afterwards.
+    fn keeps_states(&self) -> bool {
+        !(self.streams() || self.output_shards.is_some())
+            || self.state_hash
+            || self.report_locked.is_some()
+            || self.report_skips.is_some()
+            || self.policy.check_monotonic_tx
+    }
+}
+
+/// Applies the inputs to the accounts with a worker for each share of the clients, then writes the accounts and the
+/// reports asked for.
+async fn process(
+    options: Arc<Options>,
+    num_workers: usize,
+    buffer_size: usize,
+    manifest: &mut Manifest,
+) -> Result<(), Box<dyn Error>> {
+    let mut handle_set = Vec::with_capacity(num_workers);
+    let mut sender_set = Vec::with_capacity(num_workers);
+    let collected = Arc::new(Collected::default());
+    let tracer = match options.trace_clients.is_empty() {
+        true => None,
+        false => Some(Tracer { clients: Arc::new(options.trace_clients.clone()), entries: Arc::default() }),
+    };
+
+    // Streamed accounts go through a single writer, and shards through each worker, which wait for the reader
+    // to tell it read every row
+    let (stream_sender, mut stream_receiver) = tokio::sync::mpsc::channel(buffer_size);
+    let (reader_outcome, reader_outcome_receiver) = watch::channel(ReaderOutcome::Reading);
+    if options.streams() {
+        let (options, collected) = (options.clone(), collected.clone());
+        let mut reader_outcome_receiver = reader_outcome_receiver.clone();
+        handle_set.push(tokio::task::spawn_blocking(move || {
+            let result = stream_client_accounts_state(
+                options.output_path.as_deref(),
+                &mut reader_outcome_receiver,
+                &mut stream_receiver,
+                &options.output_filter,
+                options.output,
+            );
+            if let Ok(mut data) = collected.streamed_totals.lock() {
+                *data = Some(result);
+            }
+        }));
+    }
+    let stream_sender = options.streams().then_some(stream_sender);
+
+    // Rejected transactions are written by their own task, the workers only wait on it if its channel is full
+    let reject_sender = match &options.report_rejected {
+        Some(report_path) => {
+            let (sender, mut receiver) = tokio::sync::mpsc::channel(buffer_size);
+            let report_path = report_path.clone();
+            let collected = collected.clone();
+            handle_set.push(tokio::task::spawn_blocking(move || {
+                let result = write_rejected_report(&report_path, &mut receiver);
+                if let Ok(mut data) = collected.rejected_report.lock() {
+                    *data = Some(result);
+                }
+            }));
+
+            Some(sender)
+        }
+        None => None,
+    };
+
+    let dispute_lookup = Arc::new(DisputeLookup::new(num_workers));
+    for worker_index in 0..num_workers {
+        let (tx, rx) = tokio::sync::mpsc::channel(buffer_size);
+        sender_set.push(tx);
+        let (options, collected, dispute_lookup) = (options.clone(), collected.clone(), dispute_lookup.clone());
+        let (stream_sender, rejects, tracer) = (stream_sender.clone(), reject_sender.clone(), tracer.clone());
+        let mut reader_outcome = reader_outcome_receiver.clone();
+        handle_set.push(tokio::spawn(async move {
+            let (mut account_map, mut local_diagnostics) =
+                run_worker(rx, options.policy, rejects.clone(), tracer).await;
+            let mut mismatched = dispute_lookup.resolve(&mut account_map, &mut local_diagnostics, &rejects).await;
+            if let Ok(mut data) = collected.mismatched_disputes.lock() {
+                data.append(&mut mismatched);
+            }
+            log::verbose!(
+                "Worker {} applied {} transaction/s to {} account/s",
+                worker_index,
+                local_diagnostics.outcomes.applied,
+                account_map.len()
+            );
+            collected.add_worker(&mut account_map, &local_diagnostics, &options);
+
+            let outcome = &mut reader_outcome;
+            let states = worker_states(worker_index, account_map, &options, &collected, stream_sender, outcome).await;
+            if let Ok(mut data) = collected.states.lock() {
+                data.push(states);
+            }
+        }));
+    }
+    drop(stream_sender);
+
+    // Snapshots go through the channels the rows do, after every row sent before them
+    let snapshot_lock = options.dump_interval.map(|_| Arc::new(tokio::sync::Mutex::new(())));
+    let (reader_done, mut reader_finished) = oneshot::channel::<()>();
+    if let (Some(interval), Some(lock), Some(path)) =
+        (options.dump_interval, snapshot_lock.clone(), options.output_path.clone())
+    {
+        let snapshot_senders = sender_set.clone();
+        let options = options.clone();
+        handle_set.push(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    // The final accounts are written once the workers are done
+                    _ = &mut reader_finished => break,
+                }
+
+                let accounts = match take_snapshot(&snapshot_senders, &lock).await {
+                    Some(accounts) => accounts,
+                    None => break,
+                };
+                let accounts = options.output_filter.apply(&accounts);
+                if let Err(e) = save_client_accounts_state(&path, &accounts, options.output) {
+                    log::error!("{}", e);
+                }
+            }
+        }));
+    }
+
+    let (reader_options, reader_collected) = (options.clone(), collected.clone());
+    handle_set.push(tokio::spawn(async move {
+        let (options, collected) = (reader_options, reader_collected);
+        let result = extract_files(&options.inputs, num_workers, sender_set, options.reader, snapshot_lock);
+        // Only the message of the error is kept, as the rejected rows may still wait on their channel below
+        let result = result.await.map_err(|e| e.to_string());
+        let _ = reader_done.send(());
+        // Reference rows carrying an amount fail the run once the workers are done, like a reading error
+        let outcome = match &result {
+            Ok(result) if result.reference_amount_rows.is_empty() => {
+                ReaderOutcome::Read { currency: result.diagnostics.currency_rows > 0 }
+            }
+            _ => ReaderOutcome::Failed,
+        };
+        let _ = reader_outcome.send(outcome);
+
+        match result {
+            Ok(mut result) => {
+                collected.add_reader(&mut result);
+                if let Some(rejects) = reject_sender {
+                    for rejected in result.rejected_rows.drain(..) {
+                        let _ = rejects.send(rejected).await;
+                    }
+                }
+            }
+            Err(e) => {
+                if let Ok(mut data) = collected.reader_error.lock() {
+                    *data = Some(e);
+                }
+            }
+        }
+    }));
+
+    futures::future::join_all(handle_set).await;
+
+    collected.check()?;
+    let written_totals = write_accounts(&options, &collected)?;
+
+    if let (Ok(data), Ok(accounts)) = (collected.diagnostics.lock(), collected.states.lock()) {
+        data.report(&options.reader, &options.policy);
+
+        let totals = written_totals.unwrap_or_else(|| AccountTotals::new(accounts.as_ref()));
+        let summary = Summary::new(*data, totals, manifest.started.elapsed());
+        summary.report();
+        if let Ok(unknown_types) = collected.unknown_types.lock() {
+            unknown_types.report();
+        }
+        if let Some(summary_path) = &options.summary_json {
+            summary.write_json(summary_path)?;
+        }
+        manifest.summary = Some(summary);
+    };
+
+    write_reports(&options, &collected, tracer.as_ref())
+}
+
+/// Hands the accounts of a worker to the streamed output or to its shard, returning them if they are still needed.
+async fn worker_states(
+    worker_index: usize,
+    account_map: ClientAccounts,
+    options: &Options,
+    collected: &Collected,
+    stream_sender: Option<Sender<Vec<ClientState>>>,
+    reader_outcome: &mut watch::Receiver<ReaderOutcome>,
+) -> Vec<ClientState> {
+    let mut states = match stream_sender {
+        Some(sender) => {
+            let mut kept = Vec::new();
+            for account in account_map.into_values() {
+                let states = account.into_states();
+                if options.keeps_states() {
+                    kept.extend(states.iter().cloned());
+                }
+                // The writer takes every account until the last worker is done
+                let _ = sender.send(states).await;
+            }
+            kept
+        }
+        None => account_map.into_values().flat_map(ClientAccount::into_states).collect(),
+    };
+
+    if let Some(dir) = &options.output_shards {
+        if let ReaderOutcome::Read { currency } = wait_for_reader(reader_outcome).await {
+            if !options.unsorted {
+                states.sort_unstable_by_key(|state| (state.client, state.currency));
+            }
+            let output_options = OutputOptions { with_currency: currency, ..options.output };
+            let shard = tokio::task::block_in_place(|| {
+                save_shard(dir, worker_index, &states, &options.output_filter, output_options)
+            });
+            if let Ok(mut data) = collected.shards.lock() {
+                data.push(shard);
+            }
+        }
+        if !options.keeps_states() {
+            states = Vec::new();
+        }
+    }
+
+    states
+}
+
+/// Writes the accounts, unless they were streamed or sharded, and the reports made from them, returning the totals
+/// of the accounts written by the streamed or sharded output.
+fn write_accounts(options: &Options, collected: &Collected) -> Result<Option<AccountTotals>, Box<dyn Error>> {
+    let mut written_totals = match collected.streamed_totals.lock().map(|mut data| data.take()) {
+        Ok(Some(result)) => Some(result?),
+        _ => None,
+    };
+
+    if let Some(dir) = &options.output_shards {
+        if let Ok(mut data) = collected.shards.lock() {
+            let mut shards = data.drain(..).collect::<Result<Vec<_>, _>>()?;
+            shards.sort_by_key(|shard| shard.index);
+            write_shards_manifest(dir.join("manifest.csv"), &shards)?;
+
+            let mut totals = AccountTotals::default();
+            for shard in &shards {
+                totals.merge(&shard.totals);
+            }
+            written_totals = Some(totals);
+        }
+    }
+
+    if let Ok(mut data) = collected.states.lock() {
+        if !options.unsorted {
+            sort_by_client(&mut data);
+        }
+        let shown = options.output_filter.apply(data.as_ref());
+        match &options.output_path {
+            _ if options.streams() || options.output_shards.is_some() => {}
+            Some(path) => save_client_accounts_state(path, &shown, options.output)?,
+            None => match print_client_accounts_state(&shown, options.output) {
+                // Whoever reads the output stopped early, which is up to them
+                Err(e) if is_broken_pipe(&e) => {}
+                result => result?,
+            },
+        }
+
+        if let Some(report_path) = &options.report_locked {
+            write_locked_report(report_path, data.as_ref())?;
+        }
+
+        if let Some(report_path) = &options.report_skips {
+            write_skips_report(report_path, data.as_ref())?;
+        }
+
+        if options.policy.check_monotonic_tx {
+            report_tx_order(data.as_ref());
+        }
+
+        if options.state_hash {
+            let hash = format!("{:016x}", state_hash(data.iter().flatten()));
+            log::info!("State hash: {}", hash);
+            if let Some(hash_path) = &options.state_hash_file {
+                fs::write(hash_path, format!("{}\n", hash))
+                    .map_err(|e| format!("Could not write the state hash to {}: {}", hash_path.display(), e))?;
+            }
+        }
+    };
+
+    Ok(written_totals)
+}
+
+/// Writes the reports of the transactions, and saves the offset reached last, once everything the rows before it
+/// led to has been written.
+fn write_reports(options: &Options, collected: &Collected, tracer: Option<&Tracer>) -> Result<(), Box<dyn Error>> {
+    if let Some(report_path) = &options.report_declined {
+        if let Ok(data) = collected.declined.lock() {
+            write_declined_report(report_path, data.as_ref())?;
+        }
+    }
+
+    if let Ok(mut data) = collected.rejected_report.lock() {
+        if let Some(result) = data.take() {
+            result?;
+        }
+    }
+
+    if let Some(log_path) = &options.audit_log {
+        if let Ok(mut data) = collected.events.lock() {
+            // Stable, so the events of each client stay in the order they happened
+            data.sort_by_key(|entry| entry.client);
+            write_audit_log(log_path, data.as_ref())?;
+        }
+    }
+
+    if let (Some(tracer), Some(trace_path)) = (tracer, &options.trace_output) {
+        if let Ok(mut data) = tracer.entries.lock() {
+            // The transactions of each client are recorded by the worker owning it, in the order they were read
+            data.sort_by_key(|entry| (entry.client, entry.row));
+            write_trace(File::create(trace_path)?, data.as_ref())?;
+        }
+    }
+
+    if let Some(report_path) = &options.report_mismatched_disputes {
+        if let Ok(mut data) = collected.mismatched_disputes.lock() {
+            // Each worker found its own
+            data.sort_by_key(|dispute| dispute.row);
+            write_mismatched_disputes_report(report_path, data.as_ref())?;
+        }
+    }
+
+    if let Some(report_path) = &options.report_open_disputes {
+        if let Ok(mut data) = collected.open_disputes.lock() {
+            // Stable, so the disputes of each client stay sorted by tx id
+            data.sort_by_key(|dispute| dispute.client);
+            write_open_disputes_report(report_path, data.as_ref())?;
+        }
+    }
+
+    if let Some(offset_path) = &options.save_offset {
+        if let Ok(data) = collected.checkpoint.lock() {
+            if let Some(checkpoint) = data.as_ref() {
+                checkpoint.save(offset_path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the transactions read from the input to a binary file, see `tbin`. They are read the way
+/// they would be applied, so the rows dropped by the reader are left out.
+fn convert(file_paths: &[PathBuf], reader_options: ReaderOptions, output_path: &Path) -> Result<(), Box<dyn Error>> {
+    let rt = Builder::new_current_thread().enable_time().build()?;
+
+    rt.block_on(async {
+        let mut output = BufWriter::new(File::create(output_path)?);
+        tbin::write_header(&mut output)?;
+
+        // A single channel, so transfers and merges come as plain transactions
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<WorkerMessage>(100_000);
+        // Drains the channel before the reader gets its turn again, rather than a few rows at a time
+        let writer = tokio::spawn(tokio::task::unconstrained(async move {
+            let mut written = 0;
+            while let Some(message) = rx.recv().await {
+                if let Some(transaction) = message.transaction() {
+                    output.write_all(&tbin::encode(transaction)?).map_err(|e| e.to_string())?;
+                    written += 1;
+                }
+            }
+            output.flush().map_err(|e| e.to_string())?;
+
+            Ok::<u64, String>(written)
+        }));
+
+        let result = extract_files(file_paths, 1, vec![tx], reader_options, None).await;
+        let written = writer.await??;
+        let result = result.map_err(|e| format!("Stopped reading the input: {}", e))?;
+        let diagnostics = result.diagnostics;
+        log::info!("Processed {} row/s", diagnostics.rows);
+        log::info!("{} row/s skipped due to parse errors", diagnostics.malformed_rows);
+        log::info!("Wrote {} transaction/s to {}", written, output_path.display());
+
+        Ok(())
+    })
+}
+
+/// Reads the input without applying any of it, printing what is wrong with it, and returns whether it is clean.
+fn validate(file_paths: &[PathBuf], reader_options: ReaderOptions) -> Result<bool, Box<dyn Error>> {
+    let rt = Builder::new_current_thread().enable_time().build()?;
+
+    // Nothing is sent to the workers, of which there are none
+    let output = rt.block_on(extract_files(file_paths, 1, Vec::new(), reader_options, None));
+    let output = output.map_err(|e| format!("Stopped reading the input: {}", e))?;
+
+    let mut report = String::new();
+    let clean = write_validation_report(&mut report, &output, reader_options.unique_tx_ids);
+    print!("{}", report);
+
+    Ok(clean)
+}
+
+/// Applies the rows of a single client, the others being skipped before they are parsed, and writes its account,
+/// after each of its transactions and the balances they left with `history`.
+fn inspect(
+    file_paths: &[PathBuf],
+    reader_options: ReaderOptions,
+    policy: AccountPolicy,
+    output_options: OutputOptions,
+    client: ClientId,
+    history: bool,
+) -> Result<(), Box<dyn Error>> {
+    let rt = Builder::new_current_thread().enable_time().build()?;
+    let tracer = Tracer { clients: Arc::new(HashSet::from([client])), entries: Arc::default() };
+
+    rt.block_on(async {
+        // A single worker, which only the rows of the client reach
+        let (tx, rx) = tokio::sync::mpsc::channel(10_000);
+        let worker = tokio::spawn(run_worker(rx, policy, None, history.then(|| tracer.clone())));
+
+        let result = extract_files(file_paths, 1, vec![tx], reader_options, None).await;
+        let (mut accounts, _) = worker.await?;
+        let diagnostics = result.map_err(|e| format!("Stopped reading the input: {}", e))?.diagnostics;
+        log::info!(
+            "Read {} row/s, {} of client {}",
+            diagnostics.rows,
+            diagnostics.rows - diagnostics.other_client_rows,
+            client
+        );
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if history {
+            if let Ok(mut entries) = tracer.entries.lock() {
+                entries.sort_by_key(|entry| entry.row);
+                write_trace(&mut stdout, entries.as_ref())?;
+            }
+            writeln!(stdout)?;
+        }
+        let states = accounts.remove(&client).map(ClientAccount::into_states).unwrap_or_default();
+        if states.is_empty() {
+            log::info!("Client {} has no account in the input", client);
+        }
+        write_client_accounts_state(stdout, &[states], output_options)?;
+
+        Ok(())
+    })
+}
+
+/// Writes the rows of each type the input holds, and then the rows the reader would drop, by why it would.
+fn write_validation_report(report: &mut String, output: &ReaderOutput, unique_tx_ids: bool) -> bool {
+    use std::fmt::Write;
+
+    let diagnostics = &output.diagnostics;
+    let _ = writeln!(report, "rows: {}", diagnostics.rows);
+    // Told apart below, with the other rows that would be dropped
+    let types = diagnostics.types.iter().filter(|(r#type, count)| *count > 0 && *r#type != TransactionType::Unknown);
+    for (r#type, count) in types {
+        let _ = writeln!(report, "{} rows: {}", r#type.name(), count);
+    }
+
+    let rejected_amounts =
+        diagnostics.non_finite_amounts + diagnostics.scientific_amounts + diagnostics.imprecise_amounts;
+    let mut problems = vec![
+        ("malformed rows", diagnostics.malformed_rows),
+        ("rows of an unknown type", diagnostics.types.count(TransactionType::Unknown)),
+        ("invalid amounts", diagnostics.invalid_amounts),
+        ("rejected amounts", rejected_amounts),
+    ];
+    // Tx ids are only told to be reused across clients with the global index
+    if unique_tx_ids {
+        problems.push(("reused tx ids", diagnostics.reused_tx_ids));
+    }
+    for (problem, count) in &problems {
+        let _ = writeln!(report, "{}: {}", problem, count);
+    }
+
+    for error in &output.malformed_errors {
+        let _ = writeln!(report, "malformed row of {}", error);
+    }
+    for (name, count) in output.unknown_types.sorted() {
+        let _ = writeln!(report, "unknown type {:?}: {} row/s", name, count);
+    }
+    for row in &output.reused_tx_id_rows {
+        let _ = writeln!(report, "reused tx id at row {}", row);
+    }
+
+    let clean = problems.iter().all(|(_, count)| *count == 0);
+    let _ = writeln!(report, "{}", if clean { "clean" } else { "not clean" });
+
+    clean
+}
+
+/// Reads the files one after the other as a single input, their rows are numbered as if the files
+/// had been concatenated so they keep giving the order in which transactions apply. Each row is sent
+/// holding `snapshot_lock`, so the snapshots taken under it see every row before some point and none after.
+async fn extract_files<P: AsRef<Path> + Sync>(
+    file_paths: &[P],
+    num_workers: usize,
+    sender_vec: Vec<Sender<WorkerMessage>>,
+    options: ReaderOptions,
+    snapshot_lock: Option<Arc<tokio::sync::Mutex<()>>>,
+) -> Result<ReaderOutput, Box<dyn Error>> {
+    let mut output = ReaderOutput::default();
+    let mut seen_tx_ids = HashSet::<u32, RandomXxHashBuilder64>::default();
+
+    // Lines before the offset a resumed input is read from, so its rows keep their line numbers
+    let mut row_offset = options.resume_from.map_or(0, |checkpoint| checkpoint.line);
+    // Latest timestamp read so far, the inputs are read as a single file
+    let mut latest_timestamp = None;
+    // Shared by the inputs, which count towards the same limit
+    let byte_limit = options.limit_bytes.map(|bytes| Arc::new(InputLimit::new(bytes)));
+
+    for (index, file_path) in file_paths.iter().enumerate() {
+        let file_path = file_path.as_ref();
+        let mut last_row = row_offset;
+        // Only the last input can be followed, the rest would never be reached
+        let follow = options.follow && index + 1 == file_paths.len();
+        let rows_before = output.diagnostics.rows;
+        log::verbose!("Reading {}", file_path.display());
+
+        let file_result: Result<(), Box<dyn Error>> = async {
+            let mut format = options.format.unwrap_or_else(|| InputFormat::from_path(file_path));
+            let mut delimiter = options.delimiter.unwrap_or_else(|| default_delimiter(file_path));
+            let from_stdin = file_path == Path::new(Options::STDIN);
+            let from_url = http::is_url(file_path);
+
+            let keeps_offset = options.save_offset || options.resume_from.is_some();
+            if keeps_offset && format != InputFormat::Csv {
+                return Err("offsets are only kept for CSV inputs".into());
+            }
+            // Resuming one reads the header from the start of the file, then seeks
+            if keeps_offset && options.encoding == Encoding::Latin1 {
+                return Err("offsets aren't kept for Latin-1 inputs, which are parsed once decoded".into());
+            }
+            if keeps_offset && !fs::metadata(file_path)?.is_file() {
+                return Err("offsets are only kept for regular files, not pipes or devices".into());
+            }
+            // Following reads whole lines, which binary inputs don't have
+            if follow && format == InputFormat::Binary {
+                return Err("binary inputs can't be followed".into());
+            }
+            // A resumed input is read from the offset saved for it, as long as it's still the same file
+            let open_input = || -> Result<File, Box<dyn Error>> {
+                let mut file = File::open(file_path)?;
+                if let Some(checkpoint) = options.resume_from {
+                    checkpoint.check(&file.metadata()?)?;
+                    file.seek(SeekFrom::Start(checkpoint.offset))?;
+                }
+
+                Ok(file)
+            };
+            // Rows read from an offset come after the header, which is read from the start of the file
+            let given_headers = match options.resume_from {
+                _ if options.no_header => Some(Transaction::positional_headers()),
+                Some(_) => {
+                    let mut headers = csv::StringRecord::new();
+                    let mut records = InputRecords::csv(Box::new(File::open(file_path)?), delimiter, false, &options);
+                    records.read_header(&mut headers)?;
+                    Some(headers)
+                }
+                None => None,
+            };
+
+            // Followed and resumed inputs keep the format of their extension, their start is read as it comes
+            let mut sniffs = options.format.is_none() && !follow && !keeps_offset;
+            // Format and delimiter told from the start of the input, as long as it isn't only blank lines
+            let sniffed = move |start: &[u8], compression: Compression| -> Option<(InputFormat, u8)> {
+                let sniffed = format_sniffer::sniff(start)?;
+                let compressed = match compression {
+                    Compression::None => "",
+                    Compression::Gzip => ", gzip compressed",
+                    Compression::Zstd => ", zstd compressed",
+                };
+                log::info!("Reading {} as {}{}, see --format", file_path.display(), sniffed, compressed);
+
+                Some((sniffed.format, options.delimiter.or(sniffed.delimiter).unwrap_or(delimiter)))
+            };
+
+            // The fast path reads the whole file, what it can't handle is read back from that copy.
+            // It only reads UTF-8, Latin-1 files are decoded as they are read instead
+            let mut data = None;
+            if options.fast_parse
+                && format == InputFormat::Csv
+                && options.encoding != Encoding::Latin1
+                && !from_stdin
+                && !from_url
+                && !follow
+            {
+                let mut bytes = Vec::new();
+                open_input()?.read_to_end(&mut bytes)?;
+                data = Some(bytes);
+            }
+
+            let compression_of = |start: &[u8]| options.compression.unwrap_or_else(|| Compression::detect(start));
+            if let Some(data) = data.as_ref().filter(|data| sniffs && compression_of(data) == Compression::None) {
+                let start = &data[..data.len().min(format_sniffer::SAMPLE_SIZE)];
+                if let Some(told) = sniffed(start, Compression::None) {
+                    (format, delimiter) = told;
+                }
+                sniffs = false;
+            }
+
+            let mut headers = csv::StringRecord::new();
+            // Every line before the header is blank or a comment
+            let mut header_line = 0;
+            let mut records = match data {
+                Some(data)
+                    if compression_of(&data) == Compression::None
+                        && format == InputFormat::Csv
+                        && fast_csv::FastRecords::supports(&data) =>
+                {
+                    let mut data = data;
+                    if let Some(limit) = &byte_limit {
+                        data.truncate(limit.take(&data, true));
+                    }
+
+                    let records = fast_csv::FastRecords::new(data, delimiter, options.row_limits, given_headers);
+                    headers = records.headers().clone();
+                    header_line = records.line();
+                    if !options.no_validate_header {
+                        check_headers(&headers)?;
+                    }
+
+                    // The rows parsed in ranges don't keep their offsets
+                    match options.parse_threads {
+                        threads if threads > 1 && !options.save_offset => parse_chunks(records, threads, options),
+                        _ => InputRecords::Fast(records),
+                    }
+                }
+                data => {
+                    let input: Box<dyn Read + Send> = match data {
+                        Some(data) => Box::new(io::Cursor::new(data)),
+                        None if from_stdin => Box::new(io::stdin()),
+                        None if from_url => Box::new(http::get(&file_path.to_string_lossy())?),
+                        None if follow => Box::new(FollowFile::new(open_input()?, &signal::INTERRUPTED)),
+                        None => Box::new(open_input()?),
+                    };
+                    let mut input = BufReader::new(input);
+                    let compression = compression_of(input.fill_buf()?);
+                    if keeps_offset && compression != Compression::None {
+                        return Err("offsets can't be kept for compressed inputs".into());
+                    }
+                    let mut input: Box<dyn Read + Send> = match compression {
+                        Compression::None => Box::new(input),
+                        Compression::Gzip => Box::new(gzip::GzDecoder::new(input)),
+                        Compression::Zstd => Box::new(zstd::ZstdDecoder::new(input)),
+                    };
+                    if sniffs {
+                        let mut start = Vec::with_capacity(format_sniffer::SAMPLE_SIZE);
+                        let error = (&mut input).take(format_sniffer::SAMPLE_SIZE as u64).read_to_end(&mut start).err();
+                        if let Some(told) = sniffed(&start, compression) {
+                            (format, delimiter) = told;
+                        }
+                        input = Box::new(ReadStart { start: io::Cursor::new(start), error, input });
+                    }
+                    let input: Box<dyn Read + Send> = match &byte_limit {
+                        Some(limit) => Box::new(LimitedInput::new(input, Arc::clone(limit))),
+                        None => input,
+                    };
+                    let input: Box<dyn Read + Send> = match options.encoding {
+                        Encoding::Latin1 => Box::new(Latin1Input::new(input)),
+                        Encoding::Utf8 | Encoding::Utf8Lossy => input,
+                    };
+                    let lossy = options.encoding == Encoding::Utf8Lossy;
+                    match format {
+                        InputFormat::Csv => {
+                            let mut records = InputRecords::csv(input, delimiter, follow, &options);
+
+                            match given_headers {
+                                Some(given_headers) => headers = given_headers,
+                                None => header_line = records.read_header(&mut headers)?,
+                            }
+                            if !options.no_validate_header {
+                                check_headers(&headers)?;
+                            }
+
+                            records
+                        }
+                        InputFormat::Json => InputRecords::Json {
+                            input: BufReader::new(Box::new(ReadAhead::new(input, follow))),
+                            text: String::new(),
+                            line: 0,
+                            lossy,
+                            replaced_rows: 0,
+                        },
+                        InputFormat::Binary => {
+                            let mut input = BufReader::new(Box::new(ReadAhead::new(input, false)) as Box<dyn Read + Send>);
+                            tbin::read_header(&mut input)?;
+
+                            InputRecords::Binary { input, record: [0; tbin::RECORD_SIZE] }
+                        }
+                    }
+                }
+            };
+            let mut record = csv::StringRecord::new();
+            // Bytes and lines up to the end of the last row gone through, a row left unread at a limit excluded
+            let mut consumed = records.position();
+
+            let diagnostics = &mut output.diagnostics;
+            diagnostics.skipped_lines += header_line.saturating_sub(1);
+
+            // Malformed rows are skipped, or stop the reader in strict mode
+            let skip_malformed = |diagnostics: &mut Diagnostics, error: String| {
+                if options.strict {
+                    return Err(error);
+                }
+
+                // Listed in the validation report instead
+                if !options.validate_only {
+                    log::info!("Skipped a malformed row of {}: {}", file_path.display(), error);
+                }
+                diagnostics.malformed_rows += 1;
+
+                Ok(())
+            };
+
+            loop {
+                // Not a `while let`, the error would be held across the sends below
+                let (line, parsed) = match records.next_row(&mut headers, &mut record, &options) {
+                    Ok(Some(row)) => row,
+                    Ok(None) => break,
+                    Err(e) => return Err(e),
+                };
+                if let ParsedRow::Skipped | ParsedRow::Header = parsed {
+                    match parsed {
+                        ParsedRow::Header => diagnostics.repeated_headers += 1,
+                        _ => diagnostics.skipped_lines += 1,
+                    }
+                    consumed = records.position();
+                    continue;
+                }
+                if options.limit_rows == Some(diagnostics.rows) {
+                    diagnostics.truncated = true;
+                    break;
+                }
+                consumed = records.position();
+
+                let row = row_offset + line;
+                last_row = last_row.max(row);
+                diagnostics.rows += 1;
+                // The clock is only read every few thousand rows, and only when the progress is written at all
+                if diagnostics.rows % 4096 == 0 && log::enabled(log::Level::Progress) && PROGRESS.ready(0) {
+                    log::progress!("Read {} row/s so far", diagnostics.rows);
+                }
+
+                let mut transaction = match parsed {
+                    ParsedRow::Transaction { transaction, truncated, aliased, unknown_type } => {
+                        if truncated {
+                            diagnostics.truncated_amounts += 1;
+                        }
+                        if aliased {
+                            diagnostics.aliased_types += 1;
+                        }
+                        if let Some(name) = unknown_type {
+                            output.unknown_types.record(&name);
+                        }
+
+                        transaction
+                    }
+                    ParsedRow::Rejected(reason) => {
+                        match reason {
+                            AmountRejection::NonFinite => diagnostics.non_finite_amounts += 1,
+                            AmountRejection::Scientific => diagnostics.scientific_amounts += 1,
+                            AmountRejection::ExcessPrecision => diagnostics.imprecise_amounts += 1,
+                        }
+
+                        if options.strict_precision {
+                            output.rejected_amounts.push(RejectedAmount { row, reason });
+                        }
+                        if options.keep_rejected {
+                            output.rejected_rows.push(RejectedTransaction::unparsed(row, reason.name()));
+                        }
+                        continue;
+                    }
+                    ParsedRow::Malformed(error) => {
+                        if options.validate_only && output.malformed_errors.len() < ReaderOutput::MAX_LISTED {
+                            output.malformed_errors.push(format!("{}: {}", file_path.display(), error));
+                        }
+                        skip_malformed(diagnostics, error)?;
+                        if options.keep_rejected {
+                            output.rejected_rows.push(RejectedTransaction::unparsed(row, "malformed_row"));
+                        }
+                        continue;
+                    }
+                    ParsedRow::OtherClient => {
+                        diagnostics.other_client_rows += 1;
+                        continue;
+                    }
+                    ParsedRow::Skipped | ParsedRow::Header => unreachable!("Skipped lines are counted before"),
+                };
+                transaction.row = row;
+                diagnostics.types.record(transaction.r#type);
+                diagnostics.currency_rows += (transaction.currency != Currency::NONE) as u64;
+
+                if let (Some(timestamp), true) = (transaction.timestamp, options.check_chronology) {
+                    match latest_timestamp {
+                        Some(latest) if timestamp < latest => diagnostics.out_of_order_timestamps += 1,
+                        _ => latest_timestamp = Some(timestamp),
+                    }
+                }
+
+                if !transaction.validate() {
+                    diagnostics.invalid_amounts += 1;
+                    if options.keep_rejected {
+                        output.rejected_rows.push(RejectedTransaction::new(&transaction, "invalid_amount"));
+                    }
+                    continue;
+                }
+
+                // The amount of these is ignored, one being there points to a serialization bug upstream
+                if transaction.amount != Amount::ZERO && transaction.is_reference(options.partial_disputes) {
+                    diagnostics.reference_amounts += 1;
+
+                    if options.strict {
+                        output.reference_amount_rows.push(row);
+                    }
+                }
+
+                // Only deposits and withdrawals introduce new tx ids, the rest reference existing ones
+                if options.unique_tx_ids
+                    && matches!(transaction.r#type, TransactionType::Deposit | TransactionType::Withdrawal)
+                    && !seen_tx_ids.insert(transaction.tx)
+                {
+                    diagnostics.reused_tx_ids += 1;
+                    if options.keep_rejected {
+                        output.rejected_rows.push(RejectedTransaction::new(&transaction, "reused_tx_id"));
+                    }
+                    if options.validate_only && output.reused_tx_id_rows.len() < ReaderOutput::MAX_LISTED {
+                        output.reused_tx_id_rows.push(row);
+                    }
+                    continue;
+                }
+
+                if options.validate_only {
+                    continue;
+                }
+
+                // Never between the two halves of a transfer or a merge
+                let _snapshot_guard = match &snapshot_lock {
+                    Some(lock) => Some(lock.lock().await),
+                    None => None,
+                };
+                let worker_index = transaction.client as usize % num_workers;
+
+                if let (TransactionType::Transfer, Some(counterparty)) = (transaction.r#type, transaction.counterparty) {
+                    let counterparty_index = counterparty as usize % num_workers;
+
+                    // Transfers within the same worker are applied in one go
+                    if counterparty_index != worker_index {
+                        let (check_sender, check_receiver) = oneshot::channel();
+                        let (outcome_sender, outcome_receiver) = oneshot::channel();
+
+                        let transfer_out = TransferOut {
+                            transaction,
+                            incoming_check: check_receiver,
+                            outcome: outcome_sender,
+                        };
+                        let transfer_in = TransferIn {
+                            transaction,
+                            incoming_check: check_sender,
+                            outcome: outcome_receiver,
+                        };
+
+                        send_to_worker(&sender_vec, worker_index, WorkerMessage::TransferOut(Box::new(transfer_out))).await?;
+                        send_to_worker(&sender_vec, counterparty_index, WorkerMessage::TransferIn(Box::new(transfer_in))).await?;
+
+                        continue;
+                    }
+                }
+
+                if let (TransactionType::Merge, Some(source)) = (transaction.r#type, transaction.counterparty) {
+                    let source_index = source as usize % num_workers;
+
+                    // Merges within the same worker are applied in one go
+                    if source_index != worker_index {
+                        let (merged_sender, merged_receiver) = oneshot::channel();
+                        let (reply_sender, reply_receiver) = oneshot::channel();
+
+                        let merge_out = MergeOut {
+                            transaction,
+                            merged: merged_sender,
+                            reply: reply_receiver,
+                        };
+                        let merge_in = MergeIn {
+                            transaction,
+                            merged: merged_receiver,
+                            reply: reply_sender,
+                        };
+
+                        send_to_worker(&sender_vec, source_index, WorkerMessage::MergeOut(Box::new(merge_out))).await?;
+                        send_to_worker(&sender_vec, worker_index, WorkerMessage::MergeIn(Box::new(merge_in))).await?;
+
+                        continue;
+                    }
+                }
+
+                send_to_worker(&sender_vec, worker_index, WorkerMessage::Transaction(transaction)).await?;
+            }
+            output.diagnostics.lossy_rows += records.replaced_rows();
+
+            if options.save_offset {
+                let (bytes, lines) = consumed;
+                let start = options.resume_from.unwrap_or_default();
+                let offset = start.offset + records.file_offset(bytes);
+                let metadata = fs::metadata(file_path)?;
+                output.checkpoint = Some(checkpoint::Checkpoint::new(&metadata, offset, start.line + lines));
+            }
+
+            Ok(())
+        }
+        .await;
+        // A connection can drop at any point, so what was read before is worth telling
+        file_result.map_err(|e| match http::is_url(file_path) {
+            true => format!("{}: {} after {} row/s", file_path.display(), e, output.diagnostics.rows),
+            false => format!("{}: {}", file_path.display(), e),
+        })?;
+
+        row_offset = last_row;
+        log::verbose!("Read {} row/s from {}", output.diagnostics.rows - rows_before, file_path.display());
+
+        // The inputs after the limit aren't read at all
+        if byte_limit.as_ref().is_some_and(|limit| limit.reached()) {
+            output.diagnostics.truncated = true;
+        }
+        if output.diagnostics.truncated {
+            break;
+        }
+    }
+
+    // Told once every row is read, the share of the rows so far says little about the rest of the input
+    let unknown_rows = output.diagnostics.types.count(TransactionType::Unknown);
+    if let Some(ratio) = options.max_unknown_ratio {
+        if unknown_rows as f64 > output.diagnostics.rows as f64 * ratio / 100.0 {
+            output.unknown_types.report();
+            return Err(format!(
+                "Found {} row/s of an unknown type out of {}, over the --max-unknown-ratio of {}%",
+                unknown_rows, output.diagnostics.rows, ratio
+            )
+            .into());
+        }
+    }
+
+    Ok(output)
+}
+
+/// State of every account after the rows sent so far, `None` once the workers are gone.
+async fn take_snapshot(
+    sender_vec: &[Sender<WorkerMessage>],
+    snapshot_lock: &tokio::sync::Mutex<()>,
+) -> Option<Vec<Vec<ClientState>>> {
+    let mut replies = Vec::with_capacity(sender_vec.len());
+    {
+        let _guard = snapshot_lock.lock().await;
+        for sender in sender_vec {
+            let (reply, states) = oneshot::channel();
+            sender.send(WorkerMessage::Snapshot(reply)).await.ok()?;
+            replies.push(states);
+        }
+    }
+
+    let mut accounts = Vec::with_capacity(replies.len());
+    for states in replies {
+        accounts.push(states.await.ok()?);
+    }
+    sort_by_client(&mut accounts);
+
+    Some(accounts)
+}
+
+/// Limits the lines telling the reader waits on a worker to one per second for each of them.
+static BACKPRESSURE: log::Throttle = log::Throttle::new(Duration::from_secs(1));
+/// Limits the lines telling the rows read so far to one per second.
+static PROGRESS: log::Throttle = log::Throttle::new(Duration::from_secs(1));
+
+async fn send_to_worker(
+    sender_vec: &[Sender<WorkerMessage>],
+    worker_index: usize,
+    message: WorkerMessage,
+) -> Result<(), Box<dyn Error>> {
+    match sender_vec[worker_index].try_send(message) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(message)) => {
+            if BACKPRESSURE.ready(worker_index) {
+                log::info!("Buffer full for worker {}, waiting...", worker_index);
+            }
+            sender_vec[worker_index].send(message).await?;
+
+            Ok(())
+        }
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Applies the messages of the reader to the accounts of a worker, sending the transactions that weren't applied to
+/// `rejects` and recording those of the traced clients with `tracer`, if given.
+async fn run_worker(
+    mut receiver: Receiver<WorkerMessage>,
+    policy: AccountPolicy,
+    rejects: Option<Sender<RejectedTransaction>>,
+    tracer: Option<Tracer>,
+) -> (ClientAccounts, Diagnostics) {
+    let mut accounts = ClientAccounts::default();
+    let mut diagnostics = Diagnostics::default();
+    // The reader sends every message in file order and each channel keeps that order, waiting on a
+    // full buffer included, so the rows reaching a worker, and with them those of each of its
+    // clients, are strictly increasing.
+    let mut last_row = 0;
+
+    while let Some(message) = receiver.recv().await {
+        if let Some(transaction) = message.transaction() {
+            let row = transaction.row;
+            debug_assert!(row > last_row, "Row {} reached the worker after row {}", row, last_row);
+            last_row = row;
+        }
+
+        match message {
+            WorkerMessage::Transaction(transaction) => {
+                // The deposits an unlock releases are applied right after it, as rows of their own
+                let mut queue = vec![transaction];
+                while let Some(transaction) = queue.pop() {
+                    let outcome = process_transaction(transaction, &mut accounts, &policy);
+                    diagnostics.outcomes.record(outcome);
+                    if let Some(tracer) = &tracer {
+                        tracer.record(&accounts, transaction.client, &transaction, outcome);
+                        // Both clients of a transfer or merge are owned by this worker
+                        let moves_funds =
+                            matches!(transaction.r#type, TransactionType::Transfer | TransactionType::Merge);
+                        match transaction.counterparty {
+                            Some(counterparty) if moves_funds && counterparty != transaction.client => {
+                                tracer.record(&accounts, counterparty, &transaction, outcome)
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    let account = accounts.get_mut(&transaction.client);
+                    match account {
+                        // Whether the tx is another client's is only known once every worker is done
+                        Some(account)
+                            if outcome == ApplyOutcome::UnknownTxReference
+                                && transaction.r#type == TransactionType::Dispute
+                                && !account.transactions.contains_key(&transaction.tx) =>
+                        {
+                            account.records.unknown_disputes.push(transaction)
+                        }
+                        _ => send_rejected(&rejects, &transaction, outcome).await,
+                    }
+
+                    if transaction.r#type == TransactionType::Unlock {
+                        if let Some(account) = accounts.get_mut(&transaction.client) {
+                            queue.extend(account.take_released().into_iter().rev());
+                        }
+                    }
+                }
+            }
+            WorkerMessage::TransferOut(transfer) => {
+                let transaction = transfer.transaction;
+                let account = accounts
+                    .entry(transaction.client)
+                    .or_insert_with(|| ClientAccount::new(transaction.client));
+
+                // If the receiving worker is gone the transfer can't be completed
+                let incoming = transfer.incoming_check.await.unwrap_or(ApplyOutcome::Ignored);
+                let outcome = match account.check_transfer_out(&transaction, &policy) {
+                    ApplyOutcome::Applied => incoming,
+                    rejected => rejected,
+                };
+
+                if outcome == ApplyOutcome::Applied {
+                    account.transfer_out(transaction, &policy);
+                }
+
+                let _ = transfer.outcome.send(outcome);
+                account.skips.record(outcome);
+                diagnostics.outcomes.record(outcome);
+                if let Some(tracer) = &tracer {
+                    tracer.record(&accounts, transaction.client, &transaction, outcome);
+                }
+                send_rejected(&rejects, &transaction, outcome).await;
+            }
+            WorkerMessage::TransferIn(transfer) => {
+                let transaction = transfer.transaction;
+                if let Some(counterparty) = transaction.counterparty {
+                    let account = accounts
+                        .entry(counterparty)
+                        .or_insert_with(|| ClientAccount::new(counterparty));
+
+                    let _ = transfer.incoming_check.send(account.check_transfer_in(&transaction, &policy));
+
+                    // The outcome is accounted for by the sending side
+                    let outcome = transfer.outcome.await.unwrap_or(ApplyOutcome::Ignored);
+                    if outcome == ApplyOutcome::Applied {
+                        account.transfer_in(transaction, &policy);
+                    }
+                    if let Some(tracer) = &tracer {
+                        tracer.record(&accounts, counterparty, &transaction, outcome);
+                    }
+                }
+            }
+            WorkerMessage::MergeOut(merge) => {
+                let transaction = merge.transaction;
+                if let Some(source) = transaction.counterparty {
+                    let outcome = match accounts.get_mut(&source) {
+                        Some(account) => match account.check_merge_out() {
+                            ApplyOutcome::Applied => {
+                                let _ = merge.merged.send(Ok(account.take_merged()));
+
+                                // The outcome is accounted for by the receiving side, if that side is
+                                // gone the merged account is lost along with the rest of its worker
+                                match merge.reply.await {
+                                    Ok(Ok(())) => {
+                                        account.merge_out(transaction, &policy);
+                                        ApplyOutcome::Applied
+                                    }
+                                    Ok(Err((rejected, merged))) => {
+                                        account.restore_merged(merged);
+                                        rejected
+                                    }
+                                    Err(_) => ApplyOutcome::Ignored,
+                                }
+                            }
+                            rejected => {
+                                let _ = merge.merged.send(Err(rejected));
+                                rejected
+                            }
+                        },
+                        None => {
+                            let _ = merge.merged.send(Err(ApplyOutcome::Ignored));
+                            ApplyOutcome::Ignored
+                        }
+                    };
+                    if let Some(tracer) = &tracer {
+                        tracer.record(&accounts, source, &transaction, outcome);
+                    }
+                }
+            }
+            WorkerMessage::MergeIn(merge) => {
+                let transaction = merge.transaction;
+                let account = accounts
+                    .entry(transaction.client)
+                    .or_insert_with(|| ClientAccount::new(transaction.client));
+
+                let outcome = match merge.merged.await {
+                    Ok(Ok(merged)) => match account.check_merge_in(&merged, &policy) {
+                        ApplyOutcome::Applied => {
+                            account.merge_in(merged, transaction, &policy);
+                            let _ = merge.reply.send(Ok(()));
+
+                            ApplyOutcome::Applied
+                        }
+                        rejected => {
+                            let _ = merge.reply.send(Err((rejected, merged)));
+
+                            rejected
+                        }
+                    },
+                    Ok(Err(rejected)) => rejected,
+                    Err(_) => ApplyOutcome::Ignored,
+                };
+
+                account.skips.record(outcome);
+                diagnostics.outcomes.record(outcome);
+                if let Some(tracer) = &tracer {
+                    tracer.record(&accounts, transaction.client, &transaction, outcome);
+                }
+                send_rejected(&rejects, &transaction, outcome).await;
+            }
+            WorkerMessage::Snapshot(reply) => {
+                let _ = reply.send(accounts.values_mut().flat_map(ClientAccount::states).collect());
+            }
+        }
+    }
+
+    for account in accounts.values_mut() {
+        // Nothing else is coming, so the disputes that already outlived their ttl are released
+        // now, after the last row of the input
+        account.expire_disputes(last_row, &policy);
+        diagnostics.record_account(account);
+    }
+
+    (accounts, diagnostics)
+}
+
+/// Owners of the tx ids disputed by clients that never saw them, looked up in the accounts of every worker once all
+/// of them are done, which tells the disputes of the tx of another client apart from those of an unknown tx.
+struct DisputeLookup {
+    workers: tokio::sync::Barrier,
+    tx_ids: Mutex<HashSet<u32, RandomXxHashBuilder64>>,
+    /// Client holding each tx id, `None` when more than one does, like both ends of a transfer.
+    owners: Mutex<HashMap<u32, Option<ClientId>, RandomXxHashBuilder64>>,
+}
+
+impl DisputeLookup {
+    fn new(num_workers: usize) -> Self {
+        DisputeLookup {
+            workers: tokio::sync::Barrier::new(num_workers),
+            tx_ids: Mutex::default(),
+            owners: Mutex::default(),
+        }
+    }
+
+    /// Goes through the unknown disputes of the accounts of a worker, waiting on every other worker to be done. Those
+    /// of the tx of another client are no longer counted as unknown tx references and are returned.
+    async fn resolve(
+        &self,
+        accounts: &mut ClientAccounts,
+        diagnostics: &mut Diagnostics,
+        rejects: &Option<Sender<RejectedTransaction>>,
+    ) -> Vec<MismatchedDispute> {
+        if let Ok(mut tx_ids) = self.tx_ids.lock() {
+            let disputes = accounts.values().flat_map(|account| account.records.unknown_disputes.iter());
+            tx_ids.extend(disputes.map(|dispute| dispute.tx));
+        }
+        self.workers.wait().await;
+
+        // Nothing is looked up in the accounts unless some dispute is unknown, which is rare
+        if let (Ok(tx_ids), Ok(mut owners)) = (self.tx_ids.lock(), self.owners.lock()) {
+            if !tx_ids.is_empty() {
+                for account in accounts.values() {
+                    for tx in account.transactions.keys().filter(|tx| tx_ids.contains(tx)) {
+                        let owner = owners.entry(*tx).or_insert(Some(account.client));
+                        if *owner != Some(account.client) {
+                            *owner = None;
+                        }
+                    }
+                }
+            }
+        }
+        self.workers.wait().await;
+
+        let mut mismatched = Vec::new();
+        let mut rejected = Vec::new();
+        if let Ok(owners) = self.owners.lock() {
+            for account in accounts.values_mut() {
+                for dispute in account.records.unknown_disputes.drain(..) {
+                    match owners.get(&dispute.tx) {
+                        Some(Some(owner)) if *owner != dispute.client => {
+                            diagnostics.mismatched_disputes += 1;
+                            diagnostics.outcomes.unknown_tx_reference -= 1;
+                            diagnostics.skips.unknown_tx_reference -= 1;
+                            account.skips.unknown_tx_reference -= 1;
+                            mismatched.push(MismatchedDispute {
+                                client: dispute.client,
+                                tx: dispute.tx,
+                                row: dispute.row,
+                                owner: *owner,
+                            });
+                            rejected.push(RejectedTransaction::new(&dispute, "wrong_client"));
+                        }
+                        _ => rejected.push(RejectedTransaction::new(&dispute, "unknown_tx_reference")),
+                    }
+                }
+            }
+        }
+
+        if let Some(rejects) = rejects {
+            for rejected in rejected {
+                let _ = rejects.send(rejected).await;
+            }
+        }
+
+        mismatched
+    }
+}
+
+/// Sends a transaction that wasn't applied to the writer of `--report-rejected`, which only waits on a full channel.
+async fn send_rejected(
+    rejects: &Option<Sender<RejectedTransaction>>,
+    transaction: &Transaction,
+    outcome: ApplyOutcome,
+) {
+    if let (Some(rejects), Some(reason)) = (rejects, outcome.rejection()) {
+        // The writer drains the channel until every sender is gone, even if the report can't be written
+        let _ = rejects.send(RejectedTransaction::new(transaction, reason)).await;
+    }
+}
+
+fn process_transaction(tx: Transaction, accounts: &mut ClientAccounts, policy: &AccountPolicy) -> ApplyOutcome {
+    if matches!(tx.r#type, TransactionType::Transfer | TransactionType::Merge) {
+        let outcome = if tx.r#type == TransactionType::Transfer {
+            process_transfer(tx, accounts, policy)
+        } else {
+            process_merge(tx, accounts, policy)
+        };
+
+        if let Some(account) = accounts.get_mut(&tx.client) {
+            account.skips.record(outcome);
+        }
+
+        return outcome;
+    }
+
+    match accounts.entry(tx.client) {
+        Occupied(mut account) => account.get_mut().apply_transaction(tx, policy),
+        Vacant(entry) => {
+            let mut account = ClientAccount::new(tx.client);
+            let outcome = account.apply_transaction(tx, policy);
+            entry.insert(account);
+
+            outcome
+        }
+    }
+}
+
+/// Applies a transfer whose both clients live in the same set of accounts.
+fn process_transfer(tx: Transaction, accounts: &mut ClientAccounts, policy: &AccountPolicy) -> ApplyOutcome {
+    let counterparty = match tx.counterparty {
+        Some(counterparty) if counterparty != tx.client => counterparty,
+        _ => return ApplyOutcome::Ignored,
+    };
+
+    let outgoing = accounts
+        .entry(tx.client)
+        .or_insert_with(|| ClientAccount::new(tx.client))
+        .check_transfer_out(&tx, policy);
+    let incoming = accounts
+        .entry(counterparty)
+        .or_insert_with(|| ClientAccount::new(counterparty))
+        .check_transfer_in(&tx, policy);
+
+    let outcome = match outgoing {
+        ApplyOutcome::Applied => incoming,
+        rejected => rejected,
+    };
+
+    if outcome == ApplyOutcome::Applied {
+        if let Some(account) = accounts.get_mut(&tx.client) {
+            account.transfer_out(tx, policy);
+        }
+        if let Some(account) = accounts.get_mut(&counterparty) {
+            account.transfer_in(tx, policy);
+        }
+    }
+
+    outcome
+}
+
+/// Applies a merge whose both clients live in the same set of accounts.
+fn process_merge(tx: Transaction, accounts: &mut ClientAccounts, policy: &AccountPolicy) -> ApplyOutcome {
+    let source = match tx.counterparty {
+        Some(source) if source != tx.client => source,
+        _ => return ApplyOutcome::Ignored,
+    };
+
+    let merged = match accounts.get_mut(&source) {
+        Some(account) => match account.check_merge_out() {
+            ApplyOutcome::Applied => account.take_merged(),
+            rejected => return rejected,
+        },
+        None => return ApplyOutcome::Ignored,
+    };
+
+    let account = accounts
+        .entry(tx.client)
+        .or_insert_with(|| ClientAccount::new(tx.client));
+
+    let outcome = account.check_merge_in(&merged, policy);
+
+    if outcome == ApplyOutcome::Applied {
+        account.merge_in(merged, tx, policy);
+
+        if let Some(account) = accounts.get_mut(&source) {
+            account.merge_out(tx, policy);
+        }
+    } else if let Some(account) = accounts.get_mut(&source) {
+        account.restore_merged(merged);
+    }
+
+    outcome
+}
+
+fn print_client_accounts_state(accounts: &[Vec<ClientState>], options: OutputOptions) -> csv::Result<()> {
+    let stdout = io::stdout();
+    write_client_accounts_state(stdout.lock(), accounts, options)
+}
+
+/// Whether the output failed because whoever reads it stopped early.
+fn is_broken_pipe(e: &csv::Error) -> bool {
+    matches!(e.kind(), csv::ErrorKind::Io(e) if e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// What the reader tells the streamed and sharded outputs once it is done, so they only write complete accounts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ReaderOutcome {
+    Reading,
+    /// The run fails, so no account is written.
+    Failed,
+    /// Every row was read, `currency` telling whether any of them was in a named currency.
+    Read { currency: bool },
+}
+
+/// Waits for the reader to be done, which failed if it is gone without telling.
+async fn wait_for_reader(receiver: &mut watch::Receiver<ReaderOutcome>) -> ReaderOutcome {
+    loop {
+        let outcome = *receiver.borrow();
+        if outcome != ReaderOutcome::Reading {
+            return outcome;
+        }
+        if receiver.changed().await.is_err() {
+            return ReaderOutcome::Failed;
+        }
+    }
+}
+
+/// Writes the accounts the workers send as each of them is done, once the reader tells whether it read every row
+/// and whether any was in a named currency. Nothing is written if the reading failed, like in the buffered output.
+fn stream_client_accounts_state(
+    path: Option<&Path>,
+    reader_outcome: &mut watch::Receiver<ReaderOutcome>,
+    accounts: &mut Receiver<Vec<ClientState>>,
+    filter: &OutputFilter,
+    options: OutputOptions,
+) -> Result<AccountTotals, String> {
+    let mut totals = AccountTotals::default();
+    let result = match futures::executor::block_on(wait_for_reader(reader_outcome)) {
+        ReaderOutcome::Read { currency } => {
+            let options = OutputOptions { with_currency: currency, ..options };
+            let mut stream = |writer| stream_into(writer, accounts, filter, options, &mut totals);
+            match path {
+                Some(path) => save_through_temporary_file(path, |file| stream(Box::new(file))),
+                None => match stream(Box::new(io::stdout().lock())) {
+                    Err(e) if is_broken_pipe(&e) => Ok(()),
+                    result => result.map_err(|e| e.to_string()),
+                },
+            }
+        }
+        _ => Ok(()),
+    };
+
+    // The workers wait for room in the channel, whatever became of the output
+    while let Some(states) = accounts.blocking_recv() {
+        totals.add_account(&states);
+    }
+
+    result.map(|_| totals)
+}
+
+fn stream_into(
+    writer: Box<dyn Write>,
+    accounts: &mut Receiver<Vec<ClientState>>,
+    filter: &OutputFilter,
+    options: OutputOptions,
+    totals: &mut AccountTotals,
+) -> csv::Result<()> {
+    let mut writer = AccountsWriter::new(writer, options)?;
+    while let Some(states) = accounts.blocking_recv() {
+        totals.add_account(&states);
+        for state in states.iter().filter(|state| filter.keeps(state)) {
+            writer.write(state)?;
+        }
+    }
+
+    writer.finish()
+}
+
+/// Accounts of a worker written to its own file of the `--output-shards` directory.
+#[derive(Debug)]
+struct Shard {
+    index: usize,
+    file_name: String,
+    /// Rows written, those of the accounts kept by the output filter.
+    rows: u64,
+    /// Every account of the worker, written or not, like the totals of the other outputs.
+    totals: AccountTotals,
+}
+
+/// Writes the accounts of the worker `index` to `part-<index>` in `dir`, with their own header, the way `--output`
+/// writes all of them.
+fn save_shard(
+    dir: &Path,
+    index: usize,
+    accounts: &[ClientState],
+    filter: &OutputFilter,
+    options: OutputOptions,
+) -> Result<Shard, String> {
+    let file_name = format!("part-{}.{}", index, options.format.extension());
+    let mut rows = 0;
+    save_through_temporary_file(&dir.join(&file_name), |file| {
+        let mut writer = AccountsWriter::new(file, options)?;
+        for state in accounts.iter().filter(|state| filter.keeps(state)) {
+            writer.write(state)?;
+            rows += 1;
+        }
+        writer.finish()
+    })?;
+
+    let mut totals = AccountTotals::default();
+    for states in accounts.chunk_by(|a, b| a.client == b.client) {
+        totals.add_account(states);
+    }
+
+    Ok(Shard { index, file_name, rows, totals })
+}
+
+/// Writes the accounts to `path` through a temporary file next to it, so the file is never seen half written, nor
+/// left behind when the writing fails.
+fn save_client_accounts_state(
+    path: &Path,
+    accounts: &[Vec<ClientState>],
+    options: OutputOptions,
+) -> Result<(), String> {
+    save_through_temporary_file(path, |file| write_client_accounts_state(file, accounts, options))
+}
+
+/// Hands a temporary file next to `path` to `write`, and renames it into place once everything is written.
+fn save_through_temporary_file(path: &Path, write: impl FnOnce(File) -> csv::Result<()>) -> Result<(), String> {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+
+    let result = File::create(&temporary_path)
+        .map_err(csv::Error::from)
+        .and_then(write)
+        .and_then(|_| fs::rename(&temporary_path, path).map_err(csv::Error::from));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+
+    result.map_err(|e| format!("Could not write the accounts to {}: {}", path.display(), e))
+}
+
+/// Writes a row per account in the format of the options, as CSV rows after the header by default, escaped and
+/// buffered by the `csv` writer.
+fn write_client_accounts_state<W: Write>(
+    writer: W,
+    accounts: &[Vec<ClientState>],
+    options: OutputOptions,
+) -> csv::Result<()> {
+    // Only files with a currency column have rows in a currency other than the implicit one
+    let options = OutputOptions {
+        with_currency: accounts.iter().flatten().any(|state| state.currency != Currency::NONE),
+        ..options
+    };
+
+    if options.format == OutputFormat::Table {
+        return write_table(writer, accounts.iter().flatten(), options);
+    }
+
+    let mut writer = AccountsWriter::new(writer, options)?;
+    for account in accounts.iter().flatten() {
+        writer.write(account)?;
+    }
+
+    writer.finish()
+}
+
+/// Writes the accounts one at a time, in every output format but the table, which needs all of them at once to
+/// size its columns.
+struct AccountsWriter<W: Write> {
+    sink: AccountsSink<W>,
+    options: OutputOptions,
+    /// Every field is formatted into the same buffer, a million accounts being no reason for as many rows.
+    buffer: String,
+    written: u64,
+}
+
+enum AccountsSink<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Json(BufWriter<W>),
+}
+
+impl<W: Write> AccountsWriter<W> {
+    /// Starts the output with the header of the CSV, or the opening bracket of the JSON array.
+    fn new(writer: W, options: OutputOptions) -> csv::Result<Self> {
+        let sink = match options.format {
+            OutputFormat::Csv | OutputFormat::Table => {
+                let mut writer = csv::Writer::from_writer(writer);
+                writer.write_record(ClientState::header(options).split(','))?;
+                AccountsSink::Csv(Box::new(writer))
+            }
+            OutputFormat::Json | OutputFormat::Ndjson => {
+                let mut writer = BufWriter::new(writer);
+                if options.format == OutputFormat::Json {
+                    write!(writer, "[")?;
+                }
+                AccountsSink::Json(writer)
+            }
+        };
+
+        Ok(AccountsWriter { sink, options, buffer: String::new(), written: 0 })
+    }
+
+    fn write(&mut self, account: &ClientState) -> csv::Result<()> {
+        use std::fmt::Write as _;
+
+        match &mut self.sink {
+            AccountsSink::Csv(writer) => {
+                let mut result = Ok(());
+                let buffer = &mut self.buffer;
+                account.formatted(self.options).for_each_field(|field| {
+                    if result.is_ok() {
+                        buffer.clear();
+                        let _ = write!(buffer, "{}", field);
+                        result = writer.write_field(&*buffer);
+                    }
+                });
+                result?;
+                writer.write_record(None::<&[u8]>)?;
+            }
+            AccountsSink::Json(writer) => {
+                let object = account.formatted(self.options).json();
+                match (self.options.format, self.written) {
+                    (OutputFormat::Json, 0) => write!(writer, "\n{}", object)?,
+                    (OutputFormat::Json, _) => write!(writer, ",\n{}", object)?,
+                    _ => writeln!(writer, "{}", object)?,
+                }
+            }
+        }
+        self.written += 1;
+
+        Ok(())
+    }
+
+    /// Ends the output, closing the JSON array, and flushes it.
+    fn finish(self) -> csv::Result<()> {
+        match self.sink {
+            AccountsSink::Csv(mut writer) => writer.flush()?,
+            AccountsSink::Json(mut writer) => {
+                if self.options.format == OutputFormat::Json {
+                    writeln!(writer, "\n]")?;
+                }
+                writer.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the accounts as a table whose columns are as wide as their widest value.
+fn write_table<'a, W: Write>(
+    writer: W,
+    accounts: impl Iterator<Item = &'a ClientState>,
+    options: OutputOptions,
+) -> csv::Result<()> {
+    let header = ClientState::header(options);
+    let mut rows: Vec<Vec<String>> = vec![header.split(',').map(str::to_string).collect()];
+    for account in accounts {
+        let fields = account.formatted(options).fields().into_iter();
+        rows.push(fields.map(|field| with_thousands_separators(&field).unwrap_or(field)).collect());
+    }
+    rows.insert(1, rows[0].iter().map(|name| "-".repeat(name.len())).collect());
+
+    let mut widths = vec![0; rows[0].len()];
+    for row in &rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+
+    let mut writer = BufWriter::new(writer);
+    for row in &rows {
+        let cells: Vec<String> =
+            row.iter().zip(&widths).map(|(field, width)| format!("{:>width$}", field, width = width)).collect();
+        writeln!(writer, "{}", cells.join("  "))?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// A decimal number with a comma between every three digits of its whole part, `None` for anything else.
+fn with_thousands_separators(field: &str) -> Option<String> {
+    let (sign, unsigned) = field.strip_prefix('-').map_or(("", field), |unsigned| ("-", unsigned));
+    let (whole, fraction) = match unsigned.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (unsigned, None),
+    };
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit());
+    if !digits(whole) || !fraction.is_none_or(digits) {
+        return None;
+    }
+
+    let mut grouped = String::from(sign);
+    for (index, digit) in whole.chars().enumerate() {
+        if index > 0 && (whole.len() - index) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    if let Some(fraction) = fraction {
+        grouped.push('.');
+        grouped.push_str(fraction);
+    }
+
+    Some(grouped)
+}
+
+/// Merges the states of every worker into a single group sorted by client and currency, so the output doesn't
+/// depend on which worker held a client nor on the order of their hash maps.
+fn sort_by_client(accounts: &mut Vec<Vec<ClientState>>) {
+    let mut states: Vec<ClientState> = accounts.drain(..).flatten().collect();
+    states.sort_unstable_by_key(|state| (state.client, state.currency));
+    accounts.push(states);
+}
+
+/// First state of each client, the states of its other currencies share everything that isn't a balance.
+fn client_states(accounts: &[Vec<ClientState>]) -> impl Iterator<Item = &ClientState> {
+    accounts.iter().flat_map(|account_group| {
+        account_group
+            .iter()
+            .enumerate()
+            .filter(move |(index, state)| *index == 0 || account_group[index - 1].client != state.client)
+            .map(|(_, state)| state)
+    })
+}
+
+fn report_tx_order(accounts: &[Vec<ClientState>]) {
+    for account in client_states(accounts) {
+        if !account.tx_order.is_empty() {
+            log::info!(
+                "Client {} has {} out of order and {} repeated tx id/s",
+                account.client, account.tx_order.out_of_order, account.tx_order.repeated
+            );
+        }
+    }
+}
+
+fn write_declined_report<P: AsRef<Path>>(path: P, declined: &[DeclinedTransaction]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    writer.write_record(["client", "tx", "amount", "reason", "row"])?;
+    for transaction in declined {
+        writer.write_record([
+            transaction.client.to_string(),
+            transaction.tx.to_string(),
+            format!("{}", transaction.amount),
+            transaction.reason.to_string(),
+            transaction.row.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_locked_report<P: AsRef<Path>>(path: P, accounts: &[Vec<ClientState>]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    writer.write_record(["client", "tx", "amount", "row"])?;
+    for account in client_states(accounts) {
+        if let Some(cause) = account.locked_by {
+            writer.write_record([
+                account.client.to_string(),
+                cause.tx.to_string(),
+                format!("{}", cause.amount),
+                cause.row.to_string(),
+            ])?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_skips_report<P: AsRef<Path>>(path: P, accounts: &[Vec<ClientState>]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writeln!(writer, "client,{}", SkipCounters::HEADER)?;
+    for account in client_states(accounts) {
+        writeln!(writer, "{},{}", account.client, account.skips)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_audit_log<P: AsRef<Path>>(path: P, events: &[AuditEntry]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    writer.write_record(["client", "tx", "event", "amount", "available", "held", "row"])?;
+    for entry in events {
+        writer.write_record([
+            entry.client.to_string(),
+            entry.tx.to_string(),
+            entry.event.to_string(),
+            format!("{}", entry.amount),
+            format!("{}", entry.available),
+            format!("{}", entry.held),
+            entry.row.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_trace<W: Write>(writer: W, entries: &[TraceEntry]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    writer.write_record(["client", "tx", "type", "amount", "available", "held", "outcome", "reason", "row"])?;
+    for entry in entries {
+        let outcome = match entry.outcome {
+            ApplyOutcome::Applied => "applied",
+            ApplyOutcome::Queued => "queued",
+            _ => "skipped",
+        };
+        writer.write_record([
+            entry.client.to_string(),
+            entry.tx.to_string(),
+            entry.r#type.name().to_string(),
+            format!("{}", entry.amount),
+            format!("{}", entry.available),
+            format!("{}", entry.held),
+            outcome.to_string(),
+            entry.outcome.rejection().unwrap_or_default().to_string(),
+            entry.row.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_mismatched_disputes_report<P: AsRef<Path>>(
+    path: P,
+    disputes: &[MismatchedDispute],
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    writer.write_record(["client", "tx", "owner", "row"])?;
+    for dispute in disputes {
+        writer.write_record([
+            dispute.client.to_string(),
+            dispute.tx.to_string(),
+            dispute.owner.to_string(),
+            dispute.row.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_open_disputes_report<P: AsRef<Path>>(path: P, disputes: &[OpenDispute]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    // Like the accounts, only inputs with a currency column get one
+    let with_currency = disputes.iter().any(|dispute| dispute.currency != Currency::NONE);
+    let header: &[&str] = if with_currency { &["client", "tx", "held", "currency"] } else { &["client", "tx", "held"] };
+    writer.write_record(header)?;
+    for dispute in disputes {
+        let mut record = vec![dispute.client.to_string(), dispute.tx.to_string(), dispute.held.to_string()];
+        if with_currency {
+            record.push(dispute.currency.to_string());
+        }
+        writer.write_record(record)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the transactions that weren't applied as they come, the rows of each worker and of the reader in order,
+/// until every sender is gone. The channel is drained even if the report can't be written, so no worker waits on it.
+fn write_rejected_report(path: &Path, rejected: &mut Receiver<RejectedTransaction>) -> Result<(), String> {
+    let mut result = csv::Writer::from_path(path).and_then(|mut writer| {
+        writer.write_record(["row", "client", "tx", "type", "amount", "reason"])?;
+        Ok(writer)
+    });
+
+    while let Some(transaction) = rejected.blocking_recv() {
+        if let Ok(writer) = &mut result {
+            let field = |value: Option<String>| value.unwrap_or_default();
+            let record = [
+                transaction.row.to_string(),
+                field(transaction.client.map(|client| client.to_string())),
+                field(transaction.tx.map(|tx| tx.to_string())),
+                field(transaction.r#type.map(|r#type| r#type.name().to_string())),
+                field(transaction.amount.map(|amount| amount.to_string())),
+                transaction.reason.to_string(),
+            ];
+            if let Err(e) = writer.write_record(record) {
+                result = Err(e);
+            }
+        }
+    }
+
+    result
+        .and_then(|mut writer| writer.flush().map_err(csv::Error::from))
+        .map_err(|e| format!("Could not write the rejected transactions to {}: {}", path.display(), e))
+}
+
+/// Lists the shards written by `--output-shards` with their rows, in the order of their workers.
+fn write_shards_manifest<P: AsRef<Path>>(path: P, shards: &[Shard]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    writer.write_record(["shard", "rows"])?;
+    for shard in shards {
+        writer.write_record([shard.file_name.clone(), shard.rows.to_string()])?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test amounts are written as float literals, they are rounded to the nearest minor unit.
+    fn amount(value: f64) -> Amount {
+        Amount((value * Amount::SCALE as f64).round() as i64)
+    }
+
+    impl PartialEq for ClientState {
+        fn eq(&self, other: &Self) -> bool {
+            self.client == other.client
+                && self.available == other.available
+                && self.held == other.held
+                && self.locked == other.locked
+        }
+    }
+
+    impl Eq for ClientState {}
+
+    impl PartialEq for Transaction {
+        fn eq(&self, other: &Self) -> bool {
+            self.r#type == other.r#type
+                && self.client == other.client
+                && self.tx == other.tx
+                && self.amount == other.amount
+                && self.counterparty == other.counterparty
+        }
+    }
+
+    impl Eq for Transaction {}
+
+    impl Options {
+        /// Options of a run given the arguments following the name of the binary, as `main` reads them.
+        fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+            cli::parse(args).map_err(|e| e.to_string()).and_then(|matches| Options::from_matches(&matches))
+        }
+    }
+
+    #[tokio::test]
+    async fn happy_path() {
+        let file_path = "test_data/20.csv";
+
+        let expected_result = ClientState {
+            client: 1,
+            available: amount(55.0),
+            held: amount(0.0),
+            locked: false,
+            ..Default::default()
+        };
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+
+        tokio::spawn(async move {
+            extract_files(&[file_path], 1, vec![tx], ReaderOptions::default(), None).await.expect("Should finish correctly");
+        });
+
+        let mut transaction_vec = Vec::with_capacity(20);
+
+        while let Some(data) = rx.recv().await {
+            transaction_vec.push(expect_transaction(data));
+        }
+
+        assert_eq!(transaction_vec.len(), 20);
+
+        let mut accounts = ClientAccounts::default();
+
+        for transactions in transaction_vec {
+            process_transaction(transactions, &mut accounts, &AccountPolicy::default());
+        }
+
+        let account_states: Vec<ClientState> = accounts.into_values().map(ClientState::from).collect();
+
+        assert_eq!(account_states.len(), 1);
+        assert_eq!(account_states[0], expected_result);
+    }
+
+    #[tokio::test]
+    async fn happy_path_with_all_types() {
+        let file_path = "test_data/15.csv";
+
+        let expected_results = [
+            ClientState {
+                client: 1,
+                available: amount(100.0),
+                held: amount(0.0),
+                locked: true,
+                ..Default::default()
+            },
+            ClientState {
+                client: 2,
+                available: amount(135.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            },
+            ClientState {
+                client: 3,
+                available: amount(100.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            },
+        ];
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+
+        tokio::spawn(async move {
+            extract_files(&[file_path], 1, vec![tx], ReaderOptions::default(), None).await.expect("Should finish correctly");
+        });
+
+        let mut transaction_vec = Vec::with_capacity(20);
+
+        while let Some(data) = rx.recv().await {
+            transaction_vec.push(expect_transaction(data));
+        }
+
+        assert_eq!(transaction_vec.len(), 15);
+
+        let mut accounts = ClientAccounts::default();
+
+        for transactions in transaction_vec {
+            process_transaction(transactions, &mut accounts, &AccountPolicy::default());
+        }
+
+        let mut account_states: Vec<ClientState> = accounts.into_values().map(ClientState::from).collect();
+        account_states.sort_by_key(|x| x.client);
+
+        assert_eq!(account_states.len(), 3);
+        for i in 0..3 {
+            assert_eq!(account_states[i], expected_results[i]);
+        }
+
+
+    }
+
+    #[tokio::test]
+    async fn proper_record_extraction() {
+        let file_path = "test_data/sample_types.csv";
+
+        let expected_transactions = [
+            Transaction {
+                r#type: TransactionType::Withdrawal,
+                client: 10,
+                tx: 119,
+                amount: amount(15.0),
+                counterparty: None,
+                row: 0,
+                idempotency_key: None,
+                currency: Currency::NONE,
+                timestamp: None,
+            },
+            Transaction {
+                r#type: TransactionType::Deposit,
+                client: 13,
+                tx: 131,
+                amount: amount(15.3),
+                counterparty: None,
+                row: 0,
+                idempotency_key: None,
+                currency: Currency::NONE,
+                timestamp: None,
+            },
+            Transaction {
+                r#type: TransactionType::Dispute,
+                client: 20,
+                tx: 341,
+                amount: amount(15.5761),
+                counterparty: None,
+                row: 0,
+                idempotency_key: None,
+                currency: Currency::NONE,
+                timestamp: None,
+            },
+            Transaction {
+                r#type: TransactionType::Resolve,
+                client: 15,
+                tx: 391,
+                amount: amount(415.0),
+                counterparty: None,
+                row: 0,
+                idempotency_key: None,
+                currency: Currency::NONE,
+                timestamp: None,
+            },
+            Transaction {
+                r#type: TransactionType::Chargeback,
+                client: 11,
+                tx: 319,
+                amount: amount(0.0),
+                counterparty: None,
+                row: 0,
+                idempotency_key: None,
+                currency: Currency::NONE,
+                timestamp: None,
+            },
+            Transaction {
+                r#type: TransactionType::Unknown,
+                client: 41,
+                tx: 531,
+                amount: amount(165.0),
+                counterparty: None,
+                row: 0,
+                idempotency_key: None,
+                currency: Currency::NONE,
+                timestamp: None,
+            },
+        ];
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+
+        tokio::spawn(async move {
+            extract_files(&[file_path], 1, vec![tx], ReaderOptions::default(), None).await.expect("Should finish correctly");
+        });
+
+        let mut transaction_vec = Vec::with_capacity(20);
+
+        while let Some(data) = rx.recv().await {
+            transaction_vec.push(expect_transaction(data));
+        }
+
+        assert_eq!(transaction_vec.len(), 6);
+        for i in 0..6 {
+            assert_eq!(transaction_vec[i], expected_transactions[i]);
+        }
+    }
+
+    #[tokio::test]
+    async fn types_are_read_whatever_their_case_and_through_aliases() {
+        let file_path = "test_data/mixed_case_types.csv";
+        let expected_transactions = [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Deposit, 1, 2, 5.0),
+            transaction(TransactionType::Withdrawal, 1, 3, 2.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 2, 4, 0.0),
+        ];
+
+        for fast_parse in vec![false, true].into_iter() {
+            let options = ReaderOptions { fast_parse, ..Default::default() };
+            let (transaction_vec, output) = extract_all(file_path, options).await;
+            assert_eq!(transaction_vec, expected_transactions);
+            assert_eq!(output.diagnostics.aliased_types, 3);
+        }
+
+        // Only the names themselves are read with strict types
+        let options = ReaderOptions { strict_types: true, ..Default::default() };
+        let (transaction_vec, output) = extract_all(file_path, options).await;
+        let types: Vec<TransactionType> = transaction_vec.iter().map(|x| x.r#type).collect();
+        assert_eq!(types[..2], [TransactionType::Deposit, TransactionType::Deposit]);
+        assert_eq!(types[2..4], [TransactionType::Unknown, TransactionType::Dispute]);
+        assert_eq!(types[4..], [TransactionType::Unknown, TransactionType::Unknown]);
+        assert_eq!(output.diagnostics.aliased_types, 0);
+    }
+
+    #[tokio::test]
+    async fn unknown_types_are_counted_by_name() {
+        let file_path = "test_data/unknown_types.csv";
+        for fast_parse in [false, true] {
+            let options = ReaderOptions { fast_parse, ..Default::default() };
+            let (transaction_vec, output) = extract_all(file_path, options).await;
+            assert_eq!(transaction_vec.len(), 8);
+            // Typos differing only in their case are the same type
+            assert_eq!(output.unknown_types.sorted(), [("deposti", 3), ("withdrawl", 1)]);
+            assert_eq!(output.diagnostics.types.count(TransactionType::Unknown), 4);
+        }
+
+        // Half of the rows are of an unknown type
+        for (ratio, fails) in [(40.0, true), (50.0, false)] {
+            let (tx, _rx) = tokio::sync::mpsc::channel(10);
+            let options = ReaderOptions { max_unknown_ratio: Some(ratio), ..Default::default() };
+            let result = extract_files(&[file_path], 1, vec![tx], options, None).await;
+            assert_eq!(result.is_err(), fails, "With a ratio of {}%", ratio);
+        }
+
+        // Past the first names only the rows are counted
+        let mut unknown_types = UnknownTypes::default();
+        for index in 0..=UnknownTypes::MAX_NAMES {
+            unknown_types.record(&format!("type{}", index));
+        }
+        unknown_types.record("type0");
+        unknown_types.record("other");
+        assert_eq!(unknown_types.names.len(), UnknownTypes::MAX_NAMES);
+        assert_eq!(unknown_types.names["type0"], 2);
+        assert_eq!(unknown_types.others, 2);
+    }
+
+    #[tokio::test]
+    async fn headers_repeated_within_an_input_are_skipped() {
+        // The two halves of 15.csv, each with its header
+        let file_path = "test_data/concatenated.csv";
+        let fields = |transactions: Vec<Transaction>| -> Vec<_> {
+            transactions.into_iter().map(|x| (x.r#type, x.client, x.tx, x.amount)).collect()
+        };
+        let (expected, _) = extract_all("test_data/15.csv", ReaderOptions::default()).await;
+
+        for (fast_parse, parse_threads) in vec![(false, 0), (true, 0), (true, 2)].into_iter() {
+            let options = ReaderOptions { fast_parse, parse_threads, ..Default::default() };
+            let (transaction_vec, output) = extract_all(file_path, options).await;
+            assert_eq!(fields(transaction_vec), fields(expected.clone()));
+            assert_eq!(output.diagnostics.repeated_headers, 1);
+            assert_eq!(output.diagnostics.malformed_rows, 0);
+            assert_eq!(output.diagnostics.rows, 15);
+        }
+    }
+
+    #[tokio::test]
+    async fn encodings_other_than_utf8_are_read_on_request() {
+        // The notes of the first and last rows have Latin-1 accents
+        let file_path = "test_data/latin1_notes.csv";
+        let expected_transactions = [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Deposit, 1, 2, 5.0),
+            transaction(TransactionType::Withdrawal, 1, 3, 2.0),
+        ];
+
+        for fast_parse in vec![false, true].into_iter() {
+            let options = ReaderOptions { fast_parse, ..Default::default() };
+            let (transaction_vec, output) = extract_all(file_path, options).await;
+            assert_eq!(transaction_vec, expected_transactions[1..2]);
+            assert_eq!(output.diagnostics.malformed_rows, 2);
+
+            for encoding in vec![Encoding::Latin1, Encoding::Utf8Lossy].into_iter() {
+                let options = ReaderOptions { fast_parse, encoding, ..Default::default() };
+                let (transaction_vec, output) = extract_all(file_path, options).await;
+                assert_eq!(transaction_vec, expected_transactions);
+                assert_eq!(output.diagnostics.malformed_rows, 0);
+                assert_eq!(output.diagnostics.lossy_rows, if encoding == Encoding::Latin1 { 0 } else { 2 });
+            }
+        }
+
+        // Strict UTF-8 tells where the invalid byte is
+        let input = Box::new(File::open(file_path).unwrap());
+        let mut records = InputRecords::csv(input, b',', false, &ReaderOptions::default());
+        let (mut headers, mut record) = (csv::StringRecord::new(), csv::StringRecord::new());
+        records.read_header(&mut headers).unwrap();
+        match records.read(&mut headers, &mut record) {
+            Err(ReadError::Row(error)) => {
+                assert!(error.contains("line: 2: invalid UTF-8 in the note column near byte 47"), "{}", error)
+            }
+            _ => panic!("The row should be malformed"),
+        }
+
+        let mut decoded = String::new();
+        Latin1Input::new(&b"caf\xe9 \xff"[..]).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "café ÿ");
+    }
+
+    fn transaction(r#type: TransactionType, client: ClientId, tx: u32, value: f64) -> Transaction {
+        Transaction {
+            r#type,
+            client,
+            tx,
+            amount: amount(value),
+            counterparty: None,
+            row: 0,
+            idempotency_key: None,
+            currency: Currency::NONE,
+            timestamp: None,
+        }
+    }
+
+    fn transfer(client: ClientId, tx: u32, amount: f64, counterparty: ClientId) -> Transaction {
+        Transaction {
+            counterparty: Some(counterparty),
+            row: 0,
+            idempotency_key: None,
+            ..transaction(TransactionType::Transfer, client, tx, amount)
+        }
+    }
+
+    fn expect_transaction(message: WorkerMessage) -> Transaction {
+        match message {
+            WorkerMessage::Transaction(transaction) => transaction,
+            other => panic!("Expected a plain transaction, got {:?}", other),
+        }
+    }
+
+    async fn run_pipeline<P: AsRef<Path> + Sync>(
+        file_paths: &[P],
+        num_workers: usize,
+        options: ReaderOptions,
+        policy: AccountPolicy,
+    ) -> Vec<ClientState> {
+        let mut sender_set = Vec::with_capacity(num_workers);
+        let mut worker_set = Vec::with_capacity(num_workers);
+
+        for _ in 0..num_workers {
+            let (tx, rx) = tokio::sync::mpsc::channel(2);
+            sender_set.push(tx);
+            worker_set.push(tokio::spawn(run_worker(rx, policy, None, None)));
+        }
+
+        extract_files(file_paths, num_workers, sender_set, options, None)
+            .await
+            .expect("Should finish correctly");
+
+        let mut account_states = Vec::new();
+        for worker in worker_set {
+            let (accounts, _) = worker.await.unwrap();
+            account_states.extend(accounts.into_values().flat_map(ClientAccount::into_states));
+        }
+        account_states.sort_by_key(|x| (x.client, x.currency));
+
+        account_states
+    }
+
+    fn account_after(transactions: &[Transaction]) -> ClientState {
+        let mut accounts = ClientAccounts::default();
+
+        for tx in transactions {
+            process_transaction(*tx, &mut accounts, &AccountPolicy::default());
+        }
+
+        assert_eq!(accounts.len(), 1);
+        accounts.into_values().map(ClientState::from).next().unwrap()
+    }
+
+    #[test]
+    fn dispute_on_withdrawal_holds_withdrawn_amount() {
+        let state = account_after(&[
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Withdrawal, 1, 2, 40.0),
+            transaction(TransactionType::Dispute, 1, 2, 0.0),
+        ]);
+
+        assert_eq!(
+            state,
+            ClientState {
+                client: 1,
+                available: amount(60.0),
+                held: amount(40.0),
+                locked: false,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn resolve_on_withdrawal_releases_held_amount() {
+        let state = account_after(&[
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Withdrawal, 1, 2, 40.0),
+            transaction(TransactionType::Dispute, 1, 2, 0.0),
+            transaction(TransactionType::Resolve, 1, 2, 0.0),
+        ]);
+
+        assert_eq!(
+            state,
+            ClientState {
+                client: 1,
+                available: amount(60.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn chargeback_on_withdrawal_reverses_it() {
+        let state = account_after(&[
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Withdrawal, 1, 2, 40.0),
+            transaction(TransactionType::Dispute, 1, 2, 0.0),
+            transaction(TransactionType::Chargeback, 1, 2, 0.0),
+        ]);
+
+        assert_eq!(
+            state,
+            ClientState {
+                client: 1,
+                available: amount(100.0),
+                held: amount(0.0),
+                locked: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn repeated_dispute_is_only_applied_once() {
+        let transactions = [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+        ];
+
+        let single_dispute = account_after(&transactions[..2]);
+        let repeated_dispute = account_after(&transactions);
+
+        assert_eq!(
+            repeated_dispute,
+            ClientState {
+                client: 1,
+                available: amount(0.0),
+                held: amount(100.0),
+                locked: false,
+                ..Default::default()
+            }
+        );
+        assert_eq!(repeated_dispute, single_dispute);
+    }
+
+    #[test]
+    fn repeated_disputes_are_counted() {
+        let mut accounts = ClientAccounts::default();
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+        ] {
+            process_transaction(tx, &mut accounts, &AccountPolicy::default());
+        }
+
+        let mut diagnostics = Diagnostics::default();
+        for account in accounts.values() {
+            diagnostics.record_account(account);
+        }
+
+        assert_eq!(diagnostics.duplicate_disputes, 2);
+    }
+
+    async fn extract_all<P: AsRef<Path> + Send + Sync + 'static>(
+        file_path: P,
+        options: ReaderOptions,
+    ) -> (Vec<Transaction>, ReaderOutput) {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+
+        let handle = tokio::spawn(async move {
+            extract_files(&[file_path], 1, vec![tx], options, None).await.expect("Should finish correctly")
+        });
+
+        let mut transaction_vec = Vec::new();
+
+        while let Some(data) = rx.recv().await {
+            transaction_vec.push(expect_transaction(data));
+        }
+
+        (transaction_vec, handle.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn tx_ids_reused_across_clients_are_kept_by_default() {
+        let (transaction_vec, output) = extract_all("test_data/reused_tx_ids.csv", ReaderOptions::default()).await;
+
+        assert_eq!(transaction_vec.len(), 5);
+        assert_eq!(output.diagnostics.reused_tx_ids, 0);
+    }
+
+    #[tokio::test]
+    async fn tx_ids_reused_across_clients_are_dropped() {
+        let options = ReaderOptions {
+            unique_tx_ids: true,
+            ..Default::default()
+        };
+        let (transaction_vec, output) = extract_all("test_data/reused_tx_ids.csv", options).await;
+
+        let expected_transactions = [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Withdrawal, 7, 2, 10.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+        ];
+
+        assert_eq!(transaction_vec, expected_transactions);
+        assert_eq!(output.diagnostics.reused_tx_ids, 2);
+    }
+
+    #[test]
+    fn options_parsing() {
+        let args = |list: &[&str]| list.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        let options = Options::from_args(args(&["input.csv", "--unique-tx-ids"])).unwrap();
+        assert_eq!(options.inputs, [PathBuf::from("input.csv")]);
+        assert!(options.reader.unique_tx_ids);
+
+        assert_eq!(Options::from_args(args(&[])).unwrap().inputs, [PathBuf::from("-")]);
+        assert!(Options::from_args(args(&["input.csv", "--bogus"])).is_err());
+        // `process` is the same as no subcommand
+        let options = Options::from_args(args(&["process", "input.csv", "--strict"])).unwrap();
+        assert_eq!(options.inputs, [PathBuf::from("input.csv")]);
+        assert!(options.reader.strict && options.convert_output.is_none());
+        assert_eq!(Options::from_args(args(&["process"])).unwrap().inputs, [PathBuf::from("-")]);
+        let options = Options::from_args(args(&["convert", "input.csv", "--strict", "output.tbin"])).unwrap();
+        assert_eq!(options.inputs, [PathBuf::from("input.csv")]);
+        assert_eq!(options.convert_output, Some(PathBuf::from("output.tbin")));
+        assert!(Options::from_args(args(&["convert", "input.csv"])).is_err());
+
+        let options = Options::from_args(args(&["input.csv", "other.csv", "input.csv"])).unwrap();
+        assert_eq!(options.inputs, [PathBuf::from("input.csv"), PathBuf::from("other.csv")]);
+
+        assert!(Options::from_args(args(&["input.csv", "--check-chronology"])).unwrap().reader.check_chronology);
+        assert!(Options::from_args(args(&["input.csv", "--no-header"])).unwrap().reader.no_header);
+        assert!(Options::from_args(args(&["input.csv", "--no-validate-header"])).unwrap().reader.no_validate_header);
+
+        let options = Options::from_args(args(&["input.csv", "--parse-threads", "4"])).unwrap();
+        assert_eq!(options.reader.parse_threads, 4);
+        assert!(options.reader.fast_parse);
+        assert!(Options::from_args(args(&["input.csv", "--parse-threads", "0"])).is_err());
+    }
+
+    #[test]
+    fn disputes_are_settled_on_locked_accounts() {
+        let transactions = [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Deposit, 1, 2, 50.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+            transaction(TransactionType::Dispute, 1, 2, 0.0),
+        ];
+
+        assert_eq!(
+            account_after(&transactions),
+            ClientState {
+                client: 1,
+                available: amount(0.0),
+                held: amount(50.0),
+                locked: true,
+                ..Default::default()
+            }
+        );
+
+        let mut resolved = transactions.to_vec();
+        resolved.push(transaction(TransactionType::Resolve, 1, 2, 0.0));
+
+        assert_eq!(
+            account_after(&resolved),
+            ClientState {
+                client: 1,
+                available: amount(50.0),
+                held: amount(0.0),
+                locked: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn locked_accounts_reject_deposits_and_withdrawals() {
+        let state = account_after(&[
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Deposit, 1, 2, 50.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+            transaction(TransactionType::Deposit, 1, 3, 10.0),
+            transaction(TransactionType::Withdrawal, 1, 4, 10.0),
+        ]);
+
+        assert_eq!(
+            state,
+            ClientState {
+                client: 1,
+                available: amount(50.0),
+                held: amount(0.0),
+                locked: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn unlocked_accounts_accept_deposits_again() {
+        let mut account = ClientAccount::new(1);
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Deposit, 1, 2, 20.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+            transaction(TransactionType::Unlock, 1, 3, 0.0),
+            transaction(TransactionType::Deposit, 1, 4, 30.0),
+        ] {
+            account.apply_transaction(tx, &AccountPolicy::default());
+        }
+
+        assert_eq!(account.unlocks, 1);
+        assert_eq!(
+            ClientState::from(account),
+            ClientState {
+                client: 1,
+                available: amount(50.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn charged_back_transactions_cannot_be_disputed_again() {
+        let mut account = ClientAccount::new(1);
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Deposit, 1, 2, 100.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+        ] {
+            account.apply_transaction(tx, &AccountPolicy::default());
+        }
+
+        assert_eq!(account.transactions[&1].state, TransactionState::ChargedBack);
+        assert_eq!(
+            ClientState::from(account),
+            ClientState {
+                client: 1,
+                available: amount(100.0),
+                held: amount(0.0),
+                locked: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn resolved_transactions_need_a_new_dispute_to_be_charged_back() {
+        let mut account = ClientAccount::new(1);
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Resolve, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+        ] {
+            account.apply_transaction(tx, &AccountPolicy::default());
+        }
+
+        assert_eq!(account.transactions[&1].state, TransactionState::Resolved);
+        assert!(!account.locked);
+        assert_eq!(account.balances.available, amount(100.0));
+
+        account.apply_transaction(transaction(TransactionType::Dispute, 1, 1, 0.0), &AccountPolicy::default());
+        account.apply_transaction(transaction(TransactionType::Chargeback, 1, 1, 0.0), &AccountPolicy::default());
+
+        assert_eq!(account.transactions[&1].state, TransactionState::ChargedBack);
+        assert_eq!(
+            ClientState::from(account),
+            ClientState {
+                client: 1,
+                available: amount(0.0),
+                held: amount(0.0),
+                locked: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn apply_outcomes() {
+        let mut accounts = ClientAccounts::default();
+        let mut outcomes = OutcomeCounters::default();
+        let mut apply = |tx: Transaction| {
+            let outcome = process_transaction(tx, &mut accounts, &AccountPolicy::default());
+            outcomes.record(outcome);
+            outcome
+        };
+
+        assert_eq!(apply(transaction(TransactionType::Deposit, 1, 1, 10.0)), ApplyOutcome::Applied);
+        assert_eq!(apply(transaction(TransactionType::Deposit, 1, 1, 10.0)), ApplyOutcome::DuplicateTx);
+        assert_eq!(apply(transaction(TransactionType::Withdrawal, 1, 1, 5.0)), ApplyOutcome::DuplicateTx);
+        assert_eq!(apply(transaction(TransactionType::Withdrawal, 1, 2, 15.0)), ApplyOutcome::InsufficientFunds);
+        assert_eq!(apply(transaction(TransactionType::Withdrawal, 1, 3, 5.0)), ApplyOutcome::Applied);
+        assert_eq!(apply(transaction(TransactionType::Dispute, 1, 1, 0.0)), ApplyOutcome::InsufficientFunds);
+        assert_eq!(apply(transaction(TransactionType::Dispute, 1, 9, 0.0)), ApplyOutcome::UnknownTxReference);
+        assert_eq!(apply(transaction(TransactionType::Resolve, 1, 9, 0.0)), ApplyOutcome::UnknownTxReference);
+        assert_eq!(apply(transaction(TransactionType::Chargeback, 1, 9, 0.0)), ApplyOutcome::UnknownTxReference);
+        assert_eq!(apply(transaction(TransactionType::Resolve, 1, 3, 0.0)), ApplyOutcome::Ignored);
+        assert_eq!(apply(transaction(TransactionType::Chargeback, 1, 3, 0.0)), ApplyOutcome::Ignored);
+        assert_eq!(apply(transaction(TransactionType::Dispute, 1, 3, 0.0)), ApplyOutcome::Applied);
+        assert_eq!(apply(transaction(TransactionType::Dispute, 1, 3, 0.0)), ApplyOutcome::Ignored);
+        assert_eq!(apply(transaction(TransactionType::Chargeback, 1, 3, 0.0)), ApplyOutcome::Applied);
+        assert_eq!(apply(transaction(TransactionType::Deposit, 1, 4, 10.0)), ApplyOutcome::AccountLocked);
+        assert_eq!(apply(transaction(TransactionType::Withdrawal, 1, 5, 1.0)), ApplyOutcome::AccountLocked);
+        assert_eq!(apply(transaction(TransactionType::Unknown, 1, 6, 1.0)), ApplyOutcome::Ignored);
+        assert_eq!(apply(transaction(TransactionType::Unlock, 1, 7, 0.0)), ApplyOutcome::Applied);
+        assert_eq!(apply(transaction(TransactionType::Unlock, 1, 8, 0.0)), ApplyOutcome::Ignored);
+
+        assert_eq!(
+            outcomes,
+            OutcomeCounters {
+                applied: 5,
+                insufficient_funds: 2,
+                duplicate_tx: 2,
+                account_locked: 2,
+                account_closed: 0,
+                limit_exceeded: 0,
+                queued: 0,
+                unknown_tx_reference: 3,
+                balance_overflow: 0,
+                ignored: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn transactions_for_other_clients_are_ignored() {
+        let mut account = ClientAccount::new(1);
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Deposit, 2, 1, 10.0), &AccountPolicy::default()),
+            ApplyOutcome::Ignored
+        );
+    }
+
+    #[test]
+    fn declined_withdrawals_are_recorded_with_their_reason() {
+        let policy = AccountPolicy {
+            declined_cap: 10,
+            ..Default::default()
+        };
+        let mut account = ClientAccount::new(1);
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Withdrawal, 1, 2, 15.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+            transaction(TransactionType::Withdrawal, 1, 3, 5.0),
+        ] {
+            account.apply_transaction(tx, &policy);
+        }
+
+        let declined: Vec<(u32, DeclineReason)> =
+            account.records.declined.iter().map(|x| (x.tx, x.reason)).collect();
+
+        assert_eq!(
+            declined,
+            vec![(2, DeclineReason::InsufficientFunds), (3, DeclineReason::AccountLocked)]
+        );
+    }
+
+    #[test]
+    fn declined_withdrawals_are_capped() {
+        let mut account = ClientAccount::new(1);
+
+        account.apply_transaction(transaction(TransactionType::Withdrawal, 1, 1, 5.0), &AccountPolicy::default());
+        assert!(account.records.declined.is_empty());
+
+        let policy = AccountPolicy {
+            declined_cap: 2,
+            ..Default::default()
+        };
+        for tx in 2..6 {
+            account.apply_transaction(transaction(TransactionType::Withdrawal, 1, tx, 5.0), &policy);
+        }
+
+        let declined: Vec<u32> = account.records.declined.iter().map(|x| x.tx).collect();
+        assert_eq!(declined, vec![2, 3]);
+    }
+
+    #[test]
+    fn declined_report_format() {
+        let report_path = env::temp_dir().join("transactioner_declined_report.csv");
+        let declined = [
+            DeclinedTransaction {
+                client: 1,
+                tx: 2,
+                row: 3,
+                amount: amount(15.0),
+                reason: DeclineReason::InsufficientFunds,
+            },
+            DeclinedTransaction {
+                client: 3,
+                tx: 4,
+                row: 8,
+                amount: amount(1.5),
+                reason: DeclineReason::AccountLocked,
+            },
+        ];
+
+        write_declined_report(&report_path, &declined).expect("Should write the report");
+
+        assert_eq!(
+            fs::read_to_string(&report_path).unwrap(),
+            "client,tx,amount,reason,row\n1,2,15.0000,insufficient_funds,3\n3,4,1.5000,account_locked,8\n"
+        );
+        fs::remove_file(report_path).unwrap();
+    }
+
+    #[test]
+    fn local_transfer_moves_funds() {
+        let mut accounts = ClientAccounts::default();
+        let policy = AccountPolicy::default();
+
+        process_transaction(transaction(TransactionType::Deposit, 1, 1, 100.0), &mut accounts, &policy);
+
+        assert_eq!(process_transaction(transfer(1, 2, 30.0, 2), &mut accounts, &policy), ApplyOutcome::Applied);
+        assert_eq!(
+            process_transaction(transfer(1, 3, 80.0, 2), &mut accounts, &policy),
+            ApplyOutcome::InsufficientFunds
+        );
+        assert_eq!(process_transaction(transfer(2, 2, 10.0, 1), &mut accounts, &policy), ApplyOutcome::DuplicateTx);
+
+        assert_eq!(accounts[&1].balances.available, amount(70.0));
+        assert_eq!(accounts[&2].balances.available, amount(30.0));
+
+        // Disputes are raised by the receiving side
+        assert_eq!(
+            process_transaction(transaction(TransactionType::Dispute, 1, 2, 0.0), &mut accounts, &policy),
+            ApplyOutcome::Ignored
+        );
+        assert_eq!(
+            process_transaction(transaction(TransactionType::Dispute, 2, 2, 0.0), &mut accounts, &policy),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(accounts[&2].balances.held, amount(30.0));
+    }
+
+    #[tokio::test]
+    async fn transfers_across_workers_conserve_funds() {
+        let expected_results = [
+            ClientState {
+                client: 1,
+                available: amount(50.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            },
+            ClientState {
+                client: 2,
+                available: amount(90.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            },
+            ClientState {
+                client: 3,
+                available: amount(10.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            },
+            ClientState {
+                client: 4,
+                available: amount(0.0),
+                held: amount(0.0),
+                locked: true,
+                ..Default::default()
+            },
+        ];
+
+        for num_workers in 1..=4 {
+            let account_states = run_pipeline(
+                &["test_data/transfers.csv"],
+                num_workers,
+                ReaderOptions::default(),
+                AccountPolicy::default(),
+            )
+            .await;
+
+            assert_eq!(account_states, expected_results, "Using {} worker/s", num_workers);
+
+            let total: Amount = account_states.iter().map(|x| x.available + x.held).sum();
+            assert_eq!(total, amount(150.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn accounts_are_the_same_whatever_the_amount_of_workers() {
+        let fixtures = ["15.csv", "20.csv", "transfers.csv", "merges.csv", "currencies.csv"];
+        for fixture in fixtures {
+            let path = [format!("test_data/{}", fixture)];
+            let accounts = run_pipeline(&path, 1, ReaderOptions::default(), AccountPolicy::default()).await;
+            assert!(!accounts.is_empty());
+
+            for num_workers in [2, 8] {
+                let other = run_pipeline(&path, num_workers, ReaderOptions::default(), AccountPolicy::default()).await;
+                assert_eq!(other, accounts, "{} with {} workers", fixture, num_workers);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn single_message_buffers_give_the_same_accounts() {
+        // The test runtime has a single thread, so the workers only get to run once the reader waits on a full buffer,
+        // which with room for a single message it does at the second message sent to a worker
+        for fixture in ["test_data/15.csv", "test_data/transfers.csv", "test_data/merges.csv"] {
+            let mut sender_set = Vec::new();
+            let mut worker_set = Vec::new();
+            for _ in 0..2 {
+                let (tx, rx) = tokio::sync::mpsc::channel(1);
+                sender_set.push(tx);
+                worker_set.push(tokio::spawn(run_worker(rx, AccountPolicy::default(), None, None)));
+            }
+
+            extract_files(&[fixture], 2, sender_set, ReaderOptions::default(), None)
+                .await
+                .expect("Should finish correctly");
+            let mut accounts = Vec::new();
+            for worker in worker_set {
+                let (worker_accounts, _) = worker.await.unwrap();
+                accounts.extend(worker_accounts.into_values().flat_map(ClientAccount::into_states));
+            }
+            accounts.sort_by_key(|x| (x.client, x.currency));
+
+            let expected = run_pipeline(&[fixture], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+            assert_eq!(accounts, expected, "{}", fixture);
+        }
+    }
+
+    #[tokio::test]
+    async fn inspected_clients_end_like_in_a_whole_run() {
+        // Without transfers or merges, which depend on the rows of another client
+        let fixtures = ["15.csv", "15.jsonl", "open_disputes.csv", "undisputed.csv", "mismatched_disputes.csv"];
+        for fixture in fixtures {
+            let path = [format!("test_data/{}", fixture)];
+            let accounts = run_pipeline(&path, 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+            let mut clients: Vec<ClientId> = accounts.iter().map(|state| state.client).collect();
+            clients.dedup();
+            for client in clients {
+                let expected: Vec<ClientState> =
+                    accounts.iter().filter(|state| state.client == client).cloned().collect();
+                for fast_parse in [false, true] {
+                    let options = ReaderOptions { only_client: Some(client), fast_parse, ..Default::default() };
+                    let inspected = run_pipeline(&path, 1, options, AccountPolicy::default()).await;
+                    assert_eq!(inspected, expected, "client {} of {}", client, fixture);
+                }
+            }
+        }
+
+        let options = ReaderOptions { only_client: Some(1), ..Default::default() };
+        let (transactions, output) = extract_all("test_data/15.csv", options).await;
+        assert!(transactions.iter().all(|transaction| transaction.client == 1));
+        assert_eq!((output.diagnostics.rows, output.diagnostics.other_client_rows), (15, 9));
+    }
+
+    #[tokio::test]
+    async fn validation_reports_tell_what_would_be_dropped() {
+        async fn report(path: &str) -> (bool, String) {
+            let options = ReaderOptions { validate_only: true, unique_tx_ids: true, ..Default::default() };
+            // Without a single worker to send rows to
+            let output = extract_files(&[path], 1, Vec::new(), options, None).await.expect("Should finish correctly");
+            let mut report = String::new();
+            let clean = write_validation_report(&mut report, &output, true);
+            (clean, report)
+        }
+
+        let (clean, text) = report("test_data/20.csv").await;
+        assert!(clean);
+        assert_eq!(
+            text,
+            "rows: 20\ndeposit rows: 11\nwithdrawal rows: 5\ndispute rows: 2\nresolve rows: 2\nmalformed rows: 0\n\
+             rows of an unknown type: 0\ninvalid amounts: 0\nrejected amounts: 0\nreused tx ids: 0\nclean\n"
+        );
+
+        let (clean, text) = report("test_data/dirty.csv").await;
+        assert!(!clean);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[..9],
+            [
+                "rows: 8",
+                "deposit rows: 1",
+                "withdrawal rows: 2",
+                "dispute rows: 1",
+                "malformed rows: 2",
+                "rows of an unknown type: 1",
+                "invalid amounts: 1",
+                "rejected amounts: 1",
+                "reused tx ids: 1",
+            ]
+        );
+        assert!(lines[9].starts_with("malformed row of test_data/dirty.csv: ") && lines[9].contains("line: 3"));
+        assert!(lines[10].starts_with("malformed row of test_data/dirty.csv: ") && lines[10].contains("line: 7"));
+        assert_eq!(lines[11..], ["unknown type \"deposti\": 1 row/s", "reused tx id at row 5", "not clean"]);
+    }
+
+    #[tokio::test]
+    async fn lifetime_counters() {
+        let account_states =
+            run_pipeline(&["test_data/15.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+        let counters: Vec<(ClientId, u32, u32)> = account_states
+            .iter()
+            .map(|x| (x.client, x.dispute_count, x.chargeback_count))
+            .collect();
+        assert_eq!(counters, vec![(1, 1, 1), (2, 0, 0), (3, 0, 0)]);
+
+        let totals: Vec<(Amount, Amount)> = account_states
+            .iter()
+            .map(|x| (x.total_deposited, x.total_withdrawn))
+            .collect();
+        assert_eq!(
+            totals,
+            vec![(amount(200.0), Amount::ZERO), (amount(150.0), amount(15.0)), (amount(100.0), Amount::ZERO)]
+        );
+    }
+
+    #[test]
+    fn extended_output_format() {
+        let state = ClientState {
+            client: 2,
+            currency: Currency::NONE,
+            available: amount(135.0),
+            held: amount(0.0),
+            total: amount(135.0),
+            locked: false,
+            total_deposited: amount(150.0),
+            total_withdrawn: amount(15.0),
+            total_fees: amount(0.5),
+            net_adjustment: amount(-2.0),
+            dispute_count: 1,
+            chargeback_count: 0,
+            locked_by: None,
+            closed: false,
+            chargeback_shortfall: amount(0.0),
+            pending: amount(0.0),
+            skips: SkipCounters::default(),
+            tx_order: TxOrderAnomalies::default(),
+            first_timestamp: Some(1_614_834_367_089),
+            last_timestamp: None,
+        };
+
+        assert_eq!(state.to_string(), "2,135.0000,0.0000,135.0000,false");
+        assert_eq!(ClientState::header(OutputOptions::default()), "client,available,held,total,locked");
+        let extended = OutputOptions {
+            schema: OutputSchema::EXTENDED,
+            ..Default::default()
+        };
+        assert_eq!(
+            ClientState::header(extended),
+            "client,available,held,total,locked,total_deposited,total_withdrawn,dispute_count,chargeback_count,closed,chargeback_shortfall,pending,fees,net_adjustment,first_timestamp,last_timestamp"
+        );
+        assert_eq!(
+            state.formatted(extended).to_string(),
+            "2,135.0000,0.0000,135.0000,false,150.0000,15.0000,1,0,false,0.0000,0.0000,0.5000,-2.0000,2021-03-04T05:06:07.089Z,"
+        );
+
+        let custom = OutputOptions {
+            schema: OutputSchema::parse("client, total,locked_by_tx,dispute_count").unwrap(),
+            with_currency: true,
+            ..Default::default()
+        };
+        assert_eq!(ClientState::header(custom), "client,total,locked_by_tx,dispute_count,currency");
+        assert_eq!(state.formatted(custom).to_string(), "2,135.0000,,1,");
+        let locked = ClientState { locked_by: Some(LockCause { tx: 7, row: 3, amount: amount(1.0) }), ..state };
+        assert_eq!(locked.formatted(custom).to_string(), "2,135.0000,7,1,");
+
+        let unknown = OutputSchema::parse("client,deposits").unwrap_err();
+        assert!(unknown.contains("\"deposits\"") && unknown.contains("locked_by_tx"), "{}", unknown);
+        assert!(OutputSchema::parse("client,held,client").is_err());
+        assert!(OutputSchema::parse("").is_err());
+    }
+
+    #[test]
+    fn amount_formats() {
+        let format = |precision, trim_zeros| AmountFormat { precision, trim_zeros };
+
+        assert_eq!(amount(1.235).format(format(2, false)).to_string(), "1.24");
+        assert_eq!(amount(1.2349).format(format(2, false)).to_string(), "1.23");
+        assert_eq!(amount(-1.235).format(format(2, false)).to_string(), "-1.24");
+        assert_eq!(amount(-0.004).format(format(2, false)).to_string(), "0.00");
+        assert_eq!(amount(2.5).format(format(0, false)).to_string(), "3");
+        assert_eq!(amount(-9.9999).format(format(2, false)).to_string(), "-10.00");
+        assert_eq!(amount(1.5).format(format(8, false)).to_string(), "1.50000000");
+        assert_eq!(Amount(i64::MIN).format(format(8, false)).to_string(), "-922337203685477.58080000");
+
+        assert_eq!(amount(1.5).format(format(4, true)).to_string(), "1.5");
+        assert_eq!(amount(2.0).format(format(4, true)).to_string(), "2");
+        assert_eq!(amount(0.0).format(format(4, true)).to_string(), "0");
+        assert_eq!(amount(1.999).format(format(2, true)).to_string(), "2");
+        assert_eq!(amount(-0.1).format(format(8, true)).to_string(), "-0.1");
+    }
+
+    #[test]
+    fn output_precision_and_trimmed_zeros() {
+        let state = ClientState {
+            client: 1,
+            available: amount(10.125),
+            held: amount(2.5),
+            total: amount(12.625),
+            ..Default::default()
+        };
+        let output = |precision, trim_zeros| OutputOptions {
+            amounts: AmountFormat { precision, trim_zeros },
+            ..Default::default()
+        };
+
+        assert_eq!(state.formatted(output(4, false)).to_string(), state.to_string());
+        assert_eq!(state.formatted(output(2, false)).to_string(), "1,10.13,2.50,12.63,false");
+        assert_eq!(state.formatted(output(4, true)).to_string(), "1,10.125,2.5,12.625,false");
+    }
+
+    #[test]
+    fn json_output_holds_the_values_of_the_csv_output() {
+        let accounts = vec![vec![
+            ClientState {
+                client: 1,
+                available: amount(54.999_9),
+                total: amount(54.999_9),
+                locked: true,
+                first_timestamp: Some(1_614_834_367_089),
+                currency: Currency::parse("EUR").unwrap(),
+                locked_by: Some(LockCause { tx: 4, row: 9, amount: amount(10.0) }),
+                ..Default::default()
+            },
+            ClientState { client: 2, held: amount(-1.5), total: amount(-1.5), ..Default::default() },
+        ]];
+        let written = |format, schema| {
+            let mut output = Vec::new();
+            let options = OutputOptions { format, schema, ..Default::default() };
+            write_client_accounts_state(&mut output, &accounts, options).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let custom = OutputSchema::parse("locked_by_tx,client,first_timestamp,held").unwrap();
+        for schema in [OutputSchema::DEFAULT, OutputSchema::EXTENDED, custom] {
+            let csv = written(OutputFormat::Csv, schema);
+            let mut csv_lines = csv.lines();
+            let header = csv::StringRecord::from(csv_lines.next().unwrap().split(',').collect::<Vec<_>>());
+            let rows: Vec<&str> = csv_lines.collect();
+
+            let ndjson = written(OutputFormat::Ndjson, schema);
+            let json = written(OutputFormat::Json, schema);
+            let objects = json.strip_prefix("[\n").and_then(|json| json.strip_suffix("\n]\n")).unwrap();
+            let array: Vec<&str> = objects.split(",\n").collect();
+            assert_eq!(ndjson.lines().collect::<Vec<_>>(), array);
+            assert_eq!(array.len(), rows.len());
+
+            let (mut keys, mut values) = (csv::StringRecord::new(), csv::StringRecord::new());
+            for (object, row) in array.iter().zip(rows) {
+                json::parse_object(object, &mut keys, &mut values).unwrap();
+                assert_eq!(keys, header);
+                assert_eq!(values.iter().collect::<Vec<_>>().join(","), row);
+            }
+        }
+
+        // Amounts are written as numbers and flags as booleans
+        let json = written(OutputFormat::Ndjson, OutputSchema::DEFAULT);
+        let lines: Vec<&str> = json.lines().collect();
+        assert_eq!(
+            lines[0],
+            r#"{"client":1,"available":54.9999,"held":0.0000,"total":54.9999,"locked":true,"currency":"EUR"}"#
+        );
+        assert_eq!(
+            lines[1],
+            r#"{"client":2,"available":0.0000,"held":-1.5000,"total":-1.5000,"locked":false,"currency":null}"#
+        );
+        assert_eq!(written(OutputFormat::Json, OutputSchema::DEFAULT).lines().count(), 4);
+        let json = written(OutputFormat::Ndjson, custom);
+        assert_eq!(
+            json.lines().next().unwrap(),
+            r#"{"locked_by_tx":4,"client":1,"first_timestamp":"2021-03-04T05:06:07.089Z","held":0.0000,"currency":"EUR"}"#
+        );
+
+        let accounts: Vec<Vec<ClientState>> = Vec::new();
+        let mut output = Vec::new();
+        let options = OutputOptions { format: OutputFormat::Json, ..Default::default() };
+        write_client_accounts_state(&mut output, &accounts, options).unwrap();
+        assert_eq!(output, b"[\n]\n");
+    }
+
+    #[test]
+    fn table_output_aligns_the_columns() {
+        let accounts = vec![vec![
+            ClientState { client: 1, available: amount(1_234_567.5), total: amount(1_234_567.5), ..Default::default() },
+            ClientState { client: 42, held: amount(-12.25), total: amount(-12.25), locked: true, ..Default::default() },
+        ]];
+        let mut output = Vec::new();
+        let options = OutputOptions { format: OutputFormat::Table, ..Default::default() };
+        write_client_accounts_state(&mut output, &accounts, options).unwrap();
+
+        let expected = [
+            "client       available      held           total  locked",
+            "------       ---------      ----           -----  ------",
+            "     1  1,234,567.5000    0.0000  1,234,567.5000   false",
+            "    42          0.0000  -12.2500        -12.2500    true",
+            "",
+        ];
+        assert_eq!(String::from_utf8(output).unwrap(), expected.join("\n"));
+
+        assert_eq!(with_thousands_separators("1000").as_deref(), Some("1,000"));
+        assert_eq!(with_thousands_separators("-999.5").as_deref(), Some("-999.5"));
+        assert_eq!(with_thousands_separators("-1234567").as_deref(), Some("-1,234,567"));
+        for field in ["", "false", "EUR", "2021-03-04T05:06:07Z", "1.", "-", ".5"] {
+            assert_eq!(with_thousands_separators(field), None, "Grouping {:?}", field);
+        }
+    }
+
+    #[test]
+    fn output_options_parsing() {
+        let args = |list: &[&str]| list.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        let options = Options::from_args(args(&["input.csv", "--precision", "2", "--trim-zeros"])).unwrap();
+        assert_eq!(options.output.amounts, AmountFormat { precision: 2, trim_zeros: true });
+        assert_eq!(Options::from_args(args(&["input.csv"])).unwrap().output.amounts, AmountFormat::default());
+
+        assert!(Options::from_args(args(&["input.csv", "--precision", "9"])).is_err());
+        assert!(Options::from_args(args(&["input.csv", "--precision", "-1"])).is_err());
+
+        assert_eq!(Options::from_args(args(&["input.csv"])).unwrap().output.format, OutputFormat::Csv);
+        assert_eq!(Options::from_args(args(&["input.csv"])).unwrap().output_filter, OutputFilter::default());
+        let options = Options::from_args(args(&["input.csv", "--only-clients", "1, 7,42", "--only-locked"])).unwrap();
+        let clients = HashSet::from([1, 7, 42]);
+        assert_eq!(options.output_filter, OutputFilter { clients: Some(clients), only_locked: true });
+        assert!(Options::from_args(args(&["input.csv", "--only-clients", "1,x"])).is_err());
+        assert!(Options::from_args(args(&["input.csv", "--only-clients", "70000"])).is_err());
+        assert!(Options::from_args(args(&["input.csv", "--unsorted"])).unwrap().unsorted);
+        let options = Options::from_args(args(&["input.csv", "--output-format", "ndjson"])).unwrap();
+        assert_eq!(options.output.format, OutputFormat::Ndjson);
+        assert!(Options::from_args(args(&["input.csv", "--output-format", "xml"])).is_err());
+        assert_eq!(Options::from_args(args(&["input.csv"])).unwrap().log_level, log::Level::Info);
+        assert_eq!(Options::from_args(args(&["input.csv", "-q"])).unwrap().log_level, log::Level::Error);
+        assert_eq!(Options::from_args(args(&["input.csv", "--quiet"])).unwrap().log_level, log::Level::Error);
+        assert_eq!(Options::from_args(args(&["input.csv", "-v"])).unwrap().log_level, log::Level::Verbose);
+        let options = Options::from_args(args(&["input.csv", "-v", "--verbose"])).unwrap();
+        assert_eq!(options.log_level, log::Level::Progress);
+        assert_eq!(Options::from_args(args(&["input.csv", "-vvv"])).unwrap().log_level, log::Level::Progress);
+        assert!(Options::from_args(args(&["input.csv", "-q", "-v"])).is_err());
+        assert!(Options::from_args(args(&["input.csv", "--vv"])).is_err());
+        assert_eq!(Options::from_args(args(&["input.csv"])).unwrap().output.schema, OutputSchema::DEFAULT);
+        let options = Options::from_args(args(&["input.csv", "--extended-output"])).unwrap();
+        assert_eq!(options.output.schema, OutputSchema::EXTENDED);
+        let options = Options::from_args(args(&["input.csv", "--columns", "client,held,fees"])).unwrap();
+        assert_eq!(options.output.schema.columns(), [Column::Client, Column::Held, Column::Fees]);
+        let unknown = Options::from_args(args(&["input.csv", "--columns", "client,deposits"])).unwrap_err();
+        assert!(unknown.contains("client,available,held,total,locked,total_deposited"), "{}", unknown);
+        assert!(Options::from_args(args(&["input.csv", "--columns", "client", "--extended-output"])).is_err());
+        let traced = args(&["input.csv", "--trace-client", "7", "--trace-client", "2", "--trace-output", "t.csv"]);
+        assert_eq!(Options::from_args(traced).unwrap().trace_clients, HashSet::from([2, 7]));
+        let missing = Options::from_args(args(&["input.csv", "--trace-client", "7"])).unwrap_err();
+        assert_eq!(missing, "--trace-client needs a --trace-output");
+        let options = Options::from_args(args(&["-", "--trace-client", "2", "--trace-output", "t.csv"])).unwrap();
+        assert_eq!(options.trace_output, Some(PathBuf::from("t.csv")));
+        assert!(Options::from_args(args(&["input.csv", "--trace-client", "x"])).is_err());
+        assert!(Options::from_args(args(&["input.csv", "--trace-output", "t.csv"])).is_err());
+        assert_eq!(Options::from_args(args(&["input.csv"])).unwrap().trace_output, None);
+        assert_eq!(Options::from_args(args(&["input.csv"])).unwrap().workers, None);
+        assert_eq!(Options::from_args(args(&["input.csv"])).unwrap().buffer_size, None);
+        assert!(Options::from_args(args(&["input.csv", "--validate-only"])).unwrap().reader.validate_only);
+        assert!(Options::from_args(args(&["input.csv", "--validate-only", "--follow"])).is_err());
+        assert!(Options::from_args(args(&["convert", "in.csv", "out.tbin", "--validate-only"])).is_err());
+        let options = Options::from_args(args(&["inspect", "in.csv", "--client", "42", "--history"])).unwrap();
+        assert_eq!((options.inspect_client, options.reader.only_client, options.history), (Some(42), Some(42), true));
+        assert_eq!(options.inputs, [PathBuf::from("in.csv")]);
+        assert!(Options::from_args(args(&["inspect", "in.csv"])).is_err());
+        assert!(Options::from_args(args(&["inspect", "in.csv", "--client", "-1"])).is_err());
+        assert!(Options::from_args(args(&["in.csv", "--client", "42"])).is_err());
+        assert!(Options::from_args(args(&["inspect", "in.csv", "--client", "42", "--follow"])).is_err());
+        assert_eq!(Options::from_args(args(&["input.csv", "--buffer-size", "1"])).unwrap().buffer_size, Some(1));
+        let bytes = (3 * std::mem::size_of::<WorkerMessage>() + 1).to_string();
+        assert_eq!(Options::from_args(args(&["input.csv", "--buffer-bytes", &bytes])).unwrap().buffer_size, Some(3));
+        for (flag, size) in [("--buffer-size", "0"), ("--buffer-size", "100000001"), ("--buffer-bytes", "1")] {
+            let error = Options::from_args(args(&["input.csv", flag, size])).unwrap_err();
+            let prefix = format!("error: invalid value '{}' for '{}", size, flag);
+            assert!(error.starts_with(&prefix) && error.contains("has to hold from 1 to"), "{}", error);
+        }
+        assert!(Options::from_args(args(&["input.csv", "--buffer-size", "x"])).is_err());
+        assert!(Options::from_args(args(&["input.csv", "--buffer-size", "5", "--buffer-bytes", "640"])).is_err());
+        assert_eq!(Options::from_args(args(&["input.csv", "--workers", "8"])).unwrap().workers, Some(8));
+        assert!(Options::from_args(args(&["input.csv", "--workers", "auto"])).unwrap().workers >= Some(1));
+        assert!(Options::from_args(args(&["input.csv", "--workers", "0"])).is_err());
+        assert!(Options::from_args(args(&["input.csv", "--workers", "-1"])).is_err());
+        let options = Options::from_args(args(&["input.csv", "--max-unknown-ratio", "2.5"])).unwrap();
+        assert_eq!(options.reader.max_unknown_ratio, Some(2.5));
+        assert!(Options::from_args(args(&["input.csv", "--max-unknown-ratio", "101"])).is_err());
+        assert!(Options::from_args(args(&["input.csv", "--max-unknown-ratio", "-1"])).is_err());
+        let options = Options::from_args(args(&["input.csv", "--report-rejected", "rejected.csv"])).unwrap();
+        assert_eq!(options.report_rejected, Some(PathBuf::from("rejected.csv")));
+        assert!(options.reader.keep_rejected);
+        assert!(!Options::from_args(args(&["input.csv"])).unwrap().reader.keep_rejected);
+        let options = Options::from_args(args(&["input.csv", "--state-hash-file", "hash.txt"])).unwrap();
+        assert!(options.state_hash);
+        assert_eq!(options.state_hash_file, Some(PathBuf::from("hash.txt")));
+        assert!(!Options::from_args(args(&["input.csv"])).unwrap().state_hash);
+        let options = Options::from_args(args(&["input.csv", "--output-shards", "shards"])).unwrap();
+        assert_eq!(options.output_shards, Some(PathBuf::from("shards")));
+        assert!(Options::from_args(args(&["input.csv", "--output-shards", "shards", "--output", "out.csv"])).is_err());
+        let table = args(&["input.csv", "--output-shards", "shards", "--output-format", "table"]);
+        assert!(Options::from_args(table).is_err());
+        assert!(Options::from_args(args(&["input.csv", "--output-shards", "shards", "--check-invariants"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn generated_inputs_apply_cleanly() {
+        let options = generate::GenerateOptions {
+            rows: 20_000,
+            clients: 1_000,
+            dispute_rate: 0.05,
+            seed: 42,
+            ..Default::default()
+        };
+        let mut bytes = Vec::new();
+        generate::write(&options, &mut bytes).unwrap();
+
+        let file_path = env::temp_dir().join("transactioner_generated.csv");
+        fs::write(&file_path, &bytes).unwrap();
+
+        let options = ReaderOptions { validate_only: true, unique_tx_ids: true, ..Default::default() };
+        let output = extract_files(&[&file_path], 1, Vec::new(), options, None).await.expect("Should finish correctly");
+        let mut report = String::new();
+        assert!(write_validation_report(&mut report, &output, true), "{}", report);
+        assert!(report.starts_with("rows: 20000\n"));
+        for r#type in ["dispute", "resolve", "chargeback"] {
+            assert!(report.contains(&format!("\n{} rows: ", r#type)), "{}", report);
+        }
+
+        // Every row applies, disputes only referencing deposits of their own clients
+        let (tx, rx) = tokio::sync::mpsc::channel(2);
+        let worker = tokio::spawn(run_worker(rx, AccountPolicy::default(), None, None));
+        extract_files(&[&file_path], 1, vec![tx], ReaderOptions::default(), None)
+            .await
+            .expect("Should finish correctly");
+        let (accounts, diagnostics) = worker.await.unwrap();
+        assert_eq!(diagnostics.outcomes.applied, 20_000);
+        fs::remove_file(&file_path).unwrap();
+        assert!(accounts.into_values().flat_map(ClientAccount::into_states).any(|state| state.locked));
+    }
+
+    #[test]
+    fn lock_cause_is_recorded() {
+        let mut accounts = ClientAccounts::default();
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Deposit, 1, 2, 40.0),
+            transaction(TransactionType::Deposit, 2, 3, 100.0),
+            transaction(TransactionType::Dispute, 1, 2, 0.0),
+            transaction(TransactionType::Dispute, 2, 3, 0.0),
+            transaction(TransactionType::Resolve, 2, 3, 0.0),
+            Transaction {
+                row: 8,
+                ..transaction(TransactionType::Chargeback, 1, 2, 0.0)
+            },
+        ] {
+            process_transaction(tx, &mut accounts, &AccountPolicy::default());
+        }
+
+        let mut account_states: Vec<ClientState> = accounts.into_values().map(ClientState::from).collect();
+        account_states.sort_by_key(|x| x.client);
+
+        assert_eq!(account_states[0].locked_by, Some(LockCause { tx: 2, row: 8, amount: amount(40.0) }));
+        assert_eq!(account_states[1].locked_by, None);
+
+        let report_path = env::temp_dir().join("transactioner_locked_report.csv");
+        write_locked_report(&report_path, &[account_states]).expect("Should write the report");
+
+        assert_eq!(
+            fs::read_to_string(&report_path).unwrap(),
+            "client,tx,amount,row\n1,2,40.0000,8\n"
+        );
+        fs::remove_file(report_path).unwrap();
+    }
+
+    #[test]
+    fn lock_cause_follows_the_latest_lock() {
+        let mut account = ClientAccount::new(1);
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Deposit, 1, 2, 40.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+        ] {
+            account.apply_transaction(tx, &AccountPolicy::default());
+        }
+        assert_eq!(account.locked_by, Some(LockCause { tx: 1, row: 0, amount: amount(100.0) }));
+
+        account.apply_transaction(transaction(TransactionType::Unlock, 1, 3, 0.0), &AccountPolicy::default());
+        assert_eq!(account.locked_by, None);
+
+        account.apply_transaction(transaction(TransactionType::Dispute, 1, 2, 0.0), &AccountPolicy::default());
+        account.apply_transaction(transaction(TransactionType::Chargeback, 1, 2, 0.0), &AccountPolicy::default());
+        assert_eq!(account.locked_by, Some(LockCause { tx: 2, row: 0, amount: amount(40.0) }));
+    }
+
+    #[test]
+    fn amount_validation() {
+        assert!(transaction(TransactionType::Deposit, 1, 1, 10.0).validate());
+        assert!(transaction(TransactionType::Dispute, 1, 1, 0.0).validate());
+        assert!(transaction(TransactionType::Resolve, 1, 1, -1.0).validate());
+
+        for r#type in [TransactionType::Deposit, TransactionType::Withdrawal, TransactionType::Transfer] {
+            assert!(!transaction(r#type, 1, 1, -100.0).validate());
+            assert!(!transaction(r#type, 1, 1, 0.0).validate());
+        }
+    }
+
+    #[test]
+    fn amount_parsing() {
+        for (value, units) in [
+            ("1", 10_000),
+            ("1.5", 15_000),
+            ("0.0001", 1),
+            ("-2.25", -22_500),
+            ("+3.", 30_000),
+            (".5", 5_000),
+            ("16777216.0001", 167_772_160_001),
+            ("1e3", 10_000_000),
+            ("2.5E-2", 250),
+            ("1.2345e1", 123_450),
+            ("-1e-4", -1),
+            ("0e99", 0),
+        ] {
+            assert_eq!(value.parse::<Amount>(), Ok(Amount(units)), "Parsing {}", value);
+        }
+
+        for value in [
+            "", "-", ".", "1.23456", "1.2.3", "--5", "1e", "1e1.5", "1e-5", "1e20", "NaN", "inf", "-inf", "1,5",
+            "99999999999999999",
+        ] {
+            assert!(value.parse::<Amount>().is_err(), "Parsing {}", value);
+        }
+
+        assert_eq!(Amount(123_4567).to_string(), "123.4567");
+        assert_eq!(Amount(-5).to_string(), "-0.0005");
+        assert_eq!(Amount::ZERO.to_string(), "0.0000");
+    }
+
+    #[test]
+    fn amounts_round_trip() {
+        for units in [0, 1, -1, 9_999, 10_000, -10_001, 167_772_160_001, i64::MAX, i64::MIN] {
+            let written = Amount(units).to_string();
+
+            assert_eq!(written.parse::<Amount>(), Ok(Amount(units)), "Reading back {}", written);
+        }
+
+        assert_eq!("922337203685477.5807".parse::<Amount>(), Ok(Amount(i64::MAX)));
+        assert!("922337203685477.5808".parse::<Amount>().is_err());
+        assert_eq!("-922337203685477.5808".parse::<Amount>(), Ok(Amount(i64::MIN)));
+        assert!("-922337203685477.5809".parse::<Amount>().is_err());
+    }
+
+    #[test]
+    fn lenient_amounts_drop_the_thousands_separators() {
+        let normalized = |value, decimal_comma| Amount::normalize(value, decimal_comma).map(Cow::into_owned);
+
+        assert_eq!(normalized(" 12.50 ", false).as_deref(), Some("12.50"));
+        assert_eq!(normalized("1,234.5678", false).as_deref(), Some("1234.5678"));
+        assert_eq!(normalized("-1 234 567", false).as_deref(), Some("-1234567"));
+        assert_eq!(normalized("1.234,5", true).as_deref(), Some("1234.5"));
+        assert_eq!(normalized("1,5", true).as_deref(), Some("1.5"));
+        assert_eq!(normalized("1,5", false), None);
+        assert_eq!(normalized("12,34.5", false), None);
+        assert_eq!(normalized("1234,567", false), None);
+        assert_eq!(normalized("12.50", true), None);
+    }
+
+    #[tokio::test]
+    async fn lenient_amounts_are_read_on_request() {
+        let file_path = env::temp_dir().join("transactioner_lenient.csv");
+        fs::write(&file_path, "type,client,tx,amount\ndeposit,1,1,\"1,234.5678\"\ndeposit,1,2, 12.50 \ndeposit,1,3,\"12,34\"\n")
+            .unwrap();
+        let comma_path = env::temp_dir().join("transactioner_decimal_comma.csv");
+        fs::write(&comma_path, "type,client,tx,amount\ndeposit,1,1,\"1.234,5\"\ndeposit,1,2,\"1,5\"\n").unwrap();
+
+        let (transactions, output) = read_all(file_path.clone(), ReaderOptions::default()).await;
+        assert_eq!(transactions.iter().map(|x| x.amount).collect::<Vec<_>>(), [amount(12.5)]);
+        assert_eq!(output.unwrap().diagnostics.malformed_rows, 2);
+
+        let lenient = ReaderOptions { lenient_amounts: true, ..Default::default() };
+        let (transactions, output) = read_all(file_path.clone(), lenient).await;
+        assert_eq!(transactions.iter().map(|x| x.amount).collect::<Vec<_>>(), [amount(1234.5678), amount(12.5)]);
+        // A separator out of place leaves the row malformed
+        assert_eq!(output.unwrap().diagnostics.malformed_rows, 1);
+
+        let (transactions, output) = read_all(comma_path.clone(), ReaderOptions::default()).await;
+        assert!(transactions.is_empty());
+        assert_eq!(output.unwrap().diagnostics.malformed_rows, 2);
+
+        let decimal_comma = ReaderOptions { lenient_amounts: true, decimal_comma: true, ..Default::default() };
+        let (transactions, _) = read_all(comma_path.clone(), decimal_comma).await;
+        assert_eq!(transactions.iter().map(|x| x.amount).collect::<Vec<_>>(), [amount(1234.5), amount(1.5)]);
+
+        fs::remove_file(&file_path).unwrap();
+        fs::remove_file(&comma_path).unwrap();
+    }
+
+    #[test]
+    fn balances_keep_every_minor_unit() {
+        let state = account_after(&[
+            transaction(TransactionType::Deposit, 1, 1, 16_777_216.0),
+            transaction(TransactionType::Deposit, 1, 2, 0.0001),
+        ]);
+
+        assert_eq!(state.available, Amount(167_772_160_001));
+        assert_eq!(state.to_string(), "1,16777216.0001,0.0000,16777216.0001,false");
+    }
+
+    #[tokio::test]
+    async fn invalid_amounts_are_rejected() {
+        let (transaction_vec, output) =
+            extract_all("test_data/invalid_amounts.csv", ReaderOptions::default()).await;
+
+        let expected_transactions = [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Withdrawal, 1, 7, 10.0),
+        ];
+
+        assert_eq!(transaction_vec, expected_transactions);
+        assert_eq!(output.diagnostics.invalid_amounts, 2);
+        assert_eq!(output.diagnostics.non_finite_amounts, 3);
+    }
+
+    fn account_after_with(policy: AccountPolicy, transactions: &[Transaction]) -> ClientState {
+        let mut account = ClientAccount::new(transactions[0].client);
+
+        for tx in transactions {
+            account.apply_transaction(*tx, &policy);
+        }
+
+        ClientState::from(account)
+    }
+
+    #[test]
+    fn partial_dispute_then_resolve() {
+        let policy = AccountPolicy {
+            partial_disputes: true,
+            ..Default::default()
+        };
+        let transactions = [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Dispute, 1, 1, 30.0),
+        ];
+
+        assert_eq!(
+            account_after_with(policy, &transactions),
+            ClientState {
+                client: 1,
+                available: amount(70.0),
+                held: amount(30.0),
+                locked: false,
+                ..Default::default()
+            }
+        );
+
+        let mut resolved = transactions.to_vec();
+        resolved.push(transaction(TransactionType::Resolve, 1, 1, 0.0));
+
+        assert_eq!(
+            account_after_with(policy, &resolved),
+            ClientState {
+                client: 1,
+                available: amount(100.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn partial_dispute_then_chargeback() {
+        let policy = AccountPolicy {
+            partial_disputes: true,
+            ..Default::default()
+        };
+        let state = account_after_with(
+            policy,
+            &[
+                transaction(TransactionType::Deposit, 1, 1, 100.0),
+                transaction(TransactionType::Dispute, 1, 1, 30.0),
+                transaction(TransactionType::Chargeback, 1, 1, 0.0),
+            ],
+        );
+
+        assert_eq!(
+            state,
+            ClientState {
+                client: 1,
+                available: amount(70.0),
+                held: amount(0.0),
+                locked: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(state.locked_by, Some(LockCause { tx: 1, row: 0, amount: amount(30.0) }));
+    }
+
+    #[test]
+    fn dispute_amounts_are_ignored_by_default() {
+        let transactions = [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Dispute, 1, 1, 30.0),
+        ];
+        let full_dispute = ClientState {
+            client: 1,
+            available: amount(0.0),
+            held: amount(100.0),
+            locked: false,
+            ..Default::default()
+        };
+
+        assert_eq!(account_after_with(AccountPolicy::default(), &transactions), full_dispute);
+
+        // Amounts above the disputed transaction fall back to disputing all of it
+        let policy = AccountPolicy {
+            partial_disputes: true,
+            ..Default::default()
+        };
+        let oversized = [transactions[0], transaction(TransactionType::Dispute, 1, 1, 300.0)];
+
+        assert_eq!(account_after_with(policy, &oversized), full_dispute);
+    }
+
+    fn units(r#type: TransactionType, tx: u32, units: i64) -> Transaction {
+        Transaction {
+            amount: Amount(units),
+            ..transaction(r#type, 1, tx, 0.0)
+        }
+    }
+
+    #[test]
+    fn balances_past_the_representable_range_are_rejected() {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy::default();
+
+        assert_eq!(
+            account.apply_transaction(units(TransactionType::Deposit, 1, i64::MAX - 10), &policy),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(
+            account.apply_transaction(units(TransactionType::Deposit, 2, 11), &policy),
+            ApplyOutcome::BalanceOverflow
+        );
+        assert_eq!(
+            account.apply_transaction(units(TransactionType::Deposit, 3, 10), &policy),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(
+            account.apply_transaction(units(TransactionType::Deposit, 4, 1), &policy),
+            ApplyOutcome::BalanceOverflow
+        );
+
+        // Rejected deposits don't leave anything behind, so they can be retried with the same tx id
+        assert!(!account.transactions.contains_key(&2));
+        assert_eq!(account.balances.available, Amount(i64::MAX));
+        assert_eq!(account.balances.total_deposited, Amount(i64::MAX));
+    }
+
+    #[test]
+    fn overflowing_chargeback_leaves_the_dispute_open() {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy::default();
+
+        for tx in [
+            units(TransactionType::Deposit, 1, 20),
+            units(TransactionType::Withdrawal, 2, 10),
+            units(TransactionType::Dispute, 2, 0),
+            units(TransactionType::Deposit, 3, i64::MAX - 15),
+        ] {
+            account.apply_transaction(tx, &policy);
+        }
+
+        // Reversing the withdrawal would need room for another 10 minor units
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Chargeback, 1, 2, 0.0), &policy),
+            ApplyOutcome::BalanceOverflow
+        );
+        assert!(account.disputes.open.contains_key(&2));
+        assert!(!account.locked);
+        assert_eq!(account.balances.available, Amount(i64::MAX - 5));
+        assert_eq!(account.balances.held, Amount(10));
+    }
+
+    #[test]
+    fn overdraft_allows_negative_balances() {
+        let transactions = [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Withdrawal, 1, 2, 30.0),
+        ];
+
+        assert_eq!(
+            account_after_with(AccountPolicy::default(), &transactions),
+            ClientState {
+                client: 1,
+                available: amount(10.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            }
+        );
+
+        let policy = AccountPolicy {
+            allow_overdraft: true,
+            ..Default::default()
+        };
+        let state = account_after_with(policy, &transactions);
+
+        assert_eq!(
+            state,
+            ClientState {
+                client: 1,
+                available: amount(-20.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            }
+        );
+        assert_eq!(state.to_string(), "1,-20.0000,0.0000,-20.0000,false");
+    }
+
+    #[test]
+    fn overdraft_never_makes_held_negative() {
+        let policy = AccountPolicy {
+            allow_overdraft: true,
+            ..Default::default()
+        };
+        let mut account = ClientAccount::new(1);
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Withdrawal, 1, 2, 30.0),
+        ] {
+            account.apply_transaction(tx, &policy);
+        }
+
+        // The deposit funds are gone so there is nothing left to hold
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Dispute, 1, 1, 0.0), &policy),
+            ApplyOutcome::InsufficientFunds
+        );
+
+        for tx in [
+            transaction(TransactionType::Dispute, 1, 2, 0.0),
+            transaction(TransactionType::Chargeback, 1, 2, 0.0),
+        ] {
+            assert_eq!(account.apply_transaction(tx, &policy), ApplyOutcome::Applied);
+        }
+
+        assert_eq!(
+            ClientState::from(account),
+            ClientState {
+                client: 1,
+                available: amount(10.0),
+                held: amount(0.0),
+                locked: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn closed_accounts_reject_new_funds() {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy::default();
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Deposit, 1, 1, 10.0), &policy),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Close, 1, 2, 0.0), &policy),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Deposit, 1, 3, 10.0), &policy),
+            ApplyOutcome::AccountClosed
+        );
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Withdrawal, 1, 4, 5.0), &policy),
+            ApplyOutcome::AccountClosed
+        );
+
+        let state = ClientState::from(account);
+        assert!(state.closed);
+        assert_eq!(state.available, amount(10.0));
+    }
+
+    #[test]
+    fn closed_accounts_settle_disputes() {
+        let state = account_after_with(
+            AccountPolicy::default(),
+            &[
+                transaction(TransactionType::Deposit, 1, 1, 10.0),
+                transaction(TransactionType::Close, 1, 2, 0.0),
+                transaction(TransactionType::Dispute, 1, 1, 0.0),
+                transaction(TransactionType::Resolve, 1, 1, 0.0),
+            ],
+        );
+
+        assert_eq!(
+            state,
+            ClientState {
+                client: 1,
+                available: amount(10.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            }
+        );
+        assert!(state.closed);
+        assert_eq!(state.dispute_count, 1);
+    }
+
+    #[test]
+    fn closing_a_locked_account_is_ignored() {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy::default();
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+        ] {
+            account.apply_transaction(tx, &policy);
+        }
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Close, 1, 2, 0.0), &policy),
+            ApplyOutcome::Ignored
+        );
+        assert!(!account.closed);
+        assert_eq!(account.locked_close_attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn mismatched_and_unknown_disputes_are_counted_apart() {
+        for num_workers in 1..=2 {
+            let (senders, receivers): (Vec<_>, Vec<_>) =
+                (0..num_workers).map(|_| tokio::sync::mpsc::channel(10)).unzip();
+            let (rejects, mut rejected) = tokio::sync::mpsc::channel(10);
+            let lookup = Arc::new(DisputeLookup::new(num_workers));
+
+            let workers: Vec<_> = receivers
+                .into_iter()
+                .map(|rx| {
+                    let (rejects, lookup) = (Some(rejects.clone()), lookup.clone());
+                    tokio::spawn(async move {
+                        let (mut accounts, mut diagnostics) =
+                            run_worker(rx, AccountPolicy::default(), rejects.clone(), None).await;
+                        let mismatched = lookup.resolve(&mut accounts, &mut diagnostics, &rejects).await;
+
+                        (diagnostics, mismatched)
+                    })
+                })
+                .collect();
+            drop(rejects);
+            let path = ["test_data/mismatched_disputes.csv"];
+            let output = extract_files(&path, num_workers, senders, ReaderOptions::default(), None).await.unwrap();
+            assert_eq!(output.diagnostics.rows, 5);
+
+            let (mut diagnostics, mut mismatched) = (Diagnostics::default(), Vec::new());
+            for worker in workers {
+                let (worker_diagnostics, mut worker_mismatched) = worker.await.unwrap();
+                diagnostics.merge(&worker_diagnostics);
+                mismatched.append(&mut worker_mismatched);
+            }
+
+            // Client 2 disputes the tx of client 1, and a tx nobody has
+            assert_eq!(diagnostics.mismatched_disputes, 1);
+            assert_eq!(diagnostics.outcomes.unknown_tx_reference, 1);
+            assert_eq!(diagnostics.skips.unknown_tx_reference, 1);
+            assert_eq!(mismatched, [MismatchedDispute { client: 2, tx: 1, row: 4, owner: 1 }]);
+
+            let mut reasons = Vec::new();
+            while let Some(rejection) = rejected.recv().await {
+                reasons.push((rejection.row, rejection.reason));
+            }
+            reasons.sort();
+            assert_eq!(reasons, [(4, "wrong_client"), (5, "unknown_tx_reference")]);
+        }
+    }
+
+    #[tokio::test]
+    async fn audit_log_follows_the_account_history() {
+        let (transaction_vec, _) = extract_all("test_data/15.csv", ReaderOptions::default()).await;
+        let policy = AccountPolicy {
+            audit_log: true,
+            ..Default::default()
+        };
+
+        let mut accounts = ClientAccounts::default();
+        for transaction in transaction_vec {
+            process_transaction(transaction, &mut accounts, &policy);
+        }
+
+        // Rows are the lines of the fixture
+        let entry = |tx, row, event, value, available, held| AuditEntry {
+            client: 1,
+            tx,
+            row,
+            event,
+            amount: amount(value),
+            available: amount(available),
+            held: amount(held),
+        };
+
+        assert_eq!(
+            accounts[&1].records.events,
+            [
+                entry(1, 2, AccountEvent::Deposited, 100.0, 100.0, 0.0),
+                entry(4, 3, AccountEvent::Deposited, 100.0, 200.0, 0.0),
+                entry(1, 5, AccountEvent::Held, 100.0, 100.0, 100.0),
+                entry(1, 7, AccountEvent::ChargedBack, 100.0, 100.0, 0.0),
+                entry(1, 7, AccountEvent::Locked, 100.0, 100.0, 0.0),
+                entry(9, 8, AccountEvent::WithdrawalDeclined, 15.0, 100.0, 0.0),
+                entry(15, 12, AccountEvent::WithdrawalDeclined, 100.0, 100.0, 0.0),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn traces_follow_the_transactions_of_their_clients() {
+        async fn trace(path: &str, num_workers: usize) -> Vec<TraceEntry> {
+            let tracer = Tracer { clients: Arc::new(HashSet::from([2])), entries: Arc::default() };
+            let mut sender_set = Vec::with_capacity(num_workers);
+            let mut worker_set = Vec::with_capacity(num_workers);
+            for _ in 0..num_workers {
+                let (tx, rx) = tokio::sync::mpsc::channel(2);
+                sender_set.push(tx);
+                worker_set.push(tokio::spawn(run_worker(rx, AccountPolicy::default(), None, Some(tracer.clone()))));
+            }
+
+            extract_files(&[path], num_workers, sender_set, ReaderOptions::default(), None)
+                .await
+                .expect("Should finish correctly");
+            for worker in worker_set {
+                worker.await.unwrap();
+            }
+
+            let mut entries = tracer.entries.lock().unwrap().clone();
+            entries.sort_by_key(|entry| (entry.client, entry.row));
+            entries
+        }
+
+        // Rows are the lines of the fixtures
+        let entry = |tx, row, r#type, value, available, held, outcome| TraceEntry {
+            client: 2,
+            tx,
+            row,
+            r#type,
+            amount: amount(value),
+            available: amount(available),
+            held: amount(held),
+            outcome,
+        };
+
+        for num_workers in 1..=2 {
+            assert_eq!(
+                trace("test_data/15.csv", num_workers).await,
+                [
+                    entry(2, 4, TransactionType::Deposit, 15.0, 15.0, 0.0, ApplyOutcome::Applied),
+                    entry(5, 6, TransactionType::Deposit, 135.0, 150.0, 0.0, ApplyOutcome::Applied),
+                    entry(11, 9, TransactionType::Withdrawal, 15.0, 135.0, 0.0, ApplyOutcome::Applied),
+                    entry(10, 10, TransactionType::Dispute, 0.0, 135.0, 0.0, ApplyOutcome::UnknownTxReference),
+                    entry(13, 11, TransactionType::Resolve, 0.0, 135.0, 0.0, ApplyOutcome::UnknownTxReference),
+                ],
+                "Using {} worker/s",
+                num_workers
+            );
+        }
+
+        // Transfers are traced on either side, whether or not both clients share a worker
+        for num_workers in 1..=4 {
+            assert_eq!(
+                trace("test_data/transfers.csv", num_workers).await,
+                [
+                    entry(2, 3, TransactionType::Deposit, 50.0, 50.0, 0.0, ApplyOutcome::Applied),
+                    entry(3, 4, TransactionType::Transfer, 30.0, 80.0, 0.0, ApplyOutcome::Applied),
+                    entry(5, 6, TransactionType::Transfer, 500.0, 80.0, 0.0, ApplyOutcome::InsufficientFunds),
+                    entry(3, 7, TransactionType::Dispute, 0.0, 50.0, 30.0, ApplyOutcome::Applied),
+                    entry(3, 8, TransactionType::Resolve, 0.0, 80.0, 0.0, ApplyOutcome::Applied),
+                    entry(6, 10, TransactionType::Transfer, 10.0, 90.0, 0.0, ApplyOutcome::Applied),
+                    entry(8, 14, TransactionType::Transfer, 5.0, 90.0, 0.0, ApplyOutcome::AccountLocked),
+                ],
+                "Using {} worker/s",
+                num_workers
+            );
+        }
+    }
+
+    #[test]
+    fn audit_log_is_empty_unless_enabled() {
+        let mut account = ClientAccount::new(1);
+
+        account.apply_transaction(transaction(TransactionType::Deposit, 1, 1, 10.0), &AccountPolicy::default());
+
+        assert!(account.records.events.is_empty());
+        assert_eq!(account.records.events.capacity(), 0);
+    }
+
+    fn merge(client: ClientId, tx: u32, source: ClientId) -> Transaction {
+        Transaction {
+            r#type: TransactionType::Merge,
+            client,
+            tx,
+            amount: amount(0.0),
+            counterparty: Some(source),
+            row: 0,
+            idempotency_key: None,
+            currency: Currency::NONE,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn local_merge_moves_funds_and_history() {
+        let mut accounts = ClientAccounts::default();
+        let policy = AccountPolicy::default();
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Deposit, 2, 2, 50.0),
+            transaction(TransactionType::Dispute, 2, 2, 0.0),
+        ] {
+            process_transaction(tx, &mut accounts, &policy);
+        }
+
+        assert_eq!(process_transaction(merge(1, 3, 2), &mut accounts, &policy), ApplyOutcome::Applied);
+        assert_eq!(process_transaction(merge(1, 4, 2), &mut accounts, &policy), ApplyOutcome::AccountClosed);
+        assert_eq!(process_transaction(merge(1, 5, 9), &mut accounts, &policy), ApplyOutcome::Ignored);
+
+        assert!(accounts[&2].closed);
+        assert_eq!(accounts[&2].balances.available, amount(0.0));
+        assert_eq!(accounts[&2].balances.held, amount(0.0));
+        assert_eq!(accounts[&1].balances.available, amount(100.0));
+        assert_eq!(accounts[&1].balances.held, amount(50.0));
+
+        // The dispute moved along with the transaction it holds
+        assert_eq!(
+            process_transaction(transaction(TransactionType::Resolve, 1, 2, 0.0), &mut accounts, &policy),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(accounts[&1].balances.available, amount(150.0));
+    }
+
+    #[test]
+    fn refused_merges_leave_both_accounts_untouched() {
+        let mut accounts = ClientAccounts::default();
+        let policy = AccountPolicy::default();
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Deposit, 2, 1, 50.0),
+            transaction(TransactionType::Deposit, 3, 2, 10.0),
+            transaction(TransactionType::Dispute, 3, 2, 0.0),
+            transaction(TransactionType::Chargeback, 3, 2, 0.0),
+        ] {
+            process_transaction(tx, &mut accounts, &policy);
+        }
+
+        assert_eq!(process_transaction(merge(1, 3, 2), &mut accounts, &policy), ApplyOutcome::DuplicateTx);
+        assert_eq!(process_transaction(merge(1, 4, 3), &mut accounts, &policy), ApplyOutcome::AccountLocked);
+
+        assert!(!accounts[&2].closed);
+        assert_eq!(accounts[&2].balances.available, amount(50.0));
+        assert_eq!(accounts[&2].transactions.len(), 1);
+        assert_eq!(accounts[&1].balances.available, amount(100.0));
+    }
+
+    #[tokio::test]
+    async fn merges_across_workers_conserve_funds() {
+        let expected_results = [
+            ClientState {
+                client: 1,
+                available: amount(0.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            },
+            ClientState {
+                client: 2,
+                available: amount(0.0),
+                held: amount(0.0),
+                locked: false,
+                ..Default::default()
+            },
+            ClientState {
+                client: 3,
+                available: amount(70.0),
+                held: amount(100.0),
+                locked: false,
+                ..Default::default()
+            },
+            ClientState {
+                client: 5,
+                available: amount(15.0),
+                held: amount(0.0),
+                locked: true,
+                ..Default::default()
+            },
+        ];
+
+        for num_workers in 1..=4 {
+            let account_states = run_pipeline(
+                &["test_data/merges.csv"],
+                num_workers,
+                ReaderOptions::default(),
+                AccountPolicy::default(),
+            )
+            .await;
+
+            assert_eq!(account_states, expected_results, "Using {} worker/s", num_workers);
+
+            let closed: Vec<bool> = account_states.iter().map(|x| x.closed).collect();
+            assert_eq!(closed, [true, true, false, false], "Using {} worker/s", num_workers);
+
+            let total: Amount = account_states.iter().map(|x| x.available + x.held).sum();
+            assert_eq!(total, amount(185.0));
+        }
+    }
+
+    async fn worker_after<P: AsRef<Path> + Send + Sync + 'static>(
+        file_path: P,
+        policy: AccountPolicy,
+    ) -> (ClientAccounts, Diagnostics) {
+        let (tx, rx) = tokio::sync::mpsc::channel(10);
+
+        let reader = tokio::spawn(async move {
+            extract_files(&[file_path], 1, vec![tx], ReaderOptions::default(), None)
+                .await
+                .expect("Should finish correctly")
+        });
+        let (accounts, mut diagnostics) = run_worker(rx, policy, None, None).await;
+        diagnostics.merge(&reader.await.unwrap().diagnostics);
+
+        (accounts, diagnostics)
+    }
+
+    #[tokio::test]
+    async fn summary_counts_the_rows_and_accounts_of_the_run() {
+        let (accounts, diagnostics) = worker_after("test_data/15.csv", AccountPolicy::default()).await;
+        let states = vec![accounts.into_values().flat_map(ClientAccount::into_states).collect()];
+        let summary = Summary::new(diagnostics, AccountTotals::new(&states), Duration::from_millis(1500));
+        let fields = summary.fields();
+        let field = |name: &str| fields.iter().find(|(field, _)| field == name).map(|(_, value)| value.as_str());
+
+        assert_eq!((field("rows"), field("malformed_rows")), (Some("15"), Some("0")));
+        let types = ["deposit", "withdrawal", "dispute", "resolve", "chargeback", "transfer", "unknown"];
+        let counts: Vec<_> = types.iter().map(|name| field(&format!("{}_rows", name)).unwrap()).collect();
+        assert_eq!(counts, ["5", "3", "3", "2", "1", "0", "1"]);
+        assert_eq!((field("accounts"), field("locked_accounts")), (Some("3"), Some("1")));
+        assert_eq!((field("available"), field("held")), (Some("335.0000"), Some("0.0000")));
+        assert_eq!(field("skipped_account_locked"), Some("2"));
+        assert_eq!(field("skipped_unknown_tx_reference"), Some("4"));
+        assert_eq!(field("duration_seconds"), Some("1.500"));
+
+        let file_path = std::env::temp_dir().join("transactioner_summary.json");
+        summary.write_json(&file_path).unwrap();
+        let (mut keys, mut values) = (csv::StringRecord::new(), csv::StringRecord::new());
+        json::parse_object(fs::read_to_string(&file_path).unwrap().trim_end(), &mut keys, &mut values).unwrap();
+        let written = keys.iter().zip(values.iter()).map(|(key, value)| (key.to_string(), value.to_string()));
+        assert_eq!(written.collect::<Vec<_>>(), fields);
+        fs::remove_file(&file_path).unwrap();
+
+        // Balances in other currencies are added up apart
+        let (accounts, _) = worker_after("test_data/currencies.csv", AccountPolicy::default()).await;
+        let states = vec![accounts.into_values().flat_map(ClientAccount::into_states).collect()];
+        let fields = Summary::new(Diagnostics::default(), AccountTotals::new(&states), Duration::ZERO).fields();
+        assert!(fields.iter().any(|(name, _)| name.starts_with("available_")));
+    }
+
+    #[tokio::test]
+    async fn open_disputes_are_reported_with_the_funds_they_hold() {
+        // The dispute over tx 1 is resolved and the one over tx 3 charged back, only tx 2 is left open
+        let (accounts, _) = worker_after("test_data/open_disputes.csv", AccountPolicy::default()).await;
+        let open: Vec<OpenDispute> = accounts.values().flat_map(ClientAccount::open_disputes).collect();
+        assert_eq!(open, [OpenDispute { client: 1, tx: 2, held: amount(5.5), currency: Currency::NONE }]);
+        assert_eq!(accounts[&1].balances.held, amount(5.5));
+
+        let file_path = std::env::temp_dir().join("transactioner_open_disputes.csv");
+        write_open_disputes_report(&file_path, &open).unwrap();
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "client,tx,held\n1,2,5.5000\n");
+        fs::remove_file(&file_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn output_filters_keep_the_clients_and_locked_accounts_asked_for() {
+        // Client 1 ends up locked, 2 and 3 don't
+        let (accounts, _) = worker_after("test_data/15.csv", AccountPolicy::default()).await;
+        let mut states = vec![accounts.into_values().flat_map(ClientAccount::into_states).collect()];
+        sort_by_client(&mut states);
+        let shown = |clients: Option<&[ClientId]>, only_locked| -> Vec<ClientId> {
+            let clients = clients.map(|clients| clients.iter().copied().collect());
+            let filter = OutputFilter { clients, only_locked };
+            filter.apply(&states).iter().flatten().map(|state| state.client).collect()
+        };
+
+        assert_eq!(shown(None, false), [1, 2, 3]);
+        assert_eq!(shown(Some(&[3, 2, 42]), false), [2, 3]);
+        assert_eq!(shown(None, true), [1]);
+        assert_eq!(shown(Some(&[1, 3]), true), [1]);
+        assert_eq!(shown(Some(&[3]), true), []);
+
+        // The header is written even if no account is kept
+        let filter = OutputFilter { clients: Some(HashSet::from([3])), only_locked: true };
+        let mut output = Vec::new();
+        write_client_accounts_state(&mut output, &filter.apply(&states), OutputOptions::default()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), format!("{}\n", ClientState::header(OutputOptions::default())));
+    }
+
+    #[tokio::test]
+    async fn settlements_without_dispute_are_counted() {
+        let (accounts, diagnostics) = worker_after("test_data/undisputed.csv", AccountPolicy::default()).await;
+
+        assert_eq!(diagnostics.rows, 8);
+        assert_eq!(diagnostics.undisputed_resolves, 1);
+        assert_eq!(diagnostics.undisputed_chargebacks, 2);
+        // Referencing a tx that doesn't exist at all is counted apart
+        assert_eq!(diagnostics.outcomes.unknown_tx_reference, 1);
+        assert!(accounts.values().all(|account| account.records.undisputed_settlements.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn strict_disputes_keep_the_offending_rows() {
+        let policy = AccountPolicy {
+            strict_disputes: true,
+            ..Default::default()
+        };
+        let (accounts, _) = worker_after("test_data/undisputed.csv", policy).await;
+
+        let mut settlements: Vec<UndisputedSettlement> = accounts
+            .into_values()
+            .flat_map(|account| account.records.undisputed_settlements)
+            .collect();
+        settlements.sort_by_key(|settlement| settlement.row);
+
+        let settlement = |client, tx, row, r#type| UndisputedSettlement { client, tx, row, r#type };
+        assert_eq!(
+            settlements,
+            [
+                settlement(1, 1, 3, TransactionType::Resolve),
+                settlement(1, 1, 6, TransactionType::Chargeback),
+                settlement(2, 2, 8, TransactionType::Chargeback),
+            ]
+        );
+    }
+
+    fn hold_partial_account() -> ClientAccount {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy {
+            hold_partial: true,
+            ..Default::default()
+        };
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Withdrawal, 1, 2, 70.0),
+        ] {
+            account.apply_transaction(tx, &policy);
+        }
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Dispute, 1, 1, 0.0), &policy),
+            ApplyOutcome::Applied
+        );
+
+        account
+    }
+
+    #[test]
+    fn hold_partial_holds_what_is_left() {
+        let account = hold_partial_account();
+
+        assert_eq!(account.balances.available, amount(0.0));
+        assert_eq!(account.balances.held, amount(30.0));
+        assert_eq!(account.disputes.shortfalls[&1], amount(70.0));
+
+        // Without the policy the same dispute is dropped
+        let state = account_after(&[
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Withdrawal, 1, 2, 70.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+        ]);
+        assert_eq!(state.held, amount(0.0));
+    }
+
+    #[test]
+    fn hold_partial_with_nothing_available() {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy {
+            hold_partial: true,
+            ..Default::default()
+        };
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 50.0),
+            transaction(TransactionType::Withdrawal, 1, 2, 50.0),
+        ] {
+            account.apply_transaction(tx, &policy);
+        }
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Dispute, 1, 1, 0.0), &policy),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(account.balances.available, amount(0.0));
+        assert_eq!(account.balances.held, amount(0.0));
+        assert_eq!(account.disputes.shortfalls[&1], amount(50.0));
+    }
+
+    #[test]
+    fn hold_partial_resolve_restores_only_what_was_held() {
+        let mut account = hold_partial_account();
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Resolve, 1, 1, 0.0), &AccountPolicy::default()),
+            ApplyOutcome::Applied
+        );
+
+        let state = ClientState::from(account);
+        assert_eq!(state.available, amount(30.0));
+        assert_eq!(state.held, amount(0.0));
+        assert_eq!(state.chargeback_shortfall, amount(0.0));
+    }
+
+    #[test]
+    fn hold_partial_chargeback_flags_the_shortfall() {
+        let mut account = hold_partial_account();
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Chargeback, 1, 1, 0.0), &AccountPolicy::default()),
+            ApplyOutcome::Applied
+        );
+
+        let state = ClientState::from(account);
+        assert!(state.locked);
+        assert_eq!(state.available, amount(0.0));
+        assert_eq!(state.held, amount(0.0));
+        assert_eq!(state.chargeback_shortfall, amount(70.0));
+    }
+
+    #[test]
+    fn deposits_up_to_the_max_balance() {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy {
+            declined_cap: 10,
+            limits: Limits {
+                max_balance: Some(amount(100.0)),
+                max_tx_amount: None,
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Deposit, 1, 1, 60.0), &policy),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Dispute, 1, 1, 0.0), &policy),
+            ApplyOutcome::Applied
+        );
+        // Held funds count towards the balance
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Deposit, 1, 2, 40.01), &policy),
+            ApplyOutcome::LimitExceeded
+        );
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Deposit, 1, 3, 40.0), &policy),
+            ApplyOutcome::Applied
+        );
+
+        let declined: Vec<(u32, DeclineReason)> =
+            account.records.declined.iter().map(|x| (x.tx, x.reason)).collect();
+        assert_eq!(declined, [(2, DeclineReason::LimitExceeded)]);
+    }
+
+    #[test]
+    fn transactions_over_the_max_amount_are_rejected() {
+        let mut accounts = ClientAccounts::default();
+        let policy = AccountPolicy {
+            limits: Limits {
+                max_balance: None,
+                max_tx_amount: Some(amount(50.0)),
+            },
+            ..Default::default()
+        };
+
+        let mut apply = |tx| process_transaction(tx, &mut accounts, &policy);
+
+        assert_eq!(apply(transaction(TransactionType::Deposit, 1, 1, 50.0)), ApplyOutcome::Applied);
+        assert_eq!(apply(transaction(TransactionType::Deposit, 1, 2, 50.01)), ApplyOutcome::LimitExceeded);
+        assert_eq!(apply(transaction(TransactionType::Deposit, 1, 3, 50.0)), ApplyOutcome::Applied);
+        assert_eq!(apply(transaction(TransactionType::Withdrawal, 1, 4, 60.0)), ApplyOutcome::LimitExceeded);
+        assert_eq!(apply(transfer(1, 5, 60.0, 2)), ApplyOutcome::LimitExceeded);
+        assert_eq!(apply(transfer(1, 6, 50.0, 2)), ApplyOutcome::Applied);
+
+        assert_eq!(accounts[&1].balances.available, amount(50.0));
+    }
+
+    #[test]
+    fn limit_options() {
+        let args = ["in.csv", "--max-balance", "1000", "--max-tx-amount", "250.5"].iter().map(|x| x.to_string());
+        let options = Options::from_args(args).expect("Should parse");
+
+        assert_eq!(options.policy.limits.max_balance, Some(amount(1000.0)));
+        assert_eq!(options.policy.limits.max_tx_amount, Some(amount(250.5)));
+
+        let args = ["in.csv", "--max-balance", "-1"].iter().map(|x| x.to_string());
+        assert!(Options::from_args(args).is_err());
+    }
+
+    fn locked_account(policy: &AccountPolicy) -> ClientAccount {
+        let mut account = ClientAccount::new(1);
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Deposit, 1, 2, 5.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+        ] {
+            account.apply_transaction(tx, policy);
+        }
+        assert!(account.locked);
+
+        account
+    }
+
+    #[test]
+    fn queued_deposits_land_on_unlock() {
+        let policy = AccountPolicy {
+            queue_after_lock: true,
+            ..Default::default()
+        };
+        let mut account = locked_account(&policy);
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Deposit, 1, 3, 20.0), &policy),
+            ApplyOutcome::Queued
+        );
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Deposit, 1, 4, 30.0), &policy),
+            ApplyOutcome::Queued
+        );
+        assert_eq!(account.balances.available, amount(5.0));
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Unlock, 1, 5, 0.0), &policy),
+            ApplyOutcome::Applied
+        );
+        assert!(account.pending.is_empty());
+        for deposit in account.take_released() {
+            assert_eq!(account.apply_transaction(deposit, &policy), ApplyOutcome::Applied);
+        }
+        assert!(account.transactions.contains_key(&3) && account.transactions.contains_key(&4));
+
+        let state = ClientState::from(account);
+        assert!(!state.locked);
+        assert_eq!(state.available, amount(55.0));
+        assert_eq!(state.pending, amount(0.0));
+    }
+
+    #[tokio::test]
+    async fn deposits_released_by_an_unlock_are_counted_as_rows_of_their_own() {
+        let policy = AccountPolicy {
+            queue_after_lock: true,
+            limits: Limits {
+                max_tx_amount: Some(amount(25.0)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let (tx, rx) = tokio::sync::mpsc::channel(10);
+        let (rejects, mut rejected) = tokio::sync::mpsc::channel(10);
+        let worker = tokio::spawn(run_worker(rx, policy, Some(rejects), None));
+        let transactions = [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+            transaction(TransactionType::Deposit, 1, 2, 20.0),
+            transaction(TransactionType::Deposit, 1, 3, 30.0),
+            transaction(TransactionType::Unlock, 1, 4, 0.0),
+        ];
+        for (row, transaction) in transactions.iter().enumerate() {
+            let row = row as u64 + 2;
+            let transaction = Transaction { row, timestamp: Some(row as i64), ..*transaction };
+            tx.send(WorkerMessage::Transaction(transaction)).await.unwrap();
+        }
+        drop(tx);
+
+        let (accounts, diagnostics) = worker.await.unwrap();
+        // Both deposits are queued and then applied or rejected once unlocked
+        let outcomes = diagnostics.outcomes;
+        assert_eq!((outcomes.applied, outcomes.queued, outcomes.limit_exceeded), (5, 2, 1));
+        assert_eq!(accounts[&1].balances.available, amount(20.0));
+        assert_eq!(accounts[&1].last_timestamp, Some(7));
+
+        // The deposit over the limit is rejected on the row it was read on
+        let rejection = rejected.recv().await.unwrap();
+        assert_eq!((rejection.row, rejection.tx, rejection.reason), (6, Some(3), "limit_exceeded"));
+        assert!(rejected.recv().await.is_none());
+    }
+
+    #[test]
+    fn queued_deposits_stay_pending_without_unlock() {
+        let policy = AccountPolicy {
+            queue_after_lock: true,
+            ..Default::default()
+        };
+        let mut account = locked_account(&policy);
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 3, 20.0),
+            transaction(TransactionType::Withdrawal, 1, 4, 1.0),
+            transaction(TransactionType::Deposit, 1, 5, 30.0),
+        ] {
+            account.apply_transaction(tx, &policy);
+        }
+
+        let pending: Vec<_> = account.pending.iter().map(|deposit| (deposit.tx, deposit.amount)).collect();
+        assert_eq!(pending, [(3, amount(20.0)), (5, amount(30.0))]);
+
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.record_account(&account);
+        assert_eq!(diagnostics.pending_deposits, 2);
+
+        let state = ClientState::from(account);
+        assert!(state.locked);
+        assert_eq!(state.available, amount(5.0));
+        assert_eq!(state.pending, amount(50.0));
+    }
+
+    #[test]
+    fn skip_counters_tell_reasons_apart() {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy {
+            limits: Limits {
+                max_balance: None,
+                max_tx_amount: Some(amount(1_000.0)),
+            },
+            ..Default::default()
+        };
+
+        for tx in [
+            transaction(TransactionType::Deposit, 2, 1, 10.0),
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Deposit, 1, 2, 5_000.0),
+            transaction(TransactionType::Withdrawal, 1, 3, 50.0),
+            transaction(TransactionType::Withdrawal, 1, 4, 60.0),
+            transaction(TransactionType::Dispute, 1, 9, 0.0),
+            transaction(TransactionType::Resolve, 1, 1, 0.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+            transaction(TransactionType::Deposit, 1, 6, 10.0),
+            transaction(TransactionType::Withdrawal, 1, 7, 1.0),
+        ] {
+            account.apply_transaction(tx, &policy);
+        }
+
+        assert_eq!(
+            account.skips,
+            SkipCounters {
+                wrong_client: 1,
+                account_locked: 2,
+                account_closed: 0,
+                duplicate_tx: 1,
+                insufficient_funds: 2,
+                unknown_tx_reference: 1,
+                limit_exceeded: 1,
+                balance_overflow: 0,
+                ignored: 1,
+            }
+        );
+
+        let mut account = ClientAccount::new(1);
+        for tx in [
+            units(TransactionType::Deposit, 1, i64::MAX),
+            transaction(TransactionType::Deposit, 1, 2, 1.0),
+            transaction(TransactionType::Close, 1, 3, 0.0),
+            transaction(TransactionType::Deposit, 1, 4, 1.0),
+        ] {
+            account.apply_transaction(tx, &AccountPolicy::default());
+        }
+
+        assert_eq!(
+            account.skips,
+            SkipCounters {
+                balance_overflow: 1,
+                account_closed: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn second_chargeback_on_a_locked_account() {
+        let state = account_after(&[
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Deposit, 1, 2, 20.0),
+            transaction(TransactionType::Deposit, 1, 3, 5.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Dispute, 1, 2, 0.0),
+            transaction(TransactionType::Dispute, 1, 3, 0.0),
+            transaction(TransactionType::Chargeback, 1, 1, 0.0),
+            transaction(TransactionType::Chargeback, 1, 2, 0.0),
+            transaction(TransactionType::Resolve, 1, 3, 0.0),
+        ]);
+
+        assert_eq!(
+            state,
+            ClientState {
+                client: 1,
+                available: amount(5.0),
+                held: amount(0.0),
+                locked: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(state.chargeback_count, 2);
+        // The account stays locked by the first chargeback
+        assert_eq!(state.locked_by, Some(LockCause { tx: 1, row: 0, amount: amount(10.0) }));
+    }
+
+    #[test]
+    fn resolved_transactions_can_be_disputed_again() {
+        let state = account_after(&[
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Resolve, 1, 1, 0.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+        ]);
+
+        assert_eq!(state.held, amount(10.0));
+        assert_eq!(state.dispute_count, 2);
+    }
+
+    #[test]
+    fn single_dispute_rejects_disputing_a_resolved_transaction() {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy {
+            single_dispute: true,
+            ..Default::default()
+        };
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Resolve, 1, 1, 0.0),
+        ] {
+            assert_eq!(account.apply_transaction(tx, &policy), ApplyOutcome::Applied);
+        }
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Dispute, 1, 1, 0.0), &policy),
+            ApplyOutcome::Ignored
+        );
+        assert_eq!(account.disputes.repeated, 1);
+        assert_eq!(account.balances.available, amount(10.0));
+        assert_eq!(account.balances.held, amount(0.0));
+    }
+
+    #[tokio::test]
+    async fn retries_with_a_new_tx_id_are_ignored() {
+        let (accounts, diagnostics) = worker_after("test_data/idempotency_keys.csv", AccountPolicy::default()).await;
+
+        // Rows without a key are told apart by their tx id alone, keys are only shared within a client
+        assert_eq!(accounts[&1].balances.available, amount(10.0));
+        assert_eq!(accounts[&2].balances.available, amount(10.0));
+        assert_eq!(accounts[&1].idempotency.retried, 1);
+        assert_eq!(diagnostics.outcomes.duplicate_tx, 1);
+    }
+
+    #[test]
+    fn idempotency_keys_are_forgotten_past_the_window() {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy {
+            idempotency_window: 1,
+            ..Default::default()
+        };
+        let keyed = |tx, key| Transaction {
+            idempotency_key: Some(key),
+            ..transaction(TransactionType::Deposit, 1, tx, 1.0)
+        };
+
+        assert_eq!(account.apply_transaction(keyed(1, 7), &policy), ApplyOutcome::Applied);
+        assert_eq!(account.apply_transaction(keyed(2, 7), &policy), ApplyOutcome::DuplicateTx);
+        assert_eq!(account.apply_transaction(keyed(3, 8), &policy), ApplyOutcome::Applied);
+        assert_eq!(account.apply_transaction(keyed(4, 7), &policy), ApplyOutcome::Applied);
+        assert_eq!(account.idempotency.order.len(), 1);
+    }
+
+    #[test]
+    fn monotonic_tx_ids_are_checked_per_client() {
+        let mut accounts = ClientAccounts::default();
+        let policy = AccountPolicy {
+            check_monotonic_tx: true,
+            ..Default::default()
+        };
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Deposit, 2, 2, 10.0),
+            transaction(TransactionType::Withdrawal, 1, 3, 5.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Deposit, 2, 7, 10.0),
+            transaction(TransactionType::Deposit, 1, 8, 10.0),
+            transaction(TransactionType::Deposit, 2, 5, 10.0),
+            transaction(TransactionType::Withdrawal, 2, 7, 1.0),
+            transaction(TransactionType::Deposit, 2, 9, 10.0),
+            transaction(TransactionType::Deposit, 2, 8, 10.0),
+        ] {
+            process_transaction(tx, &mut accounts, &policy);
+        }
+
+        assert!(accounts[&1].tx_order.anomalies.is_empty());
+        assert_eq!(
+            accounts[&2].tx_order.anomalies,
+            TxOrderAnomalies {
+                out_of_order: 2,
+                repeated: 1
+            }
+        );
+        // Only diagnostics, the out of order deposits are applied as usual
+        assert_eq!(accounts[&2].balances.available, amount(50.0));
+    }
+
+    fn two_phase_account(settlement: Option<TransactionType>) -> (ClientAccount, AccountPolicy) {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy {
+            two_phase_deposits: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Deposit, 1, 1, 10.0), &policy),
+            ApplyOutcome::Applied
+        );
+        if let Some(r#type) = settlement {
+            assert_eq!(account.apply_transaction(transaction(r#type, 1, 1, 0.0), &policy), ApplyOutcome::Applied);
+        }
+
+        (account, policy)
+    }
+
+    #[test]
+    fn settled_deposits_become_available() {
+        let (mut account, policy) = two_phase_account(Some(TransactionType::Settle));
+
+        assert_eq!(account.balances.available, amount(10.0));
+        assert_eq!(account.balances.held, amount(0.0));
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Void, 1, 1, 0.0), &policy),
+            ApplyOutcome::Ignored
+        );
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Dispute, 1, 1, 0.0), &policy),
+            ApplyOutcome::Applied
+        );
+    }
+
+    #[test]
+    fn voided_deposits_are_taken_back() {
+        let (mut account, policy) = two_phase_account(Some(TransactionType::Void));
+
+        assert_eq!(account.balances.available, amount(0.0));
+        assert_eq!(account.balances.held, amount(0.0));
+        assert_eq!(account.balances.total_deposited, amount(0.0));
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Settle, 1, 1, 0.0), &policy),
+            ApplyOutcome::Ignored
+        );
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Dispute, 1, 1, 0.0), &policy),
+            ApplyOutcome::Ignored
+        );
+    }
+
+    #[test]
+    fn unsettled_deposits_stay_held() {
+        let (mut account, policy) = two_phase_account(None);
+
+        assert_eq!(account.balances.available, amount(0.0));
+        assert_eq!(account.balances.held, amount(10.0));
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Dispute, 1, 1, 0.0), &policy),
+            ApplyOutcome::Ignored
+        );
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Withdrawal, 1, 2, 5.0), &policy),
+            ApplyOutcome::InsufficientFunds
+        );
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Settle, 1, 3, 0.0), &policy),
+            ApplyOutcome::UnknownTxReference
+        );
+        assert_eq!(account.balances.held, amount(10.0));
+    }
+
+    #[tokio::test]
+    async fn client_order_holds_across_workers() {
+        let file_path = env::temp_dir().join("transactioner_skewed.csv");
+        let mut contents = String::from("type,client,tx,amount\n");
+        let mut deposit_next = [true; 11];
+
+        // 90% of the rows go to client 1, each client alternates deposits and withdrawals of the
+        // same amount so any withdrawal applied before its deposit is declined.
+        for tx in 1..=20_000u32 {
+            let client = if tx % 10 == 0 { 2 + (tx / 10) % 9 } else { 1 } as usize;
+            let r#type = if deposit_next[client] { "deposit" } else { "withdrawal" };
+            deposit_next[client] = !deposit_next[client];
+
+            contents.push_str(&format!("{},{},{},1.0\n", r#type, client, tx));
+        }
+        fs::write(&file_path, contents).unwrap();
+
+        let account_states =
+            run_pipeline(&[&file_path], 4, ReaderOptions::default(), AccountPolicy::default()).await;
+        fs::remove_file(&file_path).unwrap();
+
+        assert_eq!(account_states.len(), 10);
+        for state in account_states {
+            assert_eq!(state.skips, SkipCounters::default(), "Client {}", state.client);
+            assert!(state.available == Amount::ZERO || state.available == amount(1.0), "Client {}", state.client);
+            assert_eq!(state.available, state.total_deposited - state.total_withdrawn);
+        }
+    }
+
+    fn ttl_account(later_transactions: u32, resolve: bool) -> ClientAccount {
+        let mut account = ClientAccount::new(1);
+        let policy = AccountPolicy {
+            dispute_ttl: Some(3),
+            ..Default::default()
+        };
+
+        account.apply_transaction(transaction(TransactionType::Deposit, 1, 1, 10.0), &policy);
+        account.apply_transaction(transaction(TransactionType::Dispute, 1, 1, 0.0), &policy);
+        for tx in 0..later_transactions {
+            account.apply_transaction(transaction(TransactionType::Deposit, 1, 10 + tx, 1.0), &policy);
+        }
+        if resolve {
+            assert_eq!(
+                account.apply_transaction(transaction(TransactionType::Resolve, 1, 1, 0.0), &policy),
+                ApplyOutcome::Applied
+            );
+        }
+
+        account
+    }
+
+    #[test]
+    fn disputes_expire_after_their_ttl() {
+        let mut account = ttl_account(3, false);
+
+        assert_eq!(account.balances.held, amount(10.0));
+
+        // The next transaction finds the dispute expired, so the resolve has nothing left to settle
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Resolve, 1, 1, 0.0), &AccountPolicy {
+                dispute_ttl: Some(3),
+                ..Default::default()
+            }),
+            ApplyOutcome::Ignored
+        );
+        assert_eq!(account.disputes.expired, 1);
+        assert_eq!(account.balances.available, amount(13.0));
+        assert_eq!(account.balances.held, amount(0.0));
+        assert_eq!(account.transactions[&1].state, TransactionState::Resolved);
+    }
+
+    #[test]
+    fn disputes_resolved_within_their_ttl() {
+        let account = ttl_account(2, true);
+
+        assert_eq!(account.disputes.expired, 0);
+        assert_eq!(account.disputes.undisputed_resolves, 0);
+        assert_eq!(account.balances.available, amount(12.0));
+        assert!(account.disputes.opened_at.is_empty());
+    }
+
+    #[tokio::test]
+    async fn disputes_past_their_ttl_expire_at_the_end() {
+        // The last transaction uses up the ttl, so only the final sweep is left to release the dispute
+        let policy = AccountPolicy {
+            dispute_ttl: Some(1),
+            ..Default::default()
+        };
+
+        let (tx, rx) = tokio::sync::mpsc::channel(10);
+        let worker = tokio::spawn(run_worker(rx, policy, None, None));
+        let transactions = [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            transaction(TransactionType::Dispute, 1, 1, 0.0),
+            transaction(TransactionType::Deposit, 1, 2, 5.0),
+        ];
+        for (row, transaction) in transactions.iter().enumerate() {
+            let row = row as u64 + 2;
+            tx.send(WorkerMessage::Transaction(Transaction { row, ..*transaction })).await.unwrap();
+        }
+        drop(tx);
+
+        let (accounts, diagnostics) = worker.await.unwrap();
+        assert_eq!(diagnostics.expired_disputes, 1);
+        assert_eq!(accounts[&1].balances.available, amount(15.0));
+        assert_eq!(accounts[&1].balances.held, amount(0.0));
+    }
+
+    #[test]
+    fn withdrawal_fees() {
+        let policy = AccountPolicy {
+            fees: Fees {
+                withdrawal_flat: amount(0.5),
+                withdrawal_pct: amount(1.0),
+            },
+            ..Default::default()
+        };
+        let mut account = ClientAccount::new(1);
+
+        account.apply_transaction(transaction(TransactionType::Deposit, 1, 1, 100.0), &policy);
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Withdrawal, 1, 2, 50.0), &policy),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(account.balances.available, amount(49.0));
+        assert_eq!(account.balances.total_withdrawn, amount(50.0));
+        assert_eq!(account.balances.total_fees, amount(1.0));
+
+        // 48.6 would have been enough without the 0.986 fee
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Withdrawal, 1, 3, 48.6), &policy),
+            ApplyOutcome::InsufficientFunds
+        );
+        assert_eq!(account.balances.available, amount(49.0));
+
+        // Disputing the withdrawal holds what was withdrawn, not the fee
+        account.apply_transaction(transaction(TransactionType::Dispute, 1, 2, 0.0), &policy);
+        assert_eq!(account.balances.held, amount(50.0));
+    }
+
+    #[test]
+    fn percentage_fees_are_rounded() {
+        let fees = Fees {
+            withdrawal_flat: amount(0.0),
+            withdrawal_pct: amount(1.5),
+        };
+
+        assert_eq!(fees.withdrawal_fee(amount(0.0333)), amount(0.0005));
+        assert_eq!(fees.withdrawal_fee(amount(12.3456)), amount(0.1852));
+    }
+
+    #[test]
+    fn adjustments() {
+        let policy = AccountPolicy::default();
+        let mut account = ClientAccount::new(1);
+
+        account.apply_transaction(transaction(TransactionType::Deposit, 1, 1, 10.0), &policy);
+        account.apply_transaction(transaction(TransactionType::Adjustment, 1, 2, 2.5), &policy);
+        assert_eq!(account.balances.available, amount(12.5));
+
+        // Negative adjustments can go below zero without --allow-overdraft
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Adjustment, 1, 3, -20.0), &policy),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(account.balances.available, amount(-7.5));
+
+        // They aren't stored, so they can't be disputed
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Dispute, 1, 3, 0.0), &policy),
+            ApplyOutcome::UnknownTxReference
+        );
+        assert_eq!(account.adjustments, 2);
+        assert_eq!(account.balances.net_adjustment, amount(-17.5));
+        assert_eq!(account.balances.total_deposited, amount(10.0));
+    }
+
+    #[test]
+    fn adjustments_on_locked_accounts() {
+        let policy = AccountPolicy::default();
+        let mut account = locked_account(&policy);
+        let available = account.balances.available;
+
+        assert_eq!(
+            account.apply_transaction(transaction(TransactionType::Adjustment, 1, 100, 5.0), &policy),
+            ApplyOutcome::Applied
+        );
+        assert!(account.locked);
+        assert_eq!(account.balances.available, available + amount(5.0));
+    }
+
+    #[tokio::test]
+    async fn amounts_past_four_decimals_are_truncated() {
+        let (transaction_vec, output) = extract_all("test_data/precision.csv", ReaderOptions::default()).await;
+
+        let amounts: Vec<Amount> = transaction_vec.iter().map(|transaction| transaction.amount).collect();
+        assert_eq!(amounts, [amount(1.2345), amount(2.5), amount(0.1234)]);
+        assert_eq!(output.diagnostics.truncated_amounts, 2);
+        assert!(output.rejected_amounts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn amounts_past_four_decimals_are_rejected_with_strict_precision() {
+        let options = ReaderOptions {
+            strict_precision: true,
+            ..Default::default()
+        };
+        let (transaction_vec, output) = extract_all("test_data/precision.csv", options).await;
+
+        assert_eq!(transaction_vec, [transaction(TransactionType::Deposit, 1, 2, 2.5)]);
+        assert_eq!(output.diagnostics.imprecise_amounts, 2);
+        assert_eq!(output.diagnostics.truncated_amounts, 0);
+        assert_eq!(
+            output.rejected_amounts,
+            [
+                RejectedAmount {
+                    row: 2,
+                    reason: AmountRejection::ExcessPrecision,
+                },
+                RejectedAmount {
+                    row: 4,
+                    reason: AmountRejection::ExcessPrecision,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_amounts_stop_the_reader_in_strict_mode() {
+        for (name, amount) in [("garbage", "1.2.3"), ("text", "ten"), ("signs", "--5")] {
+            let file_path = env::temp_dir().join(format!("transactioner_{}_amount.csv", name));
+            fs::write(&file_path, format!("type,client,tx,amount\ndeposit,1,1,{}\n", amount)).unwrap();
+
+            let (tx, _rx) = tokio::sync::mpsc::channel(10);
+            let options = ReaderOptions {
+                strict: true,
+                ..Default::default()
+            };
+            let result = extract_files(&[&file_path], 1, vec![tx], options, None).await;
+            fs::remove_file(&file_path).unwrap();
+
+            // The error points at the offending line
+            let error = result.expect_err(&format!("Reading a {} amount", name)).to_string();
+            assert!(error.contains("line: 2"), "{}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn reference_rows_can_leave_the_amount_empty() {
+        let (transaction_vec, output) = extract_all("test_data/empty_amounts.csv", ReaderOptions::default()).await;
+
+        // The deposit and withdrawal without an amount are rejected, the rest go through
+        assert_eq!(output.diagnostics.invalid_amounts, 2);
+        assert_eq!(
+            transaction_vec,
+            [
+                transaction(TransactionType::Deposit, 1, 1, 10.0),
+                transaction(TransactionType::Dispute, 1, 1, 0.0),
+                transaction(TransactionType::Resolve, 1, 1, 0.0),
+                transaction(TransactionType::Dispute, 1, 1, 0.0),
+                transaction(TransactionType::Chargeback, 1, 1, 0.0),
+            ]
+        );
+
+        let state = account_after(&transaction_vec);
+        assert!(state.locked);
+        assert_eq!(state.available, Amount::ZERO);
+        assert_eq!(state.held, Amount::ZERO);
+    }
+
+    #[test]
+    fn tiny_amounts_add_up_exactly() {
+        let policy = AccountPolicy::default();
+        let mut account = ClientAccount::new(1);
+        let adjustment = transaction(TransactionType::Adjustment, 1, 1, 0.0001);
+
+        // Adjustments aren't stored, so this only exercises the balance arithmetic
+        for _ in 0..10_000_000 {
+            account.apply_transaction(adjustment, &policy);
+        }
+
+        assert_eq!(account.balances.available, Amount(10_000_000));
+        assert_eq!(ClientState::from(account).to_string(), "1,1000.0000,0.0000,1000.0000,false");
+    }
+
+    #[tokio::test]
+    async fn scientific_notation_is_read_unless_rejected() {
+        let (transaction_vec, output) = extract_all("test_data/special_amounts.csv", ReaderOptions::default()).await;
+
+        assert_eq!(
+            transaction_vec,
+            [
+                transaction(TransactionType::Deposit, 1, 1, 100.0),
+                transaction(TransactionType::Withdrawal, 1, 5, 25.0),
+            ]
+        );
+        assert_eq!(output.diagnostics.non_finite_amounts, 3);
+        assert!(output.rejected_amounts.is_empty());
+
+        let options = ReaderOptions {
+            reject_scientific: true,
+            strict_precision: true,
+            ..Default::default()
+        };
+        let (transaction_vec, output) = extract_all("test_data/special_amounts.csv", options).await;
+
+        assert!(transaction_vec.is_empty());
+        assert_eq!(output.diagnostics.scientific_amounts, 2);
+        let reasons: Vec<(u64, AmountRejection)> =
+            output.rejected_amounts.iter().map(|rejected| (rejected.row, rejected.reason)).collect();
+        assert_eq!(
+            reasons,
+            [
+                (2, AmountRejection::Scientific),
+                (3, AmountRejection::NonFinite),
+                (4, AmountRejection::NonFinite),
+                (5, AmountRejection::NonFinite),
+                (6, AmountRejection::Scientific),
+            ]
+        );
+    }
+
+    #[test]
+    fn invariants_hold_through_every_kind_of_flow() {
+        let policy = AccountPolicy {
+            check_invariants: true,
+            two_phase_deposits: true,
+            fees: Fees {
+                withdrawal_flat: amount(0.5),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
         let mut accounts = ClientAccounts::default();
 
-        for transactions in transaction_vec {
-            process_transaction(transactions, &mut accounts)
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Settle, 1, 1, 0.0),
+            transaction(TransactionType::Deposit, 1, 2, 20.0),
+            transaction(TransactionType::Withdrawal, 1, 3, 10.0),
+            transaction(TransactionType::Adjustment, 1, 4, -1.5),
+            transfer(1, 5, 30.0, 2),
+            transaction(TransactionType::Dispute, 2, 5, 0.0),
+            transaction(TransactionType::Chargeback, 2, 5, 0.0),
+        ] {
+            process_transaction(tx, &mut accounts, &policy);
+        }
+
+        for account in accounts.values() {
+            assert_eq!(account.check_invariants(&policy), [], "Client {}", account.client);
+        }
+    }
+
+    #[test]
+    fn open_withdrawal_disputes_keep_the_total_invariant() {
+        let policy = AccountPolicy {
+            check_invariants: true,
+            ..Default::default()
+        };
+        let mut account = ClientAccount::new(1);
+
+        for tx in [
+            transaction(TransactionType::Deposit, 1, 1, 100.0),
+            transaction(TransactionType::Withdrawal, 1, 2, 40.0),
+            transaction(TransactionType::Dispute, 1, 2, 0.0),
+        ] {
+            account.apply_transaction(tx, &policy);
+        }
+
+        // The funds held by the dispute already left with the withdrawal, they are expected on top of it
+        assert_eq!(account.currency_balances()[0].1.held, amount(40.0));
+        assert_eq!(account.check_invariants(&policy), []);
+
+        account.apply_transaction(transaction(TransactionType::Resolve, 1, 2, 0.0), &policy);
+        assert_eq!(account.check_invariants(&policy), []);
+
+        account.apply_transaction(transaction(TransactionType::Dispute, 1, 2, 0.0), &policy);
+        account.apply_transaction(transaction(TransactionType::Chargeback, 1, 2, 0.0), &policy);
+        assert_eq!(account.check_invariants(&policy), []);
+    }
+
+    fn in_currency(transaction: Transaction, code: &str) -> Transaction {
+        Transaction {
+            currency: Currency::parse(code).unwrap(),
+            ..transaction
+        }
+    }
+
+    #[test]
+    fn currency_parsing() {
+        assert_eq!(Currency::parse("eur"), Currency::parse("EUR"));
+        assert_eq!(Currency::parse("EUR").unwrap().to_string(), "EUR");
+        assert_eq!(Currency::NONE.to_string(), "");
+        assert!(Currency::parse("EURO").is_err());
+        assert!(Currency::parse("E1R").is_err());
+    }
+
+    #[test]
+    fn currencies_are_kept_apart() {
+        let policy = AccountPolicy::default();
+        let mut account = ClientAccount::new(1);
+
+        for tx in [
+            in_currency(transaction(TransactionType::Deposit, 1, 1, 100.0), "EUR"),
+            in_currency(transaction(TransactionType::Deposit, 1, 2, 50.0), "USD"),
+            in_currency(transaction(TransactionType::Withdrawal, 1, 3, 30.0), "EUR"),
+            in_currency(transaction(TransactionType::Dispute, 1, 2, 0.0), "USD"),
+        ] {
+            assert_eq!(account.apply_transaction(tx, &policy), ApplyOutcome::Applied);
+        }
+
+        // The euros left don't cover a withdrawal in dollars
+        assert_eq!(
+            account.apply_transaction(in_currency(transaction(TransactionType::Withdrawal, 1, 4, 60.0), "USD"), &policy),
+            ApplyOutcome::InsufficientFunds
+        );
+
+        let states = account.into_states();
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].currency, Currency::parse("EUR").unwrap());
+        assert_eq!((states[0].available, states[0].held), (amount(70.0), amount(0.0)));
+        assert_eq!(states[1].currency, Currency::parse("USD").unwrap());
+        assert_eq!((states[1].available, states[1].held), (amount(0.0), amount(50.0)));
+    }
+
+    #[test]
+    fn disputes_in_another_currency_are_rejected() {
+        let policy = AccountPolicy::default();
+        let mut account = ClientAccount::new(1);
+
+        account.apply_transaction(in_currency(transaction(TransactionType::Deposit, 1, 1, 100.0), "EUR"), &policy);
+        account.apply_transaction(in_currency(transaction(TransactionType::Deposit, 1, 2, 10.0), "USD"), &policy);
+
+        assert_eq!(
+            account.apply_transaction(in_currency(transaction(TransactionType::Dispute, 1, 1, 0.0), "USD"), &policy),
+            ApplyOutcome::UnknownTxReference
+        );
+        assert_eq!(account.currency_mismatches, 1);
+        assert!(account.disputes.open.is_empty());
+
+        let states = account.into_states();
+        assert_eq!((states[0].available, states[0].held), (amount(100.0), amount(0.0)));
+        assert_eq!((states[1].available, states[1].held), (amount(10.0), amount(0.0)));
+    }
+
+    #[tokio::test]
+    async fn currency_column_splits_the_output_rows() {
+        let states = run_pipeline(&["test_data/currencies.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+        let rows: Vec<String> = states
+            .iter()
+            .map(|state| format!("{},{}", state, state.currency))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                "1,70.0000,0.0000,70.0000,false,EUR",
+                "1,5.0000,50.0000,55.0000,false,USD",
+                "2,5.0000,0.0000,5.0000,false,USD",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reference_amounts_are_counted() {
+        let (transaction_vec, output) =
+            extract_all("test_data/reference_amounts.csv", ReaderOptions::default()).await;
+
+        // The rows still go through, their amount is ignored
+        assert_eq!(transaction_vec.len(), 5);
+        assert_eq!(output.diagnostics.reference_amounts, 2);
+        assert!(output.reference_amount_rows.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reference_amounts_are_kept_in_strict_mode() {
+        let options = ReaderOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let (_, output) = extract_all("test_data/reference_amounts.csv", options).await;
+
+        assert_eq!(output.diagnostics.reference_amounts, 2);
+        assert_eq!(output.reference_amount_rows, [3, 5]);
+
+        // Partial disputes need their amount
+        let options = ReaderOptions {
+            partial_disputes: true,
+            ..options
+        };
+        let (_, output) = extract_all("test_data/reference_amounts.csv", options).await;
+
+        assert_eq!(output.reference_amount_rows, [5]);
+    }
+
+    #[tokio::test]
+    async fn gzip_inputs_match_the_plain_file() {
+        let plain = run_pipeline(&["test_data/15.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+        for file_path in ["test_data/15.csv.gz", "test_data/15_stored.csv.gz"] {
+            let compressed = run_pipeline(&[file_path], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+            assert_eq!(compressed, plain, "Reading {}", file_path);
+        }
+    }
+
+    #[tokio::test]
+    async fn corrupt_gzip_inputs_stop_the_reader() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let result = extract_files(&["test_data/corrupt.csv.gz"], 1, vec![tx], ReaderOptions::default(), None).await;
+
+        let error = result.expect_err("Reading a corrupt file").to_string();
+        assert!(error.contains("corrupt gzip input"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn state_hashes_only_depend_on_the_balances() {
+        let policy = AccountPolicy::default();
+        for file_path in ["test_data/15.csv", "test_data/transfers.csv", "test_data/merges.csv"] {
+            let one_worker = run_pipeline(&[file_path], 1, ReaderOptions::default(), policy).await;
+            let four_workers = run_pipeline(&[file_path], 4, ReaderOptions::default(), policy).await;
+
+            assert_eq!(
+                state_hash(one_worker.iter()),
+                state_hash(four_workers.iter().rev()),
+                "Hashing the accounts of {}",
+                file_path
+            );
+        }
+
+        let mut states = run_pipeline(&["test_data/15.csv"], 2, ReaderOptions::default(), policy).await;
+        let hash = state_hash(states.iter());
+        states[1].available += Amount(1);
+        let changed_amount = state_hash(states.iter());
+        states[1].available -= Amount(1);
+        states[1].locked = !states[1].locked;
+
+        assert_ne!(changed_amount, hash);
+        assert_ne!(state_hash(states.iter()), hash);
+        assert_ne!(state_hash(states.iter()), changed_amount);
+        // Fixed, so hashes saved by an earlier run stay comparable
+        assert_eq!(format!("{:016x}", hash), "10cd587de0f5d2eb");
+    }
+
+    #[tokio::test]
+    async fn multiple_files_match_the_single_file() {
+        let single = run_pipeline(&["test_data/15.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+        for num_workers in 1..=4 {
+            let split = run_pipeline(
+                &["test_data/split/15_1.csv", "test_data/split/15_2.csv"],
+                num_workers,
+                ReaderOptions::default(),
+                AccountPolicy::default(),
+            )
+            .await;
+
+            assert_eq!(split, single, "Using {} worker/s", num_workers);
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_name_the_file_they_were_found_in() {
+        let file_path = env::temp_dir().join("transactioner_second_file.csv");
+        fs::write(&file_path, "type,client,tx,amount\ndeposit,1,20,5.0\ndeposit,1,21,ten\n").unwrap();
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(100);
+        let result = extract_files(
+            &[Path::new("test_data/split/15_1.csv"), &file_path],
+            1,
+            vec![tx],
+            ReaderOptions {
+                strict: true,
+                ..Default::default()
+            },
+            None,
+        )
+        .await;
+        fs::remove_file(&file_path).unwrap();
+
+        let error = result.expect_err("Reading a malformed second file").to_string();
+        assert!(error.starts_with(&file_path.display().to_string()), "{}", error);
+        assert!(error.contains("line: 3"), "{}", error);
+    }
+
+    #[test]
+    fn glob_patterns() {
+        let split = [PathBuf::from("test_data/split/15_1.csv"), PathBuf::from("test_data/split/15_2.csv")];
+
+        assert_eq!(expand_glob("test_data/split/*.csv").unwrap(), split);
+        assert_eq!(expand_glob("test_data/split/15_?.csv").unwrap(), split);
+        assert_eq!(expand_glob("test_data/split/*2*").unwrap(), split[1..]);
+        assert!(expand_glob("test_data/split/*.json").is_err());
+        assert!(expand_glob("test_data/missing/*.csv").is_err());
+
+        let args = ["--glob", "test_data/split/*.csv", "test_data/15.csv"].iter().map(|x| x.to_string());
+        let options = Options::from_args(args).unwrap();
+        assert_eq!(options.inputs[0], PathBuf::from("test_data/15.csv"));
+        assert_eq!(options.inputs[1..], split);
+    }
+
+    #[tokio::test]
+    async fn json_lines_inputs_match_the_csv_file() {
+        let plain = run_pipeline(&["test_data/15.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+        for file_path in ["test_data/15.ndjson", "test_data/15.jsonl"] {
+            let json = run_pipeline(&[file_path], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+            assert_eq!(json, plain, "Reading {}", file_path);
+        }
+
+        // The extension is only a default, `--format` reads any file as JSON Lines
+        let file_path = env::temp_dir().join("transactioner_json_lines.txt");
+        fs::copy("test_data/15.ndjson", &file_path).unwrap();
+        let options = ReaderOptions {
+            format: Some(InputFormat::Json),
+            ..Default::default()
+        };
+        let json = run_pipeline(&[&file_path], 2, options, AccountPolicy::default()).await;
+        fs::remove_file(&file_path).unwrap();
+
+        assert_eq!(json, plain);
+    }
+
+    #[tokio::test]
+    async fn malformed_json_lines_stop_the_reader_in_strict_mode() {
+        let file_path = env::temp_dir().join("transactioner_malformed.ndjson");
+        fs::write(&file_path, "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":5}\n\n{\"type\":\"deposit\",\n").unwrap();
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let options = ReaderOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let result = extract_files(&[&file_path], 1, vec![tx], options, None).await;
+        fs::remove_file(&file_path).unwrap();
+
+        let error = result.expect_err("Reading a malformed line").to_string();
+        assert!(error.contains("line: 3"), "{}", error);
+    }
+
+    #[test]
+    fn input_formats() {
+        assert_eq!(InputFormat::from_path(Path::new("in.csv")), InputFormat::Csv);
+        assert_eq!(InputFormat::from_path(Path::new("in.ndjson")), InputFormat::Json);
+        assert_eq!(InputFormat::from_path(Path::new("in.jsonl.gz")), InputFormat::Json);
+        assert_eq!(InputFormat::from_path(Path::new("-")), InputFormat::Csv);
+
+        let args = ["-", "--format", "json"].iter().map(|x| x.to_string());
+        assert_eq!(Options::from_args(args).unwrap().reader.format, Some(InputFormat::Json));
+        let args = ["-", "--format", "json", "--format", "auto"].iter().map(|x| x.to_string());
+        assert_eq!(Options::from_args(args).unwrap().reader.format, None);
+        let args = ["-", "--format", "xml"].iter().map(|x| x.to_string());
+        assert!(Options::from_args(args).is_err());
+
+        let args = ["in.csv", "--follow", "--output", "out.csv", "--dump-interval", "0.5"].iter().map(|x| x.to_string());
+        let options = Options::from_args(args).unwrap();
+        assert!(options.reader.follow);
+        assert_eq!(options.output_path, Some(PathBuf::from("out.csv")));
+        assert_eq!(options.dump_interval, Some(Duration::from_millis(500)));
+        // Snapshots need a file to go to, and following needs a file to read
+        assert!(Options::from_args(["in.csv", "--dump-interval", "1"].iter().map(|x| x.to_string())).is_err());
+        assert!(Options::from_args(["--follow"].iter().map(|x| x.to_string())).is_err());
+        assert!(Options::from_args(["http://localhost/in.csv", "--follow"].iter().map(|x| x.to_string())).is_err());
+        let args = ["in.csv", "--limit-rows", "10", "--limit-bytes", "4096"].iter().map(|x| x.to_string());
+        let options = Options::from_args(args).unwrap();
+        assert_eq!((options.reader.limit_rows, options.reader.limit_bytes), (Some(10), Some(4096)));
+        assert!(Options::from_args(["in.csv", "--limit-rows", "-1"].iter().map(|x| x.to_string())).is_err());
+        let args = ["in.csv", "--max-field-bytes", "100", "--max-row-bytes", "1000"].iter().map(|x| x.to_string());
+        let options = Options::from_args(args).unwrap();
+        assert_eq!(options.reader.row_limits, RowLimits { field_bytes: 100, row_bytes: 1000 });
+        assert!(Options::from_args(["in.csv", "--max-row-bytes", "0"].iter().map(|x| x.to_string())).is_err());
+        let args = ["in.csv", "--save-offset", "a.offset", "--resume-offset", "in.offset"].iter().map(|x| x.to_string());
+        let options = Options::from_args(args).unwrap();
+        assert!(options.reader.save_offset);
+        assert_eq!(options.resume_offset, Some(PathBuf::from("in.offset")));
+        // Offsets are kept for a single file
+        assert!(Options::from_args(["-", "--save-offset", "in.offset"].iter().map(|x| x.to_string())).is_err());
+        let args = ["a.csv", "b.csv", "--resume-offset", "in.offset"].iter().map(|x| x.to_string());
+        assert!(Options::from_args(args).is_err());
+        let options = Options::from_args(["in.csv", "--lenient-amounts"].iter().map(|x| x.to_string())).unwrap();
+        assert!(options.reader.lenient_amounts && !options.reader.decimal_comma);
+        // A decimal comma is only read leniently
+        let options = Options::from_args(["in.csv", "--decimal-comma"].iter().map(|x| x.to_string())).unwrap();
+        assert!(options.reader.lenient_amounts && options.reader.decimal_comma);
+        let options = Options::from_args(["in.csv", "--strict-types"].iter().map(|x| x.to_string())).unwrap();
+        assert!(options.reader.strict_types);
+        let options = Options::from_args(["in.csv", "--encoding", "latin1"].iter().map(|x| x.to_string())).unwrap();
+        assert_eq!(options.reader.encoding, Encoding::Latin1);
+        assert!(Options::from_args(["in.csv", "--encoding", "utf16"].iter().map(|x| x.to_string())).is_err());
+        let args = ["convert", "in.csv", "--no-header", "in.tbin"].iter().map(|x| x.to_string());
+        let options = Options::from_args(args).unwrap();
+        assert_eq!(options.inputs, [PathBuf::from("in.csv")]);
+        assert_eq!(options.convert_output, Some(PathBuf::from("in.tbin")));
+        assert!(Options::from_args(["convert", "in.csv"].iter().map(|x| x.to_string())).is_err());
+        let args = ["in.csv", "--output", "out.csv", "--dump-interval", "0"].iter().map(|x| x.to_string());
+        assert!(Options::from_args(args).is_err());
+
+        assert_eq!(InputFormat::from_path(Path::new("in.ndjson.zst")), InputFormat::Json);
+        assert_eq!(default_delimiter(Path::new("in.tsv.zst")), b'\t');
+
+        let compression = |value: &str| {
+            let args = vec!["-", "--compression", value].into_iter().map(|x| x.to_string());
+            Options::from_args(args).map(|options| options.reader.compression)
+        };
+        assert_eq!(compression("zstd"), Ok(Some(Compression::Zstd)));
+        assert_eq!(compression("none"), Ok(Some(Compression::None)));
+        assert_eq!(compression("auto"), Ok(None));
+        assert!(compression("lz4").is_err());
+    }
+
+    #[tokio::test]
+    async fn delimited_inputs_match_the_csv_file() {
+        let plain = run_pipeline(&["test_data/15.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+        let tsv = run_pipeline(&["test_data/15.tsv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+        assert_eq!(tsv, plain);
+
+        let options = ReaderOptions {
+            delimiter: Some(b';'),
+            ..Default::default()
+        };
+        let semicolon = run_pipeline(&["test_data/15_semicolon.csv"], 2, options, AccountPolicy::default()).await;
+        assert_eq!(semicolon, plain);
+    }
+
+    #[tokio::test]
+    async fn inputs_are_read_in_the_format_they_are_sniffed_as() {
+        let (expected, _) = extract_all("test_data/15.csv", ReaderOptions::default()).await;
+        let file_path = std::env::temp_dir().join("transactioner_sniffed.csv");
+        fs::copy("test_data/15.ndjson", &file_path).unwrap();
+
+        for fast_parse in [false, true] {
+            let options = ReaderOptions { fast_parse, ..Default::default() };
+            let (transactions, output) = read_all(file_path.clone(), options).await;
+            assert!(output.is_ok());
+            assert_eq!(transactions, expected);
+        }
+
+        // Given formats aren't second guessed
+        let options = ReaderOptions { format: Some(InputFormat::Csv), ..Default::default() };
+        assert!(read_all(file_path.clone(), options).await.1.is_err());
+        fs::remove_file(&file_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn single_column_headers_suggest_a_delimiter() {
+        // The delimiter is only told from the header with a format given
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let options = ReaderOptions { format: Some(InputFormat::Csv), ..Default::default() };
+        let result = extract_files(&["test_data/15_semicolon.csv"], 1, vec![tx], options, None).await;
+
+        let error = result.expect_err("Reading with the wrong delimiter").to_string();
+        assert!(error.contains("did you mean --delimiter ';'?"), "{}", error);
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let options = ReaderOptions {
+            delimiter: Some(b';'),
+            ..Default::default()
+        };
+        let result = extract_files(&["test_data/15.tsv"], 1, vec![tx], options, None).await;
+
+        let error = result.expect_err("Reading with the wrong delimiter").to_string();
+        assert!(error.contains("did you mean --delimiter '\\t'?"), "{}", error);
+    }
+
+    #[test]
+    fn delimiter_parsing() {
+        let delimiter = |value: &str| {
+            let args = vec!["-", "--delimiter", value].into_iter().map(|x| x.to_string());
+            Options::from_args(args).map(|options| options.reader.delimiter)
+        };
+
+        assert_eq!(delimiter(";"), Ok(Some(b';')));
+        assert_eq!(delimiter("\\t"), Ok(Some(b'\t')));
+        assert_eq!(delimiter("tab"), Ok(Some(b'\t')));
+        assert!(delimiter(";;").is_err());
+        assert!(delimiter("\"").is_err());
+
+        assert_eq!(default_delimiter(Path::new("in.tsv.gz")), b'\t');
+        assert_eq!(default_delimiter(Path::new("in.csv")), b',');
+    }
+
+    #[tokio::test]
+    async fn columns_are_matched_by_name() {
+        let plain = run_pipeline(&["test_data/15.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+        for file_path in ["test_data/reordered_columns.csv", "test_data/extra_columns.csv"] {
+            let states = run_pipeline(&[file_path], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+            assert_eq!(states, plain, "Reading {}", file_path);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_columns_are_named() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let result = extract_files(&["test_data/missing_tx_column.csv"], 1, vec![tx], ReaderOptions::default(), None).await;
+
+        let error = result.expect_err("Reading without a tx column").to_string();
+        assert!(error.contains("missing the required tx column"), "{}", error);
+
+        let file_path = env::temp_dir().join("transactioner_missing_field.ndjson");
+        fs::write(&file_path, "{\"type\":\"deposit\",\"client\":1,\"amount\":5}\n").unwrap();
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let options = ReaderOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let result = extract_files(&[&file_path], 1, vec![tx], options, None).await;
+        fs::remove_file(&file_path).unwrap();
+
+        let error = result.expect_err("Reading without a tx field").to_string();
+        assert!(error.contains("line: 1") && error.contains("missing the required tx field"), "{}", error);
+    }
+
+    /// Serves the responses in turn to the connections made to it, returning the URL it listens on.
+    fn serve(responses: Vec<Vec<u8>>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut request = BufReader::new(&stream);
+                let mut line = String::new();
+                while request.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                    line.clear();
+                }
+
+                (&stream).write_all(&response).unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn urls_are_streamed_like_files() {
+        let (expected, _) = extract_all("test_data/15.csv", ReaderOptions::default()).await;
+        let body = fs::read("test_data/15.csv").unwrap();
+        let mut response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+        response.extend(&body);
+
+        let url = serve(vec![response]);
+        let signed_url = PathBuf::from(format!("{}/15.csv?signature=abc", url));
+        let (transactions, output) = read_all(signed_url, ReaderOptions::default()).await;
+        assert!(output.is_ok());
+        assert_eq!(transactions, expected);
+
+        // Chunked and compressed, after a redirect
+        let compressed = fs::read("test_data/15.csv.gz").unwrap();
+        let mut chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n".to_vec();
+        chunked.extend(b"Content-Encoding: gzip\r\n\r\n");
+        for chunk in compressed.chunks(100) {
+            chunked.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+            chunked.extend(chunk);
+            chunked.extend(b"\r\n");
         }
+        chunked.extend(b"0\r\n\r\n");
+        let redirect = b"HTTP/1.1 302 Found\r\nLocation: /15.csv.gz\r\nContent-Length: 0\r\n\r\n".to_vec();
 
-        let mut account_states: Vec<ClientState> = accounts.into_values().map(ClientState::from).collect();
-        account_states.sort_by_key(|x| x.client);
+        let url = serve(vec![redirect, chunked]);
+        let (transactions, output) = read_all(PathBuf::from(format!("{}/latest", url)), ReaderOptions::default()).await;
+        assert!(output.is_ok());
+        assert_eq!(transactions, expected);
+    }
 
-        assert_eq!(account_states.len(), 3);
-        for i in 0..3 {
-            assert_eq!(account_states[i], expected_results[i]);
+    #[tokio::test]
+    async fn dropped_connections_tell_the_rows_read_before() {
+        let mut response = b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\ntype,client,tx,amount\n".to_vec();
+        response.extend(b"deposit,1,1,1.0\ndeposit,1,2,1.0\n");
+
+        let url = serve(vec![response]);
+        let input_url = PathBuf::from(format!("{}/input.csv", url));
+        let (transactions, output) = read_all(input_url, ReaderOptions::default()).await;
+        assert_eq!(transactions.len(), 2);
+        let error = output.unwrap_err();
+        assert!(error.contains("closed before the end of the body after 2 row/s"), "{}", error);
+
+        let url = serve(vec![b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n".to_vec()]);
+        let (_, output) = read_all(PathBuf::from(format!("{}/input.csv", url)), ReaderOptions::default()).await;
+        assert!(output.unwrap_err().contains("status 403 after 0 row/s"));
+    }
+
+    #[tokio::test]
+    async fn headers_are_validated_before_reading() {
+        let headers = |names: &[&str]| csv::StringRecord::from(names.to_vec());
+
+        let error = check_headers(&headers(&["type", "note"])).unwrap_err();
+        assert!(error.contains("missing the required client, tx, amount columns"), "{}", error);
+        let error = check_headers(&headers(&["type", "client", "tx", "amount", "tx", "tx"])).unwrap_err();
+        assert!(error.ends_with("has the tx column more than once, see --no-validate-header"), "{}", error);
+        assert!(check_headers(&headers(&["batch", "type", "client", "note", "tx", "amount"])).is_ok());
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let options = ReaderOptions::default();
+        let result = extract_files(&["test_data/duplicated_columns.csv"], 1, vec![tx], options, None).await;
+        assert!(result.expect_err("Reading a duplicated column").to_string().contains("more than once"));
+
+        // Column names are trimmed and matched whatever their case
+        let file_path = env::temp_dir().join("transactioner_mixed_case.csv");
+        fs::write(&file_path, " Type ,CLIENT,Tx,Amount,Note\ndeposit,1,1,10.0,first\n").unwrap();
+        for fast_parse in vec![false, true].into_iter() {
+            let options = ReaderOptions { fast_parse, ..Default::default() };
+            let (transactions, output) = read_all(file_path.clone(), options).await;
+            assert!(output.is_ok());
+            assert_eq!(transactions, [transaction(TransactionType::Deposit, 1, 1, 10.0)]);
         }
+        fs::remove_file(&file_path).unwrap();
 
+        // Without the validation the rows of the odd header are read as they come
+        let options = ReaderOptions { no_validate_header: true, ..Default::default() };
+        let (transactions, output) = read_all(PathBuf::from("test_data/missing_tx_column.csv"), options).await;
+        assert!(transactions.is_empty());
+        assert_eq!(output.unwrap().diagnostics.malformed_rows, 1);
+    }
+
+    #[tokio::test]
+    async fn malformed_rows_are_skipped() {
+        let (transaction_vec, output) = extract_all("test_data/malformed_rows.csv", ReaderOptions::default()).await;
+
+        let tx_ids: Vec<u32> = transaction_vec.iter().map(|x| x.tx).collect();
+        assert_eq!(tx_ids, [1, 3, 5]);
+        assert_eq!(output.diagnostics.malformed_rows, 2);
+        assert_eq!(output.diagnostics.rows, 5);
 
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let options = ReaderOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let result = extract_files(&["test_data/malformed_rows.csv"], 1, vec![tx], options, None).await;
+
+        let error = result.expect_err("Reading malformed rows in strict mode").to_string();
+        assert!(error.contains("line: 3"), "{}", error);
     }
 
     #[tokio::test]
-    async fn proper_record_extraction() {
-        let file_path = "test_data/sample_types.csv";
+    async fn reports_carry_the_row_of_each_transaction() {
+        let policy = AccountPolicy {
+            declined_cap: 10,
+            ..Default::default()
+        };
+        let (accounts, _) = worker_after("test_data/15.csv", policy).await;
 
-        let expected_transactions = vec![
-            Transaction {
-                r#type: TransactionType::Withdrawal,
-                client: 10,
-                tx: 119,
-                amount: 15.0,
-            },
-            Transaction {
-                r#type: TransactionType::Deposit,
-                client: 13,
-                tx: 131,
-                amount: 15.3,
-            },
-            Transaction {
-                r#type: TransactionType::Dispute,
-                client: 20,
-                tx: 341,
-                amount: 15.5761,
-            },
-            Transaction {
-                r#type: TransactionType::Resolve,
-                client: 15,
-                tx: 391,
-                amount: 415.0,
-            },
-            Transaction {
-                r#type: TransactionType::Chargeback,
-                client: 11,
-                tx: 319,
-                amount: 0.0,
-            },
-            Transaction {
-                r#type: TransactionType::Unknown,
-                client: 41,
-                tx: 531,
-                amount: 165.0,
-            },
-        ];
+        let declined: Vec<(u32, u64)> = accounts[&1].records.declined.iter().map(|x| (x.tx, x.row)).collect();
+        assert_eq!(declined, [(9, 8), (15, 12)]);
+        assert_eq!(accounts[&1].locked_by.map(|cause| cause.row), Some(7));
+    }
 
-        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+    #[tokio::test]
+    async fn read_ahead_keeps_every_byte() {
+        let plain = fs::read("test_data/perf/100_000.csv").unwrap();
+        let mut read = Vec::new();
+        ReadAhead::new(Box::new(File::open("test_data/perf/100_000.csv").unwrap()), false)
+            .read_to_end(&mut read)
+            .unwrap();
 
-        tokio::spawn(async move {
-            extract_records(file_path, 1, vec![tx]).await.expect("Should finish correctly");
-        });
+        assert!(plain.len() > ReadAhead::CHUNK_SIZE * ReadAhead::CHUNKS_AHEAD);
+        assert!(read == plain);
 
-        let mut transaction_vec = Vec::with_capacity(20);
+        let mut read = Vec::new();
+        let corrupt = gzip::GzDecoder::new(BufReader::new(File::open("test_data/corrupt.csv.gz").unwrap()));
+        let error = ReadAhead::new(Box::new(corrupt), false).read_to_end(&mut read).expect_err("Reading a corrupt file");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Reads the file the way `extract_all` does, keeping the error if the reader stops.
+    async fn read_all(file_path: PathBuf, options: ReaderOptions) -> (Vec<Transaction>, Result<ReaderOutput, String>) {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+
+        let handle =
+            tokio::spawn(async move { extract_files(&[file_path], 1, vec![tx], options, None).await.map_err(|e| e.to_string()) });
 
+        let mut transaction_vec = Vec::new();
         while let Some(data) = rx.recv().await {
-            transaction_vec.push(data);
+            transaction_vec.push(expect_transaction(data));
         }
 
-        assert_eq!(transaction_vec.len(), 6);
-        for i in 0..6 {
-            assert_eq!(transaction_vec[i], expected_transactions[i]);
+        (transaction_vec, handle.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn fast_parsing_matches_the_csv_reader() {
+        let mut file_paths: Vec<PathBuf> = fs::read_dir("test_data")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file() && InputFormat::from_path(path) == InputFormat::Csv)
+            .collect();
+        file_paths.extend(expand_glob("test_data/split/*.csv").unwrap());
+        file_paths.sort();
+
+        for file_path in file_paths {
+            for strict in [false, true] {
+                let options = ReaderOptions {
+                    strict,
+                    ..Default::default()
+                };
+                let fast_options = ReaderOptions {
+                    fast_parse: true,
+                    ..options
+                };
+
+                let (transactions, output) = read_all(file_path.clone(), options).await;
+                let (fast_transactions, fast_output) = read_all(file_path.clone(), fast_options).await;
+
+                // Every field counts, the row and currency included
+                assert_eq!(format!("{:?}", fast_transactions), format!("{:?}", transactions), "Reading {:?}", file_path);
+                match (fast_output, output) {
+                    (Ok(fast_output), Ok(output)) => {
+                        let diagnostics = format!("{:?}", output.diagnostics);
+                        assert_eq!(format!("{:?}", fast_output.diagnostics), diagnostics, "Reading {:?}", file_path);
+                    }
+                    (Err(fast_error), Err(error)) => {
+                        // The wording differs, but not the line the reader stopped at
+                        let stopped_at = |error: &str| {
+                            let rest = error.split("line: ").nth(1)?;
+                            Some(rest.chars().take_while(char::is_ascii_digit).collect::<String>())
+                        };
+                        assert_eq!(stopped_at(&fast_error), stopped_at(&error), "Reading {:?}: {}", file_path, fast_error);
+                    }
+                    (fast_output, output) => {
+                        panic!("Reading {:?}: {:?} against {:?}", file_path, fast_output.err(), output.err())
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn fast_parsing_covers_the_edge_cases() {
+        let options = ReaderOptions {
+            fast_parse: true,
+            ..Default::default()
+        };
+        let (transactions, output) = read_all(PathBuf::from("test_data/fast_parse_cases.csv"), options).await;
+        let output = output.unwrap();
+
+        let rows: Vec<(u32, u64)> = transactions.iter().map(|x| (x.tx, x.row)).collect();
+        assert_eq!(rows, [(1, 2), (2, 4), (3, 5), (1, 6), (4, 7), (5, 8), (6, 9)]);
+        assert_eq!(transactions[1].r#type, TransactionType::Withdrawal);
+        assert_eq!(transactions[1].amount, amount(0.25));
+        assert_eq!(transactions[0].currency, Currency::parse("USD").unwrap());
+        assert_eq!(transactions[0].idempotency_key, transactions[2].idempotency_key);
+        assert!(transactions[0].idempotency_key.is_some() && transactions[1].idempotency_key.is_none());
+        assert_eq!(output.diagnostics.malformed_rows, 2);
+        assert_eq!(output.diagnostics.non_finite_amounts, 1);
+    }
+
+    #[tokio::test]
+    async fn chunked_parsing_matches_the_fast_parser() {
+        let mut file_paths: Vec<PathBuf> = fs::read_dir("test_data")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file() && InputFormat::from_path(path) == InputFormat::Csv)
+            .collect();
+        file_paths.sort();
+
+        for file_path in file_paths {
+            for strict in [false, true] {
+                let options = ReaderOptions {
+                    strict,
+                    fast_parse: true,
+                    ..Default::default()
+                };
+                let (transactions, output) = read_all(file_path.clone(), options).await;
+                let output = output.map(|output| format!("{:?}", output.diagnostics));
+
+                for parse_threads in [2, 3, 16] {
+                    let chunked_options = ReaderOptions { parse_threads, ..options };
+                    let (chunked_transactions, chunked_output) = read_all(file_path.clone(), chunked_options).await;
+
+                    assert_eq!(
+                        format!("{:?}", chunked_transactions),
+                        format!("{:?}", transactions),
+                        "Reading {:?} with {} threads",
+                        file_path,
+                        parse_threads
+                    );
+                    let chunked_output = chunked_output.map(|output| format!("{:?}", output.diagnostics));
+                    assert_eq!(chunked_output, output, "Reading {:?} with {} threads", file_path, parse_threads);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn chunked_parsing_keeps_the_order_of_each_client() {
+        let file_path = env::temp_dir().join("transactioner_chunked.csv");
+        let mut contents = String::from("type,client,tx,amount\n");
+        let mut balances = [0u32; 8];
+        let mut last_deposits = [0u32; 8];
+
+        // Each client goes through deposits, withdrawals of everything it holds and disputes of
+        // its latest deposit, any row applied out of order is declined or leaves funds behind.
+        for tx in 1..=30_000u32 {
+            let client = (tx * 7 + tx / 13) % 8;
+            let row = match (balances[client as usize], tx % 5) {
+                (0, _) | (_, 0) | (_, 1) => {
+                    balances[client as usize] += 1;
+                    last_deposits[client as usize] = tx;
+                    format!("deposit,{},{},1.0\n", client, tx)
+                }
+                (held, 2) => {
+                    balances[client as usize] = 0;
+                    format!("withdrawal,{},{},{}.0\n", client, tx, held)
+                }
+                _ => {
+                    let deposit = last_deposits[client as usize];
+                    format!("dispute,{},{},\nresolve,{},{},\n", client, deposit, client, deposit)
+                }
+            };
+            contents.push_str(&row);
+        }
+        fs::write(&file_path, contents).unwrap();
+
+        let options = ReaderOptions {
+            fast_parse: true,
+            ..Default::default()
+        };
+        let expected = run_pipeline(&[&file_path], 3, options, AccountPolicy::default()).await;
+        let (expected_transactions, _) = read_all(file_path.clone(), options).await;
+
+        for parse_threads in [2, 4, 7, 64] {
+            let chunked_options = ReaderOptions { parse_threads, ..options };
+
+            let account_states = run_pipeline(&[&file_path], 3, chunked_options, AccountPolicy::default()).await;
+            assert_eq!(account_states, expected, "Parsing with {} threads", parse_threads);
+
+            let (transactions, _) = read_all(file_path.clone(), chunked_options).await;
+            assert_eq!(format!("{:?}", transactions), format!("{:?}", expected_transactions));
+        }
+        fs::remove_file(&file_path).unwrap();
+
+        assert_eq!(expected.len(), 8);
+        for state in expected {
+            assert_eq!(state.skips, SkipCounters::default(), "Client {}", state.client);
+            assert_eq!(state.held, Amount::ZERO, "Client {}", state.client);
+        }
+    }
+
+    #[tokio::test]
+    async fn bom_and_crlf_inputs_are_read_like_plain_ones() {
+        let excel_export = fs::read("test_data/excel_export.csv").unwrap();
+        assert!(excel_export.starts_with(b"\xEF\xBB\xBF") && excel_export.ends_with(b"\r\n"));
+
+        let file_path = env::temp_dir().join("transactioner_plain_export.csv");
+        let plain = String::from_utf8(excel_export[3..].to_vec()).unwrap().replace("\r\n", "\n");
+        fs::write(&file_path, plain).unwrap();
+
+        for fast_parse in [false, true] {
+            let options = ReaderOptions {
+                fast_parse,
+                strict: true,
+                ..Default::default()
+            };
+            let (transactions, output) = read_all(PathBuf::from("test_data/excel_export.csv"), options).await;
+            let (plain_transactions, _) = read_all(file_path.clone(), options).await;
+            output.unwrap();
+
+            // Every field counts, the row and the currency of the last column included
+            assert_eq!(format!("{:?}", transactions), format!("{:?}", plain_transactions));
+            let rows: Vec<(u32, u64)> = transactions.iter().map(|x| (x.tx, x.row)).collect();
+            assert_eq!(rows, [(1, 2), (2, 3), (3, 4), (2, 6), (4, 7)]);
+            assert_eq!(transactions[3].currency, Currency::parse("EUR").unwrap());
+        }
+        fs::remove_file(&file_path).unwrap();
+
+        let file_path = env::temp_dir().join("transactioner_excel_export.ndjson");
+        let json = fs::read_to_string("test_data/15.ndjson").unwrap();
+        fs::write(&file_path, format!("\u{feff}{}", json.replace('\n', "\r\n"))).unwrap();
+
+        let options = ReaderOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let (transactions, output) = read_all(file_path.clone(), options).await;
+        let (plain_transactions, _) = read_all(PathBuf::from("test_data/15.ndjson"), options).await;
+        fs::remove_file(&file_path).unwrap();
+
+        output.unwrap();
+        assert_eq!(format!("{:?}", transactions), format!("{:?}", plain_transactions));
+    }
+
+    #[tokio::test]
+    async fn timestamps_are_tracked_per_client() {
+        let account_states =
+            run_pipeline(&["test_data/timestamps.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+        let timestamps: Vec<(String, String)> = account_states
+            .iter()
+            .map(|x| (timestamp::format(x.first_timestamp.unwrap()), timestamp::format(x.last_timestamp.unwrap())))
+            .collect();
+        assert_eq!(
+            timestamps,
+            [
+                ("2021-03-04T05:06:07.000Z".to_string(), "2021-03-05T00:00:00.000Z".to_string()),
+                ("2021-03-04T06:00:00.000Z".to_string(), "2021-03-04T06:00:00.000Z".to_string()),
+            ]
+        );
+
+        // A file without the column has none
+        let account_states =
+            run_pipeline(&["test_data/15.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+        assert!(account_states.iter().all(|x| x.first_timestamp.is_none() && x.last_timestamp.is_none()));
+    }
+
+    #[tokio::test]
+    async fn chronology_is_only_checked_on_demand() {
+        let options = ReaderOptions {
+            check_chronology: true,
+            ..Default::default()
+        };
+        let (_, in_order) = read_all(PathBuf::from("test_data/timestamps.csv"), options).await;
+        let (_, out_of_order) = read_all(PathBuf::from("test_data/unordered_timestamps.csv"), options).await;
+        let (_, unchecked) = read_all(PathBuf::from("test_data/unordered_timestamps.csv"), ReaderOptions::default()).await;
+
+        assert_eq!(in_order.unwrap().diagnostics.out_of_order_timestamps, 0);
+        assert_eq!(out_of_order.unwrap().diagnostics.out_of_order_timestamps, 2);
+        assert_eq!(unchecked.unwrap().diagnostics.out_of_order_timestamps, 0);
+
+        // The balances don't depend on the timestamps, only on the order of the rows
+        let file_path = env::temp_dir().join("transactioner_untimed.csv");
+        let untimed: String = fs::read_to_string("test_data/unordered_timestamps.csv")
+            .unwrap()
+            .lines()
+            .map(|line| format!("{}\n", line.rsplit_once(',').unwrap().0))
+            .collect();
+        fs::write(&file_path, untimed).unwrap();
+
+        let mut timed =
+            run_pipeline(&["test_data/unordered_timestamps.csv"], 2, options, AccountPolicy::default()).await;
+        let untimed = run_pipeline(&[&file_path], 2, options, AccountPolicy::default()).await;
+        fs::remove_file(&file_path).unwrap();
+
+        assert_eq!(timed[1].first_timestamp, timestamp::parse("2021-03-04T04:00:00Z").ok());
+        assert_eq!(timed[1].last_timestamp, timestamp::parse("2021-03-04T05:30:00Z").ok());
+        for state in timed.iter_mut() {
+            state.first_timestamp = None;
+            state.last_timestamp = None;
+        }
+        assert_eq!(timed, untimed);
+    }
+
+    #[tokio::test]
+    async fn malformed_timestamps_are_skipped() {
+        let file_path = env::temp_dir().join("transactioner_malformed_timestamp.csv");
+        fs::write(&file_path, "type,client,tx,amount,timestamp\ndeposit,1,1,1.0,yesterday\ndeposit,1,2,1.0,\n").unwrap();
+
+        for fast_parse in [false, true] {
+            let options = ReaderOptions {
+                fast_parse,
+                ..Default::default()
+            };
+            let (transactions, output) = read_all(file_path.clone(), options).await;
+
+            assert_eq!(transactions.len(), 1);
+            assert_eq!(transactions[0].timestamp, None);
+            assert_eq!(output.unwrap().diagnostics.malformed_rows, 1);
+        }
+        fs::remove_file(&file_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn headerless_inputs_are_read_by_position() {
+        let with_header = run_pipeline(&["test_data/20.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+        for fast_parse in [false, true] {
+            let options = ReaderOptions {
+                no_header: true,
+                fast_parse,
+                ..Default::default()
+            };
+            let (transactions, _) = read_all(PathBuf::from("test_data/20_headerless.csv"), options).await;
+            assert_eq!(transactions[0].row, 1);
+
+            let headerless = run_pipeline(&["test_data/20_headerless.csv"], 2, options, AccountPolicy::default()).await;
+            assert_eq!(headerless, with_header);
+        }
+
+        // Read with a header, the first transaction gives it away
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let result = extract_files(&["test_data/20_headerless.csv"], 1, vec![tx], ReaderOptions::default(), None).await;
+
+        let error = result.expect_err("Reading a headerless file").to_string();
+        assert!(error.contains("reads like a deposit transaction, did you mean --no-header?"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn comments_and_blank_lines_are_skipped() {
+        let clean = run_pipeline(&["test_data/20.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+
+        for fast_parse in [false, true] {
+            let options = ReaderOptions {
+                fast_parse,
+                strict: true,
+                ..Default::default()
+            };
+            let commented = run_pipeline(&["test_data/20_commented.csv"], 2, options, AccountPolicy::default()).await;
+            assert_eq!(commented, clean);
+
+            let (transactions, output) = read_all(PathBuf::from("test_data/20_commented.csv"), options).await;
+            let diagnostics = output.unwrap().diagnostics;
+            assert_eq!((diagnostics.rows, diagnostics.skipped_lines, diagnostics.malformed_rows), (20, 10, 0));
+            assert_eq!((transactions[0].row, transactions[5].row), (6, 12));
+        }
+
+        // Lines of a quoted field are never skipped, whatever they start with
+        let file_path = env::temp_dir().join("transactioner_quoted_comments.csv");
+        fs::write(&file_path, "type,client,tx,amount,note\ndeposit,1,1,1.0,\"first\n\n# second\"\n# a comment\ndeposit,1,2,1.0,\n").unwrap();
+
+        let (transactions, output) = read_all(file_path.clone(), ReaderOptions::default()).await;
+        fs::remove_file(&file_path).unwrap();
+
+        let rows: Vec<(u32, u64)> = transactions.iter().map(|x| (x.tx, x.row)).collect();
+        assert_eq!(rows, [(1, 2), (2, 6)]);
+        assert_eq!(output.unwrap().diagnostics.skipped_lines, 1);
+    }
+
+    #[test]
+    fn comment_lines_are_left_blank() {
+        let filtered = |input: &str| {
+            let mut output = String::new();
+            CommentLines::new(input.as_bytes(), b'\t', RowLimits::default()).read_to_string(&mut output).unwrap();
+            output
+        };
+
+        assert_eq!(filtered("a\n#b\n\n  \r\nc"), "a\n  \n \n   \nc");
+        assert_eq!(filtered("\u{feff}# header\na"), "           \na");
+        // Tabs are the delimiter, so the line holds two fields
+        assert_eq!(filtered("\t\n# x"), "\t\n   ");
+        assert_eq!(filtered("\"a\n#b\"\"\n\"\n#c\n"), "\"a\n#b\"\"\n\"\n  \n");
+    }
+
+    #[tokio::test]
+    async fn oversized_rows_are_cut_short() {
+        // The quote of the second row is never closed, it takes the rows after it in its field
+        let file_path = PathBuf::from("test_data/unterminated_quote.csv");
+        let row_limits = RowLimits { field_bytes: 64, row_bytes: 256 };
+        let options = ReaderOptions { row_limits, ..Default::default() };
+        let (transactions, output) = read_all(file_path.clone(), options).await;
+        let txs: Vec<u32> = transactions.iter().map(|x| x.tx).collect();
+        assert_eq!(txs, [1, 6, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(output.unwrap().diagnostics.malformed_rows, 1);
+
+        let options = ReaderOptions { row_limits, strict: true, ..Default::default() };
+        let error = read_all(file_path, options).await.1.unwrap_err();
+        assert!(error.contains("line: 3: the row at byte 45 of the input goes over --max-field-bytes"), "{}", error);
+
+        // A line without an end is never held past the limit
+        let mut input = b"type,client,tx,amount\ndeposit,1,1,\"".to_vec();
+        input.resize(10 << 20, b'x');
+        input.extend(b"\ndeposit,1,2,1.0\n");
+        let mut output = Vec::new();
+        let mut lines = CommentLines::new(&input[..], b',', RowLimits { field_bytes: 1 << 20, row_bytes: 256 });
+        lines.read_to_end(&mut output).unwrap();
+        // The header, the row up to the limit with its quote closed and the next row
+        assert_eq!(output.len(), 22 + 256 + 2 + 16);
+        assert!(output.ends_with(b"xx\"\ndeposit,1,2,1.0\n"));
+        let cuts = lines.cuts().lock().unwrap().clone();
+        assert_eq!(cuts, [CutRow { offset: 22, dropped: (10 << 20) - 22 - 256, limit: "--max-row-bytes" }]);
+
+        // The fast parser goes by the same limits, the rows of the fixtures being well within them
+        let long_note = format!("type,client,tx,amount,note\ndeposit,1,1,1.0,{}\ndeposit,1,2,1.0,\n", "x".repeat(100));
+        let file_path = std::env::temp_dir().join("transactioner_long_note.csv");
+        fs::write(&file_path, long_note).unwrap();
+        for fast_parse in [false, true] {
+            let options = ReaderOptions { row_limits, fast_parse, ..Default::default() };
+            let (transactions, output) = read_all(file_path.clone(), options).await;
+            assert_eq!(transactions.iter().map(|x| x.tx).collect::<Vec<_>>(), [2]);
+            assert_eq!(output.unwrap().diagnostics.malformed_rows, 1);
+        }
+        fs::remove_file(&file_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn zstd_inputs_match_the_plain_file() {
+        let plain = run_pipeline(&["test_data/15.csv"], 2, ReaderOptions::default(), AccountPolicy::default()).await;
+        let compressed = run_pipeline(&["test_data/15.csv.zst"], 2, ReaderOptions::default(), AccountPolicy::default());
+
+        assert_eq!(compressed.await, plain);
+
+        // Forcing a compression skips the detection, the fast path reads compressed files as usual
+        let options = ReaderOptions {
+            compression: Some(Compression::Zstd),
+            fast_parse: true,
+            ..ReaderOptions::default()
+        };
+        assert_eq!(run_pipeline(&["test_data/15.csv.zst"], 2, options, AccountPolicy::default()).await, plain);
+
+        let options = ReaderOptions {
+            compression: Some(Compression::Gzip),
+            ..ReaderOptions::default()
+        };
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let result = extract_files(&["test_data/15.csv.zst"], 1, vec![tx], options, None).await;
+        assert!(result.expect_err("Reading zstd as gzip").to_string().contains("not a gzip member"));
+    }
+
+    #[test]
+    fn followed_files_are_read_in_whole_lines() {
+        static STOP: AtomicBool = AtomicBool::new(false);
+        let file_path = env::temp_dir().join("transactioner_followed.csv");
+        fs::write(&file_path, "type,client,tx,amount\ndeposit,").unwrap();
+
+        let mut input = FollowFile::new(File::open(&file_path).unwrap(), &STOP);
+        let mut buffer = [0; 64];
+        let read = input.read(&mut buffer).unwrap();
+        assert_eq!(&buffer[..read], b"type,client,tx,amount\n");
+
+        // The line being written is only read once its newline arrives
+        let mut file = fs::OpenOptions::new().append(true).open(&file_path).unwrap();
+        file.write_all(b"1,1,10.0\ndeposit,1").unwrap();
+        let read = input.read(&mut buffer).unwrap();
+        assert_eq!(&buffer[..read], b"deposit,1,1,10.0\n");
+
+        STOP.store(true, Ordering::SeqCst);
+        assert_eq!(input.read(&mut buffer).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn snapshots_hold_the_rows_sent_before() {
+        let (tx, rx) = tokio::sync::mpsc::channel(10);
+        let worker = tokio::spawn(run_worker(rx, AccountPolicy::default(), None, None));
+        let snapshot_lock = tokio::sync::Mutex::new(());
+
+        let deposits = [
+            transaction(TransactionType::Deposit, 1, 1, 10.0),
+            in_currency(transaction(TransactionType::Deposit, 1, 2, 5.0), "EUR"),
+        ];
+        for (row, deposit) in deposits.iter().enumerate() {
+            let deposit = Transaction { row: row as u64 + 1, ..*deposit };
+            tx.send(WorkerMessage::Transaction(deposit)).await.unwrap();
+        }
+        let snapshot = take_snapshot(std::slice::from_ref(&tx), &snapshot_lock).await.unwrap();
+
+        let withdrawal = Transaction { row: 3, ..transaction(TransactionType::Withdrawal, 1, 3, 4.0) };
+        tx.send(WorkerMessage::Transaction(withdrawal)).await.unwrap();
+        drop(tx);
+        let (accounts, _) = worker.await.unwrap();
+
+        let available = |states: &[ClientState]| states.iter().map(|state| state.available).collect::<Vec<_>>();
+        assert_eq!(available(&snapshot[0]), [amount(10.0), amount(5.0)]);
+        // Taking the snapshot left every currency of the account as it was
+        let states: Vec<ClientState> = accounts.into_values().flat_map(ClientAccount::into_states).collect();
+        assert_eq!(available(&states), [amount(6.0), amount(5.0)]);
+    }
+
+    #[test]
+    fn limited_inputs_end_at_the_last_line_that_fits() {
+        let read_with = |bytes: u64| {
+            let limit = Arc::new(InputLimit::new(bytes));
+            let mut read = String::new();
+            LimitedInput::new("a\nbb\nccc".as_bytes(), Arc::clone(&limit)).read_to_string(&mut read).unwrap();
+
+            (read, limit.reached())
+        };
+
+        assert_eq!(read_with(7), ("a\nbb\n".to_string(), true));
+        assert_eq!(read_with(2), ("a\n".to_string(), true));
+        assert_eq!(read_with(1), (String::new(), true));
+        // The last line needs no newline at the end of the input
+        assert_eq!(read_with(8), ("a\nbb\nccc".to_string(), false));
+    }
+
+    /// Transactions applied from the files, along with what the reader made of them.
+    async fn applied_from(file_paths: &[&PathBuf], options: ReaderOptions) -> (u64, ReaderOutput) {
+        let (tx, rx) = tokio::sync::mpsc::channel(10);
+        let worker = tokio::spawn(run_worker(rx, AccountPolicy::default(), None, None));
+
+        let output = extract_files(file_paths, 1, vec![tx], options, None).await.expect("Should finish correctly");
+        let (_, diagnostics) = worker.await.unwrap();
+
+        (diagnostics.outcomes.applied, output)
+    }
+
+    #[tokio::test]
+    async fn limits_stop_the_reading_across_inputs() {
+        // Each file has a header of 22 bytes followed by 10 rows of 17 bytes
+        let file_paths: Vec<PathBuf> = vec![1, 11]
+            .into_iter()
+            .map(|first_tx| {
+                let file_path = env::temp_dir().join(format!("transactioner_limited_{}.csv", first_tx));
+                let rows: String = (first_tx..first_tx + 10).map(|tx| format!("deposit,1,{:02},1.0\n", tx)).collect();
+                fs::write(&file_path, format!("type,client,tx,amount\n{}", rows)).unwrap();
+
+                file_path
+            })
+            .collect();
+        let inputs: Vec<&PathBuf> = file_paths.iter().collect();
+
+        for fast_parse in vec![false, true].into_iter() {
+            let limited = |limit_rows, limit_bytes| ReaderOptions { fast_parse, limit_rows, limit_bytes, ..Default::default() };
+
+            let (applied, output) = applied_from(&inputs, limited(Some(15), None)).await;
+            assert_eq!((applied, output.diagnostics.rows), (15, 15));
+            assert!(output.diagnostics.truncated);
+
+            let (applied, output) = applied_from(&inputs, limited(Some(20), None)).await;
+            assert_eq!(applied, 20);
+            assert!(!output.diagnostics.truncated);
+
+            // Only the rows ending within the limit are read
+            let (applied, output) = applied_from(&inputs, limited(None, Some(22 + 3 * 17 + 5))).await;
+            assert_eq!(applied, 3);
+            assert!(output.diagnostics.truncated);
+
+            let (applied, output) = applied_from(&inputs, limited(None, Some(2 * (22 + 10 * 17) - 17))).await;
+            assert_eq!(applied, 19);
+            assert!(output.diagnostics.truncated);
+
+            let (applied, output) = applied_from(&inputs, limited(Some(5), Some(2 * (22 + 10 * 17)))).await;
+            assert_eq!(applied, 5);
+            assert!(output.diagnostics.truncated);
+        }
+
+        for file_path in file_paths {
+            fs::remove_file(file_path).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn resumed_runs_read_what_a_single_run_does() {
+        let file_path = env::temp_dir().join("transactioner_resumed.csv");
+        fs::copy("test_data/20_commented.csv", &file_path).unwrap();
+        let file_len = fs::metadata(&file_path).unwrap().len();
+        let rows = |transactions: Vec<Transaction>| transactions.iter().map(|x| (x.tx, x.row)).collect::<Vec<_>>();
+
+        for fast_parse in vec![false, true].into_iter() {
+            let options = ReaderOptions { fast_parse, ..Default::default() };
+            let single_run = rows(read_all(file_path.clone(), options).await.0);
+
+            for (limit_rows, limit_bytes) in vec![(Some(7), None), (None, Some(400)), (Some(0), None)].into_iter() {
+                let saving = ReaderOptions { fast_parse, save_offset: true, ..Default::default() };
+                let first_stage = ReaderOptions { limit_rows, limit_bytes, ..saving };
+                let (first_transactions, output) = read_all(file_path.clone(), first_stage).await;
+                let checkpoint = output.unwrap().checkpoint.expect("Should keep the offset");
+
+                let second_stage = ReaderOptions { resume_from: Some(checkpoint), ..saving };
+                let (second_transactions, output) = read_all(file_path.clone(), second_stage).await;
+                let output = output.unwrap();
+
+                let mut two_stages = rows(first_transactions);
+                two_stages.extend(rows(second_transactions));
+                assert_eq!(two_stages, single_run);
+                assert_eq!(output.checkpoint.map(|checkpoint| checkpoint.offset), Some(file_len));
+            }
         }
+        fs::remove_file(&file_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn offsets_are_only_resumed_in_the_file_they_were_saved_for() {
+        let file_path = env::temp_dir().join("transactioner_replaced.csv");
+        fs::write(&file_path, "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\n").unwrap();
+        let saved = checkpoint::Checkpoint::new(&fs::metadata(&file_path).unwrap(), 40, 2);
+
+        let resumed = |checkpoint| ReaderOptions { resume_from: Some(checkpoint), ..Default::default() };
+        let (transactions, output) = read_all(file_path.clone(), resumed(saved)).await;
+        assert!(output.is_ok());
+        assert_eq!(transactions.iter().map(|x| (x.tx, x.row)).collect::<Vec<_>>(), [(2, 3)]);
+
+        let beyond = checkpoint::Checkpoint { offset: 1_000, ..saved };
+        let (_, output) = read_all(file_path.clone(), resumed(beyond)).await;
+        assert!(output.unwrap_err().contains("beyond the end of the file"));
+
+        let larger = checkpoint::Checkpoint { size: 1_000, ..saved };
+        let (_, output) = read_all(file_path.clone(), resumed(larger)).await;
+        assert!(output.unwrap_err().contains("shrank"));
+
+        // A new file put in place of the old one has an inode of its own
+        let replacement_path = env::temp_dir().join("transactioner_replacement.csv");
+        fs::copy(&file_path, &replacement_path).unwrap();
+        fs::rename(&replacement_path, &file_path).unwrap();
+        let (_, output) = read_all(file_path.clone(), resumed(saved)).await;
+        assert!(output.unwrap_err().contains("replaced"));
+
+        fs::remove_file(&file_path).unwrap();
     }
 }
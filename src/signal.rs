@@ -0,0 +1,22 @@
+//! Handling of Ctrl-C while following a file, which stops the reading instead of the process so
+//! the accounts read so far are still written out.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set once Ctrl-C has been pressed.
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    // A second Ctrl-C gives up on writing the accounts
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Makes Ctrl-C set `INTERRUPTED` instead of ending the process.
+pub fn handle_interrupts() {
+    // The handler only touches an atomic and exits, both fine to do from a signal handler
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
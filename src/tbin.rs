@@ -0,0 +1,183 @@
+//! Binary replay format written by `transactioner convert`, which stores the transactions read from
+//! an input so runs over the same archive skip the CSV parsing.
+//!
+//! A file starts with the `TBIN` magic, a format version and the size of its records, all little
+//! endian. Every record then has the same size and holds its transaction field by field, along with
+//! the line it was read from so reports name the same rows as for the original input.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::{Amount, Currency, Transaction, TransactionType};
+
+const MAGIC: &[u8; 4] = b"TBIN";
+const VERSION: u16 = 1;
+pub const HEADER_SIZE: usize = 8;
+pub const RECORD_SIZE: usize = 44;
+
+/// Types by their code in a record, codes only ever get added to the end.
+const TYPES: [TransactionType; 13] = [
+    TransactionType::Deposit,
+    TransactionType::Withdrawal,
+    TransactionType::Dispute,
+    TransactionType::Resolve,
+    TransactionType::Chargeback,
+    TransactionType::Unlock,
+    TransactionType::Transfer,
+    TransactionType::Close,
+    TransactionType::Merge,
+    TransactionType::Settle,
+    TransactionType::Void,
+    TransactionType::Adjustment,
+    TransactionType::Unknown,
+];
+
+/// Flags of the optional fields a record has.
+const COUNTERPARTY: u8 = 1;
+const IDEMPOTENCY_KEY: u8 = 2;
+const TIMESTAMP: u8 = 4;
+
+pub fn write_header(output: &mut impl Write) -> io::Result<()> {
+    output.write_all(MAGIC)?;
+    output.write_all(&VERSION.to_le_bytes())?;
+    output.write_all(&(RECORD_SIZE as u16).to_le_bytes())
+}
+
+/// Record of a transaction, whose row has to fit in 32 bits.
+pub fn encode(transaction: &Transaction) -> Result<[u8; RECORD_SIZE], String> {
+    let line = u32::try_from(transaction.row).map_err(|_| format!("row {} is past the last one a record can hold", transaction.row))?;
+    let code = TYPES.iter().position(|r#type| *r#type == transaction.r#type).unwrap_or(TYPES.len() - 1);
+    let flags = transaction.counterparty.map_or(0, |_| COUNTERPARTY)
+        | transaction.idempotency_key.map_or(0, |_| IDEMPOTENCY_KEY)
+        | transaction.timestamp.map_or(0, |_| TIMESTAMP);
+
+    let mut record = [0; RECORD_SIZE];
+    record[0] = code as u8;
+    record[1] = flags;
+    record[2..4].copy_from_slice(&transaction.client.to_le_bytes());
+    record[4..8].copy_from_slice(&transaction.tx.to_le_bytes());
+    record[8..16].copy_from_slice(&transaction.amount.0.to_le_bytes());
+    record[16..18].copy_from_slice(&transaction.counterparty.unwrap_or_default().to_le_bytes());
+    record[18..21].copy_from_slice(&transaction.currency.0);
+    record[24..32].copy_from_slice(&transaction.idempotency_key.unwrap_or_default().to_le_bytes());
+    record[32..40].copy_from_slice(&transaction.timestamp.unwrap_or_default().to_le_bytes());
+    record[40..44].copy_from_slice(&line.to_le_bytes());
+
+    Ok(record)
+}
+
+/// Transaction and line of a record, the row is left for the reader to number.
+pub fn decode(record: &[u8; RECORD_SIZE]) -> Result<(u64, Transaction), String> {
+    let r#type = *TYPES.get(record[0] as usize).ok_or_else(|| format!("unknown transaction type code {}", record[0]))?;
+    let u16_at = |at: usize| u16::from_le_bytes([record[at], record[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes([record[at], record[at + 1], record[at + 2], record[at + 3]]);
+    let u64_at = |at: usize| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&record[at..at + 8]);
+        u64::from_le_bytes(bytes)
+    };
+    let flagged = |flag: u8| record[1] & flag != 0;
+
+    let transaction = Transaction {
+        r#type,
+        client: u16_at(2),
+        tx: u32_at(4),
+        amount: Amount(u64_at(8) as i64),
+        counterparty: Some(u16_at(16)).filter(|_| flagged(COUNTERPARTY)),
+        idempotency_key: Some(u64_at(24)).filter(|_| flagged(IDEMPOTENCY_KEY)),
+        currency: Currency([record[18], record[19], record[20]]),
+        timestamp: Some(u64_at(32) as i64).filter(|_| flagged(TIMESTAMP)),
+        row: 0,
+    };
+
+    Ok((u64::from(u32_at(40)), transaction))
+}
+
+/// Checks the header at the start of an input, before its records are read.
+pub fn read_header(input: &mut impl Read) -> Result<(), String> {
+    let invalid = || "not a file written by transactioner convert".to_string();
+    let mut header = [0; HEADER_SIZE];
+    input.read_exact(&mut header).map_err(|_| invalid())?;
+
+    if &header[..4] != MAGIC {
+        return Err(invalid());
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    let record_size = u16::from_le_bytes([header[6], header[7]]);
+    if version != VERSION || record_size as usize != RECORD_SIZE {
+        return Err(format!("unsupported binary format version {}, convert the input again", version));
+    }
+
+    Ok(())
+}
+
+/// Reads the next record, `false` at the end of the input. An input ending part way into a record is
+/// cut short, which is an error.
+pub fn read_record(input: &mut impl Read, record: &mut [u8; RECORD_SIZE]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < RECORD_SIZE {
+        match input.read(&mut record[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the input ends within a record")),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{idempotency_key_hash, ClientId};
+
+    #[test]
+    fn binary_records_hold_every_field() {
+        let transfer = Transaction {
+            r#type: TransactionType::Transfer,
+            client: 1,
+            tx: 7,
+            amount: Amount(123_456),
+            counterparty: Some(2),
+            idempotency_key: None,
+            currency: Currency::parse("eur").unwrap(),
+            timestamp: Some(-1),
+            row: 9,
+        };
+        let deposit = Transaction {
+            r#type: TransactionType::Deposit,
+            client: ClientId::MAX,
+            tx: u32::MAX,
+            amount: Amount(-1),
+            counterparty: None,
+            idempotency_key: idempotency_key_hash("retry"),
+            currency: Currency::NONE,
+            timestamp: None,
+            row: 0,
+        };
+
+        for original in vec![transfer, deposit].into_iter() {
+            let (line, decoded) = decode(&encode(&original).unwrap()).unwrap();
+
+            assert_eq!(line, original.row);
+            assert_eq!(decoded, Transaction { row: 0, ..original });
+            assert_eq!(
+                (decoded.counterparty, decoded.idempotency_key, decoded.currency, decoded.timestamp),
+                (original.counterparty, original.idempotency_key, original.currency, original.timestamp)
+            );
+        }
+
+        let mut unknown = encode(&transfer).unwrap();
+        unknown[0] = 200;
+        assert!(decode(&unknown).is_err());
+
+        let mut header = Vec::new();
+        write_header(&mut header).unwrap();
+        assert!(read_header(&mut header.as_slice()).is_ok());
+        header[4] = 2;
+        assert!(read_header(&mut header.as_slice()).unwrap_err().contains("version 2"));
+        assert!(read_header(&mut &b"type,client,tx,amount\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! ISO-8601 timestamps of the optional `timestamp` column, kept as milliseconds since the Unix
+//! epoch so they compare and copy as plain integers.
+//!
+//! Dates are read as `YYYY-MM-DD`, optionally followed by a `T` or a space and a `HH:MM` time
+//! with optional seconds and fraction of a second, and a `Z` or `±HH:MM` offset. Timestamps
+//! without an offset are taken as UTC.
+
+const MILLIS_PER_MINUTE: i64 = 60 * 1_000;
+const MILLIS_PER_DAY: i64 = 24 * 60 * MILLIS_PER_MINUTE;
+
+/// Parses a timestamp into milliseconds since the epoch, digits past the millisecond are dropped.
+pub fn parse(text: &str) -> Result<i64, String> {
+    let invalid = || "expected an ISO-8601 timestamp like 2021-03-04T05:06:07Z".to_string();
+    let mut cursor = Cursor { bytes: text.as_bytes(), position: 0 };
+
+    let year = cursor.number(4).ok_or_else(invalid)?;
+    cursor.expect(b'-').ok_or_else(invalid)?;
+    let month = cursor.number(2).filter(|month| (1..=12).contains(month)).ok_or_else(invalid)?;
+    cursor.expect(b'-').ok_or_else(invalid)?;
+    let day = cursor.number(2).filter(|day| (1..=days_in_month(year, month)).contains(day)).ok_or_else(invalid)?;
+
+    let mut millis = days_from_civil(year, month, day) * MILLIS_PER_DAY;
+
+    if cursor.expect(b'T').or_else(|| cursor.expect(b' ')).is_some() {
+        let hour = cursor.number(2).filter(|hour| *hour < 24).ok_or_else(invalid)?;
+        cursor.expect(b':').ok_or_else(invalid)?;
+        let minute = cursor.number(2).filter(|minute| *minute < 60).ok_or_else(invalid)?;
+        millis += (hour * 60 + minute) * MILLIS_PER_MINUTE;
+
+        if cursor.expect(b':').is_some() {
+            let second = cursor.number(2).filter(|second| *second < 60).ok_or_else(invalid)?;
+            millis += second * 1_000;
+
+            if cursor.expect(b'.').is_some() {
+                let digits = cursor.digits();
+                if digits.is_empty() {
+                    return Err(invalid());
+                }
+
+                let fraction = digits.iter().take(3).fold(0, |fraction, digit| fraction * 10 + i64::from(digit - b'0'));
+                millis += fraction * 10_i64.pow(3 - std::cmp::min(digits.len(), 3) as u32);
+            }
+        }
+
+        match cursor.next() {
+            Some(b'Z') => {}
+            Some(sign @ b'+') | Some(sign @ b'-') => {
+                let hours = cursor.number(2).filter(|hours| *hours < 24).ok_or_else(invalid)?;
+                cursor.expect(b':');
+                let minutes = cursor.number(2).filter(|minutes| *minutes < 60).ok_or_else(invalid)?;
+
+                // The offset is how far ahead of UTC the local time is
+                let offset = (hours * 60 + minutes) * MILLIS_PER_MINUTE;
+                millis += if sign == b'+' { -offset } else { offset };
+            }
+            Some(_) => return Err(invalid()),
+            None => {}
+        }
+    }
+
+    if cursor.next().is_some() {
+        return Err(invalid());
+    }
+
+    Ok(millis)
+}
+
+/// Writes milliseconds since the epoch as a UTC timestamp, like `2021-03-04T05:06:07.089Z`.
+pub fn format(millis: i64) -> String {
+    let days = millis.div_euclid(MILLIS_PER_DAY);
+    let time = millis.rem_euclid(MILLIS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / (60 * MILLIS_PER_MINUTE),
+        time / MILLIS_PER_MINUTE % 60,
+        time / 1_000 % 60,
+        time % 1_000
+    )
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn next(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.position)?;
+        self.position += 1;
+
+        Some(byte)
+    }
+
+    fn expect(&mut self, expected: u8) -> Option<()> {
+        if self.bytes.get(self.position) == Some(&expected) {
+            self.position += 1;
+
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn digits(&mut self) -> &'a [u8] {
+        let start = self.position;
+        while self.bytes.get(self.position).is_some_and(u8::is_ascii_digit) {
+            self.position += 1;
+        }
+
+        &self.bytes[start..self.position]
+    }
+
+    /// Reads a number of exactly `width` digits.
+    fn number(&mut self, width: usize) -> Option<i64> {
+        let digits = self.bytes.get(self.position..self.position + width)?;
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        self.position += width;
+
+        Some(digits.iter().fold(0, |number, digit| number * 10 + i64::from(digit - b'0')))
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from the epoch to the given date of the proleptic Gregorian calendar, counting in
+/// eras of 400 years which all have the same number of days.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Years are counted from March, so the leap day is the last one of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Date of the given days from the epoch, the inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = (month_from_march + 2) % 12 + 1;
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timestamps_are_parsed_and_formatted() {
+        assert_eq!(parse("1970-01-01T00:00:00Z"), Ok(0));
+        assert_eq!(parse("2021-03-04T05:06:07.089Z"), Ok(1_614_834_367_089));
+        assert_eq!(parse("2021-03-04 05:06:07.0891"), Ok(1_614_834_367_089));
+        assert_eq!(parse("2021-03-04T07:06:07.089+02:00"), Ok(1_614_834_367_089));
+        assert_eq!(parse("2021-03-04T00:06:07.089-0500"), Ok(1_614_834_367_089));
+        assert_eq!(parse("2021-03-04"), Ok(1_614_816_000_000));
+        assert_eq!(parse("1969-12-31T23:59:59.9Z"), Ok(-100));
+        assert_eq!(parse("2024-02-29T12:00Z"), Ok(1_709_208_000_000));
+
+        for text in ["", "2021-3-04", "2021-02-29", "2021-13-01", "2021-03-04T24:00", "2021-03-04T05:06:07.", "2021-03-04Z", "1614834367089"] {
+            assert!(parse(text).is_err(), "Parsing {:?}", text);
+        }
+
+        assert_eq!(format(1_614_834_367_089), "2021-03-04T05:06:07.089Z");
+        assert_eq!(format(-100), "1969-12-31T23:59:59.900Z");
+        assert_eq!(format(951_782_400_000), "2000-02-29T00:00:00.000Z");
+    }
+}
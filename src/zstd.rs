@@ -0,0 +1,943 @@
+//! Zstandard decoding for compressed inputs, covering what reading a `.csv.zst` file takes: one
+//! or more frames made of raw, RLE and compressed blocks, whose literals are Huffman coded and
+//! whose sequences are FSE coded. Skippable frames are passed over, dictionaries aren't supported.
+//!
+//! Bitstreams are read a few bytes at a time rather than through a refilled container, which is
+//! simpler and still keeps up with the CSV parsing downstream.
+
+use std::hash::Hasher;
+use std::io::{self, BufRead, Read};
+
+use twox_hash::XxHash64;
+
+const MAGIC: u32 = 0xFD2F_B528;
+/// Skippable frames take any magic number from this one up to fifteen past it.
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+const MAX_BLOCK_SIZE: usize = 128 * 1024;
+/// Largest window accepted, the one the reference encoder goes up to with `--long`.
+const MAX_WINDOW: u64 = 1 << 27;
+
+const LITERAL_LENGTH_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64, 128, 256, 512, 1024,
+    2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LITERAL_LENGTH_EXTRA: [u8; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+const MATCH_LENGTH_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33,
+    34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027, 2051, 4099, 8195, 16387, 32771, 65539,
+];
+const MATCH_LENGTH_EXTRA: [u8; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2,
+    3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+
+/// Distributions of the predefined FSE tables, a count of -1 standing for a probability below one.
+const LITERAL_LENGTH_DEFAULT: [i32; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1, -1, -1, -1, -1,
+];
+const MATCH_LENGTH_DEFAULT: [i32; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OFFSET_DEFAULT: [i32; 29] =
+    [1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1];
+
+/// Whether the start of an input is the start of a zstd frame, skippable or not.
+pub fn is_zstd(start: &[u8]) -> bool {
+    match start.get(..4) {
+        Some(magic) => {
+            let magic = u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]);
+            magic == MAGIC || magic & !0xf == SKIPPABLE_MAGIC
+        }
+        None => false,
+    }
+}
+
+/// Decompressed size of the first frame of an input, when its header tells it.
+pub fn content_size(start: &[u8]) -> Option<u64> {
+    if start.get(..4)? != MAGIC.to_le_bytes() {
+        return None;
+    }
+
+    let descriptor = *start.get(4)?;
+    let header = start.get(5..5 + header_length(descriptor))?;
+
+    FrameHeader::parse(descriptor, header).ok()?.content_size
+}
+
+fn corrupt(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt zstd input, {}", reason))
+}
+
+/// Length of the frame header past its descriptor byte.
+fn header_length(descriptor: u8) -> usize {
+    let single_segment = descriptor & 0x20 != 0;
+    let content_size = match descriptor >> 6 {
+        0 if single_segment => 1,
+        0 => 0,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+
+    usize::from(!single_segment) + [0, 1, 2, 4][(descriptor & 3) as usize] + content_size
+}
+
+fn little_endian(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |value, byte| value << 8 | u64::from(*byte))
+}
+
+struct FrameHeader {
+    window: u64,
+    content_size: Option<u64>,
+    checksum: bool,
+}
+
+impl FrameHeader {
+    fn parse(descriptor: u8, header: &[u8]) -> io::Result<FrameHeader> {
+        if descriptor & 0x08 != 0 {
+            return Err(corrupt("reserved frame header bit set"));
+        }
+
+        let single_segment = descriptor & 0x20 != 0;
+        let mut header = header;
+        let mut window = 0;
+        if !single_segment {
+            let exponent = u64::from(header[0] >> 3);
+            let mantissa = u64::from(header[0] & 7);
+            let base = 1_u64 << (10 + exponent);
+            window = base + base / 8 * mantissa;
+            header = &header[1..];
+        }
+
+        let dictionary_length = [0, 1, 2, 4][(descriptor & 3) as usize];
+        if little_endian(&header[..dictionary_length]) != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "zstd inputs compressed with a dictionary aren't supported",
+            ));
+        }
+        let header = &header[dictionary_length..];
+
+        let content_size = match header.len() {
+            0 => None,
+            2 => Some(little_endian(header) + 256),
+            _ => Some(little_endian(header)),
+        };
+        // A single segment frame is its own window
+        if single_segment {
+            window = content_size.unwrap_or_default();
+        }
+
+        Ok(FrameHeader {
+            window,
+            content_size,
+            checksum: descriptor & 0x04 != 0,
+        })
+    }
+}
+
+/// Bits of a table description, read from the lowest bit of the first byte onwards.
+struct ForwardBits<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl ForwardBits<'_> {
+    /// Bits past the end read as zeros, callers check `position` once they are done.
+    fn peek(&self, count: u32) -> u32 {
+        (0..count).fold(0, |value, bit| {
+            let position = self.position + bit as usize;
+            let set = self.data.get(position / 8).is_some_and(|byte| byte >> (position % 8) & 1 == 1);
+            value | u32::from(set) << bit
+        })
+    }
+
+    fn bits(&mut self, count: u32) -> u32 {
+        let value = self.peek(count);
+        self.position += count as usize;
+
+        value
+    }
+}
+
+/// Bits of an entropy coded stream, which is read backwards from the marker bit of its last byte.
+struct BackwardBits<'a> {
+    data: &'a [u8],
+    /// Bits left to read, negative once reads have gone past the start and returned zeros.
+    position: isize,
+}
+
+impl<'a> BackwardBits<'a> {
+    fn new(data: &'a [u8]) -> io::Result<Self> {
+        match data.last() {
+            Some(last) if *last != 0 => Ok(BackwardBits {
+                data,
+                position: (data.len() * 8) as isize - 1 - last.leading_zeros() as isize,
+            }),
+            _ => Err(corrupt("bitstream without an end marker")),
+        }
+    }
+
+    /// Reads up to 56 bits, the highest ones of those left.
+    fn bits(&mut self, count: u32) -> u64 {
+        if count == 0 {
+            return 0;
+        }
+
+        let end = self.position;
+        self.position -= count as isize;
+        if end <= 0 {
+            return 0;
+        }
+
+        let start = self.position.max(0) as usize;
+        let end = end as usize;
+        let value = little_endian(&self.data[start / 8..=(end - 1) / 8]);
+        let value = (value >> (start % 8)) & ((1 << (end - start)) - 1);
+
+        value << (start as isize - self.position)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct FseEntry {
+    symbol: u8,
+    bits: u8,
+    base: u16,
+}
+
+/// Decoding table of a finite state entropy code, indexed by state.
+struct FseTable {
+    accuracy: u32,
+    entries: Vec<FseEntry>,
+}
+
+impl FseTable {
+    fn new(accuracy: u32, counts: &[i32]) -> io::Result<FseTable> {
+        let size = 1 << accuracy;
+        let mut entries = vec![FseEntry::default(); size];
+        let mut next = vec![0_u32; counts.len()];
+
+        // Symbols below one get a state each at the end of the table, the rest are spread over the others
+        let mut available = size;
+        for (symbol, count) in counts.iter().enumerate() {
+            if *count == -1 {
+                available = available.checked_sub(1).ok_or_else(|| corrupt("invalid FSE distribution"))?;
+                entries[available].symbol = symbol as u8;
+                next[symbol] = 1;
+            } else {
+                next[symbol] = *count as u32;
+            }
+        }
+
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut position = 0;
+        for (symbol, count) in counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+            for _ in 0..*count {
+                entries[position].symbol = symbol as u8;
+                position = (position + step) & (size - 1);
+                while position >= available {
+                    position = (position + step) & (size - 1);
+                }
+            }
+        }
+        if position != 0 {
+            return Err(corrupt("invalid FSE distribution"));
+        }
+
+        for entry in &mut entries {
+            let state = next[entry.symbol as usize];
+            next[entry.symbol as usize] += 1;
+
+            let bits = accuracy - (31 - state.leading_zeros());
+            entry.bits = bits as u8;
+            entry.base = ((state << bits) - size as u32) as u16;
+        }
+
+        Ok(FseTable { accuracy, entries })
+    }
+
+    /// Table of a single symbol, which takes no bits at all.
+    fn rle(symbol: u8) -> FseTable {
+        FseTable {
+            accuracy: 0,
+            entries: vec![FseEntry { symbol, ..FseEntry::default() }],
+        }
+    }
+
+    /// Reads a table description, returning the table and how many bytes it took.
+    fn read(data: &[u8], max_accuracy: u32, max_symbol: usize) -> io::Result<(FseTable, usize)> {
+        let mut bits = ForwardBits { data, position: 0 };
+        let accuracy = bits.bits(4) + 5;
+        if accuracy > max_accuracy {
+            return Err(corrupt("FSE table accuracy too large"));
+        }
+
+        let mut counts = Vec::new();
+        let mut remaining = (1_i32 << accuracy) + 1;
+        let mut threshold = 1_i32 << accuracy;
+        let mut width = accuracy + 1;
+
+        while remaining > 1 {
+            // Values are written with the fewest bits that can hold what remains, the smaller
+            // ones taking a bit less than the larger ones
+            let max = 2 * threshold - 1 - remaining;
+            let low = bits.peek(width - 1) as i32;
+            let value = if low < max {
+                bits.position += width as usize - 1;
+                low
+            } else {
+                let value = bits.bits(width) as i32;
+                if value >= threshold {
+                    value - max
+                } else {
+                    value
+                }
+            };
+
+            let count = value - 1;
+            remaining -= count.abs();
+            counts.push(count);
+
+            // Runs of zeros are written as repeat flags of two bits
+            if count == 0 {
+                loop {
+                    let repeat = bits.bits(2);
+                    counts.extend((0..repeat).map(|_| 0));
+                    if repeat < 3 {
+                        break;
+                    }
+                }
+            }
+
+            if counts.len() > max_symbol + 1 || remaining < 1 {
+                return Err(corrupt("invalid FSE table description"));
+            }
+            while remaining < threshold {
+                width -= 1;
+                threshold >>= 1;
+            }
+        }
+
+        let length = bits.position.div_ceil(8);
+        if remaining != 1 || length > data.len() {
+            return Err(corrupt("invalid FSE table description"));
+        }
+
+        Ok((FseTable::new(accuracy, &counts)?, length))
+    }
+
+    fn init(&self, bits: &mut BackwardBits) -> usize {
+        bits.bits(self.accuracy) as usize
+    }
+
+    fn symbol(&self, state: usize) -> u8 {
+        self.entries[state].symbol
+    }
+
+    fn update(&self, state: usize, bits: &mut BackwardBits) -> usize {
+        let entry = self.entries[state];
+        entry.base as usize + bits.bits(u32::from(entry.bits)) as usize
+    }
+}
+
+/// Decoding table of the Huffman code of the literals, indexed by the next `max_bits` bits.
+struct HuffmanTable {
+    max_bits: u32,
+    /// Symbol and length of the code starting each index.
+    entries: Vec<(u8, u8)>,
+}
+
+impl HuffmanTable {
+    /// Reads a tree description, returning the table and how many bytes it took.
+    fn read(data: &[u8]) -> io::Result<(HuffmanTable, usize)> {
+        let header = *data.first().ok_or_else(|| corrupt("missing Huffman tree"))? as usize;
+        let mut weights = Vec::with_capacity(256);
+
+        let length = if header >= 128 {
+            // Weights written as they are, four bits each
+            let count = header - 127;
+            let bytes = data.get(1..1 + count.div_ceil(2)).ok_or_else(|| corrupt("truncated Huffman tree"))?;
+            weights.extend((0..count).map(|index| bytes[index / 2] >> if index % 2 == 0 { 4 } else { 0 } & 0xf));
+
+            1 + bytes.len()
+        } else {
+            // Weights compressed with FSE, decoded by two states taking turns
+            let data = data.get(1..1 + header).ok_or_else(|| corrupt("truncated Huffman tree"))?;
+            let (table, used) = FseTable::read(data, 6, 11)?;
+            let mut bits = BackwardBits::new(&data[used..])?;
+            let mut states = [table.init(&mut bits), table.init(&mut bits)];
+
+            for turn in 0.. {
+                let state = &mut states[turn % 2];
+                weights.push(table.symbol(*state));
+                *state = table.update(*state, &mut bits);
+
+                // Once the stream runs out, the other state still holds the last weight
+                if bits.position < 0 {
+                    weights.push(table.symbol(states[(turn + 1) % 2]));
+                    break;
+                }
+                if weights.len() > 255 {
+                    return Err(corrupt("too many Huffman weights"));
+                }
+            }
+
+            1 + header
+        };
+
+        Ok((HuffmanTable::from_weights(&weights)?, length))
+    }
+
+    /// Builds the table from the weights of every symbol but the last, whose weight is implied.
+    fn from_weights(weights: &[u8]) -> io::Result<HuffmanTable> {
+        if weights.len() > 255 || weights.iter().any(|weight| *weight > 11) {
+            return Err(corrupt("invalid Huffman weights"));
+        }
+
+        let total: u32 = weights.iter().filter(|weight| **weight > 0).map(|weight| 1 << (weight - 1)).sum();
+        if total == 0 {
+            return Err(corrupt("invalid Huffman weights"));
+        }
+        let max_bits = 32 - total.leading_zeros();
+        let left = (1 << max_bits) - total;
+        if max_bits > 11 || !left.is_power_of_two() {
+            return Err(corrupt("invalid Huffman weights"));
+        }
+
+        let mut weights = weights.to_vec();
+        weights.push((32 - left.leading_zeros()) as u8);
+        let lengths: Vec<u32> =
+            weights.iter().map(|weight| if *weight > 0 { max_bits + 1 - u32::from(*weight) } else { 0 }).collect();
+
+        // Longest codes come first, symbols of the same length in their natural order
+        let mut starts = [0_usize; 12];
+        let mut next = 0;
+        for length in (1..=max_bits as usize).rev() {
+            starts[length] = next;
+            next += lengths.iter().filter(|code| **code as usize == length).count() << (max_bits as usize - length);
+        }
+
+        let mut entries = vec![(0, 0); 1 << max_bits];
+        for (symbol, length) in lengths.iter().enumerate().filter(|(_, length)| **length > 0) {
+            let start = starts[*length as usize];
+            let count = 1 << (max_bits - length);
+            entries[start..start + count].fill((symbol as u8, *length as u8));
+            starts[*length as usize] += count;
+        }
+
+        Ok(HuffmanTable { max_bits, entries })
+    }
+
+    /// Decodes the `count` literals of one stream.
+    fn decode(&self, data: &[u8], count: usize, output: &mut Vec<u8>) -> io::Result<()> {
+        let mut bits = BackwardBits::new(data)?;
+        let mask = (1 << self.max_bits) - 1;
+        let mut state = bits.bits(self.max_bits) as usize;
+
+        for _ in 0..count {
+            let (symbol, length) = self.entries[state];
+            output.push(symbol);
+            state = ((state << length) & mask) | bits.bits(u32::from(length)) as usize;
+        }
+
+        if bits.position != -(self.max_bits as isize) {
+            return Err(corrupt("Huffman stream size mismatch"));
+        }
+
+        Ok(())
+    }
+}
+
+/// What carries over from one block of a frame to the next.
+struct Frame {
+    header: FrameHeader,
+    last_block: bool,
+    checksum: XxHash64,
+    /// Bytes decoded so far, which is as far back as a match can reference.
+    size: u64,
+    huffman: Option<HuffmanTable>,
+    /// Tables of the literal lengths, offsets and match lengths of the previous block.
+    tables: [Option<FseTable>; 3],
+    offsets: [usize; 3],
+}
+
+/// Decompresses the zstd frames of the inner reader.
+pub struct ZstdDecoder<R> {
+    input: R,
+    frame: Option<Frame>,
+    /// Decompressed data, the part before `position` is kept as the window matches copy from.
+    output: Vec<u8>,
+    position: usize,
+    block: Vec<u8>,
+    literals: Vec<u8>,
+}
+
+impl<R: BufRead> ZstdDecoder<R> {
+    pub fn new(input: R) -> Self {
+        ZstdDecoder {
+            input,
+            frame: None,
+            output: Vec::new(),
+            position: 0,
+            block: Vec::with_capacity(MAX_BLOCK_SIZE),
+            literals: Vec::with_capacity(MAX_BLOCK_SIZE),
+        }
+    }
+
+    fn fill(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        self.input.read_exact(buffer).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => corrupt("the input ends abruptly"),
+            _ => e,
+        })
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        self.fill(&mut bytes)?;
+
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Reads the header of the next frame, skipping skippable ones, returning `None` at the end.
+    fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        loop {
+            if self.input.fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+
+            let magic = self.u32()?;
+            if magic & !0xf == SKIPPABLE_MAGIC {
+                let length = u64::from(self.u32()?);
+                if io::copy(&mut (&mut self.input).take(length), &mut io::sink())? != length {
+                    return Err(corrupt("the input ends abruptly"));
+                }
+                continue;
+            }
+            if magic != MAGIC {
+                return Err(corrupt("not a zstd frame"));
+            }
+
+            let mut descriptor = [0];
+            self.fill(&mut descriptor)?;
+            let mut header = vec![0; header_length(descriptor[0])];
+            self.fill(&mut header)?;
+            let header = FrameHeader::parse(descriptor[0], &header)?;
+
+            if header.window > MAX_WINDOW {
+                return Err(corrupt("window too large"));
+            }
+
+            return Ok(Some(Frame {
+                header,
+                last_block: false,
+                checksum: XxHash64::with_seed(0),
+                size: 0,
+                huffman: None,
+                tables: [None, None, None],
+                offsets: [1, 4, 8],
+            }));
+        }
+    }
+
+    fn read_trailer(&mut self, frame: &Frame) -> io::Result<()> {
+        if frame.header.checksum && self.u32()? != frame.checksum.finish() as u32 {
+            return Err(corrupt("checksum mismatch"));
+        }
+        if frame.header.content_size.is_some_and(|size| size != frame.size) {
+            return Err(corrupt("size mismatch"));
+        }
+
+        Ok(())
+    }
+
+    fn read_block(&mut self, frame: &mut Frame) -> io::Result<()> {
+        let mut header = [0; 3];
+        self.fill(&mut header)?;
+        let header = little_endian(&header) as usize;
+        let size = header >> 3;
+        frame.last_block = header & 1 == 1;
+
+        let start = self.output.len();
+        match (header >> 1) & 3 {
+            0 if size <= MAX_BLOCK_SIZE => {
+                self.output.resize(start + size, 0);
+                let mut output = std::mem::take(&mut self.output);
+                let result = self.fill(&mut output[start..]);
+                self.output = output;
+                result?;
+            }
+            1 if size <= MAX_BLOCK_SIZE => {
+                let mut byte = [0];
+                self.fill(&mut byte)?;
+                self.output.resize(start + size, byte[0]);
+            }
+            2 if size <= MAX_BLOCK_SIZE => {
+                let mut block = std::mem::take(&mut self.block);
+                block.resize(size, 0);
+                let result = self.fill(&mut block).and_then(|_| self.decode_block(frame, &block));
+                self.block = block;
+                result?;
+            }
+            3 => return Err(corrupt("invalid block type")),
+            _ => return Err(corrupt("block too large")),
+        }
+
+        frame.checksum.write(&self.output[start..]);
+        frame.size += (self.output.len() - start) as u64;
+
+        Ok(())
+    }
+
+    fn decode_block(&mut self, frame: &mut Frame, block: &[u8]) -> io::Result<()> {
+        let used = self.read_literals(frame, block)?;
+        let sequences = &block[used..];
+        let truncated = || corrupt("truncated sequences section");
+
+        let (count, used) = match *sequences.first().ok_or_else(truncated)? as usize {
+            byte if byte < 128 => (byte, 1),
+            byte if byte < 255 => (((byte - 128) << 8) + *sequences.get(1).ok_or_else(truncated)? as usize, 2),
+            _ => (little_endian(sequences.get(1..3).ok_or_else(truncated)?) as usize + 0x7f00, 3),
+        };
+        if count == 0 {
+            self.output.extend_from_slice(&self.literals);
+            return Ok(());
+        }
+
+        let modes = *sequences.get(used).ok_or_else(truncated)?;
+        if modes & 3 != 0 {
+            return Err(corrupt("reserved sequence mode bits set"));
+        }
+        let mut position = used + 1;
+
+        // Literal lengths, offsets and match lengths, each with its own table
+        let kinds: [(u8, &[i32], u32, u32, usize); 3] = [
+            (modes >> 6, &LITERAL_LENGTH_DEFAULT, 6, 9, 35),
+            (modes >> 4 & 3, &OFFSET_DEFAULT, 5, 8, 31),
+            (modes >> 2 & 3, &MATCH_LENGTH_DEFAULT, 6, 9, 52),
+        ];
+        for (table, (mode, default, default_accuracy, max_accuracy, max_symbol)) in frame.tables.iter_mut().zip(kinds) {
+            *table = Some(match mode {
+                0 => FseTable::new(default_accuracy, default)?,
+                1 => {
+                    let symbol = *sequences.get(position).ok_or_else(truncated)?;
+                    if symbol as usize > max_symbol {
+                        return Err(corrupt("invalid sequence code"));
+                    }
+                    position += 1;
+                    FseTable::rle(symbol)
+                }
+                2 => {
+                    let (table, used) = FseTable::read(&sequences[position..], max_accuracy, max_symbol)?;
+                    position += used;
+                    table
+                }
+                _ => table.take().ok_or_else(|| corrupt("repeated sequence table without a previous one"))?,
+            });
+        }
+
+        let [Some(literal_lengths), Some(offsets), Some(match_lengths)] = &frame.tables else {
+            unreachable!("every table was just set");
+        };
+        let mut bits = BackwardBits::new(&sequences[position..])?;
+        let mut literal_length_state = literal_lengths.init(&mut bits);
+        let mut offset_state = offsets.init(&mut bits);
+        let mut match_length_state = match_lengths.init(&mut bits);
+
+        let mut literal = 0;
+        for index in 0..count {
+            let offset_code = u32::from(offsets.symbol(offset_state));
+            let match_length_code = match_lengths.symbol(match_length_state) as usize;
+            let literal_length_code = literal_lengths.symbol(literal_length_state) as usize;
+
+            let offset_value = ((1_u64 << offset_code) + bits.bits(offset_code)) as usize;
+            let match_length = MATCH_LENGTH_BASE[match_length_code] as usize
+                + bits.bits(u32::from(MATCH_LENGTH_EXTRA[match_length_code])) as usize;
+            let literal_length = LITERAL_LENGTH_BASE[literal_length_code] as usize
+                + bits.bits(u32::from(LITERAL_LENGTH_EXTRA[literal_length_code])) as usize;
+
+            if index + 1 < count {
+                literal_length_state = literal_lengths.update(literal_length_state, &mut bits);
+                match_length_state = match_lengths.update(match_length_state, &mut bits);
+                offset_state = offsets.update(offset_state, &mut bits);
+            }
+
+            let literals = self.literals.get(literal..literal + literal_length);
+            self.output.extend_from_slice(literals.ok_or_else(|| corrupt("too few literals"))?);
+            literal += literal_length;
+
+            // The three latest offsets can be repeated, shifted by one when there are no literals
+            let [first, second, third] = frame.offsets;
+            let repeat = (offset_value <= 3).then(|| offset_value - 1 + usize::from(literal_length == 0));
+            let offset = match repeat {
+                None => offset_value - 3,
+                Some(0) => first,
+                Some(1) => second,
+                Some(2) => third,
+                _ => first.wrapping_sub(1),
+            };
+            frame.offsets = match repeat {
+                Some(0) => frame.offsets,
+                Some(1) => [second, first, third],
+                _ => [offset, first, second],
+            };
+
+            // The output only holds the current frame, and at least a window of it
+            if offset == 0 || offset > self.output.len() {
+                return Err(corrupt("offset too far back"));
+            }
+
+            // Matches can overlap the bytes they produce, so those are copied one byte at a time
+            let start = self.output.len() - offset;
+            if offset >= match_length {
+                self.output.extend_from_within(start..start + match_length);
+            } else {
+                for index in start..start + match_length {
+                    let byte = self.output[index];
+                    self.output.push(byte);
+                }
+            }
+        }
+
+        if bits.position != 0 {
+            return Err(corrupt("sequence stream size mismatch"));
+        }
+        self.output.extend_from_slice(&self.literals[literal..]);
+
+        Ok(())
+    }
+
+    /// Decodes the literals section of a block into `literals`, returning how many bytes it took.
+    fn read_literals(&mut self, frame: &mut Frame, block: &[u8]) -> io::Result<usize> {
+        let truncated = || corrupt("truncated literals section");
+        let first = *block.first().ok_or_else(truncated)? as usize;
+        let format = (first >> 2) & 3;
+        self.literals.clear();
+
+        if first & 3 < 2 {
+            // Raw or RLE literals, behind a header of up to three bytes
+            let (size, header) = match format {
+                0 | 2 => (first >> 3, 1),
+                1 => (little_endian(block.get(..2).ok_or_else(truncated)?) as usize >> 4, 2),
+                _ => (little_endian(block.get(..3).ok_or_else(truncated)?) as usize >> 4, 3),
+            };
+
+            return if first & 3 == 0 {
+                self.literals.extend_from_slice(block.get(header..header + size).ok_or_else(truncated)?);
+                Ok(header + size)
+            } else {
+                let byte = *block.get(header).ok_or_else(truncated)?;
+                self.literals.resize(size, byte);
+                Ok(header + 1)
+            };
+        }
+
+        // Huffman coded literals, with a new tree or the one of the previous block
+        let (streams, header, width) = match format {
+            0 => (1, 3, 10),
+            1 => (4, 3, 10),
+            2 => (4, 4, 14),
+            _ => (4, 5, 18),
+        };
+        let sizes = little_endian(block.get(..header).ok_or_else(truncated)?) as usize >> 4;
+        let size = sizes & ((1 << width) - 1);
+        let compressed_size = sizes >> width;
+        let mut data = block.get(header..header + compressed_size).ok_or_else(truncated)?;
+
+        if first & 3 == 2 {
+            let (table, used) = HuffmanTable::read(data)?;
+            frame.huffman = Some(table);
+            data = &data[used..];
+        }
+        let table = frame.huffman.as_ref().ok_or_else(|| corrupt("repeated Huffman tree without a previous one"))?;
+
+        if streams == 1 {
+            table.decode(data, size, &mut self.literals)?;
+        } else {
+            let jumps = data.get(..6).ok_or_else(truncated)?;
+            let mut stream_sizes = [0; 4];
+            for (index, stream_size) in stream_sizes.iter_mut().take(3).enumerate() {
+                *stream_size = little_endian(&jumps[2 * index..2 * index + 2]) as usize;
+            }
+            stream_sizes[3] = (data.len() - 6)
+                .checked_sub(stream_sizes[..3].iter().sum())
+                .ok_or_else(|| corrupt("invalid literals jump table"))?;
+
+            // Every stream holds a quarter of the literals, rounded up, the last one what's left
+            let quarter = size.div_ceil(4);
+            let mut data = &data[6..];
+            for (index, stream_size) in stream_sizes.iter().enumerate() {
+                let count = if index < 3 { quarter } else { size.checked_sub(3 * quarter).ok_or_else(truncated)? };
+                table.decode(&data[..*stream_size], count, &mut self.literals)?;
+                data = &data[*stream_size..];
+            }
+        }
+
+        Ok(header + compressed_size)
+    }
+
+    /// Moves the decoding forward, returning `false` once the whole input has been decoded.
+    fn step(&mut self) -> io::Result<bool> {
+        match self.frame.take() {
+            None => {
+                // Frames don't reference each other, so nothing has to stay around
+                self.output.clear();
+                self.position = 0;
+                self.frame = self.read_frame()?;
+
+                Ok(self.frame.is_some())
+            }
+            Some(frame) if frame.last_block => {
+                self.read_trailer(&frame)?;
+
+                Ok(true)
+            }
+            Some(mut frame) => {
+                // Everything has been read by now, only the window has to stay around
+                let window = std::cmp::max(frame.header.window as usize, MAX_BLOCK_SIZE);
+                if self.output.len() >= 3 * window {
+                    let consumed = self.output.len() - window;
+                    self.output.drain(..consumed);
+                    self.position -= consumed;
+                }
+
+                let result = self.read_block(&mut frame);
+                self.frame = Some(frame);
+                result.map(|_| true)
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.output.len() {
+            if !self.step()? {
+                return Ok(0);
+            }
+        }
+
+        let length = buf.len().min(self.output.len() - self.position);
+        buf[..length].copy_from_slice(&self.output[self.position..self.position + length]);
+        self.position += length;
+
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+    use std::io::BufReader;
+
+    use super::*;
+
+    /// Frame of a single raw block of `RAW_DATA`, with its content size and checksum, as written by zstd.
+    const RAW: &[u8] = b"\x28\xb5\x2f\xfd\x24\x10\x81\x00\x00deposit,1,1,1.0\n\xe8\xd5\xea\x61";
+    const RAW_DATA: &[u8] = b"deposit,1,1,1.0\n";
+    /// Frame of a single RLE block of 40 `a`, with its content size but no checksum.
+    const RLE: &[u8] = b"\x28\xb5\x2f\xfd\x20\x28\x43\x01\x00a";
+    /// Skippable frame of four bytes.
+    const SKIPPABLE: &[u8] = b"\x50\x2a\x4d\x18\x04\x00\x00\x00skip";
+
+    fn decode(input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        ZstdDecoder::new(input).read_to_end(&mut decoded)?;
+
+        Ok(decoded)
+    }
+
+    fn error(input: &[u8]) -> String {
+        decode(input).expect_err("Decoding a corrupt frame").to_string()
+    }
+
+    #[test]
+    fn every_kind_of_block_is_decoded() {
+        assert!(is_zstd(RAW) && is_zstd(SKIPPABLE));
+        assert_eq!(decode(RAW).unwrap(), RAW_DATA);
+        assert_eq!(decode(RLE).unwrap(), [b'a'; 40]);
+
+        // Written by zstd as a single compressed block
+        let compressed = fs::read("test_data/15.csv.zst").unwrap();
+        assert_eq!(decode(&compressed).unwrap(), fs::read("test_data/15.csv").unwrap());
+
+        let frames = [SKIPPABLE, RAW, RLE, SKIPPABLE, RAW].concat();
+        assert_eq!(decode(&frames).unwrap(), [RAW_DATA, &[b'a'; 40], RAW_DATA].concat());
+    }
+
+    #[test]
+    fn decoding_keeps_every_byte() {
+        // Two frames of the first 5000 transactions of the performance file around a skippable
+        // one, the second without its content size or a checksum
+        let mut decoded = Vec::new();
+        let file = BufReader::new(File::open("test_data/5_000.csv.zst").unwrap());
+        ZstdDecoder::new(file).read_to_end(&mut decoded).unwrap();
+
+        let plain = fs::read("test_data/perf/100_000.csv").unwrap();
+        let end = plain.iter().enumerate().filter(|(_, byte)| **byte == b'\n').nth(5_000).unwrap().0;
+
+        assert_eq!(decoded.len(), end + 1);
+        assert!(decoded == plain[..=end]);
+    }
+
+    #[test]
+    fn frames_tell_their_content_size() {
+        let compressed = fs::read("test_data/15.csv.zst").unwrap();
+        assert_eq!(content_size(&compressed), Some(fs::metadata("test_data/15.csv").unwrap().len()));
+
+        let compressed = fs::read("test_data/5_000.csv.zst").unwrap();
+        assert!(content_size(&compressed).is_some());
+        assert_eq!(content_size(b"type,client,tx,amount\n"), None);
+        assert_eq!(content_size(&compressed[..5]), None);
+    }
+
+    #[test]
+    fn corrupt_inputs_fail_to_decode() {
+        let compressed = fs::read("test_data/15.csv.zst").unwrap();
+
+        for index in 4..compressed.len() {
+            let mut corrupt = compressed.clone();
+            corrupt[index] ^= 0x5a;
+
+            // Every byte is covered by the checksum, when it isn't caught earlier
+            let result = ZstdDecoder::new(&corrupt[..]).read_to_end(&mut Vec::new());
+            let error = result.expect_err("Decoding a corrupt input").to_string();
+            assert!(error.contains("zstd input"), "Flipping byte {}: {}", index, error);
+        }
+
+        let result = ZstdDecoder::new(&compressed[..compressed.len() - 3]).read_to_end(&mut Vec::new());
+        assert!(result.expect_err("Decoding a truncated input").to_string().contains("ends abruptly"));
+    }
+
+    #[test]
+    fn corrupt_frames_fail_the_decoding() {
+        assert!(error(b"\x28\xb5\x2f\xfc\x24\x10").contains("not a zstd frame"));
+        let mut reserved = RAW.to_vec();
+        reserved[4] |= 0x08;
+        assert!(error(&reserved).contains("reserved frame header bit set"));
+        // A window of 256 MiB, twice the largest one
+        assert!(error(b"\x28\xb5\x2f\xfd\x00\x90").contains("window too large"));
+        assert!(error(b"\x28\xb5\x2f\xfd\x21\x01\x10").contains("with a dictionary aren't supported"));
+
+        let mut block_type = RAW.to_vec();
+        block_type[6] |= 0x06;
+        assert!(error(&block_type).contains("invalid block type"));
+        assert!(error(b"\x28\xb5\x2f\xfd\x00\x00\x0b\x00\x10a").contains("block too large"));
+
+        let mut checksum = RAW.to_vec();
+        *checksum.last_mut().unwrap() ^= 1;
+        assert!(error(&checksum).contains("checksum mismatch"));
+        let mut size = RLE.to_vec();
+        size[5] += 1;
+        assert!(error(&size).contains("size mismatch"));
+
+        for frame in [RAW, RLE, SKIPPABLE] {
+            for end in 1..frame.len() {
+                assert!(error(&frame[..end]).contains("ends abruptly"), "Cut at {} of {:?}", end, frame);
+            }
+        }
+    }
+}
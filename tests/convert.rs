@@ -0,0 +1,74 @@
+use std::fs;
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_transactioner"))
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .expect("Should run the binary")
+}
+
+/// Accounts printed for `args`, sorted as the workers write them in any order.
+fn sorted_accounts(args: &[&str]) -> Vec<String> {
+    let output = run(args);
+    assert!(output.status.success(), "Running with {:?}", args);
+
+    let mut lines: Vec<String> = String::from_utf8(output.stdout).unwrap().lines().map(String::from).collect();
+    lines.sort();
+
+    lines
+}
+
+#[test]
+fn converted_inputs_give_the_accounts_of_the_original() {
+    let converted = std::env::temp_dir().join("transactioner_converted.tbin");
+    let converted = converted.to_str().unwrap();
+
+    for input in [
+        "test_data/15.csv",
+        "test_data/15.csv.gz",
+        "test_data/15.ndjson",
+        "test_data/currencies.csv",
+        "test_data/idempotency_keys.csv",
+        "test_data/malformed_rows.csv",
+        "test_data/merges.csv",
+        "test_data/timestamps.csv",
+        "test_data/transfers.csv",
+    ] {
+        assert!(run(&["convert", input, converted]).status.success(), "Converting {}", input);
+
+        for extended in [false, true] {
+            let mut args = vec![input];
+            if extended {
+                args.push("--extended-output");
+            }
+            let expected = sorted_accounts(&args);
+            args[0] = converted;
+
+            assert_eq!(sorted_accounts(&args), expected, "Reading {} converted", input);
+        }
+    }
+
+    // A file cut short ends within a record, which stops the reading
+    let bytes = fs::read(converted).unwrap();
+    fs::write(converted, &bytes[..bytes.len() - 1]).unwrap();
+    let output = run(&[converted]);
+    assert!(!output.status.success() && output.stdout.is_empty());
+
+    fs::remove_file(converted).unwrap();
+}
+
+#[test]
+fn only_converted_files_are_read_as_binary() {
+    let converted = std::env::temp_dir().join("transactioner_not_converted.tbin");
+    fs::copy("test_data/15.csv", &converted).unwrap();
+
+    let output = run(&[converted.to_str().unwrap(), "--format", "tbin"]);
+    assert!(!output.status.success() && output.stdout.is_empty());
+    // Told apart by their start, whatever their extension
+    assert_eq!(sorted_accounts(&[converted.to_str().unwrap()]), sorted_accounts(&["test_data/15.csv"]));
+
+    fs::remove_file(converted).unwrap();
+}
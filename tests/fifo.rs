@@ -0,0 +1,46 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Accounts printed for `input`, sorted as the workers write them in any order.
+fn sorted_accounts(input: &Path) -> Vec<String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_transactioner"))
+        .arg(input)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .expect("Should run the binary");
+    assert!(output.status.success(), "Reading {}", input.display());
+
+    let mut lines: Vec<String> = String::from_utf8(output.stdout).unwrap().lines().map(String::from).collect();
+    lines.sort();
+
+    lines
+}
+
+#[test]
+fn named_pipes_are_read_as_a_stream() {
+    let expected = sorted_accounts(Path::new("test_data/15.csv"));
+
+    // The extension tells the compression, which is never peeked at before the reading
+    for fixture in ["test_data/15.csv", "test_data/15.csv.gz", "test_data/15.csv.zst"] {
+        let file_name = Path::new(fixture).file_name().unwrap().to_str().unwrap();
+        let fifo = std::env::temp_dir().join(format!("transactioner_fifo_{}", file_name));
+        let _ = fs::remove_file(&fifo);
+        assert!(Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+
+        // Opening the pipe for writing waits for the reading end to be opened
+        let writer_fifo = fifo.clone();
+        let writer = thread::spawn(move || {
+            fs::OpenOptions::new().write(true).open(&writer_fifo).unwrap().write_all(&fs::read(fixture).unwrap()).unwrap();
+        });
+
+        assert_eq!(sorted_accounts(&fifo), expected, "Reading {} through a pipe", fixture);
+        writer.join().unwrap();
+        fs::remove_file(&fifo).unwrap();
+    }
+}
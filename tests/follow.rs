@@ -0,0 +1,61 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn append(path: &Path, text: &str) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(text.as_bytes()).unwrap();
+}
+
+/// Lines of the file at `path`, sorted as the workers write their accounts in any order.
+fn sorted_lines(path: &Path) -> Vec<String> {
+    let mut lines: Vec<String> = fs::read_to_string(path).unwrap_or_default().lines().map(String::from).collect();
+    lines.sort();
+
+    lines
+}
+
+/// Waits for the snapshot at `path` to hold the `expected` lines.
+fn wait_for(path: &Path, expected: &[&str]) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while sorted_lines(path) != expected {
+        assert!(Instant::now() < deadline, "Should write {:?} to {}", expected, path.display());
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn follows_the_input_until_interrupted() {
+    let input = std::env::temp_dir().join("transactioner_follow_input.csv");
+    let output = std::env::temp_dir().join("transactioner_follow_output.csv");
+    fs::write(&input, "type,client,tx,amount\ndeposit,1,1,10.0\n").unwrap();
+    let _ = fs::remove_file(&output);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transactioner"))
+        .args([input.to_str().unwrap(), "--follow", "--dump-interval", "0.1", "--output", output.to_str().unwrap()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Should start the binary");
+
+    wait_for(&output, &["1,10.0000,0.0000,10.0000,false", "client,available,held,total,locked"]);
+    append(&input, "withdrawal,1,2,2.5\ndeposit,2,3,1.0\n");
+    let expected = [
+        "1,7.5000,0.0000,7.5000,false",
+        "2,1.0000,0.0000,1.0000,false",
+        "client,available,held,total,locked",
+    ];
+    wait_for(&output, &expected);
+
+    // The line still being written when Ctrl-C comes is left out
+    append(&input, "deposit,2,4,5.0");
+    thread::sleep(Duration::from_millis(300));
+    let status = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(status.success());
+
+    assert!(child.wait().unwrap().success());
+    assert_eq!(sorted_lines(&output), expected);
+}
@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Runs the binary with a manifest, returning whether it succeeded and the manifest it wrote.
+fn manifest_for(name: &str, args: &[&str]) -> (bool, String) {
+    let manifest = std::env::temp_dir().join(format!("transactioner_manifest_{}.json", name));
+    let _ = std::fs::remove_file(&manifest);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_transactioner"))
+        .args(args)
+        .arg("--manifest")
+        .arg(&manifest)
+        .output()
+        .expect("Should run the binary")
+        .status;
+
+    (status.success(), std::fs::read_to_string(&manifest).expect("Should write the manifest"))
+}
+
+fn output_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("transactioner_manifest_{}.csv", name))
+}
+
+#[test]
+fn manifests_tell_the_inputs_and_outputs_of_a_successful_run() {
+    let output = output_path("succeeded");
+    let (succeeded, manifest) = manifest_for("succeeded", &["test_data/15.csv", "--output", output.to_str().unwrap()]);
+
+    assert!(succeeded);
+    let start = r#"{"status":"succeeded","error":null,"workers":2,"duration_seconds":"#;
+    assert!(manifest.starts_with(start), "{}", manifest);
+    assert!(manifest.ends_with("}\n"));
+
+    let input_len = std::fs::metadata("test_data/15.csv").unwrap().len();
+    assert!(manifest.contains(&format!(r#""inputs":[{{"path":"test_data/15.csv","bytes":{}}}]"#, input_len)));
+    assert!(manifest.contains(r#""summary":{"rows":15,"malformed_rows":0,"#), "{}", manifest);
+    assert!(manifest.contains(r#""deposit_rows":"#) && manifest.contains(r#""skipped_duplicate_tx":"#));
+    assert!(manifest.contains(r#""rejected":{"malformed_row":0,"invalid_amount":0,"#), "{}", manifest);
+
+    let output_len = std::fs::metadata(&output).unwrap().len();
+    let expected =
+        format!(r#""outputs":[{{"flag":"output","path":"{}","bytes":{},"xxh64":""#, output.display(), output_len);
+    assert!(manifest.contains(&expected), "{}", manifest);
+}
+
+#[test]
+fn manifests_tell_how_a_run_with_malformed_rows_ended() {
+    // Skipped by default, the run still going through
+    let (succeeded, manifest) = manifest_for("lenient", &["test_data/malformed_rows.csv", "-q"]);
+    assert!(succeeded);
+    assert!(manifest.starts_with(r#"{"status":"partial","error":null,"#), "{}", manifest);
+    assert!(manifest.contains(r#""summary":{"rows":5,"malformed_rows":2,"#), "{}", manifest);
+    assert!(manifest.contains(r#""rejected":{"malformed_row":2,"#), "{}", manifest);
+    assert!(manifest.contains(r#""outputs":[]"#), "{}", manifest);
+
+    // Written all the same when they stop the run
+    let output = output_path("strict");
+    let _ = std::fs::remove_file(&output);
+    let strict = ["test_data/malformed_rows.csv", "--strict", "--output", output.to_str().unwrap()];
+    let (succeeded, manifest) = manifest_for("strict", &strict);
+    assert!(!succeeded);
+    let start = r#"{"status":"failed","error":"Stopped reading the input: test_data/malformed_rows.csv: "#;
+    assert!(manifest.starts_with(start), "{}", manifest);
+    assert!(manifest.contains(r#""summary":null,"rejected":null,"outputs":[]"#), "{}", manifest);
+}
@@ -0,0 +1,371 @@
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+
+fn transactioner(args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_transactioner"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Should start the binary")
+}
+
+fn output_for(args: &[&str], input: &str) -> String {
+    let mut child = transactioner(args);
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn reads_the_standard_input() {
+    let input = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,2.5\n";
+    let expected = "client,available,held,total,locked\n1,7.5000,0.0000,7.5000,false\n";
+
+    assert_eq!(output_for(&["-"], input), expected);
+    assert_eq!(output_for(&[], input), expected);
+}
+
+#[test]
+fn reads_byte_order_marks_and_crlf_line_endings() {
+    let input = "\u{feff}type,client,tx,amount\r\ndeposit,1,1,10.0\r\n\r\nwithdrawal,1,2,2.5\r\n";
+    let expected = "client,available,held,total,locked\n1,7.5000,0.0000,7.5000,false\n";
+
+    assert_eq!(output_for(&["-", "--strict"], input), expected);
+}
+
+#[test]
+fn reads_compressed_standard_input() {
+    // Accounts are written in no particular order across workers
+    let sorted = |output: String| {
+        let mut lines: Vec<String> = output.lines().map(str::to_string).collect();
+        lines.sort();
+        lines
+    };
+    let expected = sorted(output_for(&["-"], &std::fs::read_to_string("test_data/15.csv").unwrap()));
+
+    for file_path in ["test_data/15.csv.gz", "test_data/15.csv.zst"] {
+        let mut child = transactioner(&["-"]);
+        child.stdin.take().unwrap().write_all(&std::fs::read(file_path).unwrap()).unwrap();
+
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(sorted(String::from_utf8(output.stdout).unwrap()), expected, "Reading {}", file_path);
+    }
+}
+
+#[test]
+fn empty_input_only_prints_the_header() {
+    assert_eq!(output_for(&["-"], ""), "client,available,held,total,locked\n");
+}
+
+#[test]
+fn closed_output_is_not_a_failure() {
+    let mut child = transactioner(&["-"]);
+    // Nobody is reading by the time the accounts are written
+    drop(child.stdout.take());
+
+    let mut input = String::from("type,client,tx,amount\n");
+    for client in 0..1_000 {
+        input.push_str(&format!("deposit,{},{},1.0\n", client, client));
+    }
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+
+    let status = child.wait().unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn malformed_rows_only_fail_the_run_in_strict_mode() {
+    let input = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,abc,2,10.0\nwithdrawal,1,3,2.5\n";
+
+    assert_eq!(output_for(&["-"], input), "client,available,held,total,locked\n1,7.5000,0.0000,7.5000,false\n");
+
+    let mut child = transactioner(&["-", "--strict"]);
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn limited_runs_print_the_accounts_so_far() {
+    let input = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,2.5\ndeposit,2,3,1.0\n";
+    let expected = "client,available,held,total,locked\n1,7.5000,0.0000,7.5000,false\n";
+
+    assert_eq!(output_for(&["-", "--limit-rows", "2"], input), expected);
+    assert_eq!(output_for(&["-", "--limit-bytes", "60"], input), expected);
+}
+
+#[test]
+fn output_files_hold_what_the_standard_output_does() {
+    let input = "type,client,tx,amount,currency\ndeposit,1,1,10.0,EUR\ndeposit,1,2,2.5,USD\ndispute,1,1,,EUR\n";
+    let directory = std::env::temp_dir().join(format!("transactioner_output_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("accounts.csv");
+    let path_arg = path.to_str().unwrap();
+
+    for args in [&["-"][..], &["-", "--extended-output", "--precision", "2"][..]] {
+        let expected = output_for(args, input);
+        assert_eq!(output_for(&[args, &["--output", path_arg]].concat(), input), "");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected, "Running with {:?}", args);
+    }
+    assert!(!directory.join("accounts.csv.tmp").exists());
+
+    // Failing to write the file fails the run and leaves nothing behind
+    let missing = directory.join("missing").join("accounts.csv");
+    let mut child = transactioner(&["-", "--output", missing.to_str().unwrap()]);
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    assert!(!child.wait_with_output().unwrap().status.success());
+    assert!(!missing.exists());
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn accounts_are_written_in_the_order_of_their_client() {
+    let input = std::fs::read_to_string("test_data/15.csv").unwrap();
+    let output = output_for(&["-"], &input);
+
+    // The clients are spread over both workers
+    let client = |line: &str| line.split(',').next().unwrap().parse().unwrap();
+    let clients: Vec<u16> = output.lines().skip(1).map(client).collect();
+    assert!(clients.len() > 2);
+    assert!(clients.windows(2).all(|pair| pair[0] < pair[1]), "Clients in the order {:?}", clients);
+
+    for _ in 0..3 {
+        assert_eq!(output_for(&["-"], &input), output);
+    }
+}
+
+#[test]
+fn streamed_accounts_match_the_buffered_ones() {
+    // Streamed accounts come in the order the workers are done with them
+    let sorted = |output: String| {
+        let mut lines: Vec<String> = output.lines().map(|line| line.trim_end_matches(',').to_string()).collect();
+        lines.sort();
+        lines
+    };
+
+    for file_path in ["test_data/15.csv", "test_data/currencies.csv", "test_data/merges.csv"] {
+        let input = std::fs::read_to_string(file_path).unwrap();
+        for format in ["csv", "json", "ndjson"] {
+            let expected = sorted(output_for(&["-", "--output-format", format], &input));
+            let streamed = sorted(output_for(&["-", "--output-format", format, "--unsorted"], &input));
+            assert_eq!(streamed, expected, "Streaming {} as {}", file_path, format);
+        }
+    }
+
+    // Nothing is streamed from an input that fails the run
+    let mut child = transactioner(&["-", "--strict", "--unsorted"]);
+    child.stdin.take().unwrap().write_all(b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,x,2,1.0\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn shards_hold_the_accounts_of_the_single_output() {
+    for file_path in ["test_data/15.csv", "test_data/currencies.csv", "test_data/merges.csv"] {
+        let input = std::fs::read_to_string(file_path).unwrap();
+        let dir = std::env::temp_dir().join(format!("transactioner_shards_{}", file_path.replace('/', "_")));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let expected = output_for(&["-"], &input);
+        assert_eq!(output_for(&["-", "--output-shards", dir.to_str().unwrap()], &input), "");
+
+        // Each shard repeats the header, and the clients of the workers interleave once they are put together
+        let (header, expected_rows) = expected.split_once('\n').unwrap();
+        let mut expected_rows: Vec<&str> = expected_rows.lines().collect();
+        expected_rows.sort_unstable();
+        let manifest = std::fs::read_to_string(dir.join("manifest.csv")).unwrap();
+        let mut manifest_lines = manifest.lines();
+        assert_eq!(manifest_lines.next(), Some("shard,rows"));
+
+        let mut shards = Vec::new();
+        for line in manifest_lines {
+            let (shard, rows) = line.split_once(',').unwrap();
+            shards.push(std::fs::read_to_string(dir.join(shard)).unwrap());
+            let (shard_header, shard_rows) = shards.last().unwrap().split_once('\n').unwrap();
+            assert_eq!(shard_header, header, "Header of {} for {}", shard, file_path);
+            assert_eq!(shard_rows.lines().count(), rows.parse::<usize>().unwrap(), "Rows of {}", shard);
+        }
+        assert_eq!(shards.len(), 2);
+
+        let mut rows: Vec<&str> = shards.iter().flat_map(|shard| shard.lines().skip(1)).collect();
+        rows.sort_unstable();
+        assert_eq!(rows, expected_rows, "Sharding {}", file_path);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // No shard is written for an input that fails the run
+    let dir = std::env::temp_dir().join("transactioner_shards_failed");
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut child = transactioner(&["-", "--strict", "--output-shards", dir.to_str().unwrap()]);
+    child.stdin.take().unwrap().write_all(b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,x,2,1.0\n").unwrap();
+    assert!(!child.wait_with_output().unwrap().status.success());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rejected_transactions_are_reported_with_their_reason() {
+    let report_path = std::env::temp_dir().join("transactioner_rejected.csv");
+    let args = ["test_data/rejected.csv", "--reject-scientific", "--report-rejected", report_path.to_str().unwrap()];
+    output_for(&args, "");
+
+    // Each worker and the reader send their rows in order, but they interleave in the report
+    let report = std::fs::read_to_string(&report_path).unwrap();
+    let mut lines = report.lines();
+    assert_eq!(lines.next(), Some("row,client,tx,type,amount,reason"));
+    let mut rows: Vec<&str> = lines.collect();
+    rows.sort_by_key(|row| row.split(',').next().unwrap().parse::<u64>().unwrap());
+    assert_eq!(
+        rows,
+        [
+            "3,1,2,withdrawal,50.0000,insufficient_funds",
+            "4,1,1,deposit,5.0000,duplicate_tx",
+            "5,1,99,dispute,,unknown_tx_reference",
+            "9,2,4,deposit,5.0000,account_locked",
+            "10,,,,,malformed_row",
+            "11,3,6,deposit,-4.0000,invalid_amount",
+            "12,1,1,resolve,,ignored",
+            "13,1,3,dispute,,wrong_client",
+            "14,,,,,scientific_amount",
+        ]
+    );
+    std::fs::remove_file(&report_path).unwrap();
+}
+
+#[test]
+fn diff_exits_with_whether_the_outputs_differ() {
+    let status = |before: &str, after: &str| {
+        let mut child = transactioner(&["diff", before, after]);
+        drop(child.stdin.take());
+        child.wait().unwrap().code()
+    };
+
+    assert_eq!(status("test_data/diff_before.csv", "test_data/diff_reordered.csv"), Some(0));
+    assert_eq!(status("test_data/diff_before.csv", "test_data/diff_after.csv"), Some(1));
+    assert_eq!(status("test_data/diff_before.csv", "test_data/diff_duplicated.csv"), Some(2));
+}
+
+#[test]
+fn inspect_writes_the_account_of_a_single_client() {
+    let account = "client,available,held,total,locked\n1,100.0000,0.0000,100.0000,true\n";
+    assert_eq!(output_for(&["inspect", "test_data/15.csv", "--client", "1"], ""), account);
+
+    let history = output_for(&["inspect", "test_data/15.csv", "--client", "1", "--history"], "");
+    let (transactions, rest) = history.split_once("\n\n").unwrap();
+    assert_eq!(rest, account);
+    assert_eq!(
+        transactions.lines().collect::<Vec<_>>(),
+        [
+            "client,tx,type,amount,available,held,outcome,reason,row",
+            "1,1,deposit,100.0000,100.0000,0.0000,applied,,2",
+            "1,4,deposit,100.0000,200.0000,0.0000,applied,,3",
+            "1,1,dispute,0.0000,100.0000,100.0000,applied,,5",
+            "1,1,chargeback,0.0000,100.0000,0.0000,applied,,7",
+            "1,9,withdrawal,15.0000,100.0000,0.0000,skipped,account_locked,8",
+            "1,15,withdrawal,100.0000,100.0000,0.0000,skipped,account_locked,12",
+        ]
+    );
+}
+
+#[test]
+fn generated_inputs_can_be_piped_into_a_run() {
+    let generated = output_for(&["generate", "--rows", "2000", "--clients", "20", "--seed", "42"], "");
+    assert_eq!(generated.lines().count(), 2001);
+    assert_eq!(output_for(&["generate", "--rows", "2000", "--clients", "20", "--seed", "42"], ""), generated);
+
+    let accounts = output_for(&["--strict"], &generated);
+    assert_eq!(accounts.lines().count(), 21);
+
+    let mut child = transactioner(&["generate", "--dispute-rate", "2"]);
+    drop(child.stdin.take());
+    assert_eq!(child.wait().unwrap().code(), Some(2));
+}
+
+#[test]
+fn config_files_are_read_from_the_environment() {
+    let config_path = std::env::temp_dir().join("transactioner_stdin_config.toml");
+    let run = |config: &str, args: &[&str]| {
+        std::fs::write(&config_path, config).unwrap();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_transactioner"))
+            .args(args)
+            .env("TRANSACTIONER_CONFIG", &config_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Should start the binary");
+        // The run may have exited over the config before reading any of it
+        let _ = child.stdin.take().unwrap().write_all(b"type,client,tx,amount\ndeposit,1,1,10.5\n");
+        let output = child.wait_with_output().unwrap();
+        (output.status.code(), String::from_utf8(output.stdout).unwrap())
+    };
+
+    let expected = "client,available,held,total,locked\n1,10.50,0.00,10.50,false\n".to_string();
+    assert_eq!(run("[output]\nprecision = 2\n", &[]), (Some(0), expected));
+    let expected = "client,available,held,total,locked\n1,10.5,0.0,10.5,false\n".to_string();
+    assert_eq!(run("[output]\nprecision = 2\n", &["--precision", "1"]), (Some(0), expected));
+    assert_eq!(run("[output]\nprecisoin = 2\n", &[]), (Some(2), String::new()));
+    std::fs::remove_file(&config_path).unwrap();
+}
+
+#[test]
+fn help_and_unreadable_arguments_exit_with_their_status() {
+    let run = |args: &[&str]| {
+        let mut child = transactioner(args);
+        drop(child.stdin.take());
+        let output = child.wait_with_output().unwrap();
+        (output.status.code(), String::from_utf8(output.stdout).unwrap())
+    };
+
+    let (status, help) = run(&["--help"]);
+    assert_eq!(status, Some(0));
+    assert!(help.starts_with("transactioner ") && help.contains("Usage: transactioner [process]"), "{}", help);
+    let (status, process_help) = run(&["process", "-h"]);
+    assert_eq!(status, Some(0));
+    assert!(process_help.contains("Usage: transactioner process [options]"), "{}", process_help);
+    assert_eq!(run(&["--version"]), (Some(0), format!("transactioner {}\n", env!("CARGO_PKG_VERSION"))));
+
+    assert_eq!(run(&["test_data/15.csv", "--outptu", "out.csv"]), (Some(2), String::new()));
+    assert_eq!(run(&["test_data/missing.csv"]), (Some(1), String::new()));
+    // The shorthand and the subcommand give the same accounts
+    assert_eq!(run(&["process", "test_data/15.csv"]), run(&["test_data/15.csv"]));
+}
+
+#[test]
+fn accounts_are_the_same_whatever_the_amount_of_workers() {
+    let input = std::fs::read_to_string("test_data/transfers.csv").unwrap();
+    let expected = output_for(&["--workers", "1"], &input);
+
+    assert_eq!(output_for(&["--workers", "2"], &input), expected);
+    assert_eq!(output_for(&["--workers", "8"], &input), expected);
+    assert_eq!(output_for(&["--workers", "auto"], &input), expected);
+    assert_eq!(output_for(&["--workers", "8", "--buffer-size", "1"], &input), expected);
+}
+
+#[test]
+fn validated_inputs_exit_with_whether_they_are_clean() {
+    let validate = |input: &str| {
+        let mut child = transactioner(&["--validate-only", input]);
+        drop(child.stdin.take());
+        let output = child.wait_with_output().unwrap();
+        (output.status.code(), String::from_utf8(output.stdout).unwrap())
+    };
+
+    let (status, report) = validate("test_data/20.csv");
+    assert_eq!(status, Some(0));
+    assert!(report.starts_with("rows: 20\n") && report.ends_with("\nclean\n"), "{}", report);
+
+    let (status, report) = validate("test_data/dirty.csv");
+    assert_eq!(status, Some(1));
+    assert!(report.contains("\nmalformed rows: 2\n") && report.ends_with("\nnot clean\n"), "{}", report);
+}
@@ -0,0 +1,59 @@
+use std::process::Command;
+
+fn stderr_for(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_transactioner"))
+        .args(args)
+        .output()
+        .expect("Should run the binary");
+
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn quiet_runs_only_write_errors() {
+    let stderr = stderr_for(&["test_data/15.csv"]);
+    assert!(stderr.contains("Using 2 worker thread/s"), "{}", stderr);
+    assert!(stderr.contains("Summary:"), "{}", stderr);
+    assert!(!stderr.lines().any(|line| line == "Reading test_data/15.csv"), "{}", stderr);
+
+    assert_eq!(stderr_for(&["test_data/15.csv", "-q"]), "");
+    assert_eq!(
+        stderr_for(&["test_data/missing.csv", "--quiet"]),
+        "File path \"test_data/missing.csv\" is invalid, exiting...\n"
+    );
+    let invalid = stderr_for(&["test_data/15.csv", "-q", "--precision", "9"]);
+    assert!(invalid.starts_with("error: invalid value '9' for '--precision <n>'"), "{}", invalid);
+}
+
+#[test]
+fn verbose_runs_tell_each_input_and_worker() {
+    let stderr = stderr_for(&["test_data/15.csv", "test_data/20.csv", "-v"]);
+    let lines: Vec<&str> = stderr.lines().collect();
+
+    assert!(lines.contains(&"Reading test_data/15.csv"), "{}", stderr);
+    assert!(lines.contains(&"Reading test_data/20.csv"), "{}", stderr);
+    assert!(lines.iter().any(|line| line.starts_with("Read ") && line.ends_with(" row/s from test_data/20.csv")));
+    for worker in 0..2 {
+        let prefix = format!("Worker {} applied ", worker);
+        assert!(lines.iter().any(|line| line.starts_with(&prefix)), "{}", stderr);
+    }
+    // Everything written by default still is
+    assert!(stderr.contains("Summary:"), "{}", stderr);
+}
+
+#[test]
+fn zero_counters_are_only_written_when_asked_for() {
+    let stderr = stderr_for(&["test_data/15.csv"]);
+    assert!(stderr.contains("Processed 15 row/s"), "{}", stderr);
+    assert!(stderr.contains("  skipped_account_locked  "), "{}", stderr);
+    assert!(!stderr.contains("Released 0 expired dispute/s"), "{}", stderr);
+    assert!(!stderr.contains("  transfer_rows  "), "{}", stderr);
+
+    // Unless the option counting them is given, or the run is verbose
+    let stderr = stderr_for(&["test_data/15.csv", "--dispute-ttl", "5"]);
+    assert!(stderr.contains("Released 0 expired dispute/s"), "{}", stderr);
+    assert!(!stderr.contains("Dropped 0 transaction/s for tx id reuse"), "{}", stderr);
+    let stderr = stderr_for(&["test_data/15.csv", "-v"]);
+    assert!(stderr.contains("Dropped 0 transaction/s for tx id reuse"), "{}", stderr);
+    assert!(stderr.contains("  transfer_rows  "), "{}", stderr);
+}